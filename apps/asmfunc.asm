@@ -1,7 +1,11 @@
 [BITS 32]
 
+; システムコール番号とエラーコードはsrc/abi.rsを参照
+; 失敗したときはEAXに負のエラーコードが返る
+
 		GLOBAL	_api_putchar
 		GLOBAL	_api_putstr0
+		GLOBAL	_api_putstr1
 		GLOBAL	_api_end
 		GLOBAL	_api_openwin
 		GLOBAL	_api_putstrwin
@@ -26,6 +30,22 @@
 		GLOBAL	_api_fread
 		GLOBAL	_api_cmdline
 		GLOBAL	_api_getlang
+		GLOBAL	_api_gettime
+		GLOBAL	_api_getnsec
+		GLOBAL	_api_setperiodic
+		GLOBAL	_api_sleep
+		GLOBAL	_api_canceltimer
+		GLOBAL	_api_gettick
+		GLOBAL	_api_getheap
+		GLOBAL	_api_argc
+		GLOBAL	_api_getarg
+		GLOBAL	_api_getenv
+		GLOBAL	_api_read
+		GLOBAL	_api_write
+		GLOBAL	_api_exit
+		GLOBAL	_api_signal
+		GLOBAL	_api_sigreturn
+		GLOBAL	_api_alarm
 
 [SECTION .text]
 
@@ -43,6 +63,15 @@ _api_putstr0:	; void api_putstr0(char *s);
 		POP		EBX
 		RET
 
+_api_putstr1:	; void api_putstr1(char *s, int l);
+		PUSH	EBX
+		MOV		EDX,3
+		MOV		EBX,[ESP+ 8]	; s
+		MOV		ECX,[ESP+12]	; l
+		INT		0x40
+		POP		EBX
+		RET
+
 _api_end:	; void api_end(void);
 		MOV		EDX,4
 		INT		0x40
@@ -101,33 +130,33 @@ _api_boxfilwin:	; void api_boxfilwin(int win, int x0, int y0, int x1, int y1, in
 		POP		EDI
 		RET
 
-_api_initmalloc:	; void api_initmalloc(void);
+; malloc系はヘッダを読まずに、api_getheapで得た領域の先頭に置いたメモリ管理の番地(memman)を渡す
+; .hrbでもELFでも同じように使える
+_api_initmalloc:	; void api_initmalloc(int memman, char *addr, int size);
 		PUSH	EBX
 		MOV		EDX,8
-		MOV		EBX,[CS:0x0020]		; malloc領域の番地
-		MOV		EAX,EBX
-		ADD		EAX,32*1024			; 32KBを足す
-		MOV		ECX,[CS:0x0000]		; データセグメントの大きさ
-		SUB		ECX,EAX
+		MOV		EBX,[ESP+ 8]		; memman
+		MOV		EAX,[ESP+12]		; addr
+		MOV		ECX,[ESP+16]		; size
 		INT		0x40
 		POP		EBX
 		RET
 
-_api_malloc:		; char *api_malloc(int size);
+_api_malloc:		; char *api_malloc(int memman, int size);
 		PUSH	EBX
 		MOV		EDX,9
-		MOV		EBX,[CS:0x0020]
-		MOV		ECX,[ESP+8]			; size
+		MOV		EBX,[ESP+ 8]		; memman
+		MOV		ECX,[ESP+12]		; size
 		INT		0x40
 		POP		EBX
 		RET
 
-_api_free:			; void api_free(char *addr, int size);
+_api_free:			; void api_free(int memman, char *addr, int size);
 		PUSH	EBX
 		MOV		EDX,10
-		MOV		EBX,[CS:0x0020]
-		MOV		EAX,[ESP+ 8]		; addr
-		MOV		ECX,[ESP+12]		; size
+		MOV		EBX,[ESP+ 8]		; memman
+		MOV		EAX,[ESP+12]		; addr
+		MOV		ECX,[ESP+16]		; size
 		INT		0x40
 		POP		EBX
 		RET
@@ -287,3 +316,123 @@ _api_getlang:		; int api_getlang(void);
 		MOV		EDX,27
 		INT		0x40
 		RET
+
+_api_gettime:		; void api_gettime(int *buf);
+		PUSH	EBX
+		MOV		EDX,28
+		MOV		EBX,[ESP+8]			; buf
+		INT		0x40
+		POP		EBX
+		RET
+
+_api_getnsec:		; unsigned long long api_getnsec(void);
+		MOV		EDX,29
+		INT		0x40
+		RET
+
+_api_setperiodic:	; void api_setperiodic(int timer, int interval);
+		PUSH	EBX
+		MOV		EDX,30
+		MOV		EBX,[ESP+ 8]		; timer
+		MOV		EAX,[ESP+12]		; interval
+		INT		0x40
+		POP		EBX
+		RET
+
+_api_sleep:		; void api_sleep(int ms);
+		MOV		EDX,31
+		MOV		EAX,[ESP+4]			; ms
+		INT		0x40
+		RET
+
+_api_canceltimer:	; int api_canceltimer(int timer);
+		PUSH	EBX
+		MOV		EDX,32
+		MOV		EBX,[ESP+8]			; timer
+		INT		0x40
+		POP		EBX
+		RET
+
+_api_gettick:		; int api_gettick(void);
+		MOV		EDX,33
+		INT		0x40
+		RET
+
+_api_getheap:	; void api_getheap(int *info);
+		PUSH	EBX
+		MOV		EDX,34
+		MOV		EBX,[ESP+8]			; info
+		INT		0x40
+		POP		EBX
+		RET
+
+_api_argc:		; int api_argc(void);
+		MOV		EDX,35
+		INT		0x40
+		RET
+
+_api_getarg:		; int api_getarg(int i, char *buf, int maxsize);
+		PUSH	EBX
+		MOV		EDX,36
+		MOV		EAX,[ESP+ 8]		; i
+		MOV		EBX,[ESP+12]		; buf
+		MOV		ECX,[ESP+16]		; maxsize
+		INT		0x40
+		POP		EBX
+		RET
+
+_api_getenv:		; int api_getenv(char *name, char *buf, int maxsize);
+		PUSH	EBX
+		MOV		EDX,37
+		MOV		EAX,[ESP+ 8]		; name
+		MOV		EBX,[ESP+12]		; buf
+		MOV		ECX,[ESP+16]		; maxsize
+		INT		0x40
+		POP		EBX
+		RET
+
+_api_read:		; int api_read(int fd, char *buf, int maxsize);
+		PUSH	EBX
+		MOV		EDX,38
+		MOV		EAX,[ESP+ 8]		; fd
+		MOV		EBX,[ESP+12]		; buf
+		MOV		ECX,[ESP+16]		; maxsize
+		INT		0x40
+		POP		EBX
+		RET
+
+_api_write:		; int api_write(int fd, char *buf, int len);
+		PUSH	EBX
+		MOV		EDX,39
+		MOV		EAX,[ESP+ 8]		; fd
+		MOV		EBX,[ESP+12]		; buf
+		MOV		ECX,[ESP+16]		; len
+		INT		0x40
+		POP		EBX
+		RET
+
+_api_exit:		; void api_exit(int status);
+		MOV		EDX,40
+		MOV		EAX,[ESP+4]		; status
+		INT		0x40
+
+_api_signal:	; int api_signal(int sig, int handler, int restorer);
+		PUSH	EBX
+		MOV		EDX,41
+		MOV		EAX,[ESP+ 8]		; sig
+		MOV		EBX,[ESP+12]		; handler
+		MOV		ECX,[ESP+16]		; restorer
+		INT		0x40
+		POP		EBX
+		RET
+
+_api_sigreturn:	; void api_sigreturn(void);
+		; シグナルハンドラからのRETでここに来る。スタックを動かさずに呼ぶこと
+		MOV		EDX,42
+		INT		0x40
+
+_api_alarm:		; int api_alarm(int time);
+		MOV		EDX,43
+		MOV		EAX,[ESP+4]		; time
+		INT		0x40
+		RET
@@ -1,69 +1,28 @@
 #![no_std]
-#![feature(asm)]
-#![feature(start)]
-#![feature(naked_functions)]
 
-use core::fmt;
-use core::panic::PanicInfo;
+extern crate alloc;
 
-const INVALID: i32 = -0x7fffffff;
-
-extern "C" {
-    fn _api_putstr0(string_ptr: usize);
-    fn _api_cmdline(buf_addr: usize, maxsize: usize);
-}
+use alloc::vec::Vec;
+use haribote_sdk::{args, entry, println};
 
-const RESULT_LENGTH: usize = 30;
+const INVALID: i32 = -0x7fffffff;
 
-struct CalcResult {
-    r: [u8; RESULT_LENGTH],
-    p: usize,
-}
+entry!(main);
 
-impl CalcResult {
-    fn new() -> CalcResult {
-        CalcResult {
-            r: [0; RESULT_LENGTH],
-            p: 0,
-        }
-    }
-}
-
-impl fmt::Write for CalcResult {
-    fn write_str(&mut self, s: &str) -> fmt::Result {
-        let str_bytes = s.as_bytes();
-        for i in 0..str_bytes.len() {
-            if self.p >= RESULT_LENGTH {
-                break;
-            }
-            self.r[self.p] = str_bytes[i];
-            self.p += 1;
-        }
-        Ok(())
+fn main() {
+    // 引数を空白でつないで1つの式にする
+    let mut buf: Vec<u8> = Vec::new();
+    for arg in args().skip(1) {
+        buf.extend_from_slice(&arg);
+        buf.push(b' ');
     }
-}
-
-#[no_mangle]
-#[start]
-pub extern "C" fn hrmain() {
-    use core::fmt::Write;
-    let buf: [u8; 30] = [0; 30];
-    unsafe { _api_cmdline(buf.as_ptr() as usize, 30) };
-    let mut pi = 0;
-    let mut p = buf[pi];
-    while p > b' ' {
-        pi += 1;
-        p = buf[pi];
-    }
-    let (i, _) = getnum(&buf[pi..(buf.len())], 0, 9);
+    buf.push(0);
+    let (i, _) = getnum(&buf, 0, 9);
     if i == INVALID {
-        unsafe { _api_putstr0(b"error!\n\0".as_ptr() as usize) };
+        println!("error!");
     } else {
-        let mut r = CalcResult::new();
-        write!(r, "= {} = 0x{:x}\n\0", i, i).unwrap();
-        unsafe { _api_putstr0(r.r.as_ptr() as usize) };
+        println!("= {} = 0x{:x}", i, i);
     }
-    end();
 }
 
 fn skipspace(buf: &[u8], i: usize) -> usize {
@@ -226,18 +185,3 @@ fn getnum(buf: &[u8], bi: usize, priority: i32) -> (i32, usize) {
     }
     (i, bi)
 }
-
-#[naked]
-fn end() {
-    unsafe {
-        asm!("MOV EDX,4
-              INT 0x40" : : : : "intel");
-    }
-}
-
-#[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
-    loop {
-        unsafe { asm!("HLT") }
-    }
-}
@@ -0,0 +1,69 @@
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::str::from_utf8;
+
+use haribote_sdk::io::{self, STDIN};
+use haribote_sdk::{args, entry, eprintln, exit, put_bytes, File};
+
+entry!(main);
+
+fn main() {
+    let mut args = args().skip(1);
+    let pattern = match args.next() {
+        Some(pattern) => pattern,
+        None => {
+            eprintln!("usage: grep PATTERN [FILE]...");
+            exit(2);
+        }
+    };
+    let filenames: Vec<Vec<u8>> = args.collect();
+    // 見つかれば0、見つからなければ1、エラーなら2で終了する
+    let mut found = false;
+    let mut failed = false;
+    // ファイル名がなければ標準入力から探す
+    if filenames.is_empty() {
+        match io::read_to_end(STDIN) {
+            Ok(text) => found |= grep(&pattern, &text),
+            Err(e) => {
+                eprintln!("grep: {}", e);
+                failed = true;
+            }
+        }
+    }
+    for filename in filenames.iter() {
+        match File::open(filename).and_then(|mut file| file.read_to_end()) {
+            Ok(text) => found |= grep(&pattern, &text),
+            Err(e) => {
+                eprintln!("grep: {}: {}", from_utf8(filename).unwrap_or("?"), e);
+                failed = true;
+            }
+        }
+    }
+    exit(if failed {
+        2
+    } else if found {
+        0
+    } else {
+        1
+    });
+}
+
+/// patternを含む行を出力する。1行でもあればtrueを返す
+fn grep(pattern: &[u8], text: &[u8]) -> bool {
+    let text = match text.last() {
+        Some(b'\n') => &text[..(text.len() - 1)],
+        _ => text,
+    };
+    let mut found = false;
+    for line in text.split(|c| *c == b'\n') {
+        if pattern.is_empty() || line.windows(pattern.len()).any(|w| w == pattern) {
+            put_bytes(line);
+            put_bytes(b"\n");
+            found = true;
+        }
+    }
+    found
+}
@@ -0,0 +1,52 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::null_mut;
+
+use crate::{exit, sys};
+
+/// ヒープの先頭に置く、カーネルのメモリ管理の大きさ
+const MEMMAN_SIZE: i32 = 32 * 1024;
+
+/// メモリ管理の番地。initで決まる
+static mut MEMMAN: i32 = 0;
+
+/// カーネルに教えてもらったヒープの先頭にメモリ管理を置き、残りを確保に使う
+pub fn init() {
+    let mut info = [0i32; 2];
+    unsafe {
+        sys::_api_getheap(info.as_mut_ptr());
+        let (addr, size) = (info[0], info[1]);
+        if size > MEMMAN_SIZE {
+            MEMMAN = addr;
+            sys::_api_initmalloc(addr, addr + MEMMAN_SIZE, size - MEMMAN_SIZE);
+        }
+    }
+}
+
+struct Allocator;
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // api_mallocは16バイト単位で確保するので、それより大きい境界には合わせられない
+        if layout.align() > 16 {
+            return null_mut();
+        }
+        let addr = sys::_api_malloc(MEMMAN, layout.size() as i32);
+        if addr <= 0 {
+            return null_mut();
+        }
+        addr as *mut u8
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        sys::_api_free(MEMMAN, ptr, layout.size() as i32)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Allocator = Allocator;
+
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    eprintln!("out of memory ({} bytes)", layout.size());
+    exit(1)
+}
@@ -0,0 +1,69 @@
+//! 標準出力と標準エラー出力への書き込み
+
+use core::fmt;
+
+use crate::abi::STDERR;
+use crate::sys;
+
+pub struct Console;
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        put_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+pub struct Stderr;
+
+impl fmt::Write for Stderr {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        crate::io::write(STDERR, s.as_bytes())
+            .map(|_| ())
+            .map_err(|_| fmt::Error)
+    }
+}
+
+pub fn putchar(c: u8) {
+    unsafe { sys::_api_putchar(c as i32) }
+}
+
+/// UTF-8ではない文字列(Shift_JISやEUC-JP)もそのまま出力できる
+pub fn put_bytes(s: &[u8]) {
+    unsafe { sys::_api_putstr1(s.as_ptr(), s.len() as i32) }
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    let _ = Console.write_fmt(args);
+}
+
+#[doc(hidden)]
+pub fn _eprint(args: fmt::Arguments) {
+    use core::fmt::Write;
+    let _ = Stderr.write_fmt(args);
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::console::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+/// 標準エラー出力に書く。標準出力をパイプなどにつないでいてもコンソールに表示される
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => ($crate::console::_eprint(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprintln {
+    () => ($crate::eprint!("\n"));
+    ($($arg:tt)*) => ($crate::eprint!("{}\n", format_args!($($arg)*)));
+}
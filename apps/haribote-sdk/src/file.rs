@@ -0,0 +1,70 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::abi::E_INVAL;
+use crate::{check, sys, Error, Result};
+
+/// 読み込み専用で開いたファイル。dropされると閉じる
+pub struct File {
+    handle: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(i32),
+    Current(i32),
+    End(i32),
+}
+
+impl File {
+    pub fn open<P: AsRef<[u8]>>(name: P) -> Result<File> {
+        let name = name.as_ref();
+        let mut name_buf = [0u8; 64];
+        if name.len() >= name_buf.len() {
+            return Err(Error(E_INVAL));
+        }
+        name_buf[..name.len()].copy_from_slice(name);
+        let handle = check(unsafe { sys::_api_fopen(name_buf.as_ptr()) })?;
+        Ok(File { handle })
+    }
+
+    pub fn size(&self) -> usize {
+        unsafe { sys::_api_fsize(self.handle, 0) as usize }
+    }
+
+    /// 次に読む位置
+    pub fn pos(&self) -> usize {
+        unsafe { sys::_api_fsize(self.handle, 1) as usize }
+    }
+
+    /// ファイルの範囲外を指定したときは先頭か末尾に合わせられる
+    pub fn seek(&mut self, pos: SeekFrom) {
+        let (offset, mode) = match pos {
+            SeekFrom::Start(offset) => (offset, 0),
+            SeekFrom::Current(offset) => (offset, 1),
+            SeekFrom::End(offset) => (offset, 2),
+        };
+        unsafe { sys::_api_fseek(self.handle, offset, mode) }
+    }
+
+    /// 読めたバイト数を返す。0ならファイルの終わり
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let size =
+            check(unsafe { sys::_api_fread(buf.as_mut_ptr(), buf.len() as i32, self.handle) })?;
+        Ok(size as usize)
+    }
+
+    /// 今の位置から最後まで読む
+    pub fn read_to_end(&mut self) -> Result<Vec<u8>> {
+        let mut buf = vec![0; self.size() - self.pos()];
+        let size = self.read(&mut buf)?;
+        buf.truncate(size);
+        Ok(buf)
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        unsafe { sys::_api_fclose(self.handle) }
+    }
+}
@@ -0,0 +1,33 @@
+//! 標準入出力
+//!
+//! コンソールから起動したアプリでは、標準入力はキーボード、標準出力と標準エラー出力は
+//! コンソールの画面につながっている。`|`や`<`, `>`で起動されたときは前後のアプリの出力や
+//! ファイルにつながる。キーボードからは1行ずつ読め、行の先頭でCtrl+Dを押すと終わりになる。
+
+use alloc::vec::Vec;
+
+pub use crate::abi::{STDERR, STDIN, STDOUT};
+use crate::{check, sys, Result};
+
+/// 読めたバイト数を返す。0なら入力の終わり
+pub fn read(fd: i32, buf: &mut [u8]) -> Result<usize> {
+    let size = check(unsafe { sys::_api_read(fd, buf.as_mut_ptr(), buf.len() as i32) })?;
+    Ok(size as usize)
+}
+
+pub fn write(fd: i32, buf: &[u8]) -> Result<usize> {
+    let size = check(unsafe { sys::_api_write(fd, buf.as_ptr(), buf.len() as i32) })?;
+    Ok(size as usize)
+}
+
+/// 入力の終わりまで読む
+pub fn read_to_end(fd: i32) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut buf = [0; 512];
+    loop {
+        match read(fd, &mut buf)? {
+            0 => return Ok(data),
+            size => data.extend_from_slice(&buf[..size]),
+        }
+    }
+}
@@ -0,0 +1,224 @@
+//! はりぼてOSのアプリ用ライブラリ
+//!
+//! システムコールを安全に呼べるようにした関数・構造体と、アプリに必要な
+//! エントリポイント・パニックハンドラ・グローバルアロケータをまとめている。
+//! `alloc`クレートの`Vec`や`String`もそのまま使える。
+//!
+//! ```ignore
+//! #![no_std]
+//!
+//! use haribote_sdk::{entry, println};
+//!
+//! entry!(main);
+//!
+//! fn main() {
+//!     println!("hello, world");
+//! }
+//! ```
+
+#![no_std]
+#![feature(alloc_error_handler)]
+
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+#[path = "../../../src/abi.rs"]
+pub mod abi;
+#[macro_use]
+pub mod console;
+mod allocator;
+pub mod file;
+pub mod io;
+pub mod signal;
+pub mod sys;
+pub mod timer;
+pub mod window;
+
+pub use console::{put_bytes, putchar};
+pub use file::{File, SeekFrom};
+pub use timer::Timer;
+pub use window::Window;
+
+use core::fmt;
+use core::panic::PanicInfo;
+
+use abi::*;
+
+/// システムコールが返した負のエラーコード
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Error(pub i32);
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self.0 {
+            E_AGAIN => "no data",
+            E_NOSYS => "no such system call",
+            E_INVAL => "invalid argument",
+            E_NOMEM => "out of memory",
+            E_NOWIN => "too many windows",
+            E_NOTIMER => "too many timers",
+            E_NOENT => "file not found",
+            E_NOFILE => "too many open files",
+            E_FAULT => "bad address",
+            E_BADF => "bad handle",
+            E_INTR => "interrupted",
+            code => return write!(f, "error {}", code),
+        };
+        f.write_str(msg)
+    }
+}
+
+fn check(ret: i32) -> Result<i32> {
+    if ret < 0 {
+        Err(Error(ret))
+    } else {
+        Ok(ret)
+    }
+}
+
+/// `entry!`で作られる`hrmain`から最初に呼ばれる
+#[doc(hidden)]
+pub fn init() {
+    allocator::init();
+}
+
+/// アプリを終了する
+pub fn end() -> ! {
+    unsafe { sys::_api_end() }
+}
+
+/// 終了ステータスを指定してアプリを終了する
+/// 0なら成功で、スクリプトの`if`や`$?`で参照できる
+pub fn exit(status: i32) -> ! {
+    unsafe { sys::_api_exit(status) }
+}
+
+/// アプリのエントリポイント`hrmain`を定義する
+/// 渡した関数から戻るとアプリは終了する
+#[macro_export]
+macro_rules! entry {
+    ($main:path) => {
+        #[no_mangle]
+        pub extern "C" fn hrmain() {
+            let main: fn() = $main;
+            $crate::init();
+            main();
+            $crate::end();
+        }
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    En,
+    JpJis,
+    JpEuc,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct DateTime {
+    pub year: i32,
+    pub month: i32,
+    pub day: i32,
+    pub hour: i32,
+    pub minute: i32,
+    pub second: i32,
+}
+
+/// キー入力かタイマのデータを1つ取り出す
+/// waitがfalseのときは、データがなければ待たずにNoneを返す
+pub fn getkey(wait: bool) -> Option<i32> {
+    check(unsafe { sys::_api_getkey(wait as i32) }).ok()
+}
+
+/// toneは周波数(mHz)。0で止める
+pub fn beep(tone: i32) {
+    unsafe { sys::_api_beep(tone) }
+}
+
+/// ms単位で眠る(10ms単位に切り上げられる)
+pub fn sleep(ms: u32) {
+    unsafe { sys::_api_sleep(ms as i32) }
+}
+
+/// 起動してからの時間(10ms単位)
+pub fn gettick() -> u32 {
+    unsafe { sys::_api_gettick() as u32 }
+}
+
+/// 起動してからの時間(ns単位)
+pub fn getnsec() -> u64 {
+    unsafe { sys::_api_getnsec() }
+}
+
+pub fn gettime() -> DateTime {
+    let mut datetime = DateTime::default();
+    unsafe { sys::_api_gettime(&mut datetime as *mut DateTime as *mut i32) };
+    datetime
+}
+
+/// コマンドラインをbufに読み込む。入りきらない分は切り捨てられる
+pub fn cmdline(buf: &mut [u8]) -> &[u8] {
+    if buf.is_empty() {
+        return buf;
+    }
+    let len = unsafe { sys::_api_cmdline(buf.as_mut_ptr(), buf.len() as i32) };
+    &buf[..len.max(0) as usize]
+}
+
+/// 引数の数。0番目はアプリの名前
+pub fn argc() -> usize {
+    unsafe { sys::_api_argc() as usize }
+}
+
+/// i番目の引数
+/// コマンドラインはカーネルが空白で区切っていて、"..."や'...'で囲んだ部分は1つの引数になる
+pub fn arg(i: usize) -> Option<Vec<u8>> {
+    read_string(|buf| unsafe { sys::_api_getarg(i as i32, buf.as_mut_ptr(), buf.len() as i32) })
+}
+
+/// 0番目(アプリの名前)から順に引数を返す
+pub fn args() -> impl Iterator<Item = Vec<u8>> {
+    (0..argc()).filter_map(arg)
+}
+
+/// アプリを起動したコンソールの環境変数
+pub fn getenv(name: &str) -> Option<Vec<u8>> {
+    let mut name_buf: Vec<u8> = name.bytes().collect();
+    name_buf.push(0);
+    read_string(|buf| unsafe {
+        sys::_api_getenv(name_buf.as_ptr(), buf.as_mut_ptr(), buf.len() as i32)
+    })
+}
+
+/// 文字列全体の長さを返すシステムコールを、bufに入りきるまで呼びなおす
+fn read_string<F: FnMut(&mut [u8]) -> i32>(mut f: F) -> Option<Vec<u8>> {
+    let mut buf = vec![0; 64];
+    loop {
+        let len = check(f(&mut buf)).ok()? as usize;
+        if len < buf.len() {
+            buf.truncate(len);
+            return Some(buf);
+        }
+        buf.resize(len + 1, 0);
+    }
+}
+
+pub fn getlang() -> Lang {
+    match unsafe { sys::_api_getlang() } {
+        1 => Lang::JpJis,
+        2 => Lang::JpEuc,
+        _ => Lang::En,
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    eprintln!("{}", info);
+    exit(1)
+}
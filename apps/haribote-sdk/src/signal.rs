@@ -0,0 +1,44 @@
+//! シグナル
+//!
+//! Ctrl+Cやウィンドウの×ボタン、コンソールの`kill`などで、アプリにシグナルが送られる。
+//! ハンドラを登録していなければアプリは強制終了する。ハンドラはアプリのスタックの上で、
+//! どこを実行していても割り込んで呼ばれ、戻ると割り込まれたところから続きを実行する。
+//! 待っている`getkey`や`sleep`などは`E_INTR`で戻る。
+//!
+//! ハンドラはアプリの処理の途中で呼ばれるので、メモリの確保など途中で割り込まれると困ることはせず、
+//! static変数に印をつけるくらいにしておく。`SIGTERM`はハンドラから戻るとアプリが終了する。
+
+pub use crate::abi::{SIGALRM, SIGCLOSE, SIGINT, SIGKILL, SIGTERM};
+
+use crate::abi::{SIG_DFL, SIG_IGN};
+use crate::{check, sys, Result};
+
+/// シグナルの番号を引数にして呼ばれる
+pub type Handler = extern "C" fn(i32);
+
+fn set(signal: i32, handler: i32) -> Result<()> {
+    let restorer = sys::_api_sigreturn as usize as i32;
+    check(unsafe { sys::_api_signal(signal, handler, restorer) })?;
+    Ok(())
+}
+
+/// signalが届いたらhandlerを呼ぶ。`SIGKILL`には登録できない
+pub fn set_handler(signal: i32, handler: Handler) -> Result<()> {
+    set(signal, handler as usize as i32)
+}
+
+/// signalを無視する。`SIGTERM`と`SIGKILL`は無視できない
+pub fn ignore(signal: i32) -> Result<()> {
+    set(signal, SIG_IGN)
+}
+
+/// signalが届いたら強制終了するように戻す
+pub fn reset(signal: i32) -> Result<()> {
+    set(signal, SIG_DFL)
+}
+
+/// time(10ms単位)後に`SIGALRM`を送る。0なら取り消す
+pub fn alarm(time: u32) -> Result<()> {
+    check(unsafe { sys::_api_alarm(time as i32) })?;
+    Ok(())
+}
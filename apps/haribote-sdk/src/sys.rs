@@ -0,0 +1,48 @@
+//! apps/asmfunc.asmのシステムコールのラッパ
+//! 引数と戻り値はasmfunc.asmのコメントにあるC言語の宣言に合わせている
+
+extern "C" {
+    pub fn _api_putchar(c: i32);
+    pub fn _api_putstr0(s: *const u8);
+    pub fn _api_putstr1(s: *const u8, l: i32);
+    pub fn _api_end() -> !;
+    pub fn _api_openwin(buf: *mut u8, xsiz: i32, ysiz: i32, col_inv: i32, title: *const u8) -> i32;
+    pub fn _api_putstrwin(win: i32, x: i32, y: i32, col: i32, len: i32, s: *const u8);
+    pub fn _api_boxfilwin(win: i32, x0: i32, y0: i32, x1: i32, y1: i32, col: i32);
+    pub fn _api_initmalloc(memman: i32, addr: i32, size: i32);
+    pub fn _api_malloc(memman: i32, size: i32) -> i32;
+    pub fn _api_free(memman: i32, addr: *mut u8, size: i32);
+    pub fn _api_point(win: i32, x: i32, y: i32, col: i32);
+    pub fn _api_refreshwin(win: i32, x0: i32, y0: i32, x1: i32, y1: i32);
+    pub fn _api_linewin(win: i32, x0: i32, y0: i32, x1: i32, y1: i32, col: i32);
+    pub fn _api_closewin(win: i32);
+    pub fn _api_getkey(mode: i32) -> i32;
+    pub fn _api_alloctimer() -> i32;
+    pub fn _api_inittimer(timer: i32, data: i32);
+    pub fn _api_settimer(timer: i32, time: i32);
+    pub fn _api_freetimer(timer: i32);
+    pub fn _api_beep(tone: i32);
+    pub fn _api_fopen(fname: *const u8) -> i32;
+    pub fn _api_fclose(fhandle: i32);
+    pub fn _api_fseek(fhandle: i32, offset: i32, mode: i32);
+    pub fn _api_fsize(fhandle: i32, mode: i32) -> i32;
+    pub fn _api_fread(buf: *mut u8, maxsize: i32, fhandle: i32) -> i32;
+    pub fn _api_cmdline(buf: *mut u8, maxsize: i32) -> i32;
+    pub fn _api_getlang() -> i32;
+    pub fn _api_gettime(buf: *mut i32);
+    pub fn _api_getnsec() -> u64;
+    pub fn _api_setperiodic(timer: i32, interval: i32);
+    pub fn _api_sleep(ms: i32);
+    pub fn _api_canceltimer(timer: i32) -> i32;
+    pub fn _api_gettick() -> i32;
+    pub fn _api_getheap(info: *mut i32);
+    pub fn _api_argc() -> i32;
+    pub fn _api_getarg(i: i32, buf: *mut u8, maxsize: i32) -> i32;
+    pub fn _api_getenv(name: *const u8, buf: *mut u8, maxsize: i32) -> i32;
+    pub fn _api_read(fd: i32, buf: *mut u8, maxsize: i32) -> i32;
+    pub fn _api_write(fd: i32, buf: *const u8, len: i32) -> i32;
+    pub fn _api_exit(status: i32) -> !;
+    pub fn _api_signal(sig: i32, handler: i32, restorer: i32) -> i32;
+    pub fn _api_sigreturn() -> !;
+    pub fn _api_alarm(time: i32) -> i32;
+}
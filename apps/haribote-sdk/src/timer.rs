@@ -0,0 +1,39 @@
+use crate::{check, sys, Result};
+
+/// アプリ用のタイマ。dropされると解放する
+/// 時間が来ると、initで指定したデータがgetkeyで読めるようになる
+pub struct Timer {
+    handle: i32,
+}
+
+impl Timer {
+    pub fn alloc() -> Result<Timer> {
+        let handle = check(unsafe { sys::_api_alloctimer() })?;
+        Ok(Timer { handle })
+    }
+
+    pub fn init(&mut self, data: i32) {
+        unsafe { sys::_api_inittimer(self.handle, data) }
+    }
+
+    /// timeは10ms単位
+    pub fn set(&mut self, time: u32) {
+        unsafe { sys::_api_settimer(self.handle, time as i32) }
+    }
+
+    /// cancelするまでinterval(10ms単位)ごとにデータを送り続ける
+    pub fn set_periodic(&mut self, interval: u32) {
+        unsafe { sys::_api_setperiodic(self.handle, interval as i32) }
+    }
+
+    /// 動いていたタイマを止めたときはtrue
+    pub fn cancel(&mut self) -> bool {
+        unsafe { sys::_api_canceltimer(self.handle) > 0 }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        unsafe { sys::_api_freetimer(self.handle) }
+    }
+}
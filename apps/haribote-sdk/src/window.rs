@@ -0,0 +1,99 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::abi::UNREFRESH_OFFSET;
+use crate::{check, sys, Result};
+
+/// アプリが開いたウィンドウ
+/// 描画用のバッファも一緒に持ち、dropされるとウィンドウを閉じる
+pub struct Window {
+    handle: i32,
+    xsize: usize,
+    ysize: usize,
+    buf: Vec<u8>,
+    refresh: bool,
+}
+
+impl Window {
+    /// col_invは透明色にする色番号。透明色を使わないときは-1
+    pub fn open(xsize: usize, ysize: usize, col_inv: i32, title: &str) -> Result<Window> {
+        let mut buf = vec![0; xsize * ysize];
+        let mut title_buf = [0u8; 30];
+        let len = title.len().min(title_buf.len() - 1);
+        title_buf[..len].copy_from_slice(&title.as_bytes()[..len]);
+        let handle = check(unsafe {
+            sys::_api_openwin(
+                buf.as_mut_ptr(),
+                xsize as i32,
+                ysize as i32,
+                col_inv,
+                title_buf.as_ptr(),
+            )
+        })?;
+        Ok(Window {
+            handle,
+            xsize,
+            ysize,
+            buf,
+            refresh: true,
+        })
+    }
+
+    pub fn xsize(&self) -> usize {
+        self.xsize
+    }
+
+    pub fn ysize(&self) -> usize {
+        self.ysize
+    }
+
+    pub fn buf(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// バッファに直接書いたときはrefreshを呼ぶ
+    pub fn buf_mut(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+
+    /// falseにすると、描画のたびに画面を更新しなくなる
+    /// まとめて描画してから最後にrefreshを呼ぶと速い
+    pub fn set_auto_refresh(&mut self, refresh: bool) {
+        self.refresh = refresh;
+    }
+
+    fn win(&self) -> i32 {
+        if self.refresh {
+            self.handle
+        } else {
+            self.handle + UNREFRESH_OFFSET
+        }
+    }
+
+    /// sを描く。0終端にしなくてよく、途中に0があればそこまで描く
+    pub fn putstr(&mut self, x: i32, y: i32, col: u8, s: &[u8]) {
+        unsafe { sys::_api_putstrwin(self.win(), x, y, col as i32, s.len() as i32, s.as_ptr()) }
+    }
+
+    pub fn boxfill(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, col: u8) {
+        unsafe { sys::_api_boxfilwin(self.win(), x0, y0, x1, y1, col as i32) }
+    }
+
+    pub fn point(&mut self, x: i32, y: i32, col: u8) {
+        unsafe { sys::_api_point(self.win(), x, y, col as i32) }
+    }
+
+    pub fn line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, col: u8) {
+        unsafe { sys::_api_linewin(self.win(), x0, y0, x1, y1, col as i32) }
+    }
+
+    pub fn refresh(&mut self, x0: i32, y0: i32, x1: i32, y1: i32) {
+        unsafe { sys::_api_refreshwin(self.handle, x0, y0, x1, y1) }
+    }
+}
+
+impl Drop for Window {
+    fn drop(&mut self) {
+        unsafe { sys::_api_closewin(self.handle) }
+    }
+}
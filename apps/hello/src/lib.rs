@@ -0,0 +1,21 @@
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::str::from_utf8;
+
+use haribote_sdk::{args, entry, println};
+
+// ELF形式のアプリの例。ヒープはカーネルが読み込んだ内容の後ろに用意してくれる
+entry!(main);
+
+fn main() {
+    println!("hello, ELF");
+    let args: Vec<Vec<u8>> = args().collect();
+    for (i, arg) in args.iter().enumerate().skip(1) {
+        println!("arg{} = {}", i, from_utf8(arg).unwrap_or("?"));
+    }
+    let squares: Vec<u32> = (1..=10).map(|i| i * i).collect();
+    println!("sum = {}", squares.iter().sum::<u32>());
+}
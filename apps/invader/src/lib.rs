@@ -1,10 +1,10 @@
 #![no_std]
-#![feature(asm)]
-#![feature(start)]
-#![feature(naked_functions)]
 
+extern crate alloc;
+
+use alloc::format;
 use core::mem::swap;
-use core::panic::PanicInfo;
+use haribote_sdk::{entry, getkey, sleep, Window};
 
 const CHARSET: [u8; 16 * 8] = [
     /* invader(0) */
@@ -24,26 +24,8 @@ const CHARSET: [u8; 16 * 8] = [
     /* laser */
     0x00, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x00,
 ];
-const MAX_SHEETS: usize = 256;
 const INVADER_STR: &[u8; 32] = b" abcd abcd abcd abcd abcd      \0";
 
-extern "C" {
-    fn _api_openwin(
-        buf_addr: usize,
-        xsize: usize,
-        ysize: usize,
-        col_inv: i8,
-        title_addr: usize,
-    ) -> usize;
-    fn _api_boxfilwin(win: usize, x0: i32, y0: i32, x1: i32, y1: i32, col: u8);
-    fn _api_putstrwin(win: usize, x: i32, y: i32, col: i32, len: usize, str_ptr: usize);
-    fn _api_refreshwin(win: usize, x0: i32, y0: i32, x1: i32, y1: i32);
-    fn _api_alloctimer() -> usize;
-    fn _api_inittimer(timer_index: usize, data: i32);
-    fn _api_settimer(timer_index: usize, time: i32);
-    fn _api_getkey(mode: i32) -> u8;
-}
-
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 struct Point {
     x: usize,
@@ -132,55 +114,20 @@ impl KeyFlag {
     }
 }
 
-struct DisplayScore {
-    s: [u8; 10],
-    p: usize,
-}
-
-impl DisplayScore {
-    fn new() -> DisplayScore {
-        DisplayScore { s: [0; 10], p: 0 }
-    }
-}
-
-impl core::fmt::Write for DisplayScore {
-    fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        let str_bytes = s.as_bytes();
-        for i in 0..str_bytes.len() {
-            if self.p >= 10 {
-                break;
-            }
-            self.s[self.p] = str_bytes[i];
-            self.p += 1;
-        }
-        Ok(())
-    }
-}
+entry!(main);
 
-#[no_mangle]
-#[start]
-pub extern "C" fn hrmain() {
-    let mut buf: [u8; 336 * 261] = [0; 336 * 261];
-    let win = unsafe {
-        _api_openwin(
-            buf.as_ptr() as usize,
-            336,
-            261,
-            -1,
-            b"invader".as_ptr() as usize,
-        )
-    };
-    unsafe { _api_boxfilwin(win, 6, 27, 329, 254, 0) };
-    let timer_index = unsafe { _api_alloctimer() };
-    unsafe { _api_inittimer(timer_index, 128) };
-    putstr(win, &mut buf, 22, 0, 7, b"HIGH:00000000");
+fn main() {
+    let mut win = Window::open(336, 261, -1, "invader").unwrap();
+    win.boxfill(6, 27, 329, 254, 0);
+    // 以降はputstrでまとめて画面を更新する
+    win.set_auto_refresh(false);
+    putstr(&mut win, 22, 0, 7, b"HIGH:00000000");
     let mut m: MyState = MyState::new();
     let mut i: InvaderState = InvaderState::new();
     let mut l: LaserState = LaserState::new();
     let mut k: KeyFlag = KeyFlag::new();
-    startup(&mut m, &mut i, &mut k, &mut l, win, &mut buf, timer_index);
-    unsafe { _api_getkey(1) };
-    end();
+    startup(&mut m, &mut i, &mut k, &mut l, &mut win);
+    getkey(true);
 }
 
 fn init(
@@ -188,9 +135,7 @@ fn init(
     i: &mut InvaderState,
     k: &mut KeyFlag,
     l: &mut LaserState,
-    win: usize,
-    buf: &mut [u8; 336 * 261],
-    timer_index: usize,
+    win: &mut Window,
     high: usize,
 ) {
     swap(m, &mut MyState::new());
@@ -198,20 +143,19 @@ fn init(
     swap(i, &mut InvaderState::new());
     swap(k, &mut KeyFlag::new());
     swap(l, &mut LaserState::new());
-    putstr(win, buf, 4, 0, 7, b"SCORE:00000000");
-    putstr(win, buf, m.x, 13, 6, b"efg");
-    wait(100, timer_index, k);
+    putstr(win, 4, 0, 7, b"SCORE:00000000");
+    putstr(win, m.x, 13, 6, b"efg");
+    wait(100, k);
     for n in 0..6 {
         putstr(
             win,
-            buf,
             i.point.x + 1,
             i.point.y + n,
             2,
             &i.map[(n * 32)..((n + 1) * 32)],
         );
     }
-    wait(100, timer_index, k);
+    wait(100, k);
 }
 
 fn startup(
@@ -219,14 +163,12 @@ fn startup(
     i: &mut InvaderState,
     k: &mut KeyFlag,
     l: &mut LaserState,
-    win: usize,
-    buf: &mut [u8; 336 * 261],
-    timer_index: usize,
+    win: &mut Window,
 ) {
     let mut high = 0;
     loop {
-        init(m, i, k, l, win, buf, timer_index, high);
-        main_loop(m, i, k, l, win, buf, timer_index);
+        init(m, i, k, l, win, high);
+        main_loop(m, i, k, l, win);
         high = m.high;
     }
 }
@@ -236,29 +178,27 @@ fn main_loop(
     i: &mut InvaderState,
     k: &mut KeyFlag,
     l: &mut LaserState,
-    win: usize,
-    buf: &mut [u8; 336 * 261],
-    timer_index: usize,
+    win: &mut Window,
 ) {
     loop {
         if l.wait != 0 {
             l.wait -= 1;
             k.space = false;
         }
-        wait(4, timer_index, k);
+        wait(4, k);
 
         // 自機の処理
         if k.left && m.x > 0 {
             m.x -= 1;
-            putstr(win, buf, m.x, 13, 6, b"efg \0");
+            putstr(win, m.x, 13, 6, b"efg \0");
             k.left = false;
         }
         if k.right && m.x < 36 {
             m.x += 1;
             if m.x == 35 {
-                putstr(win, buf, m.x, 13, 6, b" efg\0");
+                putstr(win, m.x, 13, 6, b" efg\0");
             } else {
-                putstr(win, buf, m.x - 1, 13, 6, b"  efg\0");
+                putstr(win, m.x - 1, 13, 6, b"  efg\0");
             }
             k.right = false;
         }
@@ -287,7 +227,6 @@ fn main_loop(
                 };
                 putstr(
                     win,
-                    buf,
                     i.point.x + 1,
                     i.point.y,
                     0,
@@ -305,7 +244,6 @@ fn main_loop(
             for n in 0..i.line {
                 putstr(
                     win,
-                    buf,
                     i.point.x,
                     i.point.y + n,
                     2,
@@ -325,19 +263,18 @@ fn main_loop(
                     let n = l.point.y - i.point.y;
                     putstr(
                         win,
-                        buf,
                         i.point.x,
                         l.point.y,
                         2,
                         &i.map[(n * 32)..((n + 1) * 32)],
                     );
                 } else {
-                    putstr(win, buf, l.point.x, l.point.y, 0, b" ");
+                    putstr(win, l.point.x, l.point.y, 0, b" ");
                 }
             }
             l.point.y -= 1;
             if l.point.y > 0 {
-                putstr(win, buf, l.point.x, l.point.y, 3, b"h");
+                putstr(win, l.point.x, l.point.y, 3, b"h");
             } else {
                 if m.score_unit >= 10 {
                     m.score_unit -= 10;
@@ -355,13 +292,11 @@ fn main_loop(
                 if i.map[p] != b' ' {
                     m.score += m.score_unit;
                     m.score_unit += 1;
-                    let mut s = DisplayScore::new();
-                    use core::fmt::Write;
-                    write!(s, "{:<08}", m.score).unwrap();
-                    putstr(win, buf, 10, 0, 7, &s.s);
+                    let s = format!("{:<08}", m.score);
+                    putstr(win, 10, 0, 7, s.as_bytes());
                     if m.high < m.score {
                         m.high = m.score;
-                        putstr(win, buf, 27, 0, 7, &s.s);
+                        putstr(win, 27, 0, 7, s.as_bytes());
                     }
                     p -= 1;
                     while i.map[p] != b' ' {
@@ -373,7 +308,6 @@ fn main_loop(
                     let n = l.point.y - i.point.y;
                     putstr(
                         win,
-                        buf,
                         i.point.x,
                         l.point.y,
                         2,
@@ -396,7 +330,7 @@ fn main_loop(
                     }
                     l.point.y = 0;
                     if !alive {
-                        wait(100, timer_index, k);
+                        wait(100, k);
                         let init_wait = i.init_wait;
                         swap(i, &mut InvaderState::new());
                         i.init_wait -= init_wait / 3;
@@ -404,7 +338,6 @@ fn main_loop(
                         for n in 0..6 {
                             putstr(
                                 win,
-                                buf,
                                 i.point.x + 1,
                                 i.point.y + n,
                                 2,
@@ -412,42 +345,26 @@ fn main_loop(
                             );
                         }
                         swap(k, &mut KeyFlag::new());
-                        wait(100, timer_index, k)
+                        wait(100, k)
                     }
                 }
             }
         }
     }
-    putstr(win, buf, 15, 6, 1, b"GAME OVER");
-    wait(0, timer_index, k);
+    putstr(win, 15, 6, 1, b"GAME OVER");
+    wait(0, k);
     for n in 1..14 {
-        putstr(
-            win,
-            buf,
-            0,
-            n,
-            0,
-            b"                                        ",
-        );
+        putstr(win, 0, n, 0, b"                                        ");
     }
 }
 
-fn putstr(win: usize, buf: &mut [u8; 336 * 261], x: usize, y: usize, col: i32, string: &[u8]) {
+fn putstr(win: &mut Window, x: usize, y: usize, col: u8, string: &[u8]) {
     let mut x = x * 8 + 8;
     let y = y * 16 + 29;
     let x0 = x;
     let i = string.len();
-    unsafe {
-        _api_boxfilwin(
-            win + MAX_SHEETS,
-            x as i32,
-            y as i32,
-            (x + i * 8) as i32,
-            (y + 15) as i32,
-            0,
-        )
-    };
-    let mut q = buf.as_ptr() as usize + y * 336;
+    win.boxfill(x as i32, y as i32, (x + i * 8) as i32, (y + 15) as i32, 0);
+    let mut q = y * 336;
     for ci in 0..string.len() {
         let c = string[ci];
         if c == 0 {
@@ -455,82 +372,56 @@ fn putstr(win: usize, buf: &mut [u8; 336 * 261], x: usize, y: usize, col: i32, s
         }
         if c != b' ' {
             if b'a' <= c && c <= b'h' {
-                let p = CHARSET.as_ptr() as usize + 16 * (c - b'a') as usize;
+                let p = 16 * (c - b'a') as usize;
                 q += x;
+                let buf = win.buf_mut();
                 for i in 0..16 {
-                    let pv = unsafe { *((p + i) as *const u8) };
+                    let pv = CHARSET[p + i];
                     for j in 0..8 {
                         if (pv & 1 << (7 - j)) != 0 {
-                            let q_ptr = unsafe { &mut *((q + j) as *mut i8) };
-                            *q_ptr = col as i8;
+                            buf[q + j] = col;
                         }
                     }
                     q += 336;
                 }
                 q -= 336 * 16 + x;
             } else {
-                unsafe {
-                    _api_putstrwin(
-                        win + MAX_SHEETS,
-                        x as i32,
-                        y as i32,
-                        col,
-                        1,
-                        [c, 0].as_ptr() as usize,
-                    )
-                };
+                win.putstr(x as i32, y as i32, col, &[c]);
             }
         }
         x += 8
     }
-    unsafe {
-        // 縁を再描画
-        _api_boxfilwin(win + MAX_SHEETS, 2, 27, 5, 254, 8);
-        _api_boxfilwin(win + MAX_SHEETS, 1, 27, 1, 254, 7);
-        _api_boxfilwin(win + MAX_SHEETS, 0, 27, 0, 254, 8);
-        _api_boxfilwin(win + MAX_SHEETS, 330, 27, 333, 254, 8);
-        _api_boxfilwin(win + MAX_SHEETS, 334, 27, 334, 254, 15);
-        _api_boxfilwin(win + MAX_SHEETS, 335, 27, 335, 254, 0);
-        _api_refreshwin(
-            win,
-            (x0 - 8) as i32,
-            y as i32,
-            (x + 8) as i32,
-            (y + 16) as i32,
-        );
-    }
+    // 縁を再描画
+    win.boxfill(2, 27, 5, 254, 8);
+    win.boxfill(1, 27, 1, 254, 7);
+    win.boxfill(0, 27, 0, 254, 8);
+    win.boxfill(330, 27, 333, 254, 8);
+    win.boxfill(334, 27, 334, 254, 15);
+    win.boxfill(335, 27, 335, 254, 0);
+    win.refresh((x0 - 8) as i32, y as i32, (x + 8) as i32, (y + 16) as i32);
 }
 
-fn wait(i: i32, timer_index: usize, keyflag: &mut KeyFlag) {
-    let mut i = i;
+fn wait(i: u32, keyflag: &mut KeyFlag) {
     if i > 0 {
-        unsafe { _api_settimer(timer_index, i) };
-        i = 128;
+        sleep(i * 10);
+        // 寝ている間に押されたキーをまとめて読む
+        while let Some(k) = getkey(false) {
+            set_keyflag(keyflag, k as u8);
+        }
     } else {
-        i = 0x0a; // Enter
-    }
-    loop {
-        let k = unsafe { _api_getkey(1) };
-        if i == k as i32 {
-            break;
+        loop {
+            let k = getkey(true).unwrap_or(0);
+            if k == 0x0a {
+                // Enter
+                break;
+            }
+            set_keyflag(keyflag, k as u8);
         }
-        keyflag.left = k == b'4';
-        keyflag.right = k == b'6';
-        keyflag.space = k == b' ';
     }
 }
 
-#[naked]
-fn end() {
-    unsafe {
-        asm!("MOV EDX,4
-              INT 0x40" : : : : "intel");
-    }
-}
-
-#[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
-    loop {
-        unsafe { asm!("HLT") }
-    }
+fn set_keyflag(keyflag: &mut KeyFlag, k: u8) {
+    keyflag.left = k == b'4';
+    keyflag.right = k == b'6';
+    keyflag.space = k == b' ';
 }
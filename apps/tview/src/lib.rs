@@ -1,42 +1,12 @@
 #![no_std]
-#![feature(asm)]
-#![feature(start)]
-#![feature(naked_functions)]
 
-use core::cmp::{max, min};
-use core::panic::PanicInfo;
-
-extern "C" {
-    fn _api_putchar(chr: u8);
-
-    fn _api_putstr0(string_ptr: usize);
-    fn _api_openwin(
-        buf_addr: usize,
-        xsize: usize,
-        ysize: usize,
-        col_inv: i8,
-        title_addr: usize,
-    ) -> usize;
-    fn _api_putstrwin(
-        sheet_index: usize,
-        x: i32,
-        y: i32,
-        color: i32,
-        len: usize,
-        string_addr: usize,
-    );
-    fn _api_boxfilwin(win: usize, x0: i32, y0: i32, x1: i32, y1: i32, col: u8);
-    fn _api_refreshwin(sheet_index: usize, x0: i32, y0: i32, x1: i32, y1: i32);
-    fn _api_cmdline(buf_addr: usize, maxsize: usize);
-    fn _api_getkey(mode: i32) -> u8;
-    fn _api_fopen(string_addr: usize) -> usize;
-    fn _api_fclose(fhandle: usize);
-    fn _api_fsize(fhandle: usize, mode: i32) -> usize;
-    fn _api_fread(buf_addr: usize, maxsize: usize, fhandler_addr: usize) -> i32;
-    fn _api_getlang() -> usize;
-}
+extern crate alloc;
 
-const MAX_SHEETS: usize = 256;
+use alloc::vec::Vec;
+use core::cmp::{max, min};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use haribote_sdk::signal::{self, SIGCLOSE, SIGINT, SIGTERM};
+use haribote_sdk::{args, entry, getkey, getlang, println, put_bytes, File, Lang, Window};
 
 const MIN_WIDTH: usize = 20;
 const MAX_WIDTH: usize = 126;
@@ -47,13 +17,19 @@ const DEFAULT_HEIGHT: usize = 10;
 const MIN_TAB: usize = 1;
 const DEFAULT_TAB: usize = 4;
 
+/// 表示している先頭の行。シグナルで終わるときに表示する
+static LINE: AtomicUsize = AtomicUsize::new(1);
+/// シグナルを受け取ったので終わる
+static STOPPED: AtomicBool = AtomicBool::new(false);
+
 struct Viewer {
     width: usize,
     height: usize,
     tab: usize,
-    filename_start: usize,
-    filename_end: usize,
-    lang: usize,
+    /// 最初に表示する行
+    line: usize,
+    filename: Vec<u8>,
+    lang: Lang,
 }
 
 impl Viewer {
@@ -62,71 +38,72 @@ impl Viewer {
             width: DEFAULT_WIDTH,
             height: DEFAULT_HEIGHT,
             tab: DEFAULT_TAB,
-            filename_start: 0,
-            filename_end: 0,
-            lang: 0,
+            line: 1,
+            filename: Vec::new(),
+            lang: Lang::En,
         }
     }
 }
 
-#[no_mangle]
-#[start]
-pub extern "C" fn hrmain() {
-    let buf: [u8; 30] = [0; 30];
-    let winbuf: [u8; 1024 * 757] = [0; 1024 * 757];
-    let mut textbuf: [u8; 240 * 1024] = [0; 240 * 1024];
-    unsafe { _api_cmdline(buf.as_ptr() as usize, 30) };
+entry!(main);
+
+fn main() {
     let mut v = Viewer::new();
-    v.lang = unsafe { _api_getlang() };
-    if let Err(e) = parse_options(&buf, &mut v) {
-        unsafe { _api_putstr0(e.as_bytes().as_ptr() as usize) };
-        end();
+    v.lang = getlang();
+    if let Err(e) = parse_options(&mut v) {
+        put_bytes(e.as_bytes());
         return;
     }
 
-    let win = init_window(winbuf.as_ptr() as usize, &v);
+    let mut win = init_window(&v);
 
-    if let Err(e) = load_file(&buf, &mut textbuf, &v) {
-        unsafe { _api_putstr0(e.as_bytes().as_ptr() as usize) };
-        end();
-        return;
+    let textbuf = match load_file(&v) {
+        Ok(textbuf) => textbuf,
+        Err(e) => {
+            put_bytes(e.as_bytes());
+            return;
+        }
+    };
+    for sig in [SIGINT, SIGCLOSE, SIGTERM].iter() {
+        let _ = signal::set_handler(*sig, on_signal);
     }
-    main_loop(win, &textbuf, &mut v);
-    end();
+    main_loop(&mut win, &textbuf, &mut v);
 }
 
-fn init_window(winbuf_addr: usize, v: &Viewer) -> usize {
-    let win = unsafe {
-        _api_openwin(
-            winbuf_addr,
-            v.width * 8 + 16,
-            v.height * 16 + 37,
-            -1,
-            b"tview".as_ptr() as usize,
-        )
-    };
-    unsafe {
-        _api_boxfilwin(
-            win,
-            6,
-            27,
-            v.width as i32 * 8 + 9,
-            v.height as i32 * 16 + 30,
-            7,
-        )
-    };
+/// Ctrl+Cや×ボタン、killで終わるときに、同じところから開き直せるように-lの値を表示する
+/// SIGTERMはハンドラから戻るとすぐ終了させられるので、表示はハンドラの中でする
+extern "C" fn on_signal(_sig: i32) {
+    if !STOPPED.swap(true, Ordering::Relaxed) {
+        println!(" stopped at -l{}", LINE.load(Ordering::Relaxed));
+    }
+}
+
+fn init_window(v: &Viewer) -> Window {
+    let mut win = Window::open(v.width * 8 + 16, v.height * 16 + 37, -1, "tview").unwrap();
+    win.boxfill(6, 27, v.width as i32 * 8 + 9, v.height as i32 * 16 + 30, 7);
+    // 以降はtextviewでまとめて画面を更新する
+    win.set_auto_refresh(false);
     win
 }
 
-fn main_loop(win: usize, textbuf: &[u8], v: &mut Viewer) {
+fn main_loop(win: &mut Window, textbuf: &[u8], v: &mut Viewer) {
     let mut ti = 1;
+    let mut line = 1;
+    while line < v.line {
+        match next_line(textbuf, ti) {
+            Some(next) => ti = next,
+            None => break,
+        }
+        line += 1;
+    }
     let mut xskip = 0;
     let mut spd_x = 1;
     let mut spd_y = 1;
     loop {
+        LINE.store(line, Ordering::Relaxed);
         textview(win, ti, v, textbuf, xskip);
-        let k = unsafe { _api_getkey(1) };
-        if k == b'Q' || k == b'q' {
+        let k = getkey(true).unwrap_or(0) as u8;
+        if STOPPED.load(Ordering::Relaxed) || k == b'Q' || k == b'q' {
             break;
         } else if b'A' <= k && k <= b'F' {
             spd_x = 1 << (k - b'A');
@@ -141,45 +118,40 @@ fn main_loop(win: usize, textbuf: &[u8], v: &mut Viewer) {
                 if xskip >= spd_x {
                     xskip -= spd_x;
                 }
-                if unsafe { _api_getkey(0) } != b'4' {
+                if getkey(false) != Some(b'4' as i32) {
                     break;
                 }
             }
         } else if k == b'6' {
             loop {
                 xskip += spd_x;
-                if unsafe { _api_getkey(0) } != b'6' {
+                if getkey(false) != Some(b'6' as i32) {
                     break;
                 }
             }
         } else if k == b'8' {
             loop {
                 for _ in 0..spd_y {
-                    if ti < 2 {
-                        break;
-                    }
-                    ti -= 1;
-                    while textbuf[ti - 1] != 0x0a {
-                        ti -= 1;
+                    match prev_line(textbuf, ti) {
+                        Some(prev) => ti = prev,
+                        None => break,
                     }
+                    line -= 1;
                 }
-                if unsafe { _api_getkey(0) } != b'8' {
+                if getkey(false) != Some(b'8' as i32) {
                     break;
                 }
             }
         } else if k == b'2' {
             loop {
                 for _ in 0..spd_y {
-                    let mut ci = ti;
-                    while textbuf[ci] != 0 && textbuf[ci] != 0x0a {
-                        ci += 1;
-                    }
-                    if textbuf[ci] == 0 {
-                        break;
+                    match next_line(textbuf, ti) {
+                        Some(next) => ti = next,
+                        None => break,
                     }
-                    ti = ci + 1;
+                    line += 1;
                 }
-                if unsafe { _api_getkey(0) } != b'2' {
+                if getkey(false) != Some(b'2' as i32) {
                     break;
                 }
             }
@@ -187,34 +159,48 @@ fn main_loop(win: usize, textbuf: &[u8], v: &mut Viewer) {
     }
 }
 
-fn textview(win: usize, ti: usize, v: &Viewer, textbuf: &[u8], xskip: usize) {
+/// tiの行の前の行の先頭。先頭の行ならNone
+fn prev_line(textbuf: &[u8], ti: usize) -> Option<usize> {
+    if ti < 2 {
+        return None;
+    }
+    let mut ti = ti - 1;
+    while textbuf[ti - 1] != 0x0a {
+        ti -= 1;
+    }
+    Some(ti)
+}
+
+/// tiの行の次の行の先頭。最後の行ならNone
+fn next_line(textbuf: &[u8], ti: usize) -> Option<usize> {
+    let mut ci = ti;
+    while textbuf[ci] != 0 && textbuf[ci] != 0x0a {
+        ci += 1;
+    }
+    if textbuf[ci] == 0 {
+        return None;
+    }
+    Some(ci + 1)
+}
+
+fn textview(win: &mut Window, ti: usize, v: &Viewer, textbuf: &[u8], xskip: usize) {
     let mut ti = ti;
-    unsafe {
-        _api_boxfilwin(
-            win + MAX_SHEETS,
-            8,
-            29,
-            v.width as i32 * 8 + 7,
-            v.height as i32 * 16 + 28,
-            7,
-        )
-    };
+    win.boxfill(8, 29, v.width as i32 * 8 + 7, v.height as i32 * 16 + 28, 7);
 
     for i in 0..v.height {
         ti = lineview(win, i * 16 + 29, ti, v, textbuf, xskip);
     }
-    unsafe {
-        _api_refreshwin(
-            win,
-            8,
-            29,
-            v.width as i32 * 8 + 8,
-            v.height as i32 * 16 + 29,
-        )
-    }
+    win.refresh(8, 29, v.width as i32 * 8 + 8, v.height as i32 * 16 + 29);
 }
 
-fn lineview(win: usize, y: usize, ti: usize, v: &Viewer, textbuf: &[u8], xskip: usize) -> usize {
+fn lineview(
+    win: &mut Window,
+    y: usize,
+    ti: usize,
+    v: &Viewer,
+    textbuf: &[u8],
+    xskip: usize,
+) -> usize {
     let mut ti = ti;
     let mut x = -(xskip as i32);
     let mut s: [u8; 130] = [0; 130];
@@ -228,7 +214,7 @@ fn lineview(win: usize, y: usize, ti: usize, v: &Viewer, textbuf: &[u8], xskip:
             ti += 1;
             break;
         }
-        if v.lang == 0 {
+        if v.lang == Lang::En {
             // ASCII
             if p == 0x09 {
                 x = puttab(x, w, xskip, &mut s, v.tab);
@@ -239,7 +225,7 @@ fn lineview(win: usize, y: usize, ti: usize, v: &Viewer, textbuf: &[u8], xskip:
                 x += 1;
             }
             ti += 1;
-        } else if v.lang == 1 {
+        } else if v.lang == Lang::JpJis {
             // SJIS
             if p == 0x09 {
                 x = puttab(x, w, xskip, &mut s, v.tab);
@@ -268,7 +254,7 @@ fn lineview(win: usize, y: usize, ti: usize, v: &Viewer, textbuf: &[u8], xskip:
                 x += 1;
                 ti += 1;
             }
-        } else if v.lang == 2 {
+        } else if v.lang == Lang::JpEuc {
             // EUC
             if p == 0x09 {
                 x = puttab(x, w, xskip, &mut s, v.tab);
@@ -303,17 +289,7 @@ fn lineview(win: usize, y: usize, ti: usize, v: &Viewer, textbuf: &[u8], xskip:
         x = w as i32;
     }
     if x > 0 {
-        s[x as usize] = 0;
-        unsafe {
-            _api_putstrwin(
-                win + MAX_SHEETS,
-                8,
-                y as i32,
-                0,
-                x as usize,
-                s.as_ptr() as usize,
-            )
-        };
+        win.putstr(8, y as i32, 0, &s[..x as usize]);
     }
     ti
 }
@@ -332,106 +308,58 @@ fn puttab(x: i32, w: usize, xskip: usize, s: &mut [u8], tab: usize) -> i32 {
     x
 }
 
-fn load_file(buf: &[u8], textbuf: &mut [u8; 240 * 1024], v: &Viewer) -> Result<(), &'static str> {
-    let mut filename: [u8; 30] = [0; 30];
-    (&mut filename[0..(v.filename_end - v.filename_start)])
-        .copy_from_slice(&buf[v.filename_start..v.filename_end]);
-    let fi = unsafe { _api_fopen(filename.as_ptr() as usize) };
-    if fi == 0 {
-        return Err(" FILE OPEN ERROR\n\0");
-    }
-    let size = unsafe { _api_fsize(fi, 0) };
-    let mut j = size;
-    if size >= (240 * 1024 - 1) {
-        j = 240 * 1024 - 2;
-    }
-    textbuf[0] = 0x0a; // 番兵用の改行
-    unsafe { _api_fread(textbuf[1..(240 * 1024)].as_ptr() as usize, j as usize, fi) };
-    unsafe { _api_fclose(fi) };
-    let mut ti = 1;
-    let mut ti2 = 1;
-    while ti < textbuf.len() && textbuf[ti] != 0 {
-        if textbuf[ti] != 0x0d {
-            textbuf[ti2] = textbuf[ti];
-            ti2 += 1;
+fn load_file(v: &Viewer) -> Result<Vec<u8>, &'static str> {
+    let mut file = File::open(&v.filename).map_err(|_| " FILE OPEN ERROR\n")?;
+    let text = file.read_to_end().map_err(|_| " FILE OPEN ERROR\n")?;
+    let mut textbuf = Vec::with_capacity(text.len() + 2);
+    textbuf.push(0x0a); // 番兵用の改行
+    for &c in text.iter() {
+        if c == 0 {
+            break;
+        }
+        if c != 0x0d {
+            textbuf.push(c);
         }
-        ti += 1;
     }
-    Ok(())
+    textbuf.push(0);
+    Ok(textbuf)
 }
 
-fn parse_options(buf: &[u8], v: &mut Viewer) -> Result<(), &'static str> {
-    let mut bi = 0;
-    let mut p = buf[bi];
-    while p > b' ' {
-        bi += 1;
-        p = buf[bi];
-    }
-    while buf[bi] != 0 {
-        bi = skipspace(buf, bi);
-        if buf[bi] == b'-' {
-            if buf[bi + 1] == b'w' {
-                let r = strtol(buf, bi + 2);
-                v.width = min(max(r.0, MIN_WIDTH), MAX_WIDTH);
-                bi = r.1;
-            } else if buf[bi + 1] == b'h' {
-                let r = strtol(buf, bi + 2);
-                v.height = min(max(r.0, MIN_HEIGHT), MAX_HEIGHT);
-                bi = r.1;
-            } else if buf[bi + 1] == b't' {
-                let r = strtol(buf, bi + 2);
-                v.tab = if r.0 < MIN_TAB { MIN_TAB } else { r.0 };
-                bi = r.1;
+fn parse_options(v: &mut Viewer) -> Result<(), &'static str> {
+    for arg in args().skip(1) {
+        if arg[0] == b'-' && arg.len() >= 2 {
+            if arg[1] == b'w' {
+                v.width = min(max(strtol(&arg, 2), MIN_WIDTH), MAX_WIDTH);
+            } else if arg[1] == b'h' {
+                v.height = min(max(strtol(&arg, 2), MIN_HEIGHT), MAX_HEIGHT);
+            } else if arg[1] == b't' {
+                let tab = strtol(&arg, 2);
+                v.tab = if tab < MIN_TAB { MIN_TAB } else { tab };
+            } else if arg[1] == b'l' {
+                v.line = max(strtol(&arg, 2), 1);
             } else {
-                return Err(" INVALID OPTION\n >tview file [-w30 -h10 -t4]\n\0");
+                return Err(" INVALID OPTION\n >tview file [-w30 -h10 -t4 -l1]\n");
             }
         } else {
-            if v.filename_start != 0 {
-                return Err(" FILE NAME DUPLICATE\n >tview file [-w30 -h10 -t4]\n\0");
-            }
-            v.filename_start = bi;
-            while (bi < (buf.len() - 1)) && (buf[bi] > b' ') {
-                bi += 1;
-            }
-            v.filename_end = bi;
-            if v.filename_start == v.filename_end {
-                return Err(" FILE NAME NOT FOUND\n >tview file [-w30 -h10 -t4]\n\0");
+            if !v.filename.is_empty() {
+                return Err(" FILE NAME DUPLICATE\n >tview file [-w30 -h10 -t4 -l1]\n");
             }
+            v.filename = arg;
         }
     }
-    Ok(())
-}
-
-fn skipspace(buf: &[u8], i: usize) -> usize {
-    let mut i = i;
-    while (i < (buf.len() - 1)) && (buf[i] == b' ') {
-        i += 1;
+    if v.filename.is_empty() {
+        return Err(" FILE NAME NOT FOUND\n >tview file [-w30 -h10 -t4 -l1]\n");
     }
-    i
+    Ok(())
 }
 
-fn strtol(buf: &[u8], i: usize) -> (usize, usize) {
+fn strtol(buf: &[u8], i: usize) -> usize {
     let mut n = 0;
     let mut i = i;
-    while (i < (buf.len() - 1)) && (b'0' <= buf[i]) && (buf[i] <= b'9') {
+    while i < buf.len() && b'0' <= buf[i] && buf[i] <= b'9' {
         n *= 10;
         n += (buf[i] - b'0') as usize;
         i += 1;
     }
-    (n, i)
-}
-
-#[naked]
-fn end() {
-    unsafe {
-        asm!("MOV EDX,4
-              INT 0x40" : : : : "intel");
-    }
-}
-
-#[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
-    loop {
-        unsafe { asm!("HLT") }
-    }
+    n
 }
@@ -12,16 +12,30 @@ BOTPAK	EQU		0x00280000		; bootpackのロード先
 DSKCAC	EQU		0x00100000		; ディスクキャッシュの場所
 DSKCAC0	EQU		0x00008000		; ディスクキャッシュの場所（リアルモード）
 
-; BOOT_INFO関係
 CYLS	EQU		0x0ff0			; ブートセクタが設定する
-LEDS	EQU		0x0ff1
-VMODE	EQU		0x0ff2			; 色数に関する情報。何ビットカラーか？
-SCRNX	EQU		0x0ff4			; 解像度のX
-SCRNY	EQU		0x0ff6			; 解像度のY
-VRAM	EQU		0x0ff8			; グラフィックバッファの開始番地
+
+; BOOT_INFO関係(カーネルのboot_info.rsのBootInfoと同じ並び)
+BOOTINFO	EQU		0x0600			; BootInfoを置く場所。番地はhrmainに渡す
+BI_VERSION	EQU		2				; BOOT_INFO_VERSIONと合わせる
+BI_SIZE	EQU		28				; BootInfoの大きさ
+LEDS	EQU		BOOTINFO+9
+VMODE	EQU		BOOTINFO+10		; 色数に関する情報。何ビットカラーか？
+SCRNX	EQU		BOOTINFO+12		; 解像度のX
+SCRNY	EQU		BOOTINFO+14		; 解像度のY
+VRAM	EQU		BOOTINFO+16		; グラフィックバッファの開始番地
 
 		ORG		0xc200			; このプログラムがどこに読み込まれるのか
 
+; BootInfoの版と大きさ、IPLが読んだシリンダ数
+
+		MOV		DWORD [BOOTINFO],BI_VERSION
+		MOV		DWORD [BOOTINFO+4],BI_SIZE
+		MOV		AL,[CYLS]
+		MOV		[BOOTINFO+8],AL
+		MOV		BYTE [BOOTINFO+11],0x00	; 起動したドライブ(IPLはAドライブから読む)
+		MOV		DWORD [BOOTINFO+20],0	; 使えるメモリの終わり(カーネルがmemtestで調べる)
+		MOV		DWORD [BOOTINFO+24],0	; Multiboot情報はない
+
 ; VBE存在確認
 
 		MOV		AX,0x9000
@@ -168,6 +182,8 @@ pipelineflush:
 		CALL	memcpy
 skip:
 		MOV		ESP,[EBX+12]	; スタック初期値
+		PUSH	DWORD BOOTINFO	; hrmainの引数
+		PUSH	DWORD 0			; 戻り番地(hrmainからは戻らない)
 		JMP		DWORD 2*8:0x0000001b
 
 waitkbdout:
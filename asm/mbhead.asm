@@ -0,0 +1,132 @@
+; haribote-os multiboot head
+; TAB=4
+; asmheadの代わりにkernel.binの前につなぐと、GRUBやQEMUの-kernelから起動できる
+; ローダはプロテクトモードにして、EAX=0x2badb002、EBX=Multiboot情報の番地で飛んでくる
+
+[BITS 32]
+
+MB_MAGIC	EQU		0x1badb002
+MB_FLAGS	EQU		0x00010006		; bit1:メモリの情報 bit2:画面モード bit16:下の番地に読み込む
+MB_LOADED	EQU		0x2badb002		; ローダがEAXに入れてくる値
+
+LOADADR	EQU		0x00100000		; このファイルを読み込ませる場所
+RESERVED	EQU		0x00400000		; ここまではカーネルが使うので、モジュールを置かせない
+
+BOTPAK	EQU		0x00280000		; bootpackのロード先
+
+; BOOT_INFO関係(カーネルのboot_info.rsのBootInfoと同じ並び)
+BOOTINFO	EQU		0x0600			; BootInfoを置く場所。番地はhrmainに渡す
+BI_VERSION	EQU		2				; BOOT_INFO_VERSIONと合わせる
+BI_SIZE	EQU		28				; BootInfoの大きさ
+BIOS_LEDS	EQU		0x0417			; BIOSデータ領域のキーボードの状態
+
+		ORG		LOADADR
+
+; Multibootヘッダ。ファイルの先頭8KBの中にあればよい
+
+mbheader:
+		DD		MB_MAGIC
+		DD		MB_FLAGS
+		DD		-(MB_MAGIC+MB_FLAGS)
+		DD		mbheader		; header_addr
+		DD		LOADADR			; load_addr
+		DD		0				; load_end_addr(ファイル全部)
+		DD		RESERVED		; bss_end_addr(ここまで0で埋めてもらう)
+		DD		entry			; entry_addr
+		DD		0				; リニアフレームバッファ
+		DD		640				; 幅
+		DD		480				; 高さ
+		DD		8				; 8bitカラー
+
+entry:
+		CLI
+		MOV		ESP,RESERVED	; 仮のスタック
+		CMP		EAX,MB_LOADED
+		JNE		fin				; Multibootのローダから来たのでなければ止まる
+
+; PICが一切の割り込みを受け付けないようにする
+
+		MOV		AL,0xff
+		OUT		0x21,AL
+		NOP
+		OUT		0xa1,AL
+
+; BootInfo。画面と使えるメモリはカーネルがMultiboot情報から埋める
+
+		MOV		DWORD [BOOTINFO],BI_VERSION
+		MOV		DWORD [BOOTINFO+4],BI_SIZE
+		MOV		BYTE [BOOTINFO+8],0		; フロッピーからは読んでいない
+		MOV		AL,[BIOS_LEDS]
+		MOV		[BOOTINFO+9],AL
+		MOV		BYTE [BOOTINFO+10],0
+		MOV		BYTE [BOOTINFO+11],0xff	; 起動したドライブ(わからない)
+		TEST	DWORD [EBX],0x00000002
+		JZ		nodrive
+		MOV		AL,[EBX+15]				; boot_deviceの最上位バイトがドライブ番号
+		MOV		[BOOTINFO+11],AL
+nodrive:
+		MOV		DWORD [BOOTINFO+12],0
+		MOV		DWORD [BOOTINFO+16],0
+		MOV		DWORD [BOOTINFO+20],0
+		MOV		[BOOTINFO+24],EBX		; Multiboot情報の番地
+
+; asmheadと同じ暫定GDTにする
+
+		LGDT	[GDTR0]
+		MOV		AX,1*8			;  読み書き可能セグメント32bit
+		MOV		DS,AX
+		MOV		ES,AX
+		MOV		FS,AX
+		MOV		GS,AX
+		MOV		SS,AX
+
+; bootpackの転送
+
+		MOV		ESI,bootpack	; 転送元
+		MOV		EDI,BOTPAK		; 転送先
+		MOV		ECX,512*1024/4
+		CALL	memcpy
+
+; bootpackの起動(ここからはasmheadと同じ)
+
+		MOV		EBX,BOTPAK
+		MOV		ECX,[EBX+16]
+		ADD		ECX,3			; ECX += 3;
+		SHR		ECX,2			; ECX /= 4;
+		JZ		skip			; 転送するべきものがない
+		MOV		ESI,[EBX+20]	; 転送元
+		ADD		ESI,EBX
+		MOV		EDI,[EBX+12]	; 転送先
+		CALL	memcpy
+skip:
+		MOV		ESP,[EBX+12]	; スタック初期値
+		PUSH	DWORD BOOTINFO	; hrmainの引数
+		PUSH	DWORD 0			; 戻り番地(hrmainからは戻らない)
+		JMP		DWORD 2*8:0x0000001b
+
+fin:
+		HLT
+		JMP		fin
+
+memcpy:
+		MOV		EAX,[ESI]
+		ADD		ESI,4
+		MOV		[EDI],EAX
+		ADD		EDI,4
+		SUB		ECX,1
+		JNZ		memcpy			; 引き算した結果が0でなければmemcpyへ
+		RET
+
+		ALIGN	16, DB 0
+GDT0:
+		TIMES	8 DB 0				; ヌルセレクタ
+		DW		0xffff,0x0000,0x9200,0x00cf	; 読み書き可能セグメント32bit
+		DW		0xffff,0x0000,0x9a28,0x0047	; 実行可能セグメント32bit（bootpack用）
+
+		DW		0
+GDTR0:
+		DW		8*3-1
+		DD		GDT0
+
+		ALIGNB	16
+bootpack:
@@ -0,0 +1,356 @@
+/// コマンドラインの最大長(終端の0を含む)
+pub const MAX_CMD: usize = 256;
+pub const MAX_ARGS: usize = 32;
+/// |でつなげられるコマンドの最大数
+pub const MAX_PIPELINE: usize = 8;
+
+/// コマンドラインを空白で区切った引数
+/// "..."と'...'で囲んだ部分は空白を含めて1つの引数になり、'...'の外では\で次の1文字をそのまま使う
+/// <と>の次の引数はリダイレクト先のファイル名として別に持つ
+/// 最後の&はコマンドを裏で動かす印で、引数には入れない
+#[derive(Clone, Copy)]
+pub struct Args {
+    buf: [u8; MAX_CMD],
+    /// buf上の各引数の(開始位置, 長さ)
+    argv: [(usize, usize); MAX_ARGS],
+    argc: usize,
+    input: Option<(usize, usize)>,
+    output: Option<(usize, usize)>,
+}
+
+impl Args {
+    fn new() -> Args {
+        Args {
+            buf: [0; MAX_CMD],
+            argv: [(0, 0); MAX_ARGS],
+            argc: 0,
+            input: None,
+            output: None,
+        }
+    }
+
+    /// cmdline[*ci..]から、クォートされていない|か&か終わりまでを1つのコマンドとして読む
+    fn parse_command(cmdline: &[u8], ci: &mut usize) -> Result<(Args, Separator), &'static str> {
+        let mut args = Args::new();
+        let mut bi = 0;
+        let mut redirect = None;
+        let mut separator = Separator::End;
+        loop {
+            while *ci < cmdline.len() && cmdline[*ci] == b' ' {
+                *ci += 1;
+            }
+            if *ci == cmdline.len() {
+                break;
+            }
+            let c = cmdline[*ci];
+            if is_operator(c) {
+                if redirect.is_some() {
+                    return Err("Missing File Name");
+                }
+                *ci += 1;
+                if c == b'|' {
+                    separator = Separator::Pipe;
+                    break;
+                }
+                if c == b'&' {
+                    // &のあとには何も書けない
+                    while *ci < cmdline.len() && cmdline[*ci] == b' ' {
+                        *ci += 1;
+                    }
+                    if *ci < cmdline.len() {
+                        return Err("Unexpected &");
+                    }
+                    separator = Separator::Background;
+                    break;
+                }
+                redirect = Some(c);
+                continue;
+            }
+            if redirect.is_none() && args.argc == MAX_ARGS {
+                return Err("Too Many Arguments");
+            }
+            if bi >= MAX_CMD {
+                return Err("Command Too Long");
+            }
+            let start = bi;
+            let mut quote = None;
+            while *ci < cmdline.len() {
+                let c = cmdline[*ci];
+                if let Some(q) = quote {
+                    if c == q {
+                        quote = None;
+                        *ci += 1;
+                        continue;
+                    }
+                } else if c == b' ' || is_operator(c) {
+                    break;
+                } else if c == b'"' || c == b'\'' {
+                    quote = Some(c);
+                    *ci += 1;
+                    continue;
+                }
+                *ci += 1;
+                let c = if c == b'\\' && quote != Some(b'\'') && *ci < cmdline.len() {
+                    *ci += 1;
+                    cmdline[*ci - 1]
+                } else {
+                    c
+                };
+                // 引数ごとに終端の0を入れるので、元のコマンドラインより長くなることがある
+                if bi >= MAX_CMD - 1 {
+                    return Err("Command Too Long");
+                }
+                args.buf[bi] = c;
+                bi += 1;
+            }
+            if quote.is_some() {
+                return Err("Unterminated Quote");
+            }
+            let word = (start, bi - start);
+            bi += 1;
+            match redirect.take() {
+                Some(b'<') if args.input.is_none() => args.input = Some(word),
+                Some(b'>') if args.output.is_none() => args.output = Some(word),
+                Some(_) => return Err("Too Many Redirections"),
+                None => {
+                    args.argv[args.argc] = word;
+                    args.argc += 1;
+                }
+            }
+        }
+        if redirect.is_some() {
+            return Err("Missing File Name");
+        }
+        Ok((args, separator))
+    }
+
+    pub fn len(&self) -> usize {
+        self.argc
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.argc == 0
+    }
+
+    pub fn get(&self, i: usize) -> Option<&[u8]> {
+        if i >= self.argc {
+            return None;
+        }
+        let (start, len) = self.argv[i];
+        Some(&self.buf[start..(start + len)])
+    }
+
+    /// 最初の引数を取り除いたもの。リダイレクト先はそのまま
+    pub fn shift(&self) -> Args {
+        let mut args = *self;
+        if args.argc > 0 {
+            for i in 1..args.argc {
+                args.argv[i - 1] = args.argv[i];
+            }
+            args.argc -= 1;
+        }
+        args
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> {
+        (0..self.argc).map(move |i| self.get(i).unwrap())
+    }
+
+    /// <で指定されたファイル名
+    pub fn input(&self) -> Option<&[u8]> {
+        self.input
+            .map(|(start, len)| &self.buf[start..(start + len)])
+    }
+
+    /// >で指定されたファイル名
+    pub fn output(&self) -> Option<&[u8]> {
+        self.output
+            .map(|(start, len)| &self.buf[start..(start + len)])
+    }
+}
+
+fn is_operator(c: u8) -> bool {
+    c == b'|' || c == b'<' || c == b'>' || c == b'&'
+}
+
+/// コマンドの終わり方
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Separator {
+    End,
+    Pipe,
+    Background,
+}
+
+/// |でつながったコマンドの並び
+pub struct Pipeline {
+    commands: [Args; MAX_PIPELINE],
+    len: usize,
+    /// 最後に&がついていた
+    background: bool,
+}
+
+impl Pipeline {
+    pub fn parse(cmdline: &[u8]) -> Result<Pipeline, &'static str> {
+        let cmdline = match cmdline.iter().position(|c| *c == 0) {
+            Some(len) => &cmdline[..len],
+            None => cmdline,
+        };
+        let mut pipeline = Pipeline {
+            commands: [Args::new(); MAX_PIPELINE],
+            len: 0,
+            background: false,
+        };
+        let mut ci = 0;
+        loop {
+            if pipeline.len == MAX_PIPELINE {
+                return Err("Too Many Pipes");
+            }
+            let (args, separator) = Args::parse_command(cmdline, &mut ci)?;
+            let piped = separator == Separator::Pipe;
+            if args.is_empty()
+                && (piped || pipeline.len > 0 || args.input.is_some() || args.output.is_some())
+            {
+                return Err("Empty Command");
+            }
+            pipeline.commands[pipeline.len] = args;
+            pipeline.len += 1;
+            if separator == Separator::Background {
+                if args.is_empty() {
+                    return Err("Empty Command");
+                }
+                pipeline.background = true;
+            }
+            if !piped {
+                break;
+            }
+        }
+        Ok(pipeline)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Args> {
+        self.commands[..self.len].iter()
+    }
+
+    /// &で裏で動かすように指定された
+    pub fn background(&self) -> bool {
+        self.background
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argv(cmdline: &[u8]) -> Vec<Vec<u8>> {
+        let pipeline = Pipeline::parse(cmdline).unwrap();
+        assert_eq!(pipeline.len(), 1);
+        let args = pipeline.iter().next().unwrap();
+        args.iter().map(|arg| arg.to_vec()).collect()
+    }
+
+    fn error(cmdline: &[u8]) -> &'static str {
+        match Pipeline::parse(cmdline) {
+            Ok(_) => panic!("parsed"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn splits_on_spaces() {
+        assert_eq!(
+            argv(b"  cat  a.txt b.txt "),
+            [&b"cat"[..], b"a.txt", b"b.txt"]
+        );
+        // 終端の0より後ろは読まない
+        assert_eq!(argv(b"echo hi\0 ignored"), [&b"echo"[..], b"hi"]);
+        assert!(argv(b"   ").is_empty());
+    }
+
+    #[test]
+    fn quotes_keep_spaces_and_operators() {
+        assert_eq!(
+            argv(br#"echo "a b" 'c | d' e"f"g"#),
+            [&b"echo"[..], b"a b", b"c | d", b"efg"]
+        );
+        assert_eq!(argv(br#"echo "" ''"#), [&b"echo"[..], b"", b""]);
+        // 違う種類のクォートはそのまま
+        assert_eq!(
+            argv(br#"echo "it's" '"q"'"#),
+            [&b"echo"[..], b"it's", b"\"q\""]
+        );
+    }
+
+    #[test]
+    fn backslash_escapes_outside_single_quotes() {
+        assert_eq!(
+            argv(br#"echo a\ b \" "x\"y" 'p\q'"#),
+            [&b"echo"[..], b"a b", b"\"", b"x\"y", b"p\\q"]
+        );
+        // 最後の\はそのまま
+        assert_eq!(argv(br"echo a\"), [&b"echo"[..], b"a\\"]);
+    }
+
+    #[test]
+    fn redirections_and_pipes() {
+        let pipeline = Pipeline::parse(b"sort <in.txt | uniq -c > out.txt").unwrap();
+        assert_eq!(pipeline.len(), 2);
+        assert!(!pipeline.background());
+        let mut commands = pipeline.iter();
+        let sort = commands.next().unwrap();
+        assert_eq!(sort.get(0), Some(&b"sort"[..]));
+        assert_eq!(sort.len(), 1);
+        assert_eq!(sort.input(), Some(&b"in.txt"[..]));
+        assert_eq!(sort.output(), None);
+        let uniq = commands.next().unwrap();
+        assert_eq!(uniq.iter().collect::<Vec<_>>(), [&b"uniq"[..], b"-c"]);
+        assert_eq!(uniq.output(), Some(&b"out.txt"[..]));
+        // shiftしてもリダイレクト先は残る
+        let shifted = uniq.shift();
+        assert_eq!(shifted.get(0), Some(&b"-c"[..]));
+        assert_eq!(shifted.output(), Some(&b"out.txt"[..]));
+    }
+
+    #[test]
+    fn trailing_ampersand_runs_in_background() {
+        let pipeline = Pipeline::parse(b"sleep 10 & ").unwrap();
+        assert!(pipeline.background());
+        assert_eq!(pipeline.iter().next().unwrap().len(), 2);
+        assert_eq!(error(b"sleep 10 & echo"), "Unexpected &");
+        assert_eq!(error(b"&"), "Empty Command");
+    }
+
+    #[test]
+    fn rejects_malformed_command_lines() {
+        assert_eq!(error(b"echo \"abc"), "Unterminated Quote");
+        assert_eq!(error(b"echo 'abc"), "Unterminated Quote");
+        assert_eq!(error(b"cat >"), "Missing File Name");
+        assert_eq!(error(b"cat > | wc"), "Missing File Name");
+        assert_eq!(error(b"cat < a < b"), "Too Many Redirections");
+        assert_eq!(error(b"| wc"), "Empty Command");
+        assert_eq!(error(b"cat a |"), "Empty Command");
+        assert_eq!(error(b"> out.txt"), "Empty Command");
+        let mut cmdline = b"a | ".repeat(MAX_PIPELINE - 1);
+        cmdline.push(b'a');
+        assert_eq!(Pipeline::parse(&cmdline).unwrap().len(), MAX_PIPELINE);
+        cmdline.extend_from_slice(b" | a");
+        assert_eq!(error(&cmdline), "Too Many Pipes");
+    }
+
+    #[test]
+    fn limits_argument_count_and_length() {
+        let many = b"a ".repeat(MAX_ARGS);
+        assert_eq!(argv(&many).len(), MAX_ARGS);
+        assert_eq!(error(&b"a ".repeat(MAX_ARGS + 1)), "Too Many Arguments");
+        // 引数ごとに0が入るので、MAX_CMD-1文字の1つの引数までは入る
+        assert_eq!(argv(&vec![b'x'; MAX_CMD - 1])[0].len(), MAX_CMD - 1);
+        assert_eq!(error(&vec![b'x'; MAX_CMD]), "Command Too Long");
+    }
+}
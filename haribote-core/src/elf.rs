@@ -0,0 +1,313 @@
+//! 静的リンクされた32bit ELF実行ファイルの読み込み
+//!
+//! PT_LOADのセグメントをアプリのセグメントの中に、p_vaddrをそのままオフセットとして配置する。
+//! コードとデータは同じベースアドレスのセグメントに置くので、p_vaddrは0から始まる小さな値で
+//! リンクしておく必要がある(例: `ld -m elf_i386 -Ttext-segment=0`)。
+//! 読み込んだ内容の後ろにELF_HEAP_SIZEのヒープ、その後ろにELF_STACK_SIZEのスタックを確保し、
+//! ESPはセグメントの末尾を指す。ヒープの場所はapi_getheapでアプリに教える。
+
+use core::mem::size_of;
+use core::ptr::read_unaligned;
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_386: u16 = 3;
+const PT_LOAD: u32 = 1;
+
+/// アプリに渡すスタックの大きさ
+pub const ELF_STACK_SIZE: usize = 64 * 1024;
+/// アプリに渡すヒープの大きさ
+pub const ELF_HEAP_SIZE: usize = 1024 * 1024;
+/// これより上のアドレスにはセグメントを置けない
+pub const ELF_MAX_ADDR: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Elf32Header {
+    ident: [u8; 16],
+    etype: u16,
+    machine: u16,
+    version: u32,
+    entry: u32,
+    phoff: u32,
+    shoff: u32,
+    flags: u32,
+    ehsize: u16,
+    phentsize: u16,
+    phnum: u16,
+    shentsize: u16,
+    shnum: u16,
+    shstrndx: u16,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct ProgramHeader {
+    ptype: u32,
+    offset: u32,
+    vaddr: u32,
+    paddr: u32,
+    filesz: u32,
+    memsz: u32,
+    flags: u32,
+    align: u32,
+}
+
+pub struct Elf<'a> {
+    content: &'a [u8],
+    header: Elf32Header,
+    /// PT_LOADのセグメントの終わりのうち、一番大きいアドレス
+    image_end: usize,
+}
+
+impl<'a> Elf<'a> {
+    pub fn is_elf(content: &[u8]) -> bool {
+        content.len() >= 4 && content[0..4] == ELF_MAGIC
+    }
+
+    pub fn parse(content: &'a [u8]) -> Result<Elf<'a>, &'static str> {
+        if content.len() < size_of::<Elf32Header>() || !Elf::is_elf(content) {
+            return Err("ELF: Bad Header");
+        }
+        let header = unsafe { read_unaligned(content.as_ptr() as *const Elf32Header) };
+        if header.ident[4] != ELFCLASS32 {
+            return Err("ELF: Not 32bit");
+        }
+        if header.ident[5] != ELFDATA2LSB || header.ident[6] != EV_CURRENT {
+            return Err("ELF: Bad Header");
+        }
+        if header.etype != ET_EXEC {
+            return Err("ELF: Not Executable");
+        }
+        if header.machine != EM_386 {
+            return Err("ELF: Not i386");
+        }
+        if header.phentsize as usize != size_of::<ProgramHeader>() {
+            return Err("ELF: Bad Program Header");
+        }
+        let phend = (header.phoff as usize)
+            .checked_add(header.phnum as usize * size_of::<ProgramHeader>())
+            .ok_or("ELF: Bad Program Header")?;
+        if phend > content.len() {
+            return Err("ELF: Bad Program Header");
+        }
+
+        let mut elf = Elf {
+            content,
+            header,
+            image_end: 0,
+        };
+        for i in 0..header.phnum as usize {
+            let ph = elf.program_header(i);
+            if ph.ptype != PT_LOAD {
+                continue;
+            }
+            let file_end = (ph.offset as usize)
+                .checked_add(ph.filesz as usize)
+                .ok_or("ELF: Bad Segment")?;
+            if file_end > content.len() || ph.filesz > ph.memsz {
+                return Err("ELF: Bad Segment");
+            }
+            let end = (ph.vaddr as usize)
+                .checked_add(ph.memsz as usize)
+                .ok_or("ELF: Segment Address Too High")?;
+            if end > ELF_MAX_ADDR {
+                return Err("ELF: Segment Address Too High");
+            }
+            // 先に見たPT_LOADのセグメントと重なっていないか
+            for j in 0..i {
+                let other = elf.program_header(j);
+                if other.ptype == PT_LOAD
+                    && (ph.vaddr as usize) < other.vaddr as usize + other.memsz as usize
+                    && (other.vaddr as usize) < end
+                {
+                    return Err("ELF: Overlapping Segments");
+                }
+            }
+            elf.image_end = elf.image_end.max(end);
+        }
+        if elf.image_end == 0 {
+            return Err("ELF: No Loadable Segment");
+        }
+        if header.entry as usize >= elf.image_end {
+            return Err("ELF: Bad Entry Point");
+        }
+        Ok(elf)
+    }
+
+    fn program_header(&self, i: usize) -> ProgramHeader {
+        let addr = self.content.as_ptr() as usize
+            + self.header.phoff as usize
+            + i * size_of::<ProgramHeader>();
+        unsafe { read_unaligned(addr as *const ProgramHeader) }
+    }
+
+    pub fn entry(&self) -> usize {
+        self.header.entry as usize
+    }
+
+    /// ヒープの先頭。読み込んだ内容の後ろの4KB境界
+    pub fn heap_addr(&self) -> usize {
+        (self.image_end + 0xfff) & !0xfff
+    }
+
+    pub fn heap_size(&self) -> usize {
+        ELF_HEAP_SIZE
+    }
+
+    /// ヒープとスタックを含めた、アプリのセグメントに必要な大きさ
+    pub fn segment_size(&self) -> usize {
+        self.heap_addr() + ELF_HEAP_SIZE + ELF_STACK_SIZE
+    }
+
+    /// segment_size()以上の大きさのsegmentにPT_LOADのセグメントを読み込む
+    /// ファイルに含まれない部分(.bssなど)は0で埋める
+    pub fn load(&self, segment: &mut [u8]) {
+        for b in segment.iter_mut() {
+            *b = 0;
+        }
+        for i in 0..self.header.phnum as usize {
+            let ph = self.program_header(i);
+            if ph.ptype != PT_LOAD {
+                continue;
+            }
+            let (vaddr, offset, filesz) =
+                (ph.vaddr as usize, ph.offset as usize, ph.filesz as usize);
+            segment[vaddr..(vaddr + filesz)]
+                .copy_from_slice(&self.content[offset..(offset + filesz)]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EHSIZE: usize = size_of::<Elf32Header>();
+    const PHSIZE: usize = size_of::<ProgramHeader>();
+
+    /// (p_offset, p_vaddr, p_filesz, p_memsz)のPT_LOADを並べたELFを作る。中身は0x90で埋める
+    fn image(segments: &[(u32, u32, u32, u32)], entry: u32) -> Vec<u8> {
+        let mut v = vec![0x90u8; 0x1000];
+        v[..EHSIZE].iter_mut().for_each(|b| *b = 0);
+        v[0..4].copy_from_slice(&ELF_MAGIC);
+        v[4] = ELFCLASS32;
+        v[5] = ELFDATA2LSB;
+        v[6] = EV_CURRENT;
+        v[16..18].copy_from_slice(&ET_EXEC.to_le_bytes());
+        v[18..20].copy_from_slice(&EM_386.to_le_bytes());
+        v[24..28].copy_from_slice(&entry.to_le_bytes());
+        v[28..32].copy_from_slice(&(EHSIZE as u32).to_le_bytes());
+        v[42..44].copy_from_slice(&(PHSIZE as u16).to_le_bytes());
+        v[44..46].copy_from_slice(&(segments.len() as u16).to_le_bytes());
+        for (i, &(offset, vaddr, filesz, memsz)) in segments.iter().enumerate() {
+            let ph = EHSIZE + i * PHSIZE;
+            let fields = [PT_LOAD, offset, vaddr, vaddr, filesz, memsz, 5, 0x1000];
+            for (j, field) in fields.iter().enumerate() {
+                v[(ph + j * 4)..(ph + j * 4 + 4)].copy_from_slice(&field.to_le_bytes());
+            }
+        }
+        v
+    }
+
+    fn error(content: &[u8]) -> &'static str {
+        match Elf::parse(content) {
+            Ok(_) => panic!("parsed"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn loads_segments_and_places_heap_and_stack() {
+        let mut content = image(
+            &[(0x100, 0, 0x200, 0x200), (0x300, 0x1000, 0x10, 0x800)],
+            0x20,
+        );
+        content[0x300] = 0xab;
+        let elf = Elf::parse(&content).unwrap();
+        assert_eq!(elf.entry(), 0x20);
+        assert_eq!(elf.heap_addr(), 0x2000);
+        assert_eq!(elf.heap_size(), ELF_HEAP_SIZE);
+        assert_eq!(elf.segment_size(), 0x2000 + ELF_HEAP_SIZE + ELF_STACK_SIZE);
+        let mut segment = vec![0xffu8; elf.segment_size()];
+        elf.load(&mut segment);
+        assert_eq!(&segment[..0x200], &content[0x100..0x300]);
+        assert_eq!(segment[0x1000], 0xab);
+        // p_fileszより後ろ(.bss)は0
+        assert!(segment[0x1010..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn rejects_truncated_header() {
+        let content = image(&[(0x100, 0, 0x10, 0x10)], 0);
+        assert_eq!(error(&content[..4]), "ELF: Bad Header");
+        assert_eq!(error(&content[..(EHSIZE - 1)]), "ELF: Bad Header");
+        // プログラムヘッダがファイルの外にはみ出す
+        assert_eq!(error(&content[..(EHSIZE + 4)]), "ELF: Bad Program Header");
+    }
+
+    #[test]
+    fn rejects_malformed_header() {
+        let content = image(&[(0x100, 0, 0x10, 0x10)], 0);
+        let mut bad = content.clone();
+        bad[4] = 2;
+        assert_eq!(error(&bad), "ELF: Not 32bit");
+        let mut bad = content.clone();
+        bad[16] = 3;
+        assert_eq!(error(&bad), "ELF: Not Executable");
+        let mut bad = content.clone();
+        bad[18] = 62;
+        assert_eq!(error(&bad), "ELF: Not i386");
+        let mut bad = content.clone();
+        bad[42] = 0x10;
+        assert_eq!(error(&bad), "ELF: Bad Program Header");
+        let mut bad = content.clone();
+        bad[28..32].copy_from_slice(&0xffff_fff0u32.to_le_bytes());
+        assert_eq!(error(&bad), "ELF: Bad Program Header");
+    }
+
+    #[test]
+    fn rejects_bad_segments() {
+        // p_memsz < p_filesz
+        assert_eq!(
+            error(&image(&[(0x100, 0, 0x20, 0x10)], 0)),
+            "ELF: Bad Segment"
+        );
+        // ファイルの外を指している
+        assert_eq!(
+            error(&image(&[(0xff0, 0, 0x20, 0x20)], 0)),
+            "ELF: Bad Segment"
+        );
+        assert_eq!(
+            error(&image(&[(0xffff_fff0, 0, 0x20, 0x20)], 0)),
+            "ELF: Bad Segment"
+        );
+        assert_eq!(
+            error(&image(&[(0x100, ELF_MAX_ADDR as u32, 0x10, 0x10)], 0)),
+            "ELF: Segment Address Too High"
+        );
+        assert_eq!(
+            error(&image(&[(0x100, 0xffff_fff0, 0x10, 0x20)], 0)),
+            "ELF: Segment Address Too High"
+        );
+        assert_eq!(error(&image(&[], 0)), "ELF: No Loadable Segment");
+        assert_eq!(
+            error(&image(&[(0x100, 0, 0x10, 0x10)], 0x10)),
+            "ELF: Bad Entry Point"
+        );
+    }
+
+    #[test]
+    fn rejects_overlapping_segments() {
+        let segments = [(0x100, 0, 0x200, 0x400), (0x300, 0x3ff, 0x10, 0x10)];
+        assert_eq!(error(&image(&segments, 0)), "ELF: Overlapping Segments");
+        let segments = [(0x300, 0x400, 0x10, 0x10), (0x100, 0, 0x200, 0x401)];
+        assert_eq!(error(&image(&segments, 0)), "ELF: Overlapping Segments");
+        // 隣り合っているだけならよい
+        let segments = [(0x100, 0, 0x200, 0x400), (0x300, 0x400, 0x10, 0x10)];
+        assert!(Elf::parse(&image(&segments, 0)).is_ok());
+    }
+}
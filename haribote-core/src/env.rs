@@ -0,0 +1,148 @@
+pub const MAX_ENV: usize = 32;
+pub const MAX_ENV_NAME: usize = 16;
+pub const MAX_ENV_VALUE: usize = 128;
+
+#[derive(Debug, Clone, Copy)]
+struct EnvVar {
+    name: [u8; MAX_ENV_NAME],
+    name_len: usize,
+    value: [u8; MAX_ENV_VALUE],
+    value_len: usize,
+}
+
+impl EnvVar {
+    fn name(&self) -> &[u8] {
+        &self.name[..self.name_len]
+    }
+
+    fn value(&self) -> &[u8] {
+        &self.value[..self.value_len]
+    }
+}
+
+/// コンソールごとの環境変数
+/// そのコンソールから起動したアプリはapi_getenvで読める
+pub struct Env {
+    vars: [EnvVar; MAX_ENV],
+}
+
+impl Default for Env {
+    fn default() -> Env {
+        Env::new()
+    }
+}
+
+impl Env {
+    pub fn new() -> Env {
+        Env {
+            vars: [EnvVar {
+                name: [0; MAX_ENV_NAME],
+                name_len: 0,
+                value: [0; MAX_ENV_VALUE],
+                value_len: 0,
+            }; MAX_ENV],
+        }
+    }
+
+    pub fn get(&self, name: &[u8]) -> Option<&[u8]> {
+        self.vars
+            .iter()
+            .find(|v| v.name_len > 0 && v.name() == name)
+            .map(|v| v.value())
+    }
+
+    pub fn set(&mut self, name: &[u8], value: &[u8]) -> Result<(), &'static str> {
+        if name.is_empty() || name.iter().any(|c| *c == b'=' || *c == b' ' || *c == 0) {
+            return Err("Bad Variable Name");
+        }
+        if name.len() > MAX_ENV_NAME {
+            return Err("Variable Name Too Long");
+        }
+        if value.len() > MAX_ENV_VALUE {
+            return Err("Value Too Long");
+        }
+        let i = match self
+            .vars
+            .iter()
+            .position(|v| v.name_len > 0 && v.name() == name)
+        {
+            Some(i) => i,
+            None => self
+                .vars
+                .iter()
+                .position(|v| v.name_len == 0)
+                .ok_or("Too Many Variables")?,
+        };
+        let var = &mut self.vars[i];
+        var.name[..name.len()].copy_from_slice(name);
+        var.name_len = name.len();
+        var.value[..value.len()].copy_from_slice(value);
+        var.value_len = value.len();
+        Ok(())
+    }
+
+    pub fn unset(&mut self, name: &[u8]) {
+        for v in self.vars.iter_mut() {
+            if v.name_len > 0 && v.name() == name {
+                v.name_len = 0;
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.vars
+            .iter()
+            .filter(|v| v.name_len > 0)
+            .map(|v| (v.name(), v.value()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_get_and_unset() {
+        let mut env = Env::new();
+        assert_eq!(env.get(b"PATH"), None);
+        env.set(b"PATH", b"/bin").unwrap();
+        env.set(b"HOME", b"").unwrap();
+        assert_eq!(env.get(b"PATH"), Some(&b"/bin"[..]));
+        assert_eq!(env.get(b"HOME"), Some(&b""[..]));
+        // 上書きしても数は増えない
+        env.set(b"PATH", b"/usr/bin").unwrap();
+        assert_eq!(env.get(b"PATH"), Some(&b"/usr/bin"[..]));
+        assert_eq!(env.iter().count(), 2);
+        env.unset(b"PATH");
+        assert_eq!(env.get(b"PATH"), None);
+        assert_eq!(env.iter().collect::<Vec<_>>(), [(&b"HOME"[..], &b""[..])]);
+    }
+
+    #[test]
+    fn rejects_bad_names_and_long_values() {
+        let mut env = Env::new();
+        assert_eq!(env.set(b"", b"x"), Err("Bad Variable Name"));
+        assert_eq!(env.set(b"A=B", b"x"), Err("Bad Variable Name"));
+        assert_eq!(env.set(b"A B", b"x"), Err("Bad Variable Name"));
+        let name = [b'N'; MAX_ENV_NAME + 1];
+        assert_eq!(env.set(&name, b"x"), Err("Variable Name Too Long"));
+        assert!(env.set(&name[..MAX_ENV_NAME], b"x").is_ok());
+        let value = [b'v'; MAX_ENV_VALUE + 1];
+        assert_eq!(env.set(b"V", &value), Err("Value Too Long"));
+        assert!(env.set(b"V", &value[..MAX_ENV_VALUE]).is_ok());
+    }
+
+    #[test]
+    fn fills_freed_slots_when_full() {
+        let mut env = Env::new();
+        for i in 0..MAX_ENV {
+            env.set(format!("V{}", i).as_bytes(), b"x").unwrap();
+        }
+        assert_eq!(env.set(b"NEW", b"x"), Err("Too Many Variables"));
+        // 既にある変数は上書きできる
+        assert!(env.set(b"V0", b"y").is_ok());
+        env.unset(b"V3");
+        assert!(env.set(b"NEW", b"x").is_ok());
+        assert_eq!(env.get(b"NEW"), Some(&b"x"[..]));
+    }
+}
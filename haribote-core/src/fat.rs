@@ -0,0 +1,387 @@
+//! 1440KBのフロッピーディスクのイメージ(FAT12)
+//!
+//! 番地はイメージの先頭からのオフセットで、カーネルはイメージを読み込んだ番地を足して使う。
+
+use core::mem::size_of;
+
+/// ルートディレクトリのオフセット
+pub const FILE_OFFSET: usize = 0x002600;
+pub const MAX_FILE_INFO: usize = 224;
+pub const MAX_FAT: usize = 2880;
+/// 2つあるFATのオフセット
+pub const FAT0_OFFSET: usize = 0x000200;
+pub const FAT1_OFFSET: usize = 0x001400;
+/// FATの大きさ。2つのクラスタを3バイトに詰めている
+pub const FAT_BYTES: usize = MAX_FAT / 2 * 3;
+/// クラスタ番号0に当たるオフセット(実際のデータはクラスタ2から)
+pub const CLUSTER_OFFSET: usize = 0x003e00;
+pub const CLUSTER_SIZE: usize = 512;
+pub const IMAGE_SIZE: usize = 1440 * 1024;
+/// 1440KBのイメージに収まるクラスタ番号の上限
+pub const MAX_CLUSTER: usize = (IMAGE_SIZE - CLUSTER_OFFSET) / CLUSTER_SIZE;
+pub const FAT_EOF: u32 = 0xfff;
+
+/// ftypeのビット
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_HIDDEN: u8 = 0x02;
+pub const ATTR_SYSTEM: u8 = 0x04;
+pub const ATTR_VOLUME: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C, packed)]
+pub struct FileInfo {
+    pub name: [u8; 8],
+    pub ext: [u8; 3],
+    pub ftype: u8,
+    pub reserve: [i8; 10],
+    pub time: u16,
+    pub date: u16,
+    pub clustno: u16,
+    pub size: u32,
+}
+
+impl FileInfo {
+    pub fn empty() -> FileInfo {
+        FileInfo {
+            name: [0; 8],
+            ext: [0; 3],
+            ftype: 0,
+            reserve: [0; 10],
+            time: 0,
+            date: 0,
+            clustno: 0,
+            size: 0,
+        }
+    }
+
+    /// 使っていない(消された)エントリ。0x00ならそこから後ろにエントリはない
+    pub fn is_free(&self) -> bool {
+        self.name[0] == 0x00 || self.name[0] == 0xe5
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.ftype & ATTR_DIRECTORY != 0
+    }
+
+    /// クラスタの鎖をたどって中身をbufに読む。clustersはCLUSTER_OFFSETから始まるイメージ
+    /// 鎖がイメージの外を指していたら、そこまでで止める
+    pub fn load(&self, buf: &mut [u8], fat: &[u32; MAX_FAT], clusters: &[u8]) {
+        let size = (self.size as usize).min(buf.len());
+        let mut clustno = self.clustno as usize;
+        for chunk in buf[..size].chunks_mut(CLUSTER_SIZE) {
+            let start = clustno * CLUSTER_SIZE;
+            match clusters.get(start..(start + chunk.len())) {
+                Some(src) if clustno < MAX_FAT => chunk.copy_from_slice(src),
+                _ => break,
+            }
+            clustno = fat[clustno] as usize;
+        }
+    }
+
+    /// 空白を除いてNAME.EXTの形にした名前と、その長さ
+    pub fn file_name(&self) -> ([u8; 12], usize) {
+        let mut name = [0; 12];
+        let base = trim_name(&self.name);
+        let ext = trim_name(&self.ext);
+        name[..base.len()].copy_from_slice(base);
+        let mut len = base.len();
+        if !ext.is_empty() {
+            name[len] = b'.';
+            name[(len + 1)..(len + 1 + ext.len())].copy_from_slice(ext);
+            len += 1 + ext.len();
+        }
+        (name, len)
+    }
+
+    /// *と?を使ったパターンに名前が当てはまるか。大文字と小文字は区別しない
+    /// パターンに.があれば名前と拡張子を別々に比べるので、*.*は拡張子のないファイルにも当てはまる
+    pub fn matches(&self, pattern: &[u8]) -> bool {
+        match pattern.iter().position(|c| *c == b'.') {
+            Some(i) => {
+                wildcard_match(&pattern[..i], trim_name(&self.name))
+                    && wildcard_match(&pattern[(i + 1)..], trim_name(&self.ext))
+            }
+            None => {
+                let (name, len) = self.file_name();
+                wildcard_match(pattern, &name[..len])
+            }
+        }
+    }
+
+    /// (年, 月, 日, 時, 分)
+    pub fn datetime(&self) -> (u32, u32, u32, u32, u32) {
+        let (date, time) = (self.date as u32, self.time as u32);
+        (
+            (date >> 9) + 1980,
+            date >> 5 & 0x0f,
+            date & 0x1f,
+            time >> 11,
+            time >> 5 & 0x3f,
+        )
+    }
+
+    /// 秒は2秒単位になる
+    pub fn set_datetime(
+        &mut self,
+        year: u32,
+        month: u32,
+        day: u32,
+        hour: u32,
+        minute: u32,
+        second: u32,
+    ) {
+        self.time = (hour << 11 | minute << 5 | (second / 2)) as u16;
+        self.date = ((year - 1980) << 9 | month << 5 | day) as u16;
+    }
+}
+
+/// 後ろの空白を除く
+fn trim_name(s: &[u8]) -> &[u8] {
+    let len = s.iter().position(|c| *c == b' ').unwrap_or(s.len());
+    &s[..len]
+}
+
+fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|i| wildcard_match(rest, &name[i..])),
+        Some((c, rest)) => match name.split_first() {
+            Some((n, name_rest)) => {
+                (*c == b'?' || c.eq_ignore_ascii_case(n)) && wildcard_match(rest, name_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+/// ファイル名を、ディレクトリエントリと同じ大文字・空白埋めの名前と拡張子にする
+pub fn to_fat_name(filename: &[u8]) -> ([u8; 8], [u8; 3]) {
+    // 拡張子の前後でわける
+    let mut filename = filename.split(|c| *c == b'.');
+    let basename = filename.next().unwrap_or(b"");
+    let extname = filename.next().unwrap_or(b"");
+    let mut b = [b' '; 8];
+    let mut e = [b' '; 3];
+    for (dst, src) in b.iter_mut().zip(basename.iter()) {
+        // 小文字は大文字で正規化しておく
+        *dst = src.to_ascii_uppercase();
+    }
+    for (dst, src) in e.iter_mut().zip(extname.iter()) {
+        *dst = src.to_ascii_uppercase();
+    }
+    (b, e)
+}
+
+/// 名前が不正ならエラーにするto_fat_name
+pub fn to_valid_fat_name(filename: &[u8]) -> Result<([u8; 8], [u8; 3]), &'static str> {
+    let (b, e) = to_fat_name(filename);
+    if b[0] == b' ' || b[0] == 0xe5 || filename.iter().any(|c| *c == b'*' || *c == b'?') {
+        return Err("Bad File Name");
+    }
+    Ok((b, e))
+}
+
+/// イメージのルートディレクトリをエントリの並びとして見る。imageは先頭からFILE_OFFSETの先まで必要
+pub fn root_dir(image: &[u8]) -> &[FileInfo] {
+    let dir = &image[FILE_OFFSET..(FILE_OFFSET + MAX_FILE_INFO * size_of::<FileInfo>())];
+    // FileInfoはpackedなので、どの番地からでも読める
+    unsafe { core::slice::from_raw_parts(dir.as_ptr() as *const FileInfo, MAX_FILE_INFO) }
+}
+
+/// 使われているエントリの番号。0x00のエントリで終わる
+pub fn entries(dir: &[FileInfo]) -> impl Iterator<Item = usize> + '_ {
+    (0..dir.len())
+        .take_while(move |i| dir[*i].name[0] != 0x00)
+        .filter(move |i| dir[*i].name[0] != 0xe5)
+}
+
+/// 同じ名前のファイルを探す。ディレクトリとボリュームラベルは除く
+pub fn search_file(dir: &[FileInfo], filename: &[u8]) -> Option<FileInfo> {
+    let (b, e) = to_fat_name(filename);
+    entries(dir).map(|i| dir[i]).find(|finfo| {
+        finfo.ftype & (ATTR_VOLUME | ATTR_DIRECTORY) == 0 && finfo.name == b && finfo.ext == e
+    })
+}
+
+/// ボリュームラベル以外で同じ名前のエントリを探す。ディレクトリも含む
+pub fn find_entry(dir: &[FileInfo], b: &[u8; 8], e: &[u8; 3]) -> Option<usize> {
+    entries(dir).find(|i| {
+        let finfo = dir[*i];
+        finfo.ftype & ATTR_VOLUME == 0 && finfo.name == *b && finfo.ext == *e
+    })
+}
+
+/// 使っていないディレクトリエントリ
+pub fn free_entry(dir: &[FileInfo]) -> Option<usize> {
+    (0..dir.len()).find(|i| dir[*i].is_free())
+}
+
+/// FATを読む。12ビットずつ詰められているのを1エントリずつにする
+pub fn read_fat(fat: &mut [u32; MAX_FAT], img: &[u8]) {
+    let mut j = 0;
+    for i in (0..MAX_FAT).step_by(2) {
+        fat[i] = ((img[j] as u32) | (img[j + 1] as u32) << 8) & 0xfff;
+        fat[i + 1] = ((img[j + 1] as u32) >> 4 | (img[j + 2] as u32) << 4) & 0xfff;
+        j += 3;
+    }
+}
+
+/// read_fatの逆
+pub fn write_fat(fat: &[u32; MAX_FAT], img: &mut [u8]) {
+    let mut j = 0;
+    for i in (0..MAX_FAT).step_by(2) {
+        img[j] = fat[i] as u8;
+        img[j + 1] = (fat[i] >> 8 & 0x0f | (fat[i + 1] & 0x0f) << 4) as u8;
+        img[j + 2] = (fat[i + 1] >> 4) as u8;
+        j += 3;
+    }
+}
+
+/// clustnoから始まるクラスタの鎖
+pub fn chain(fat: &[u32; MAX_FAT], clustno: usize) -> impl Iterator<Item = usize> + '_ {
+    let mut next = clustno;
+    core::iter::from_fn(move || {
+        if !(2..MAX_CLUSTER).contains(&next) {
+            return None;
+        }
+        let clustno = next;
+        next = fat[clustno] as usize;
+        Some(clustno)
+    })
+}
+
+/// clustnoから始まるクラスタの鎖を空きにする
+pub fn free_clusters(mut clustno: usize, fat: &mut [u32; MAX_FAT]) {
+    while (2..MAX_CLUSTER).contains(&clustno) {
+        let next = fat[clustno] as usize;
+        fat[clustno] = 0;
+        clustno = next;
+    }
+}
+
+/// 空いているクラスタ
+pub fn free_cluster(fat: &[u32; MAX_FAT]) -> Option<usize> {
+    (2..MAX_CLUSTER).find(|c| fat[*c] == 0)
+}
+
+/// 空いている容量(バイト)
+pub fn free_space(fat: &[u32; MAX_FAT]) -> usize {
+    (2..MAX_CLUSTER).filter(|c| fat[*c] == 0).count() * CLUSTER_SIZE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &[u8]) -> FileInfo {
+        let (b, e) = to_fat_name(name);
+        let mut finfo = FileInfo::empty();
+        finfo.name = b;
+        finfo.ext = e;
+        finfo.ftype = ATTR_ARCHIVE;
+        finfo
+    }
+
+    #[test]
+    fn decodes_packed_entries() {
+        // 0xff0(メディア), 0xfff, 0x003, 0x004, 0xfff, 0x000 の並び
+        let mut img = [0u8; FAT_BYTES];
+        img[..9].copy_from_slice(&[0xf0, 0xff, 0xff, 0x03, 0x40, 0x00, 0xff, 0x0f, 0x00]);
+        let mut fat = [0; MAX_FAT];
+        read_fat(&mut fat, &img);
+        assert_eq!(&fat[..6], &[0xff0, 0xfff, 0x003, 0x004, 0xfff, 0x000]);
+        let mut encoded = [0u8; FAT_BYTES];
+        write_fat(&fat, &mut encoded);
+        assert_eq!(&encoded[..], &img[..]);
+    }
+
+    #[test]
+    fn follows_chain_until_eof() {
+        let mut fat = [0; MAX_FAT];
+        fat[2] = 5;
+        fat[5] = 3;
+        fat[3] = FAT_EOF;
+        assert_eq!(chain(&fat, 2).collect::<Vec<_>>(), vec![2, 5, 3]);
+        assert_eq!(chain(&fat, 0).count(), 0);
+        free_clusters(2, &mut fat);
+        assert!(fat[2] == 0 && fat[3] == 0 && fat[5] == 0);
+        assert_eq!(free_cluster(&fat), Some(2));
+        assert_eq!(free_space(&fat), (MAX_CLUSTER - 2) * CLUSTER_SIZE);
+    }
+
+    #[test]
+    fn loads_file_across_clusters() {
+        let mut clusters = vec![0u8; 8 * CLUSTER_SIZE];
+        for (c, fill) in [(4, 0xaa), (2, 0xbb), (6, 0xcc)].iter() {
+            for b in clusters[c * CLUSTER_SIZE..(c + 1) * CLUSTER_SIZE].iter_mut() {
+                *b = *fill;
+            }
+        }
+        let mut fat = [0; MAX_FAT];
+        fat[4] = 2;
+        fat[2] = 6;
+        fat[6] = FAT_EOF;
+        let mut finfo = file(b"a.txt");
+        finfo.clustno = 4;
+        finfo.size = 2 * CLUSTER_SIZE as u32 + 10;
+        let mut buf = vec![0u8; finfo.size as usize];
+        finfo.load(&mut buf, &fat, &clusters);
+        assert!(buf[..CLUSTER_SIZE].iter().all(|b| *b == 0xaa));
+        assert!(buf[CLUSTER_SIZE..2 * CLUSTER_SIZE]
+            .iter()
+            .all(|b| *b == 0xbb));
+        assert!(buf[2 * CLUSTER_SIZE..].iter().all(|b| *b == 0xcc));
+    }
+
+    #[test]
+    fn normalizes_names() {
+        assert_eq!(to_fat_name(b"hello.txt"), (*b"HELLO   ", *b"TXT"));
+        assert_eq!(to_fat_name(b"noext"), (*b"NOEXT   ", *b"   "));
+        assert_eq!(to_fat_name(b"toolongname.text"), (*b"TOOLONGN", *b"TEX"));
+        assert!(to_valid_fat_name(b".txt").is_err());
+        assert!(to_valid_fat_name(b"a*.txt").is_err());
+        assert_eq!(file(b"hello.txt").file_name(), (*b"HELLO.TXT\0\0\0", 9));
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        let finfo = file(b"tview.hrb");
+        assert!(finfo.matches(b"*"));
+        assert!(finfo.matches(b"*.hrb"));
+        assert!(finfo.matches(b"t?iew.*"));
+        assert!(finfo.matches(b"TVIEW.HRB"));
+        assert!(!finfo.matches(b"*.txt"));
+        assert!(file(b"noext").matches(b"*.*"));
+    }
+
+    #[test]
+    fn datetime_roundtrip() {
+        let mut finfo = file(b"a.txt");
+        finfo.set_datetime(2020, 4, 29, 13, 45, 31);
+        assert_eq!(finfo.datetime(), (2020, 4, 29, 13, 45));
+    }
+
+    #[test]
+    fn searches_directory() {
+        let mut dir = [FileInfo::empty(); 6];
+        dir[0] = file(b"volume");
+        dir[0].ftype = ATTR_VOLUME;
+        dir[1] = file(b"a.txt");
+        dir[1].name[0] = 0xe5;
+        dir[2] = file(b"a.txt");
+        dir[2].clustno = 7;
+        dir[3] = file(b"sub");
+        dir[3].ftype = ATTR_DIRECTORY;
+        // 0x00のエントリより後ろは見ない
+        dir[5] = file(b"b.txt");
+        assert_eq!(search_file(&dir, b"A.TXT").map(|f| f.clustno), Some(7));
+        assert_eq!(search_file(&dir, b"volume"), None);
+        assert_eq!(search_file(&dir, b"sub"), None);
+        assert_eq!(search_file(&dir, b"b.txt"), None);
+        assert_eq!(find_entry(&dir, b"SUB     ", b"   "), Some(3));
+        assert_eq!(free_entry(&dir), Some(1));
+        assert_eq!(entries(&dir).collect::<Vec<_>>(), vec![0, 2, 3]);
+    }
+}
@@ -0,0 +1,134 @@
+use core::cell::{Cell, RefCell};
+
+/// FIFOにためられるデータの最大数
+pub const FIFO_CAPACITY: usize = 128;
+
+const FLAGS_OVERRUN: u32 = 0x0001;
+
+/// 割り込みハンドラからも入れられるように、&selfで読み書きするリングバッファ
+pub struct FifoBuffer {
+    buf: RefCell<[u32; FIFO_CAPACITY]>,
+    /// 次に書く位置
+    p: Cell<u32>,
+    /// 次に読む位置
+    q: Cell<u32>,
+    free: Cell<u32>,
+    flags: Cell<u32>,
+    size: u32,
+}
+
+impl FifoBuffer {
+    /// sizeはFIFO_CAPACITYまで
+    pub fn new(size: u32) -> FifoBuffer {
+        FifoBuffer {
+            buf: RefCell::new([0; FIFO_CAPACITY]),
+            p: Cell::new(0),
+            q: Cell::new(0),
+            free: Cell::new(size),
+            flags: Cell::new(0),
+            size,
+        }
+    }
+
+    pub fn put(&self, data: u32) -> Result<(), &'static str> {
+        if self.free.get() == 0 {
+            self.flags.set(self.flags.get() | FLAGS_OVERRUN);
+            return Err("FLAGS_OVERRUN ERROR");
+        }
+        self.buf.borrow_mut()[self.p.get() as usize] = data;
+        self.p.set(self.p.get() + 1);
+        if self.p.get() == self.size {
+            self.p.set(0);
+        }
+        self.free.set(self.free.get() - 1);
+        Ok(())
+    }
+
+    pub fn get(&self) -> Result<u32, &'static str> {
+        if self.free.get() == self.size {
+            return Err("NO DATA");
+        }
+        let data = self.buf.borrow()[self.q.get() as usize];
+        self.q.set(self.q.get() + 1);
+        if self.q.get() == self.size {
+            self.q.set(0);
+        }
+        self.free.set(self.free.get() + 1);
+        Ok(data)
+    }
+
+    /// たまっているデータの数
+    pub fn status(&self) -> u32 {
+        self.size - self.free.get()
+    }
+
+    /// いっぱいのときにputされて、データを捨てたことがある
+    pub fn overrun(&self) -> bool {
+        self.flags.get() & FLAGS_OVERRUN != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_fifo_has_no_data() {
+        let fifo = FifoBuffer::new(4);
+        assert_eq!(fifo.status(), 0);
+        assert!(fifo.get().is_err());
+    }
+
+    #[test]
+    fn keeps_order_across_wraparound() {
+        let fifo = FifoBuffer::new(4);
+        let mut next = 0;
+        let mut expected = 0;
+        // 書く位置と読む位置が何周もするように、3つ入れて2つ出すのを繰り返す
+        for _ in 0..10 {
+            while fifo.status() < 3 {
+                fifo.put(next).unwrap();
+                next += 1;
+            }
+            for _ in 0..2 {
+                assert_eq!(fifo.get(), Ok(expected));
+                expected += 1;
+            }
+        }
+        while let Ok(data) = fifo.get() {
+            assert_eq!(data, expected);
+            expected += 1;
+        }
+        assert_eq!(expected, next);
+        assert!(!fifo.overrun());
+    }
+
+    #[test]
+    fn full_fifo_drops_and_flags_overrun() {
+        let fifo = FifoBuffer::new(3);
+        for i in 0..3 {
+            fifo.put(i).unwrap();
+        }
+        assert!(fifo.put(3).is_err());
+        assert!(fifo.overrun());
+        assert_eq!(fifo.status(), 3);
+        assert_eq!(fifo.get(), Ok(0));
+        fifo.put(4).unwrap();
+        assert_eq!(fifo.get(), Ok(1));
+        assert_eq!(fifo.get(), Ok(2));
+        assert_eq!(fifo.get(), Ok(4));
+        assert!(fifo.get().is_err());
+    }
+
+    #[test]
+    fn full_capacity() {
+        let fifo = FifoBuffer::new(FIFO_CAPACITY as u32);
+        for i in 0..FIFO_CAPACITY as u32 {
+            fifo.put(i).unwrap();
+        }
+        assert!(fifo.put(0).is_err());
+        for i in 0..FIFO_CAPACITY as u32 {
+            assert_eq!(fifo.get(), Ok(i));
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! kernel.ldでリンクした.hrb実行ファイルの読み込み
+//!
+//! ファイルの先頭にヘッダがあり、0x1bからコードが始まる。コードはファイルをそのまま使い、
+//! データセグメントはヘッダに書かれた大きさで確保して、ESPの位置に.dataを転送する。
+//! .bssの後ろからデータセグメントの終わりまでがヒープになる。
+
+use core::mem::size_of;
+use core::ptr::read_unaligned;
+
+const HRB_SIGNATURE: [u8; 4] = *b"Hari";
+const HRB_ENTRY: usize = 0x1b;
+
+/// これより大きいデータセグメントは確保しない
+pub const HRB_MAX_SEGMENT_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct HrbHeader {
+    segment_size: u32,
+    signature: [u8; 4],
+    mmarea: u32,
+    esp: u32,
+    data_size: u32,
+    data_offset: u32,
+    jmp: [u8; 4],
+    entry: u32,
+    malloc_start: u32,
+}
+
+pub struct Hrb<'a> {
+    content: &'a [u8],
+    header: HrbHeader,
+}
+
+impl<'a> Hrb<'a> {
+    /// 4から7バイト目で判定する
+    pub fn is_hrb(content: &[u8]) -> bool {
+        content.len() >= 8 && content[4..8] == HRB_SIGNATURE
+    }
+
+    pub fn parse(content: &'a [u8]) -> Result<Hrb<'a>, &'static str> {
+        if content.len() < size_of::<HrbHeader>() || !Hrb::is_hrb(content) {
+            return Err("HRB: Bad Header");
+        }
+        let header = unsafe { read_unaligned(content.as_ptr() as *const HrbHeader) };
+        let segment_size = header.segment_size as usize;
+        if segment_size == 0 || segment_size > HRB_MAX_SEGMENT_SIZE {
+            return Err("HRB: Bad Segment Size");
+        }
+        let data_end = (header.esp as usize)
+            .checked_add(header.data_size as usize)
+            .ok_or("HRB: Bad Data Section")?;
+        if data_end > segment_size {
+            return Err("HRB: Bad Data Section");
+        }
+        let file_end = (header.data_offset as usize)
+            .checked_add(header.data_size as usize)
+            .ok_or("HRB: Bad Data Section")?;
+        if file_end > content.len() {
+            return Err("HRB: Bad Data Section");
+        }
+        if header.malloc_start as usize > segment_size {
+            return Err("HRB: Bad Heap");
+        }
+        Ok(Hrb { content, header })
+    }
+
+    pub fn entry(&self) -> usize {
+        HRB_ENTRY
+    }
+
+    pub fn esp(&self) -> usize {
+        self.header.esp as usize
+    }
+
+    pub fn heap_addr(&self) -> usize {
+        self.header.malloc_start as usize
+    }
+
+    pub fn heap_size(&self) -> usize {
+        self.segment_size() - self.heap_addr()
+    }
+
+    /// アプリのデータセグメントに必要な大きさ
+    pub fn segment_size(&self) -> usize {
+        self.header.segment_size as usize
+    }
+
+    /// segment_size()以上の大きさのsegmentに.dataを転送する
+    pub fn load(&self, segment: &mut [u8]) {
+        let (esp, offset, size) = (
+            self.header.esp as usize,
+            self.header.data_offset as usize,
+            self.header.data_size as usize,
+        );
+        segment[esp..(esp + size)].copy_from_slice(&self.content[offset..(offset + size)]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER_SIZE: usize = size_of::<HrbHeader>();
+
+    /// kernel.ldが書くのと同じヘッダを持ち、後ろに.dataが続く.hrbを作る
+    fn image(segment_size: u32, esp: u32, data: &[u8], malloc_start: u32) -> Vec<u8> {
+        let fields = [
+            segment_size,
+            u32::from_le_bytes(HRB_SIGNATURE),
+            0,
+            esp,
+            data.len() as u32,
+            HEADER_SIZE as u32,
+            0xe900_0000,
+            0,
+            malloc_start,
+        ];
+        let mut v: Vec<u8> = fields
+            .iter()
+            .flat_map(|f| f.to_le_bytes().to_vec())
+            .collect();
+        v.extend_from_slice(data);
+        v
+    }
+
+    fn error(content: &[u8]) -> &'static str {
+        match Hrb::parse(content) {
+            Ok(_) => panic!("parsed"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn loads_data_at_esp() {
+        let content = image(0x2000, 0x400, b"hello", 0x410);
+        let hrb = Hrb::parse(&content).unwrap();
+        assert_eq!(hrb.entry(), HRB_ENTRY);
+        assert_eq!(hrb.esp(), 0x400);
+        assert_eq!(hrb.segment_size(), 0x2000);
+        assert_eq!((hrb.heap_addr(), hrb.heap_size()), (0x410, 0x1bf0));
+        let mut segment = vec![0u8; hrb.segment_size()];
+        hrb.load(&mut segment);
+        assert_eq!(&segment[0x400..0x405], b"hello");
+    }
+
+    #[test]
+    fn rejects_truncated_header() {
+        let content = image(0x2000, 0x400, b"", 0x400);
+        assert!(!Hrb::is_hrb(&content[..7]));
+        assert_eq!(error(&content[..8]), "HRB: Bad Header");
+        assert_eq!(error(&content[..(HEADER_SIZE - 1)]), "HRB: Bad Header");
+        let mut bad = content.clone();
+        bad[4] = b'h';
+        assert_eq!(error(&bad), "HRB: Bad Header");
+    }
+
+    #[test]
+    fn rejects_bad_sizes() {
+        assert_eq!(error(&image(0, 0, b"", 0)), "HRB: Bad Segment Size");
+        assert_eq!(
+            error(&image(HRB_MAX_SEGMENT_SIZE as u32 + 1, 0, b"", 0)),
+            "HRB: Bad Segment Size"
+        );
+        // .dataがデータセグメントからはみ出す
+        assert_eq!(
+            error(&image(0x400, 0x3fc, b"hello", 0)),
+            "HRB: Bad Data Section"
+        );
+        assert_eq!(
+            error(&image(0x400, 0xffff_fffe, b"hello", 0)),
+            "HRB: Bad Data Section"
+        );
+        // .dataがファイルの外を指している
+        let mut bad = image(0x2000, 0x400, b"hello", 0x410);
+        bad.truncate(bad.len() - 1);
+        assert_eq!(error(&bad), "HRB: Bad Data Section");
+        assert_eq!(error(&image(0x2000, 0x400, b"", 0x2001)), "HRB: Bad Heap");
+    }
+}
@@ -0,0 +1,17 @@
+//! カーネルのうち、決まった番地やハードウェアに触らない部分
+//!
+//! FIFO、メモリの空き管理、タイマの待ち行列、FAT12の読み書き、実行ファイルのヘッダの検査、コマンドラインの解釈、環境変数、スクリプトの解析、コンソールの行編集、VT100のエスケープシーケンスの解釈を、ホストでも`cargo test`できるように切り出している。
+//! `make test`で動かせる。カーネルは番地を決めてここにある型を置き、割り込みの禁止やタスクを起こすことは自分でする。
+#![cfg_attr(not(test), no_std)]
+
+pub mod args;
+pub mod elf;
+pub mod env;
+pub mod fat;
+pub mod fifo;
+pub mod hrb;
+pub mod line_editor;
+pub mod memman;
+pub mod script;
+pub mod timer;
+pub mod vt100;
@@ -0,0 +1,310 @@
+use crate::args::MAX_CMD;
+
+/// 覚えておくコマンドの数
+pub const MAX_HISTORY: usize = 16;
+
+/// コンソールで入力中の1行と、これまでに入力した行の履歴
+/// 画面への表示はしないので、呼び出し側で変わった部分を描き直す
+pub struct LineEditor {
+    /// 終端の0の分を空けておくので、長さはMAX_CMD - 1まで
+    buf: [u8; MAX_CMD],
+    len: usize,
+    /// カーソルのある位置(0からlenまで)
+    pos: usize,
+    /// 古いものから上書きしていくリングバッファ
+    history: [[u8; MAX_CMD]; MAX_HISTORY],
+    history_lens: [usize; MAX_HISTORY],
+    history_count: usize,
+    history_next: usize,
+    /// 履歴の何個前を表示しているか。0なら入力中の行
+    browsing: usize,
+    /// 履歴をさかのぼる前に入力していた行
+    saved: [u8; MAX_CMD],
+    saved_len: usize,
+}
+
+impl Default for LineEditor {
+    fn default() -> LineEditor {
+        LineEditor::new()
+    }
+}
+
+impl LineEditor {
+    pub fn new() -> LineEditor {
+        LineEditor {
+            buf: [0; MAX_CMD],
+            len: 0,
+            pos: 0,
+            history: [[0; MAX_CMD]; MAX_HISTORY],
+            history_lens: [0; MAX_HISTORY],
+            history_count: 0,
+            history_next: 0,
+            browsing: 0,
+            saved: [0; MAX_CMD],
+            saved_len: 0,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    /// カーソルの位置の文字。行末なら空白
+    pub fn current(&self) -> u8 {
+        if self.pos < self.len {
+            self.buf[self.pos]
+        } else {
+            b' '
+        }
+    }
+
+    /// カーソルの位置に挿入する。入りきらなければfalseを返す
+    pub fn insert(&mut self, chr: u8) -> bool {
+        if self.len == MAX_CMD - 1 {
+            return false;
+        }
+        self.buf.copy_within(self.pos..self.len, self.pos + 1);
+        self.buf[self.pos] = chr;
+        self.len += 1;
+        self.pos += 1;
+        true
+    }
+
+    /// カーソルの前の1文字を消す
+    pub fn backspace(&mut self) -> bool {
+        if self.pos == 0 {
+            return false;
+        }
+        self.pos -= 1;
+        self.delete()
+    }
+
+    /// カーソルの位置の1文字を消す
+    pub fn delete(&mut self) -> bool {
+        if self.pos == self.len {
+            return false;
+        }
+        self.buf.copy_within((self.pos + 1)..self.len, self.pos);
+        self.len -= 1;
+        self.buf[self.len] = 0;
+        true
+    }
+
+    pub fn move_to(&mut self, pos: usize) -> bool {
+        let pos = pos.min(self.len);
+        let moved = pos != self.pos;
+        self.pos = pos;
+        moved
+    }
+
+    pub fn left(&mut self) -> bool {
+        self.pos > 0 && self.move_to(self.pos - 1)
+    }
+
+    pub fn right(&mut self) -> bool {
+        self.move_to(self.pos + 1)
+    }
+
+    /// 行を入れ替えて、カーソルを行末に置く
+    pub fn set(&mut self, line: &[u8]) {
+        let len = line.len().min(MAX_CMD - 1);
+        self.buf = [0; MAX_CMD];
+        self.buf[..len].copy_from_slice(&line[..len]);
+        self.len = len;
+        self.pos = len;
+    }
+
+    /// 入力中の行を履歴に入れて、空にする
+    /// 空の行と直前と同じ行は履歴に入れない
+    pub fn finish(&mut self) {
+        let last = (self.history_next + MAX_HISTORY - 1) % MAX_HISTORY;
+        let same_as_last = self.history_count > 0
+            && self.history[last][..self.history_lens[last]] == self.buf[..self.len];
+        if self.len > 0 && !same_as_last {
+            self.history[self.history_next] = self.buf;
+            self.history_lens[self.history_next] = self.len;
+            self.history_next = (self.history_next + 1) % MAX_HISTORY;
+            self.history_count = (self.history_count + 1).min(MAX_HISTORY);
+        }
+        self.browsing = 0;
+        self.set(b"");
+    }
+
+    /// 1つ前の履歴を表示する。もうなければfalseを返す
+    pub fn history_prev(&mut self) -> bool {
+        if self.browsing == self.history_count {
+            return false;
+        }
+        if self.browsing == 0 {
+            self.saved = self.buf;
+            self.saved_len = self.len;
+        }
+        self.browsing += 1;
+        self.show_history();
+        true
+    }
+
+    /// 1つ後の履歴を表示する。最後まで来たら入力中だった行に戻る
+    pub fn history_next(&mut self) -> bool {
+        if self.browsing == 0 {
+            return false;
+        }
+        self.browsing -= 1;
+        self.show_history();
+        true
+    }
+
+    fn show_history(&mut self) {
+        if self.browsing == 0 {
+            let saved = self.saved;
+            self.set(&saved[..self.saved_len]);
+        } else {
+            let i = (self.history_next + MAX_HISTORY - self.browsing) % MAX_HISTORY;
+            let line = self.history[i];
+            self.set(&line[..self.history_lens[i]]);
+        }
+    }
+
+    /// カーソルの前にある単語の先頭の位置と、その単語がコマンド名かどうか
+    /// 行頭か|の直後の単語をコマンド名とみなす
+    pub fn word_before_cursor(&self) -> (usize, bool) {
+        let mut start = self.pos;
+        while start > 0 && !is_separator(self.buf[start - 1]) {
+            start -= 1;
+        }
+        let mut i = start;
+        while i > 0 && self.buf[i - 1] == b' ' {
+            i -= 1;
+        }
+        (start, i == 0 || self.buf[i - 1] == b'|')
+    }
+}
+
+fn is_separator(c: u8) -> bool {
+    c == b' ' || c == b'|' || c == b'<' || c == b'>'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn editor(line: &[u8]) -> LineEditor {
+        let mut editor = LineEditor::new();
+        editor.set(line);
+        editor
+    }
+
+    #[test]
+    fn inserts_and_deletes_in_the_middle() {
+        let mut e = editor(b"ac");
+        assert!(e.left());
+        assert!(e.insert(b'b'));
+        assert_eq!(e.as_bytes(), b"abc");
+        assert_eq!(e.pos(), 2);
+        assert_eq!(e.current(), b'c');
+        assert!(e.backspace());
+        assert_eq!(e.as_bytes(), b"ac");
+        assert_eq!(e.pos(), 1);
+        assert!(e.delete());
+        assert_eq!(e.as_bytes(), b"a");
+        assert_eq!(e.current(), b' ');
+    }
+
+    #[test]
+    fn edits_at_line_edges() {
+        let mut e = editor(b"bc");
+        // 行末ではdeleteもrightもできない
+        assert!(!e.delete());
+        assert!(!e.right());
+        assert!(e.move_to(0));
+        // 行頭ではbackspaceもleftもできない
+        assert!(!e.backspace());
+        assert!(!e.left());
+        assert!(e.insert(b'a'));
+        assert_eq!(e.as_bytes(), b"abc");
+        assert!(!e.move_to(1));
+        assert!(e.move_to(100));
+        assert_eq!(e.pos(), 3);
+        let mut e = editor(b"");
+        assert!(!e.backspace());
+        assert!(!e.delete());
+        assert!(e.is_empty());
+    }
+
+    #[test]
+    fn stops_at_max_length() {
+        let mut e = editor(&[b'x'; MAX_CMD + 10]);
+        assert_eq!(e.len(), MAX_CMD - 1);
+        assert!(!e.insert(b'y'));
+        // 途中にも入らない
+        e.move_to(0);
+        assert!(!e.insert(b'y'));
+        assert!(e.delete());
+        assert!(e.insert(b'y'));
+        assert_eq!(e.as_bytes()[0], b'y');
+        assert_eq!(e.len(), MAX_CMD - 1);
+    }
+
+    #[test]
+    fn browses_history_and_restores_the_input() {
+        let mut e = LineEditor::new();
+        for line in [&b"ls"[..], b"", b"cat a", b"cat a", b"dir"].iter() {
+            e.set(line);
+            e.finish();
+        }
+        assert!(e.is_empty());
+        e.set(b"ty");
+        // 空の行と続けて同じ行は入らない
+        assert!(e.history_prev());
+        assert_eq!(e.as_bytes(), b"dir");
+        assert!(e.history_prev());
+        assert_eq!(e.as_bytes(), b"cat a");
+        assert!(e.history_prev());
+        assert_eq!(e.as_bytes(), b"ls");
+        assert!(!e.history_prev());
+        assert!(e.history_next());
+        assert!(e.history_next());
+        assert!(e.history_next());
+        assert_eq!(e.as_bytes(), b"ty");
+        assert_eq!(e.pos(), 2);
+        assert!(!e.history_next());
+    }
+
+    #[test]
+    fn history_keeps_the_newest_lines() {
+        let mut e = LineEditor::new();
+        for i in 0..(MAX_HISTORY + 3) {
+            e.set(format!("cmd{}", i).as_bytes());
+            e.finish();
+        }
+        let mut seen = Vec::new();
+        while e.history_prev() {
+            seen.push(String::from_utf8(e.as_bytes().to_vec()).unwrap());
+        }
+        assert_eq!(seen.len(), MAX_HISTORY);
+        assert_eq!(seen[0], format!("cmd{}", MAX_HISTORY + 2));
+        assert_eq!(seen[MAX_HISTORY - 1], "cmd3");
+    }
+
+    #[test]
+    fn finds_the_word_before_the_cursor() {
+        assert_eq!(editor(b"ca").word_before_cursor(), (0, true));
+        assert_eq!(editor(b"cat a.t").word_before_cursor(), (4, false));
+        assert_eq!(editor(b"cat a |  gr").word_before_cursor(), (9, true));
+        assert_eq!(editor(b"cat >ou").word_before_cursor(), (5, false));
+        assert_eq!(editor(b"cat ").word_before_cursor(), (4, false));
+        assert_eq!(editor(b"").word_before_cursor(), (0, true));
+    }
+}
@@ -0,0 +1,220 @@
+/// 空き領域を覚えておける数
+pub const MEMMAN_FREES: u32 = 4090; // 約32KB
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(C, packed)]
+struct FreeInfo {
+    addr: u32,
+    size: u32,
+}
+
+/// 空き領域を番地の順に並べて管理する。隣り合う空き領域はつなげておく
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+pub struct MemMan {
+    frees: u32,
+    maxfrees: u32,
+    lostsize: u32,
+    losts: u32,
+    free: [FreeInfo; MEMMAN_FREES as usize],
+}
+
+impl Default for MemMan {
+    fn default() -> MemMan {
+        MemMan::new()
+    }
+}
+
+impl MemMan {
+    pub fn new() -> MemMan {
+        MemMan {
+            frees: 0,
+            maxfrees: 0,
+            lostsize: 0,
+            losts: 0,
+            free: [FreeInfo { addr: 0, size: 0 }; MEMMAN_FREES as usize],
+        }
+    }
+
+    pub fn total(&self) -> u32 {
+        let mut t = 0;
+        for i in 0..self.frees {
+            t += self.free[i as usize].size;
+        }
+        t
+    }
+
+    /// 空き領域の(番地, 大きさ)を番地の順に
+    pub fn free_blocks(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        (0..self.frees as usize).map(move |i| {
+            let info = self.free[i];
+            (info.addr, info.size)
+        })
+    }
+
+    pub fn alloc(&mut self, size: u32) -> Result<u32, &'static str> {
+        for i in 0..self.frees {
+            let i = i as usize;
+            if self.free[i].size >= size {
+                let a = self.free[i].addr;
+                self.free[i].addr += size;
+                self.free[i].size -= size;
+                if self.free[i].size == 0 {
+                    // 使い切ったので後ろを詰める
+                    self.frees -= 1;
+                    for j in i..(self.frees as usize) {
+                        self.free[j] = self.free[j + 1];
+                    }
+                }
+                return Ok(a);
+            }
+        }
+        Err("CANNOT ALLOCATE MEMORY")
+    }
+
+    pub fn free(&mut self, addr: u32, size: u32) -> Result<(), &'static str> {
+        // addrの順に並ぶように、insertすべきindexを決める。どれよりも後ろなら最後
+        let idx = (0..self.frees as usize)
+            .find(|i| self.free[*i].addr > addr)
+            .unwrap_or(self.frees as usize);
+        if idx > 0 && self.free[idx - 1].addr + self.free[idx - 1].size == addr {
+            // 前の空き領域につなげる
+            self.free[idx - 1].size += size;
+            if idx < self.frees as usize && addr + size == self.free[idx].addr {
+                // 後ろともつながったので1つにする
+                self.free[idx - 1].size += self.free[idx].size;
+                self.frees -= 1;
+                for i in idx..(self.frees as usize) {
+                    self.free[i] = self.free[i + 1];
+                }
+            }
+            return Ok(());
+        }
+        if idx < self.frees as usize && addr + size == self.free[idx].addr {
+            // 後ろの空き領域につなげる
+            self.free[idx].addr = addr;
+            self.free[idx].size += size;
+            return Ok(());
+        }
+        if self.frees < MEMMAN_FREES {
+            let mut j = self.frees as usize;
+            while j > idx {
+                self.free[j] = self.free[j - 1];
+                j -= 1;
+            }
+            self.frees += 1;
+            if self.maxfrees < self.frees {
+                self.maxfrees = self.frees;
+            }
+            self.free[idx].addr = addr;
+            self.free[idx].size = size;
+            return Ok(());
+        }
+        self.losts += 1;
+        self.lostsize += size;
+        Err("CANNOT FREE MEMORY")
+    }
+
+    pub fn alloc_4k(&mut self, size: u32) -> Result<u32, &'static str> {
+        let size = (size + 0xfff) & 0xfffff000;
+        self.alloc(size)
+    }
+
+    pub fn free_4k(&mut self, addr: u32, size: u32) -> Result<(), &'static str> {
+        let size = (size + 0xfff) & 0xfffff000;
+        self.free(addr, size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks(man: &MemMan) -> Vec<(u32, u32)> {
+        man.free_blocks().collect()
+    }
+
+    #[test]
+    fn alloc_splits_first_fit() {
+        let mut man = MemMan::new();
+        man.free(0x1000, 0x1000).unwrap();
+        man.free(0x8000, 0x4000).unwrap();
+        assert_eq!(man.alloc(0x2000), Ok(0x8000));
+        assert_eq!(blocks(&man), vec![(0x1000, 0x1000), (0xa000, 0x2000)]);
+        assert_eq!(man.alloc(0x800), Ok(0x1000));
+        assert_eq!(blocks(&man), vec![(0x1800, 0x800), (0xa000, 0x2000)]);
+        assert_eq!(man.total(), 0x2800);
+    }
+
+    #[test]
+    fn alloc_whole_block_keeps_the_rest() {
+        let mut man = MemMan::new();
+        man.free(0x1000, 0x1000).unwrap();
+        man.free(0x4000, 0x1000).unwrap();
+        man.free(0x8000, 0x1000).unwrap();
+        man.free(0xc000, 0x1000).unwrap();
+        assert_eq!(man.alloc(0x1000), Ok(0x1000));
+        assert_eq!(
+            blocks(&man),
+            vec![(0x4000, 0x1000), (0x8000, 0x1000), (0xc000, 0x1000)]
+        );
+    }
+
+    #[test]
+    fn alloc_fails_when_nothing_fits() {
+        let mut man = MemMan::new();
+        man.free(0x1000, 0x1000).unwrap();
+        assert!(man.alloc(0x1001).is_err());
+        assert_eq!(man.total(), 0x1000);
+    }
+
+    #[test]
+    fn free_merges_with_neighbours() {
+        let mut man = MemMan::new();
+        man.free(0x1000, 0x1000).unwrap();
+        man.free(0x3000, 0x1000).unwrap();
+        // 前とだけつながる
+        man.free(0x2000, 0x800).unwrap();
+        assert_eq!(blocks(&man), vec![(0x1000, 0x1800), (0x3000, 0x1000)]);
+        // 後ろとだけつながる
+        man.free(0x5800, 0x800).unwrap();
+        man.free(0x5000, 0x800).unwrap();
+        assert_eq!(
+            blocks(&man),
+            vec![(0x1000, 0x1800), (0x3000, 0x1000), (0x5000, 0x1000)]
+        );
+        // 前後の両方とつながって1つになる
+        man.free(0x2800, 0x800).unwrap();
+        assert_eq!(blocks(&man), vec![(0x1000, 0x3000), (0x5000, 0x1000)]);
+        man.free(0x4000, 0x1000).unwrap();
+        assert_eq!(blocks(&man), vec![(0x1000, 0x5000)]);
+    }
+
+    #[test]
+    fn free_above_every_block_goes_last() {
+        let mut man = MemMan::new();
+        man.free(0x1000, 0x1000).unwrap();
+        man.free(0x8000, 0x1000).unwrap();
+        assert_eq!(blocks(&man), vec![(0x1000, 0x1000), (0x8000, 0x1000)]);
+    }
+
+    #[test]
+    fn free_fails_when_table_is_full() {
+        let mut man = MemMan::new();
+        for i in 0..MEMMAN_FREES {
+            man.free(i * 0x2000, 0x1000).unwrap();
+        }
+        assert!(man.free(MEMMAN_FREES * 0x2000, 0x1000).is_err());
+        assert_eq!(man.free_blocks().count(), MEMMAN_FREES as usize);
+    }
+
+    #[test]
+    fn alloc_4k_rounds_up() {
+        let mut man = MemMan::new();
+        man.free(0x10000, 0x10000).unwrap();
+        assert_eq!(man.alloc_4k(1), Ok(0x10000));
+        assert_eq!(man.alloc_4k(0x1001), Ok(0x11000));
+        man.free_4k(0x10000, 1).unwrap();
+        assert_eq!(blocks(&man), vec![(0x10000, 0x1000), (0x13000, 0xd000)]);
+    }
+}
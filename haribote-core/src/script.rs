@@ -0,0 +1,389 @@
+//! .batファイル(シェルスクリプト)の解釈
+//!
+//! 1行に1つのコマンドを書き、上から順に実行する。#で始まる行はコメント。
+//!
+//! ```text
+//! # 引数は$1から$9、直前のコマンドの終了ステータスは$?で参照できる
+//! set NAME=$1
+//! if test -e $NAME.txt
+//!     cat $NAME.txt
+//! else
+//!     echo "$NAME.txt not found"
+//! end
+//! for f in a.txt b.txt
+//!     grep foo $f
+//! end
+//! ```
+//!
+//! ifは続くコマンドの終了ステータスが0なら次の行へ進み、そうでなければelseかendの次へ飛ぶ。
+//! `if ! コマンド`で条件を反転できる。forはinの後の単語を1つずつ変数に入れてendまでを繰り返す。
+
+use crate::args::{Args, MAX_CMD};
+use crate::env::Env;
+
+pub const MAX_SCRIPT_LINES: usize = 256;
+/// if, forを入れ子にできる深さ
+pub const MAX_NEST: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Line {
+    /// 空行とコメント
+    Empty,
+    Command,
+    If,
+    Else,
+    For,
+    End,
+}
+
+pub struct Script<'a> {
+    text: &'a [u8],
+    /// text上の各行の(開始位置, 長さ)。行頭の空白と行末の\rは除いてある
+    lines: [(usize, usize); MAX_SCRIPT_LINES],
+    kinds: [Line; MAX_SCRIPT_LINES],
+    /// if→elseかend, else→end, for→end, end→対応するifかelseかfor
+    jumps: [usize; MAX_SCRIPT_LINES],
+    len: usize,
+}
+
+impl<'a> Script<'a> {
+    pub fn parse(text: &'a [u8]) -> Result<Script<'a>, &'static str> {
+        let mut script = Script {
+            text,
+            lines: [(0, 0); MAX_SCRIPT_LINES],
+            kinds: [Line::Empty; MAX_SCRIPT_LINES],
+            jumps: [0; MAX_SCRIPT_LINES],
+            len: 0,
+        };
+        let mut blocks = [0; MAX_NEST];
+        let mut depth = 0;
+        let mut start = 0;
+        for line in text.split(|c| *c == b'\n') {
+            if script.len == MAX_SCRIPT_LINES {
+                return Err("Script Too Long");
+            }
+            let i = script.len;
+            let mut s = start;
+            let mut end = start + line.len();
+            start = end + 1;
+            while s < end && (text[s] == b' ' || text[s] == b'\t') {
+                s += 1;
+            }
+            if s < end && text[end - 1] == b'\r' {
+                end -= 1;
+            }
+            if end - s >= MAX_CMD {
+                return Err("Command Too Long");
+            }
+            script.lines[i] = (s, end - s);
+            script.len += 1;
+            let line = &text[s..end];
+            let keyword = line.split(|c| *c == b' ').next().unwrap_or(b"");
+            script.kinds[i] = if line.is_empty() || line[0] == b'#' {
+                Line::Empty
+            } else if keyword == b"if" {
+                Line::If
+            } else if keyword == b"else" {
+                Line::Else
+            } else if keyword == b"for" {
+                Line::For
+            } else if keyword == b"end" {
+                Line::End
+            } else {
+                Line::Command
+            };
+            match script.kinds[i] {
+                Line::If | Line::For => {
+                    if depth == MAX_NEST {
+                        return Err("Nested Too Deeply");
+                    }
+                    blocks[depth] = i;
+                    depth += 1;
+                }
+                Line::Else => {
+                    if depth == 0 || script.kinds[blocks[depth - 1]] != Line::If {
+                        return Err("Unexpected else");
+                    }
+                    script.jumps[blocks[depth - 1]] = i;
+                    blocks[depth - 1] = i;
+                }
+                Line::End => {
+                    if depth == 0 {
+                        return Err("Unexpected end");
+                    }
+                    depth -= 1;
+                    script.jumps[blocks[depth]] = i;
+                    script.jumps[i] = blocks[depth];
+                }
+                _ => {}
+            }
+        }
+        if depth > 0 {
+            return Err("Missing end");
+        }
+        Ok(script)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn line(&self, i: usize) -> &'a [u8] {
+        let (start, len) = self.lines[i];
+        &self.text[start..(start + len)]
+    }
+
+    pub fn kind(&self, i: usize) -> Line {
+        self.kinds[i]
+    }
+
+    pub fn jump(&self, i: usize) -> usize {
+        self.jumps[i]
+    }
+}
+
+fn is_name_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+/// コマンドラインの$NAME, ${NAME}, $?, $#, $0から$9を値に置き換えてoutに書き、長さを返す
+/// '...'の中と\$は置き換えない。定義されていない変数は空になる
+pub fn expand(
+    cmdline: &[u8],
+    env: &Env,
+    status: i32,
+    args: Option<&Args>,
+    out: &mut [u8; MAX_CMD],
+) -> Result<usize, &'static str> {
+    let mut len = 0;
+    let mut push = |bytes: &[u8]| -> Result<(), &'static str> {
+        // 終端の0の分を残しておく
+        if len + bytes.len() >= MAX_CMD {
+            return Err("Command Too Long");
+        }
+        out[len..(len + bytes.len())].copy_from_slice(bytes);
+        len += bytes.len();
+        Ok(())
+    };
+    let mut single_quoted = false;
+    let mut i = 0;
+    while i < cmdline.len() && cmdline[i] != 0 {
+        let c = cmdline[i];
+        if c == b'\'' {
+            single_quoted = !single_quoted;
+        } else if c == b'\\' && !single_quoted && i + 1 < cmdline.len() {
+            push(&cmdline[i..(i + 2)])?;
+            i += 2;
+            continue;
+        } else if c == b'$' && !single_quoted && i + 1 < cmdline.len() {
+            let next = cmdline[i + 1];
+            let mut number = [0; 11];
+            if next == b'?' {
+                push(format_int(status, &mut number))?;
+                i += 2;
+                continue;
+            } else if next == b'#' {
+                let argc = args.map(|args| args.len().max(1) - 1).unwrap_or(0);
+                push(format_int(argc as i32, &mut number))?;
+                i += 2;
+                continue;
+            } else if next.is_ascii_digit() {
+                if let Some(arg) = args.and_then(|args| args.get((next - b'0') as usize)) {
+                    push(arg)?;
+                }
+                i += 2;
+                continue;
+            } else if next == b'{' {
+                let end = cmdline[(i + 2)..]
+                    .iter()
+                    .position(|c| *c == b'}')
+                    .ok_or("Bad Substitution")?;
+                let name = &cmdline[(i + 2)..(i + 2 + end)];
+                if let Some(value) = env.get(name) {
+                    push(value)?;
+                }
+                i += end + 3;
+                continue;
+            } else if is_name_char(next) {
+                let mut end = i + 1;
+                while end < cmdline.len() && is_name_char(cmdline[end]) {
+                    end += 1;
+                }
+                if let Some(value) = env.get(&cmdline[(i + 1)..end]) {
+                    push(value)?;
+                }
+                i = end;
+                continue;
+            }
+        }
+        push(&[c])?;
+        i += 1;
+    }
+    Ok(len)
+}
+
+/// 10進数の文字列にする
+fn format_int(n: i32, buf: &mut [u8; 11]) -> &[u8] {
+    let mut i = buf.len();
+    let mut m = (n as i64).abs();
+    loop {
+        i -= 1;
+        buf[i] = b'0' + (m % 10) as u8;
+        m /= 10;
+        if m == 0 {
+            break;
+        }
+    }
+    if n < 0 {
+        i -= 1;
+        buf[i] = b'-';
+    }
+    &buf[i..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::Pipeline;
+
+    fn kinds(script: &Script) -> Vec<Line> {
+        (0..script.len()).map(|i| script.kind(i)).collect()
+    }
+
+    fn error(text: &[u8]) -> &'static str {
+        match Script::parse(text) {
+            Ok(_) => panic!("parsed"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn strips_indent_and_crlf() {
+        let script = Script::parse(b"# comment\r\n\techo a\r\n\n   \nend_of_file").unwrap();
+        assert_eq!(script.len(), 5);
+        assert_eq!(script.line(1), b"echo a");
+        assert_eq!(script.line(3), b"");
+        assert_eq!(
+            kinds(&script),
+            [
+                Line::Empty,
+                Line::Command,
+                Line::Empty,
+                Line::Empty,
+                Line::Command
+            ]
+        );
+    }
+
+    #[test]
+    fn links_if_else_for_and_end() {
+        let script = Script::parse(
+            b"if test -e a\n  for f in x y\n    echo $f\n  end\nelse\n  echo none\nend\n",
+        )
+        .unwrap();
+        use Line::*;
+        assert_eq!(
+            kinds(&script),
+            [If, For, Command, End, Else, Command, End, Empty]
+        );
+        assert_eq!(script.jump(0), 4);
+        assert_eq!(script.jump(4), 6);
+        assert_eq!(script.jump(6), 4);
+        assert_eq!(script.jump(1), 3);
+        assert_eq!(script.jump(3), 1);
+    }
+
+    #[test]
+    fn limits_nesting() {
+        let nested = |depth: usize| {
+            let mut text = b"if true\n".repeat(depth);
+            text.extend(b"end\n".repeat(depth));
+            text
+        };
+        let text = nested(MAX_NEST);
+        let script = Script::parse(&text).unwrap();
+        // いちばん内側のifは最初のendに、いちばん外側のifは最後のendに飛ぶ
+        assert_eq!(script.jump(MAX_NEST - 1), MAX_NEST);
+        assert_eq!(script.jump(0), 2 * MAX_NEST - 1);
+        assert_eq!(error(&nested(MAX_NEST + 1)), "Nested Too Deeply");
+    }
+
+    #[test]
+    fn rejects_unbalanced_blocks() {
+        assert_eq!(error(b"echo a\nend"), "Unexpected end");
+        assert_eq!(error(b"else"), "Unexpected else");
+        assert_eq!(error(b"for f in a\nelse\nend"), "Unexpected else");
+        assert_eq!(error(b"if true\necho a"), "Missing end");
+        assert_eq!(error(b"if true\nelse\nelse\nend"), "Unexpected else");
+    }
+
+    #[test]
+    fn limits_lines_and_line_length() {
+        assert!(Script::parse(&b"\n".repeat(MAX_SCRIPT_LINES - 1)).is_ok());
+        assert_eq!(error(&b"\n".repeat(MAX_SCRIPT_LINES)), "Script Too Long");
+        assert!(Script::parse(&vec![b'x'; MAX_CMD - 1]).is_ok());
+        assert_eq!(error(&vec![b'x'; MAX_CMD]), "Command Too Long");
+    }
+
+    fn expanded(
+        cmdline: &[u8],
+        env: &Env,
+        status: i32,
+        args: Option<&Args>,
+    ) -> Result<Vec<u8>, &'static str> {
+        let mut out = [0; MAX_CMD];
+        let len = expand(cmdline, env, status, args, &mut out)?;
+        Ok(out[..len].to_vec())
+    }
+
+    #[test]
+    fn expands_variables_and_arguments() {
+        let mut env = Env::new();
+        env.set(b"NAME", b"hari").unwrap();
+        let pipeline = Pipeline::parse(b"run.bat one two").unwrap();
+        let args = pipeline.iter().next().unwrap();
+        assert_eq!(
+            expanded(b"echo $NAME ${NAME}bote $UNSET.", &env, 0, None),
+            Ok(b"echo hari haribote .".to_vec())
+        );
+        assert_eq!(
+            expanded(b"echo $0 $1 $2 $3 $#", &env, 0, Some(args)),
+            Ok(b"echo run.bat one two  2".to_vec())
+        );
+        assert_eq!(
+            expanded(b"echo $? $#", &env, -12, None),
+            Ok(b"echo -12 0".to_vec())
+        );
+    }
+
+    #[test]
+    fn keeps_quoted_and_escaped_dollars() {
+        let mut env = Env::new();
+        env.set(b"A", b"x").unwrap();
+        assert_eq!(
+            expanded(br#"echo '$A' "$A" \$A $"#, &env, 0, None),
+            Ok(br#"echo '$A' "x" \$A $"#.to_vec())
+        );
+        // 終端の0より後ろは読まない
+        assert_eq!(expanded(b"$A\0$A", &env, 0, None), Ok(b"x".to_vec()));
+    }
+
+    #[test]
+    fn rejects_bad_substitution_and_overflow() {
+        let mut env = Env::new();
+        assert_eq!(
+            expanded(b"echo ${A", &env, 0, None),
+            Err("Bad Substitution")
+        );
+        env.set(b"LONG", &[b'v'; 128]).unwrap();
+        assert!(expanded(b"$LONG", &env, 0, None).is_ok());
+        assert_eq!(
+            expanded(b"$LONG$LONG", &env, 0, None),
+            Err("Command Too Long")
+        );
+    }
+}
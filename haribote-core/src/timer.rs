@@ -0,0 +1,388 @@
+pub const MAX_TIMER: usize = 500;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Timer {
+    pub timeout: u32,
+    pub interval: u32, // 0以外なら周期タイマ
+    pub flag: TimerFlag,
+    pub from_app: bool,
+    pub data: i32,
+    pub fifo_addr: usize,
+    heap_index: usize,       // COUNTINGのときのheap上の位置
+    app_prev: Option<usize>, // アプリ用タイマの一覧
+    app_next: Option<usize>,
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer {
+            timeout: 0,
+            interval: 0,
+            flag: TimerFlag::AVAILABLE,
+            from_app: false,
+            data: 0,
+            fifo_addr: 0,
+            heap_index: 0,
+            app_prev: None,
+            app_next: None,
+        }
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Timer {
+        Timer::new()
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerFlag {
+    AVAILABLE,
+    USED,
+    COUNTING,
+}
+
+/// タイマの待ち行列。countはtickごとにカーネルが進める
+pub struct TimerQueue {
+    pub count: u32,
+    /// 一番早い期限。待っているタイマがなければ0xffffffff
+    pub next_tick: u32,
+    heap: [usize; MAX_TIMER], // timeoutが小さい順の二分ヒープ
+    heap_len: usize,
+    app_t0: Option<usize>,
+    pub timers_data: [Timer; MAX_TIMER],
+}
+
+impl Default for TimerQueue {
+    fn default() -> TimerQueue {
+        TimerQueue::new()
+    }
+}
+
+impl TimerQueue {
+    pub fn new() -> TimerQueue {
+        TimerQueue {
+            count: 0,
+            next_tick: 0xffffffff,
+            heap: [0; MAX_TIMER],
+            heap_len: 0,
+            app_t0: None,
+            timers_data: [Timer::new(); MAX_TIMER],
+        }
+    }
+
+    pub fn alloc(&mut self) -> Result<usize, &'static str> {
+        for i in 0..MAX_TIMER {
+            if self.timers_data[i].flag == TimerFlag::AVAILABLE {
+                let timer = &mut self.timers_data[i];
+                timer.flag = TimerFlag::USED;
+                timer.interval = 0;
+                return Ok(i);
+            }
+        }
+        Err("CANNOT ASSIGN TIMER")
+    }
+
+    /// アプリ用のタイマを確保する。アプリ終了時にcancel_allでまとめて解放できるように一覧につなぐ
+    pub fn alloc_app(&mut self) -> Result<usize, &'static str> {
+        let timer_index = self.alloc()?;
+        let app_t0 = self.app_t0;
+        {
+            let timer = &mut self.timers_data[timer_index];
+            timer.from_app = true;
+            timer.app_prev = None;
+            timer.app_next = app_t0;
+        }
+        if let Some(t0) = app_t0 {
+            self.timers_data[t0].app_prev = Some(timer_index);
+        }
+        self.app_t0 = Some(timer_index);
+        Ok(timer_index)
+    }
+
+    pub fn set_time(&mut self, timer_index: usize, timeout: u32) {
+        self.start(timer_index, timeout, 0);
+    }
+
+    /// intervalごとに自動で再設定されるタイマ
+    pub fn set_periodic(&mut self, timer_index: usize, interval: u32) {
+        self.start(timer_index, interval, interval);
+    }
+
+    fn start(&mut self, timer_index: usize, timeout: u32, interval: u32) {
+        if self.timers_data[timer_index].flag == TimerFlag::COUNTING {
+            let heap_index = self.timers_data[timer_index].heap_index;
+            self.heap_remove(heap_index);
+        }
+        {
+            let timer = &mut self.timers_data[timer_index];
+            timer.timeout = timeout + self.count;
+            timer.interval = interval;
+            timer.flag = TimerFlag::COUNTING;
+        }
+        self.heap_push(timer_index);
+        self.update_next_tick();
+    }
+
+    pub fn init_timer(&mut self, timer_index: usize, fifo_addr: usize, data: i32) {
+        let timer = &mut self.timers_data[timer_index];
+        timer.fifo_addr = fifo_addr;
+        timer.data = data;
+    }
+
+    pub fn free(&mut self, i: usize) {
+        self.cancel(i);
+        let timer = self.timers_data[i];
+        if timer.from_app {
+            if let Some(prev) = timer.app_prev {
+                self.timers_data[prev].app_next = timer.app_next;
+            } else {
+                self.app_t0 = timer.app_next;
+            }
+            if let Some(next) = timer.app_next {
+                self.timers_data[next].app_prev = timer.app_prev;
+            }
+        }
+        let timer = &mut self.timers_data[i];
+        timer.flag = TimerFlag::AVAILABLE;
+        timer.from_app = false;
+        timer.app_prev = None;
+        timer.app_next = None;
+    }
+
+    pub fn cancel(&mut self, timer_index: usize) -> bool {
+        let timer = self.timers_data[timer_index];
+        if timer.flag == TimerFlag::COUNTING {
+            self.heap_remove(timer.heap_index);
+            self.timers_data[timer_index].flag = TimerFlag::USED;
+            self.update_next_tick();
+            return true;
+        }
+        false
+    }
+
+    /// fifo_addrに送るアプリ用のタイマをすべて解放する
+    pub fn cancel_all(&mut self, fifo_addr: usize) {
+        let mut t_index = self.app_t0;
+        while let Some(i) = t_index {
+            t_index = self.timers_data[i].app_next;
+            if self.timers_data[i].fifo_addr == fifo_addr {
+                self.free(i);
+            }
+        }
+    }
+
+    /// countまでに期限が来たタイマを、期限の早い順にfに渡す
+    /// 周期タイマは次の期限で入れなおす。止まっていて過ぎてしまった分は飛ばす
+    pub fn expire<F: FnMut(usize, &Timer)>(&mut self, mut f: F) {
+        while self.heap_len > 0 {
+            let t_index = self.heap[0];
+            let timer = self.timers_data[t_index];
+            if timer.timeout > self.count {
+                break;
+            }
+            self.heap_remove(0);
+            f(t_index, &timer);
+            if timer.interval > 0 {
+                let mut timeout = timer.timeout + timer.interval;
+                if timeout <= self.count {
+                    timeout = self.count + timer.interval;
+                }
+                self.timers_data[t_index].timeout = timeout;
+                self.heap_push(t_index);
+            } else {
+                self.timers_data[t_index].flag = TimerFlag::USED;
+            }
+        }
+        self.update_next_tick();
+    }
+
+    fn update_next_tick(&mut self) {
+        self.next_tick = if self.heap_len > 0 {
+            self.timers_data[self.heap[0]].timeout
+        } else {
+            0xffffffff
+        };
+    }
+
+    fn heap_less(&self, a: usize, b: usize) -> bool {
+        self.timers_data[self.heap[a]].timeout < self.timers_data[self.heap[b]].timeout
+    }
+
+    fn heap_swap(&mut self, a: usize, b: usize) {
+        self.heap.swap(a, b);
+        let ta = self.heap[a];
+        let tb = self.heap[b];
+        self.timers_data[ta].heap_index = a;
+        self.timers_data[tb].heap_index = b;
+    }
+
+    fn heap_push(&mut self, timer_index: usize) {
+        let i = self.heap_len;
+        self.heap[i] = timer_index;
+        self.timers_data[timer_index].heap_index = i;
+        self.heap_len += 1;
+        self.sift_up(i);
+    }
+
+    fn heap_remove(&mut self, i: usize) {
+        let last = self.heap_len - 1;
+        if i != last {
+            self.heap_swap(i, last);
+        }
+        self.heap_len -= 1;
+        if i < self.heap_len {
+            self.sift_down(i);
+            self.sift_up(i);
+        }
+    }
+
+    fn sift_up(&mut self, mut i: usize) {
+        while i > 0 {
+            let parent = (i - 1) / 2;
+            if !self.heap_less(i, parent) {
+                break;
+            }
+            self.heap_swap(i, parent);
+            i = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut i: usize) {
+        loop {
+            let left = i * 2 + 1;
+            let right = left + 1;
+            let mut smallest = i;
+            if left < self.heap_len && self.heap_less(left, smallest) {
+                smallest = left;
+            }
+            if right < self.heap_len && self.heap_less(right, smallest) {
+                smallest = right;
+            }
+            if smallest == i {
+                break;
+            }
+            self.heap_swap(i, smallest);
+            i = smallest;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// countをtickまで進めて、期限が来たタイマのdataを並べる
+    fn run_until(queue: &mut TimerQueue, tick: u32) -> Vec<(u32, i32)> {
+        let mut fired = Vec::new();
+        while queue.count < tick {
+            queue.count += 1;
+            let count = queue.count;
+            queue.expire(|_, timer| fired.push((count, timer.data)));
+        }
+        fired
+    }
+
+    fn timer(queue: &mut TimerQueue, data: i32, timeout: u32) -> usize {
+        let t = queue.alloc().unwrap();
+        queue.init_timer(t, 0, data);
+        queue.set_time(t, timeout);
+        t
+    }
+
+    #[test]
+    fn fires_in_timeout_order() {
+        let mut queue = TimerQueue::new();
+        timer(&mut queue, 3, 30);
+        timer(&mut queue, 1, 10);
+        timer(&mut queue, 2, 20);
+        assert_eq!(queue.next_tick, 10);
+        assert_eq!(run_until(&mut queue, 40), vec![(10, 1), (20, 2), (30, 3)]);
+        assert_eq!(queue.next_tick, 0xffffffff);
+    }
+
+    #[test]
+    fn one_shot_becomes_used() {
+        let mut queue = TimerQueue::new();
+        let t = timer(&mut queue, 1, 1);
+        run_until(&mut queue, 1);
+        assert_eq!(queue.timers_data[t].flag, TimerFlag::USED);
+        assert!(!queue.cancel(t));
+    }
+
+    #[test]
+    fn cancel_removes_only_that_timer() {
+        let mut queue = TimerQueue::new();
+        timer(&mut queue, 1, 10);
+        let t = timer(&mut queue, 2, 5);
+        timer(&mut queue, 3, 20);
+        assert_eq!(queue.next_tick, 5);
+        assert!(queue.cancel(t));
+        assert_eq!(queue.next_tick, 10);
+        assert_eq!(queue.timers_data[t].flag, TimerFlag::USED);
+        assert_eq!(run_until(&mut queue, 30), vec![(10, 1), (20, 3)]);
+    }
+
+    #[test]
+    fn set_time_again_moves_the_timer() {
+        let mut queue = TimerQueue::new();
+        let t = timer(&mut queue, 1, 10);
+        timer(&mut queue, 2, 20);
+        queue.set_time(t, 30);
+        assert_eq!(run_until(&mut queue, 40), vec![(20, 2), (30, 1)]);
+    }
+
+    #[test]
+    fn periodic_timer_rearms_and_skips_missed_ticks() {
+        let mut queue = TimerQueue::new();
+        let t = queue.alloc().unwrap();
+        queue.init_timer(t, 0, 7);
+        queue.set_periodic(t, 10);
+        assert_eq!(run_until(&mut queue, 30), vec![(10, 7), (20, 7), (30, 7)]);
+        // アイドル中にまとめて進んだときは、1回だけ知らせて次の期限を今から数えなおす
+        queue.count = 55;
+        let mut fired = 0;
+        queue.expire(|_, _| fired += 1);
+        assert_eq!(fired, 1);
+        assert_eq!(queue.next_tick, 65);
+    }
+
+    #[test]
+    fn cancel_all_frees_app_timers_of_the_fifo() {
+        let mut queue = TimerQueue::new();
+        let a = queue.alloc_app().unwrap();
+        let b = queue.alloc_app().unwrap();
+        let c = queue.alloc_app().unwrap();
+        let kernel = queue.alloc().unwrap();
+        queue.init_timer(a, 100, 1);
+        queue.init_timer(b, 200, 2);
+        queue.init_timer(c, 100, 3);
+        queue.init_timer(kernel, 100, 4);
+        for t in [a, b, c, kernel].iter() {
+            queue.set_time(*t, 10);
+        }
+        queue.cancel_all(100);
+        assert_eq!(queue.timers_data[a].flag, TimerFlag::AVAILABLE);
+        assert_eq!(queue.timers_data[c].flag, TimerFlag::AVAILABLE);
+        assert_eq!(queue.timers_data[b].flag, TimerFlag::COUNTING);
+        assert_eq!(queue.timers_data[kernel].flag, TimerFlag::COUNTING);
+        let mut fired = run_until(&mut queue, 10);
+        fired.sort();
+        assert_eq!(fired, vec![(10, 2), (10, 4)]);
+        // 一覧に残っていたbも片付けられる
+        queue.cancel_all(200);
+        assert_eq!(queue.timers_data[b].flag, TimerFlag::AVAILABLE);
+    }
+
+    #[test]
+    fn alloc_fails_when_all_used() {
+        let mut queue = TimerQueue::new();
+        for _ in 0..MAX_TIMER {
+            queue.alloc().unwrap();
+        }
+        assert!(queue.alloc().is_err());
+        queue.free(3);
+        assert_eq!(queue.alloc(), Ok(3));
+    }
+}
@@ -0,0 +1,283 @@
+/// CSIシーケンスで受け取れる数値の数。これより多い分は捨てる
+pub const MAX_PARAMS: usize = 8;
+
+const ESC: u8 = 0x1b;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    /// ESCを受け取った
+    Escape,
+    /// ESC [を受け取った
+    Csi,
+}
+
+/// ESC [ ... の形のシーケンス
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Csi {
+    params: [u16; MAX_PARAMS],
+    len: usize,
+    /// ESC [ ? ... の形だった
+    pub private: bool,
+    /// 最後の文字(Hやmなど)
+    pub command: u8,
+}
+
+impl Csi {
+    /// i番目の数値。省略されていたり0だったりしたらdefaultを返す
+    pub fn param(&self, i: usize, default: u16) -> u16 {
+        if i < self.len && self.params[i] != 0 {
+            self.params[i]
+        } else {
+            default
+        }
+    }
+
+    /// すべての数値。1つもなければ0が1つあるものとして扱う
+    pub fn params(&self) -> &[u16] {
+        if self.len == 0 {
+            &[0]
+        } else {
+            &self.params[..self.len]
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Action {
+    /// まだシーケンスの途中なので何もしない
+    None,
+    /// そのまま表示する文字(制御文字も含む)
+    Print(u8),
+    Csi(Csi),
+    /// ESC 7
+    SaveCursor,
+    /// ESC 8
+    RestoreCursor,
+    /// ESC c
+    Reset,
+}
+
+/// コンソールに出力されたバイト列からVT100のエスケープシーケンスを取り出す
+/// 画面への表示はしないので、呼び出し側でActionに合わせて描く
+#[derive(Clone, Copy)]
+pub struct Vt100 {
+    state: State,
+    csi: Csi,
+}
+
+impl Default for Vt100 {
+    fn default() -> Vt100 {
+        Vt100::new()
+    }
+}
+
+impl Vt100 {
+    pub fn new() -> Vt100 {
+        Vt100 {
+            state: State::Ground,
+            csi: Csi {
+                params: [0; MAX_PARAMS],
+                len: 0,
+                private: false,
+                command: 0,
+            },
+        }
+    }
+
+    /// シーケンスの途中で捨てる
+    pub fn reset(&mut self) {
+        self.state = State::Ground;
+    }
+
+    pub fn feed(&mut self, chr: u8) -> Action {
+        match self.state {
+            State::Ground => {
+                if chr == ESC {
+                    self.state = State::Escape;
+                    Action::None
+                } else {
+                    Action::Print(chr)
+                }
+            }
+            State::Escape => {
+                self.state = State::Ground;
+                match chr {
+                    b'[' => {
+                        self.state = State::Csi;
+                        self.csi.params = [0; MAX_PARAMS];
+                        self.csi.len = 0;
+                        self.csi.private = false;
+                        Action::None
+                    }
+                    b'7' => Action::SaveCursor,
+                    b'8' => Action::RestoreCursor,
+                    b'c' => Action::Reset,
+                    ESC => {
+                        self.state = State::Escape;
+                        Action::None
+                    }
+                    // 知らないシーケンスは捨てる
+                    _ => Action::None,
+                }
+            }
+            State::Csi => match chr {
+                b'0'..=b'9' => {
+                    if self.csi.len == 0 {
+                        self.csi.len = 1;
+                    }
+                    let i = self.csi.len - 1;
+                    if i < MAX_PARAMS {
+                        let n = self.csi.params[i] as u32 * 10 + (chr - b'0') as u32;
+                        self.csi.params[i] = n.min(0xffff) as u16;
+                    }
+                    Action::None
+                }
+                b';' => {
+                    if self.csi.len == 0 {
+                        self.csi.len = 1;
+                    }
+                    self.csi.len += 1;
+                    Action::None
+                }
+                b'?' => {
+                    self.csi.private = true;
+                    Action::None
+                }
+                0x40..=0x7e => {
+                    self.state = State::Ground;
+                    self.csi.len = self.csi.len.min(MAX_PARAMS);
+                    self.csi.command = chr;
+                    Action::Csi(self.csi)
+                }
+                ESC => {
+                    self.state = State::Escape;
+                    Action::None
+                }
+                // 途中の文字(空白など)は読み飛ばす
+                _ => Action::None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(vt: &mut Vt100, bytes: &[u8]) -> Vec<Action> {
+        bytes
+            .iter()
+            .map(|b| vt.feed(*b))
+            .filter(|a| *a != Action::None)
+            .collect()
+    }
+
+    fn csi(bytes: &[u8]) -> Csi {
+        let mut vt = Vt100::new();
+        match feed(&mut vt, bytes).pop() {
+            Some(Action::Csi(csi)) => csi,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn passes_plain_bytes_through() {
+        let mut vt = Vt100::new();
+        assert_eq!(
+            feed(&mut vt, b"a\n\x08"),
+            [
+                Action::Print(b'a'),
+                Action::Print(b'\n'),
+                Action::Print(0x08)
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_csi_params() {
+        let c = csi(b"\x1b[12;5H");
+        assert_eq!(c.command, b'H');
+        assert_eq!(c.params(), &[12, 5]);
+        assert!(!c.private);
+        // 省略したものや0はdefaultになる
+        let c = csi(b"\x1b[;0;3m");
+        assert_eq!(c.params(), &[0, 0, 3]);
+        assert_eq!(c.param(0, 1), 1);
+        assert_eq!(c.param(1, 1), 1);
+        assert_eq!(c.param(2, 1), 3);
+        assert_eq!(c.param(3, 7), 7);
+        let c = csi(b"\x1b[J");
+        assert_eq!(c.params(), &[0]);
+        assert_eq!(c.param(0, 2), 2);
+    }
+
+    #[test]
+    fn caps_param_values_and_count() {
+        let c = csi(b"\x1b[99999999A");
+        assert_eq!(c.params(), &[0xffff]);
+        let c = csi(b"\x1b[1;2;3;4;5;6;7;8;9;10m");
+        assert_eq!(c.params(), &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(c.param(MAX_PARAMS, 42), 42);
+        // あふれた後に続くシーケンスには影響しない
+        let mut vt = Vt100::new();
+        feed(&mut vt, b"\x1b[1;2;3;4;5;6;7;8;9;10m");
+        match feed(&mut vt, b"\x1b[4m").pop() {
+            Some(Action::Csi(c)) => assert_eq!(c.params(), &[4]),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn reads_private_mode() {
+        let c = csi(b"\x1b[?25l");
+        assert!(c.private);
+        assert_eq!(c.command, b'l');
+        assert_eq!(c.params(), &[25]);
+        // 次のシーケンスでは戻る
+        let mut vt = Vt100::new();
+        feed(&mut vt, b"\x1b[?25h");
+        match feed(&mut vt, b"\x1b[2J").pop() {
+            Some(Action::Csi(c)) => assert!(!c.private),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn esc_restarts_a_sequence() {
+        let mut vt = Vt100::new();
+        // CSIの途中のESCはそれまでを捨てて、新しいシーケンスを始める
+        let actions = feed(&mut vt, b"\x1b[31\x1b[2Kx");
+        assert_eq!(actions.len(), 2);
+        match actions[0] {
+            Action::Csi(c) => {
+                assert_eq!(c.command, b'K');
+                assert_eq!(c.params(), &[2]);
+            }
+            ref other => panic!("{:?}", other),
+        }
+        assert_eq!(actions[1], Action::Print(b'x'));
+        assert_eq!(feed(&mut vt, b"\x1b\x1b7"), [Action::SaveCursor]);
+    }
+
+    #[test]
+    fn simple_escapes_and_unknown_ones() {
+        let mut vt = Vt100::new();
+        assert_eq!(
+            feed(&mut vt, b"\x1b7\x1b8\x1bc"),
+            [Action::SaveCursor, Action::RestoreCursor, Action::Reset]
+        );
+        // 知らないESCの続きは1文字だけ捨てる
+        assert_eq!(feed(&mut vt, b"\x1bZy"), [Action::Print(b'y')]);
+        // CSIの途中の空白は読み飛ばす
+        assert_eq!(csi(b"\x1b[1 ;2H").params(), &[1, 2]);
+    }
+
+    #[test]
+    fn reset_drops_a_partial_sequence() {
+        let mut vt = Vt100::new();
+        feed(&mut vt, b"\x1b[3");
+        vt.reset();
+        assert_eq!(feed(&mut vt, b"m"), [Action::Print(b'm')]);
+    }
+}
@@ -0,0 +1,102 @@
+//! `make img`で作ったイメージ(mformatとmcopyで書いたFAT12)を読む
+//!
+//! イメージが必要なので普段は動かさない。`make test-image`か`cargo test -- --ignored`で動かす。
+//! 置き場所はHARIBOTE_IMGで変えられる。
+
+use std::fs;
+use std::path::PathBuf;
+
+use haribote_core::fat::*;
+
+fn image() -> Vec<u8> {
+    let path = match std::env::var_os("HARIBOTE_IMG") {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../build/haribote.img"),
+    };
+    match fs::read(&path) {
+        Ok(image) => image,
+        Err(e) => panic!("{}: {} (run make img)", path.display(), e),
+    }
+}
+
+fn fat(image: &[u8], offset: usize) -> [u32; MAX_FAT] {
+    let mut fat = [0; MAX_FAT];
+    read_fat(&mut fat, &image[offset..(offset + FAT_BYTES)]);
+    fat
+}
+
+#[test]
+#[ignore = "needs build/haribote.img"]
+fn fats_are_consistent() {
+    let image = image();
+    assert_eq!(image.len(), IMAGE_SIZE);
+    let fat0 = fat(&image, FAT0_OFFSET);
+    assert_eq!(&fat0[..], &fat(&image, FAT1_OFFSET)[..]);
+    // 1440KBのフロッピーのメディア記述子
+    assert_eq!(fat0[0], 0xff0);
+    assert_eq!(fat0[1], 0xfff);
+    // write_fatで書き戻すと同じバイト列になる
+    let mut encoded = vec![0u8; FAT_BYTES];
+    write_fat(&fat0, &mut encoded);
+    assert_eq!(&encoded[..], &image[FAT0_OFFSET..(FAT0_OFFSET + FAT_BYTES)]);
+}
+
+#[test]
+#[ignore = "needs build/haribote.img"]
+fn chains_match_file_sizes() {
+    let image = image();
+    let fat = fat(&image, FAT0_OFFSET);
+    let dir = root_dir(&image);
+    let mut used = vec![false; MAX_FAT];
+    for i in entries(dir) {
+        let finfo = dir[i];
+        if finfo.ftype & (ATTR_VOLUME | ATTR_DIRECTORY) != 0 {
+            continue;
+        }
+        let clusters: Vec<usize> = chain(&fat, finfo.clustno as usize).collect();
+        let size = finfo.size as usize;
+        assert_eq!(
+            clusters.len(),
+            size.div_ceil(CLUSTER_SIZE),
+            "{:?}",
+            finfo.file_name()
+        );
+        if let Some(last) = clusters.last() {
+            assert!(fat[*last] >= 0xff8);
+        }
+        // 2つのファイルが同じクラスタを使っていない
+        for c in clusters {
+            assert!(!used[c]);
+            used[c] = true;
+        }
+    }
+    let used_space = used.iter().filter(|u| **u).count() * CLUSTER_SIZE;
+    assert_eq!(
+        free_space(&fat) + used_space,
+        (MAX_CLUSTER - 2) * CLUSTER_SIZE
+    );
+}
+
+#[test]
+#[ignore = "needs build/haribote.img"]
+fn files_match_sources() {
+    let image = image();
+    let fat = fat(&image, FAT0_OFFSET);
+    let dir = root_dir(&image);
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("..");
+    for (name, source) in [
+        (&b"sjis.txt"[..], "texts/sjis.txt"),
+        (&b"goat.bmp"[..], "images/goat.bmp"),
+        (&b"fall.jpg"[..], "images/fall.jpg"),
+        (&b"nihongo.fnt"[..], "fonts/nihongo.fnt"),
+    ]
+    .iter()
+    {
+        let finfo = search_file(dir, name).expect("file not found in image");
+        let expected = fs::read(root.join(source)).unwrap();
+        assert_eq!(finfo.size as usize, expected.len());
+        let mut buf = vec![0u8; expected.len()];
+        finfo.load(&mut buf, &fat, &image[CLUSTER_OFFSET..]);
+        assert!(buf == expected, "{} differs", source);
+    }
+}
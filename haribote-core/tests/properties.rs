@@ -0,0 +1,180 @@
+//! 乱数で作った操作の列を、Vecなどで書いた素直なモデルと比べる
+
+use std::collections::VecDeque;
+
+use haribote_core::fat::{read_fat, write_fat, FAT_BYTES, MAX_FAT};
+use haribote_core::fifo::{FifoBuffer, FIFO_CAPACITY};
+use haribote_core::memman::MemMan;
+use haribote_core::timer::{TimerFlag, TimerQueue, MAX_TIMER};
+
+/// 再現できるように種を固定したxorshift
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+const SEEDS: [u64; 4] = [1, 0x2545f4914f6cdd1d, 0xdeadbeef, 20200429];
+
+#[test]
+fn memman_keeps_free_list_sorted_and_merged() {
+    const BASE: u32 = 0x00400000;
+    const SIZE: u32 = 0x00400000;
+    for seed in SEEDS.iter() {
+        let mut rng = Rng(*seed);
+        let mut man = MemMan::new();
+        man.free(BASE, SIZE).unwrap();
+        let mut allocated: Vec<(u32, u32)> = Vec::new();
+        for _ in 0..2000 {
+            if allocated.is_empty() || rng.below(3) != 0 {
+                let size = (rng.below(16) as u32 + 1) * 0x1000;
+                if let Ok(addr) = man.alloc_4k(size) {
+                    allocated.push((addr, size));
+                }
+            } else {
+                let i = rng.below(allocated.len() as u64) as usize;
+                let (addr, size) = allocated.swap_remove(i);
+                man.free_4k(addr, size).unwrap();
+            }
+
+            let blocks: Vec<(u32, u32)> = man.free_blocks().collect();
+            // 番地の順に並び、重ならず、隣り合うものは1つにつながっている
+            for pair in blocks.windows(2) {
+                assert!(pair[0].0 + pair[0].1 < pair[1].0, "{:x?}", blocks);
+            }
+            assert!(blocks.iter().all(|b| b.1 > 0));
+            let used: u32 = allocated.iter().map(|a| a.1).sum();
+            assert_eq!(man.total() + used, SIZE);
+            // 確保した領域と空き領域は重ならない
+            for (addr, size) in allocated.iter() {
+                assert!(BASE <= *addr && addr + size <= BASE + SIZE);
+                assert!(blocks
+                    .iter()
+                    .all(|(b, s)| addr + size <= *b || b + s <= *addr));
+            }
+        }
+        // 全部返せば最初の1つの領域に戻る
+        for (addr, size) in allocated.drain(..) {
+            man.free_4k(addr, size).unwrap();
+        }
+        assert_eq!(man.free_blocks().collect::<Vec<_>>(), vec![(BASE, SIZE)]);
+    }
+}
+
+#[test]
+fn timer_queue_fires_like_a_sorted_list() {
+    for seed in SEEDS.iter() {
+        let mut rng = Rng(*seed);
+        let mut queue = TimerQueue::new();
+        let timers: Vec<usize> = (0..64).map(|_| queue.alloc().unwrap()).collect();
+        for (i, t) in timers.iter().enumerate() {
+            queue.init_timer(*t, 0, i as i32);
+        }
+        // モデル: タイマごとの期限
+        let mut deadlines: Vec<Option<u32>> = vec![None; timers.len()];
+        for _ in 0..3000 {
+            match rng.below(4) {
+                0 | 1 => {
+                    let i = rng.below(timers.len() as u64) as usize;
+                    let timeout = rng.below(50) as u32 + 1;
+                    queue.set_time(timers[i], timeout);
+                    deadlines[i] = Some(queue.count + timeout);
+                }
+                2 => {
+                    let i = rng.below(timers.len() as u64) as usize;
+                    assert_eq!(queue.cancel(timers[i]), deadlines[i].is_some());
+                    deadlines[i] = None;
+                }
+                _ => {
+                    queue.count += 1;
+                    let mut fired = Vec::new();
+                    queue.expire(|_, timer| fired.push((timer.timeout, timer.data as usize)));
+                    let mut expected: Vec<(u32, usize)> = deadlines
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(i, d)| d.filter(|d| *d <= queue.count).map(|d| (d, i)))
+                        .collect();
+                    // 期限の順に来る。同じ期限どうしの順は決まっていない
+                    assert!(fired.windows(2).all(|p| p[0].0 <= p[1].0));
+                    fired.sort();
+                    expected.sort();
+                    assert_eq!(fired, expected);
+                    for (_, i) in expected {
+                        deadlines[i] = None;
+                        assert_eq!(queue.timers_data[timers[i]].flag, TimerFlag::USED);
+                    }
+                }
+            }
+            let next = deadlines
+                .iter()
+                .flatten()
+                .min()
+                .cloned()
+                .unwrap_or(0xffffffff);
+            assert_eq!(queue.next_tick, next);
+        }
+    }
+}
+
+#[test]
+fn timer_queue_holds_every_timer() {
+    let mut rng = Rng(7);
+    let mut queue = TimerQueue::new();
+    for i in 0..MAX_TIMER {
+        let t = queue.alloc().unwrap();
+        queue.init_timer(t, 0, i as i32);
+        queue.set_time(t, rng.below(1000) as u32 + 1);
+    }
+    queue.count = 1000;
+    let mut fired = Vec::new();
+    queue.expire(|_, timer| fired.push(timer.timeout));
+    assert_eq!(fired.len(), MAX_TIMER);
+    assert!(fired.windows(2).all(|p| p[0] <= p[1]));
+}
+
+#[test]
+fn fifo_matches_vecdeque() {
+    for seed in SEEDS.iter() {
+        let mut rng = Rng(*seed);
+        let size = rng.below(FIFO_CAPACITY as u64) as u32 + 1;
+        let fifo = FifoBuffer::new(size);
+        let mut model = VecDeque::new();
+        for n in 0..10000 {
+            if rng.below(2) == 0 {
+                let ok = fifo.put(n).is_ok();
+                assert_eq!(ok, model.len() < size as usize);
+                if ok {
+                    model.push_back(n);
+                }
+            } else {
+                assert_eq!(fifo.get().ok(), model.pop_front());
+            }
+            assert_eq!(fifo.status() as usize, model.len());
+        }
+    }
+}
+
+#[test]
+fn fat_encoding_roundtrips() {
+    for seed in SEEDS.iter() {
+        let mut rng = Rng(*seed);
+        let mut fat = [0; MAX_FAT];
+        for entry in fat.iter_mut() {
+            *entry = rng.below(0x1000) as u32;
+        }
+        let mut img = vec![0u8; FAT_BYTES];
+        write_fat(&fat, &mut img);
+        let mut decoded = [0; MAX_FAT];
+        read_fat(&mut decoded, &img);
+        assert_eq!(&decoded[..], &fat[..]);
+    }
+}
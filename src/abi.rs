@@ -0,0 +1,108 @@
+//! アプリとOSの間のシステムコール(INT 0x40)の取り決め
+//!
+//! EDXにシステムコール番号を入れて呼び出す。引数はEBX, ECX, EAX, ESI, EDI, EBPで渡し、
+//! 戻り値があるものはEAXに返す(api_getnsecだけはEDX:EAX)。
+//! api_putchar, api_putstr0, api_putstr1は標準出力に書く。
+//! 失敗したときは、戻り値のないシステムコールも含めてEAXに負のエラーコードが入る。
+//! アプリ側からも同じ定義を使えるように、このファイルは他のモジュールに依存しない。
+
+pub const API_PUTCHAR: i32 = 1;
+pub const API_PUTSTR0: i32 = 2;
+pub const API_PUTSTR1: i32 = 3;
+pub const API_END: i32 = 4;
+pub const API_OPENWIN: i32 = 5;
+/// EBPからECXバイトの文字列をウィンドウに描く。途中に0があればそこまで
+pub const API_PUTSTRWIN: i32 = 6;
+pub const API_BOXFILWIN: i32 = 7;
+pub const API_INITMALLOC: i32 = 8;
+pub const API_MALLOC: i32 = 9;
+pub const API_FREE: i32 = 10;
+pub const API_POINT: i32 = 11;
+pub const API_REFRESHWIN: i32 = 12;
+pub const API_LINEWIN: i32 = 13;
+pub const API_CLOSEWIN: i32 = 14;
+pub const API_GETKEY: i32 = 15;
+pub const API_ALLOCTIMER: i32 = 16;
+pub const API_INITTIMER: i32 = 17;
+pub const API_SETTIMER: i32 = 18;
+pub const API_FREETIMER: i32 = 19;
+pub const API_BEEP: i32 = 20;
+pub const API_FOPEN: i32 = 21;
+pub const API_FCLOSE: i32 = 22;
+pub const API_FSEEK: i32 = 23;
+pub const API_FSIZE: i32 = 24;
+pub const API_FREAD: i32 = 25;
+pub const API_CMDLINE: i32 = 26;
+pub const API_GETLANG: i32 = 27;
+pub const API_GETTIME: i32 = 28;
+pub const API_GETNSEC: i32 = 29;
+pub const API_SETPERIODIC: i32 = 30;
+pub const API_SLEEP: i32 = 31;
+pub const API_CANCELTIMER: i32 = 32;
+pub const API_GETTICK: i32 = 33;
+/// EBXの番地に、ヒープに使える領域の番地と大きさをint 2つで書く
+/// .hrbなら.bssの後ろからデータセグメントの終わりまで、ELFならローダが確保した領域
+pub const API_GETHEAP: i32 = 34;
+pub const API_ARGC: i32 = 35;
+pub const API_GETARG: i32 = 36;
+pub const API_GETENV: i32 = 37;
+pub const API_READ: i32 = 38;
+pub const API_WRITE: i32 = 39;
+/// EAXを終了ステータスにしてアプリを終了する。API_ENDは終了ステータス0
+pub const API_EXIT: i32 = 40;
+/// EAXのシグナルにEBXのハンドラを登録し、EAXに前のハンドラを返す
+/// ハンドラからretするとECXの番地に飛ぶので、そこでAPI_SIGRETURNを呼ぶ
+pub const API_SIGNAL: i32 = 41;
+/// シグナルハンドラから、割り込まれたところに戻る
+pub const API_SIGRETURN: i32 = 42;
+/// EAX(10ms単位)後にSIGALRMを送る。0なら取り消す
+pub const API_ALARM: i32 = 43;
+
+/// api_read/api_writeに渡す標準入出力の番号
+/// コンソールのほか、パイプや<, >でリダイレクトされたファイルにつながっていることがある
+pub const STDIN: i32 = 0;
+pub const STDOUT: i32 = 1;
+pub const STDERR: i32 = 2;
+
+/// ウィンドウ番号にこれを足して描画のシステムコールを呼ぶと、描画したあとに画面を更新しない
+pub const UNREFRESH_OFFSET: i32 = 256;
+
+/// api_getkey(0)で読めるデータがない
+pub const E_AGAIN: i32 = -1;
+/// 存在しないシステムコール番号
+pub const E_NOSYS: i32 = -2;
+/// 引数がおかしい
+pub const E_INVAL: i32 = -3;
+/// メモリが足りない
+pub const E_NOMEM: i32 = -4;
+/// これ以上ウィンドウを開けない
+pub const E_NOWIN: i32 = -5;
+/// これ以上タイマを確保できない
+pub const E_NOTIMER: i32 = -6;
+/// ファイルが見つからない
+pub const E_NOENT: i32 = -7;
+/// これ以上ファイルを開けない
+pub const E_NOFILE: i32 = -8;
+/// アプリのデータセグメントの外を指すポインタが渡された
+pub const E_FAULT: i32 = -9;
+/// そのアプリが持っていないファイル・ウィンドウ・タイマの番号が渡された
+pub const E_BADF: i32 = -10;
+/// 待っている間にシグナルが届いた。ハンドラから戻ったあとに返る
+pub const E_INTR: i32 = -11;
+
+/// Ctrl+Cか、コンソールのウィンドウでShift+F1が押された
+pub const SIGINT: i32 = 1;
+/// killやコンソールを閉じたときに送られる。ハンドラから戻るとアプリは終了する
+pub const SIGTERM: i32 = 2;
+/// api_alarmで指定した時間が経った
+pub const SIGALRM: i32 = 3;
+/// アプリのウィンドウの×ボタンが押された
+pub const SIGCLOSE: i32 = 4;
+/// ハンドラを登録できず、必ず強制終了する
+pub const SIGKILL: i32 = 5;
+/// シグナルの番号はこれより小さい
+pub const NSIG: i32 = 6;
+
+/// api_signalに渡すハンドラ。SIG_DFLは強制終了、SIG_IGNは無視する
+pub const SIG_DFL: i32 = 0;
+pub const SIG_IGN: i32 = 1;
@@ -0,0 +1,855 @@
+use core::mem::size_of;
+use core::str::from_utf8;
+
+use haribote_core::args::Args;
+use haribote_core::env::Env;
+
+use crate::abi::*;
+use crate::asm::{cli, in8, out8, sti};
+use crate::console::{Console, CONSOLE_BACKSPACE, CONSOLE_DELETE, CONSOLE_ENTER, STATUS_ABORTED};
+use crate::fifo::Fifo;
+use crate::file::*;
+use crate::handle::{Handle, HandleTable};
+use crate::job::Job;
+use crate::memory::{MemMan, MEMMAN_ADDR};
+use crate::mt::{LangMode, Task, TaskManager, TASK_MANAGER_ADDR};
+use crate::rtc::{self, DateTime};
+use crate::sheet::{Sheet, SheetFlag, SheetManager};
+use crate::signal::{self, SigAction};
+use crate::stdio::{Stdio, Stream};
+use crate::timer::{self, TimerFlag, TIMER_MANAGER};
+use crate::vga::{
+    boxfill, draw_line, make_window, print_char_wrapper, to_color, SCREEN_HEIGHT, SCREEN_WIDTH,
+};
+
+const APP_SLEEP: i32 = 5;
+/// Ctrl+D
+const KEY_EOF: i32 = 0x04;
+
+/// interrupt_hrb_apiが最初のPUSHADで保存したアプリのレジスタ
+/// ここに書き込んだ値がアプリに戻る
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct ApiRegs {
+    pub edi: i32,
+    pub esi: i32,
+    pub ebp: i32,
+    pub esp: i32,
+    pub ebx: i32,
+    pub edx: i32,
+    pub ecx: i32,
+    pub eax: i32,
+}
+
+pub struct ApiContext {
+    pub regs: &'static mut ApiRegs,
+    pub task_index: usize,
+    pub task: Task,
+    pub task_manager: &'static mut TaskManager,
+    pub memman: &'static mut MemMan,
+    pub console: &'static mut Console,
+    pub sheet_manager: &'static mut SheetManager,
+}
+
+impl ApiContext {
+    fn set_eax(&mut self, value: i32) {
+        self.regs.eax = value;
+    }
+
+    fn job(&self) -> &'static mut Job {
+        unsafe { &mut *(self.task.job_addr as *mut Job) }
+    }
+
+    fn handles(&self) -> &'static mut HandleTable {
+        unsafe { &mut *(self.task.handle_table_addr as *mut HandleTable) }
+    }
+
+    /// アプリの番地addrからsizeバイトがデータセグメントに収まっていれば、カーネルから見た番地を返す
+    fn app_ptr(&self, addr: i32, size: usize) -> Result<usize, i32> {
+        let addr = addr as u32 as usize;
+        match addr.checked_add(size) {
+            Some(end) if end <= self.task.ds_limit => Ok(addr + self.task.ds_base),
+            _ => Err(E_FAULT),
+        }
+    }
+
+    /// 0終端の文字列がデータセグメントに収まっていれば、カーネルから見た番地と長さを返す
+    fn app_str(&self, addr: i32) -> Result<(usize, usize), i32> {
+        let start = self.app_ptr(addr, 0)?;
+        let end = self.task.ds_base + self.task.ds_limit;
+        for ptr in start..end {
+            if unsafe { *(ptr as *const u8) } == 0 {
+                return Ok((start, ptr - start));
+            }
+        }
+        Err(E_FAULT)
+    }
+
+    /// +UNREFRESH_OFFSETされたウィンドウ番号はリフレッシュしない
+    fn window(&self, win: i32) -> Result<(usize, bool), i32> {
+        let (win, refresh) = if win as u32 >= UNREFRESH_OFFSET as u32 {
+            (win.wrapping_sub(UNREFRESH_OFFSET), false)
+        } else {
+            (win, true)
+        };
+        match self.handles().get_mut(win) {
+            Some(&mut Handle::Window(sheet_index)) => {
+                let sheet = self.sheet_manager.sheets_data[sheet_index];
+                if sheet.flag == SheetFlag::AVAILABLE || sheet.task_index != self.task_index {
+                    return Err(E_BADF);
+                }
+                Ok((sheet_index, refresh))
+            }
+            _ => Err(E_BADF),
+        }
+    }
+
+    /// srcを0終端でアプリのbufにコピーし、EAXにsrcの長さを返す
+    /// 入りきらない分は切り捨てるので、EAXがmaxsize以上なら切り捨てられている
+    fn copy_to_app(&mut self, buf: i32, maxsize: i32, src: &[u8]) -> Result<(), i32> {
+        if maxsize <= 0 {
+            return Err(E_INVAL);
+        }
+        let addr = self.app_ptr(buf, maxsize as usize)?;
+        let len = src.len().min(maxsize as usize - 1);
+        let dst = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, len + 1) };
+        dst[..len].copy_from_slice(&src[..len]);
+        dst[len] = 0;
+        self.set_eax(src.len() as i32);
+        Ok(())
+    }
+
+    fn stdio(&self) -> &'static mut Stdio {
+        unsafe { &mut *(self.task.stdio_addr as *mut Stdio) }
+    }
+
+    /// 標準出力などに書く。コンソールにつながっていれば画面に表示する
+    fn write(&mut self, fd: i32, addr: usize, len: usize) -> Result<(), i32> {
+        match self.stdio().get_mut(fd).ok_or(E_BADF)? {
+            Stream::Console => {
+                self.console.put_string(addr, len, None);
+                Ok(())
+            }
+            Stream::Buffer(buf) => {
+                let data = unsafe { core::slice::from_raw_parts(addr as *const u8, len) };
+                buf.write(data).map_err(|_| E_NOMEM)
+            }
+        }
+    }
+
+    fn timer(&self, timer: i32) -> Result<usize, i32> {
+        match self.handles().get_mut(timer) {
+            Some(&mut Handle::Timer(timer_index)) => Ok(timer_index),
+            _ => Err(E_BADF),
+        }
+    }
+
+    fn file_handler(&self, fhandle: i32) -> Result<&'static mut FileHandler, i32> {
+        match self.handles().get_mut(fhandle) {
+            Some(Handle::File(fh)) => Ok(fh),
+            _ => Err(E_BADF),
+        }
+    }
+}
+
+/// 描画する範囲がウィンドウの中に収まっているか
+fn in_sheet(sheet: &Sheet, x0: i32, y0: i32, x1: i32, y1: i32) -> Result<(), i32> {
+    if x0 < 0 || y0 < 0 || x0 > x1 || y0 > y1 || x1 >= sheet.width || y1 >= sheet.height {
+        return Err(E_INVAL);
+    }
+    Ok(())
+}
+
+type ApiHandler = fn(&mut ApiContext) -> Result<(), i32>;
+
+// API_END、API_EXIT、API_SIGRETURNはアプリに戻るときのレジスタを変えるのでhrb_apiで直接扱う
+static API_TABLE: [Option<ApiHandler>; 44] = [
+    None,
+    Some(api_putchar),
+    Some(api_putstr0),
+    Some(api_putstr1),
+    None,
+    Some(api_openwin),
+    Some(api_putstrwin),
+    Some(api_boxfilwin),
+    Some(api_initmalloc),
+    Some(api_malloc),
+    Some(api_free),
+    Some(api_point),
+    Some(api_refreshwin),
+    Some(api_linewin),
+    Some(api_closewin),
+    Some(api_getkey),
+    Some(api_alloctimer),
+    Some(api_inittimer),
+    Some(api_settimer),
+    Some(api_freetimer),
+    Some(api_beep),
+    Some(api_fopen),
+    Some(api_fclose),
+    Some(api_fseek),
+    Some(api_fsize),
+    Some(api_fread),
+    Some(api_cmdline),
+    Some(api_getlang),
+    Some(api_gettime),
+    Some(api_getnsec),
+    Some(api_setperiodic),
+    Some(api_sleep),
+    Some(api_canceltimer),
+    Some(api_gettick),
+    Some(api_getheap),
+    Some(api_argc),
+    Some(api_getarg),
+    Some(api_getenv),
+    Some(api_read),
+    Some(api_write),
+    None,
+    Some(api_signal),
+    None,
+    Some(api_alarm),
+];
+
+#[no_mangle]
+pub extern "C" fn hrb_api(
+    _edi: i32,
+    _esi: i32,
+    _ebp: i32,
+    _esp: i32,
+    _ebx: i32,
+    _edx: i32,
+    _ecx: i32,
+    eax: i32,
+) -> usize {
+    let task_manager = unsafe { &mut *(TASK_MANAGER_ADDR as *mut TaskManager) };
+    let task_index = task_manager.now_index();
+    let regs = unsafe { &mut *((&eax as *const i32 as usize + 4) as *mut ApiRegs) };
+    let job = unsafe { &mut *(task_manager.tasks_data[task_index].job_addr as *mut Job) };
+    // 強制終了させられたアプリもここで終わらせる
+    if job.aborted || regs.edx == API_END || regs.edx == API_EXIT {
+        if !job.aborted {
+            job.status = if regs.edx == API_EXIT { regs.eax } else { 0 };
+        }
+        return unsafe { &task_manager.tasks_data[task_index].tss.esp0 } as *const i32 as usize;
+    }
+    let task = task_manager.tasks_data[task_index];
+    if regs.edx == API_SIGRETURN {
+        if !signal::sigreturn(&task, job) {
+            job.status = STATUS_ABORTED;
+            return unsafe { &task_manager.tasks_data[task_index].tss.esp0 } as *const i32 as usize;
+        }
+        return 0;
+    }
+    let console = unsafe { &mut *(task.console_addr as *mut Console) };
+    let sheet_manager = unsafe { &mut *(console.sheet_manager_addr as *mut SheetManager) };
+    let mut ctx = ApiContext {
+        regs,
+        task_index,
+        task,
+        task_manager,
+        memman: unsafe { &mut *(MEMMAN_ADDR as *mut MemMan) },
+        console,
+        sheet_manager,
+    };
+    let handler = API_TABLE.get(ctx.regs.edx as usize).and_then(|h| *h);
+    let result = match handler {
+        Some(handler) => handler(&mut ctx),
+        None => Err(E_NOSYS),
+    };
+    if let Err(code) = result {
+        ctx.set_eax(code);
+    }
+    signal::api_return(&task, job);
+    0
+}
+
+fn api_putchar(ctx: &mut ApiContext) -> Result<(), i32> {
+    let chr = ctx.regs.eax as u8;
+    ctx.write(STDOUT, &chr as *const u8 as usize, 1)
+}
+
+fn api_putstr0(ctx: &mut ApiContext) -> Result<(), i32> {
+    let (addr, len) = ctx.app_str(ctx.regs.ebx)?;
+    ctx.write(STDOUT, addr, len)
+}
+
+fn api_putstr1(ctx: &mut ApiContext) -> Result<(), i32> {
+    let len = ctx.regs.ecx as usize;
+    let addr = ctx.app_ptr(ctx.regs.ebx, len)?;
+    ctx.write(STDOUT, addr, len)
+}
+
+fn api_openwin(ctx: &mut ApiContext) -> Result<(), i32> {
+    let (xsize, ysize) = (ctx.regs.esi, ctx.regs.edi);
+    if xsize <= 0 || ysize <= 0 {
+        return Err(E_INVAL);
+    }
+    let buf_size = (xsize as usize)
+        .checked_mul(ysize as usize)
+        .ok_or(E_INVAL)?;
+    let buf_addr = ctx.app_ptr(ctx.regs.ebx, buf_size)?;
+    let (title_addr, title_len) = ctx.app_str(ctx.regs.ecx)?;
+    let title = unsafe { core::slice::from_raw_parts(title_addr as *const u8, title_len.min(29)) };
+    let title = from_utf8(title).map_err(|_| E_INVAL)?;
+    let sheet_index = ctx.sheet_manager.alloc().ok_or(E_NOWIN)?;
+    let win = match ctx.handles().insert(Handle::Window(sheet_index)) {
+        Some(win) => win,
+        None => {
+            ctx.sheet_manager.free(sheet_index);
+            return Err(E_NOWIN);
+        }
+    };
+    {
+        let mut new_sheet = &mut ctx.sheet_manager.sheets_data[sheet_index];
+        new_sheet.set(buf_addr, xsize, ysize, to_color(ctx.regs.eax as i8));
+        new_sheet.task_index = ctx.task_index;
+        new_sheet.from_app = true;
+    }
+    make_window(buf_addr, xsize as isize, ysize as isize, title, false);
+    ctx.sheet_manager.slide(
+        sheet_index,
+        ((*SCREEN_WIDTH as i32 - xsize) / 2) & !3,
+        (*SCREEN_HEIGHT as i32 - ysize) / 2,
+    );
+    ctx.sheet_manager
+        .updown(sheet_index, ctx.sheet_manager.z_max);
+    ctx.set_eax(win);
+    Ok(())
+}
+
+fn api_putstrwin(ctx: &mut ApiContext) -> Result<(), i32> {
+    let (sheet_index, refresh) = ctx.window(ctx.regs.ebx)?;
+    let color = to_color(ctx.regs.eax as i8).ok_or(E_INVAL)?;
+    let (x, y) = (ctx.regs.esi, ctx.regs.edi);
+    if ctx.regs.ecx < 0 {
+        return Err(E_INVAL);
+    }
+    let len = ctx.regs.ecx as usize;
+    let string_ptr = ctx.app_ptr(ctx.regs.ebp, len)?;
+    let string = unsafe { core::slice::from_raw_parts(string_ptr as *const u8, len) };
+    // 0終端の文字列も渡せるように、途中に0があればそこまでにする
+    let string = match string.iter().position(|&chr| chr == 0) {
+        Some(len) => &string[..len],
+        None => string,
+    };
+    if string.is_empty() {
+        return Ok(());
+    }
+    let sheet = ctx.sheet_manager.sheets_data[sheet_index];
+    let x1 = (string.len() as i32)
+        .checked_mul(8)
+        .and_then(|width| x.checked_add(width - 1))
+        .ok_or(E_INVAL)?;
+    let y1 = y.checked_add(15).ok_or(E_INVAL)?;
+    in_sheet(&sheet, x, y, x1, y1)?;
+    let mut cursor_x = x;
+    for &chr in string {
+        print_char_wrapper(
+            ctx.console.sheet_manager_addr,
+            sheet_index,
+            chr,
+            color,
+            cursor_x as isize,
+            y as isize,
+        );
+        cursor_x += 8;
+    }
+    if refresh {
+        ctx.sheet_manager.refresh(sheet_index, x, y, x1 + 1, y1 + 1);
+    }
+    Ok(())
+}
+
+fn api_boxfilwin(ctx: &mut ApiContext) -> Result<(), i32> {
+    let (sheet_index, refresh) = ctx.window(ctx.regs.ebx)?;
+    let color = to_color(ctx.regs.ebp as i8).ok_or(E_INVAL)?;
+    let (x0, y0, x1, y1) = (ctx.regs.eax, ctx.regs.ecx, ctx.regs.esi, ctx.regs.edi);
+    let sheet = ctx.sheet_manager.sheets_data[sheet_index];
+    in_sheet(&sheet, x0, y0, x1, y1)?;
+    boxfill(
+        sheet.buf_addr,
+        sheet.width as isize,
+        color,
+        x0 as isize,
+        y0 as isize,
+        x1 as isize,
+        y1 as isize,
+    );
+    if refresh {
+        ctx.sheet_manager
+            .refresh(sheet_index, x0, y0, x1 + 1, y1 + 1);
+    }
+    Ok(())
+}
+
+fn app_memman(ctx: &ApiContext) -> Result<&'static mut MemMan, i32> {
+    let addr = ctx.app_ptr(ctx.regs.ebx, size_of::<MemMan>())?;
+    Ok(unsafe { &mut *(addr as *mut MemMan) })
+}
+
+fn api_initmalloc(ctx: &mut ApiContext) -> Result<(), i32> {
+    let memman = app_memman(ctx)?;
+    let bytes = ctx.regs.ecx as u32 & 0xfffffff0;
+    ctx.app_ptr(ctx.regs.eax, bytes as usize)?;
+    *memman = MemMan::new();
+    memman.free(ctx.regs.eax as u32, bytes).map_err(|_| E_INVAL)
+}
+
+/// 16バイト単位に切り上げる
+fn round_up16(bytes: i32) -> Result<u32, i32> {
+    let bytes = (bytes as u32).checked_add(0x0f).ok_or(E_INVAL)?;
+    Ok(bytes & 0xfffffff0)
+}
+
+fn api_malloc(ctx: &mut ApiContext) -> Result<(), i32> {
+    let bytes = round_up16(ctx.regs.ecx)?;
+    let memman = app_memman(ctx)?;
+    let addr = memman.alloc(bytes).map_err(|_| E_NOMEM)?;
+    ctx.set_eax(addr as i32);
+    Ok(())
+}
+
+fn api_free(ctx: &mut ApiContext) -> Result<(), i32> {
+    let bytes = round_up16(ctx.regs.ecx)?;
+    let memman = app_memman(ctx)?;
+    ctx.app_ptr(ctx.regs.eax, bytes as usize)?;
+    memman.free(ctx.regs.eax as u32, bytes).map_err(|_| E_INVAL)
+}
+
+fn api_point(ctx: &mut ApiContext) -> Result<(), i32> {
+    let (sheet_index, refresh) = ctx.window(ctx.regs.ebx)?;
+    let (x, y) = (ctx.regs.esi, ctx.regs.edi);
+    let sheet = ctx.sheet_manager.sheets_data[sheet_index];
+    in_sheet(&sheet, x, y, x, y)?;
+    let ptr = unsafe {
+        &mut *((sheet.buf_addr + sheet.width as usize * y as usize + x as usize) as *mut u8)
+    };
+    *ptr = ctx.regs.eax as u8;
+    if refresh {
+        ctx.sheet_manager.refresh(sheet_index, x, y, x + 1, y + 1);
+    }
+    Ok(())
+}
+
+fn api_refreshwin(ctx: &mut ApiContext) -> Result<(), i32> {
+    let (sheet_index, _) = ctx.window(ctx.regs.ebx)?;
+    let (x0, y0, x1, y1) = (ctx.regs.eax, ctx.regs.ecx, ctx.regs.esi, ctx.regs.edi);
+    // 画面からはみ出す分はrefreshが切り詰めるが、画面上の座標に直すときにあふれないようにする
+    let sheet = ctx.sheet_manager.sheets_data[sheet_index];
+    for &(base, offset) in [(sheet.x, x0), (sheet.y, y0), (sheet.x, x1), (sheet.y, y1)].iter() {
+        base.checked_add(offset).ok_or(E_INVAL)?;
+    }
+    ctx.sheet_manager.refresh(sheet_index, x0, y0, x1, y1);
+    Ok(())
+}
+
+fn api_linewin(ctx: &mut ApiContext) -> Result<(), i32> {
+    let (sheet_index, refresh) = ctx.window(ctx.regs.ebx)?;
+    let (x0, y0, x1, y1) = (ctx.regs.eax, ctx.regs.ecx, ctx.regs.esi, ctx.regs.edi);
+    let sheet = ctx.sheet_manager.sheets_data[sheet_index];
+    in_sheet(&sheet, x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1))?;
+    draw_line(sheet.buf_addr, sheet.width, x0, y0, x1, y1, ctx.regs.ebp);
+    if refresh {
+        ctx.sheet_manager.refresh(
+            sheet_index,
+            x0.min(x1),
+            y0.min(y1),
+            x0.max(x1) + 1,
+            y0.max(y1) + 1,
+        );
+    }
+    Ok(())
+}
+
+fn api_closewin(ctx: &mut ApiContext) -> Result<(), i32> {
+    let (sheet_index, _) = ctx.window(ctx.regs.ebx)?;
+    ctx.handles().remove(ctx.regs.ebx % UNREFRESH_OFFSET);
+    ctx.sheet_manager.free(sheet_index);
+    Ok(())
+}
+
+fn api_getkey(ctx: &mut ApiContext) -> Result<(), i32> {
+    let key = wait_key(ctx, ctx.regs.eax != 0)?;
+    ctx.set_eax(key);
+    Ok(())
+}
+
+/// キー入力を1つ受け取る。waitがfalseで入力がなければE_AGAIN
+/// コンソールのウィンドウに来たキーは、フォアグラウンドのジョブならコンソールが渡してくれる
+fn wait_key(ctx: &mut ApiContext, wait: bool) -> Result<i32, i32> {
+    let fifo_addr = ctx.task.fifo_addr;
+    loop {
+        cli();
+        if signal::has_pending(ctx.job()) {
+            sti();
+            return Err(E_INTR);
+        }
+        let fifo = unsafe { &*(fifo_addr as *const Fifo) };
+        if fifo.status() == 0 {
+            if wait {
+                ctx.task_manager.sleep(ctx.task_index);
+            } else {
+                sti();
+                return Err(E_AGAIN);
+            }
+        }
+        let i = match fifo.get() {
+            Ok(i) => i,
+            Err(_) => {
+                sti();
+                continue;
+            }
+        };
+        sti();
+        if 256 <= i {
+            return Ok(i as i32 - 256);
+        }
+    }
+}
+
+fn api_alloctimer(ctx: &mut ApiContext) -> Result<(), i32> {
+    let timer_index = TIMER_MANAGER.lock().alloc_app().map_err(|_| E_NOTIMER)?;
+    let timer = match ctx.handles().insert(Handle::Timer(timer_index)) {
+        Some(timer) => timer,
+        None => {
+            TIMER_MANAGER.lock().free(timer_index);
+            return Err(E_NOTIMER);
+        }
+    };
+    ctx.set_eax(timer);
+    Ok(())
+}
+
+fn api_inittimer(ctx: &mut ApiContext) -> Result<(), i32> {
+    let timer_index = ctx.timer(ctx.regs.ebx)?;
+    let data = ctx.regs.eax.checked_add(256).ok_or(E_INVAL)?;
+    TIMER_MANAGER
+        .lock()
+        .init_timer(timer_index, ctx.task.fifo_addr, data);
+    Ok(())
+}
+
+/// 今からtimeout tick後の期限がカウンタに収まるか
+fn check_timeout(timeout: u32) -> Result<u32, i32> {
+    let count = TIMER_MANAGER.lock().queue.count;
+    count.checked_add(timeout).ok_or(E_INVAL)?;
+    Ok(timeout)
+}
+
+fn api_settimer(ctx: &mut ApiContext) -> Result<(), i32> {
+    let timer_index = ctx.timer(ctx.regs.ebx)?;
+    let timeout = check_timeout(ctx.regs.eax as u32)?;
+    TIMER_MANAGER.lock().set_time(timer_index, timeout);
+    Ok(())
+}
+
+fn api_freetimer(ctx: &mut ApiContext) -> Result<(), i32> {
+    let timer_index = ctx.timer(ctx.regs.ebx)?;
+    ctx.handles().remove(ctx.regs.ebx);
+    TIMER_MANAGER.lock().free(timer_index);
+    Ok(())
+}
+
+fn api_beep(ctx: &mut ApiContext) -> Result<(), i32> {
+    let tone = ctx.regs.eax;
+    if tone == 0 {
+        let i = in8(0x61);
+        out8(0x61, i & 0x0d);
+    } else {
+        let i = 1193180000 / tone;
+        out8(0x43, 0xb6);
+        out8(0x42, i as u8);
+        out8(0x42, (i >> 8) as u8);
+        let i = in8(0x61);
+        out8(0x61, (i | 0x03) & 0x0f);
+    }
+    Ok(())
+}
+
+fn api_fopen(ctx: &mut ApiContext) -> Result<(), i32> {
+    let (filename_addr, filename_len) = ctx.app_str(ctx.regs.ebx)?;
+    let filename = unsafe { core::slice::from_raw_parts(filename_addr as *const u8, filename_len) };
+    let finfo = search_file(filename).ok_or(E_NOENT)?;
+    let fat = unsafe { &*(ctx.task.fat_addr as *const [u32; MAX_FAT]) };
+    let buf_addr = ctx.memman.alloc_4k(finfo.size).map_err(|_| E_NOMEM)? as usize;
+    let mut fh = FileHandler::new();
+    fh.buf_addr = buf_addr;
+    fh.size = finfo.size as i32;
+    let fhandle = match ctx.handles().insert(Handle::File(fh)) {
+        Some(fhandle) => fhandle,
+        None => {
+            let _ = ctx.memman.free_4k(buf_addr as u32, finfo.size);
+            return Err(E_NOFILE);
+        }
+    };
+    load_file(&finfo, buf_addr, fat);
+    ctx.set_eax(fhandle);
+    Ok(())
+}
+
+fn api_fclose(ctx: &mut ApiContext) -> Result<(), i32> {
+    let fh = *ctx.file_handler(ctx.regs.eax)?;
+    ctx.handles().remove(ctx.regs.eax);
+    ctx.memman
+        .free_4k(fh.buf_addr as u32, fh.size as u32)
+        .map_err(|_| E_INVAL)
+}
+
+fn api_fseek(ctx: &mut ApiContext) -> Result<(), i32> {
+    let fh = ctx.file_handler(ctx.regs.eax)?;
+    let offset = ctx.regs.ebx;
+    fh.pos = match ctx.regs.ecx {
+        0 => offset,
+        1 => fh.pos.checked_add(offset).ok_or(E_INVAL)?,
+        2 => fh.size.checked_add(offset).ok_or(E_INVAL)?,
+        _ => return Err(E_INVAL),
+    };
+    if fh.pos < 0 {
+        fh.pos = 0;
+    }
+    if fh.pos > fh.size {
+        fh.pos = fh.size;
+    }
+    Ok(())
+}
+
+fn api_fsize(ctx: &mut ApiContext) -> Result<(), i32> {
+    let fh = ctx.file_handler(ctx.regs.eax)?;
+    let size = match ctx.regs.ecx {
+        0 => fh.size,
+        1 => fh.pos,
+        2 => fh.pos - fh.size,
+        _ => return Err(E_INVAL),
+    };
+    ctx.set_eax(size);
+    Ok(())
+}
+
+fn api_fread(ctx: &mut ApiContext) -> Result<(), i32> {
+    let fh = ctx.file_handler(ctx.regs.eax)?;
+    let maxsize = ctx.regs.ecx.max(0) as usize;
+    let buf_addr = ctx.app_ptr(ctx.regs.ebx, maxsize)?;
+    let mut size: usize = 0;
+    for i in 0..maxsize {
+        if fh.pos == fh.size {
+            break;
+        }
+        let ptr = unsafe { &mut *((buf_addr + i) as *mut u8) };
+        *ptr = unsafe { *((fh.buf_addr + fh.pos as usize) as *const u8) };
+        fh.pos += 1;
+        size = i + 1;
+    }
+    ctx.set_eax(size as i32);
+    Ok(())
+}
+
+fn api_cmdline(ctx: &mut ApiContext) -> Result<(), i32> {
+    let maxsize = ctx.regs.ecx as usize;
+    if ctx.regs.ecx <= 0 {
+        return Err(E_INVAL);
+    }
+    let buf_addr = ctx.app_ptr(ctx.regs.ebx, maxsize)?;
+    let mut i = 0;
+    loop {
+        let ptr = unsafe { &mut *((buf_addr + i) as *mut u8) };
+        let chr = unsafe { *((ctx.task.cmdline_addr + i) as *const u8) };
+        if chr == 0 || i == maxsize - 1 {
+            // 入りきらない分は切り捨てる
+            *ptr = 0;
+            break;
+        }
+        *ptr = chr;
+        i += 1;
+    }
+    ctx.set_eax(i as i32);
+    Ok(())
+}
+
+fn api_getlang(ctx: &mut ApiContext) -> Result<(), i32> {
+    let lang_mode: LangMode = ctx.task.lang_mode;
+    ctx.set_eax(lang_mode as i32);
+    Ok(())
+}
+
+fn api_gettime(ctx: &mut ApiContext) -> Result<(), i32> {
+    let addr = ctx.app_ptr(ctx.regs.ebx, size_of::<DateTime>())?;
+    let datetime = unsafe { &mut *(addr as *mut DateTime) };
+    *datetime = rtc::now();
+    Ok(())
+}
+
+fn api_getnsec(ctx: &mut ApiContext) -> Result<(), i32> {
+    let nsec = timer::nsec();
+    ctx.set_eax(nsec as i32);
+    ctx.regs.edx = (nsec >> 32) as i32;
+    Ok(())
+}
+
+fn api_setperiodic(ctx: &mut ApiContext) -> Result<(), i32> {
+    let timer_index = ctx.timer(ctx.regs.ebx)?;
+    if ctx.regs.eax <= 0 {
+        return Err(E_INVAL);
+    }
+    // 期限が来ると次の期限はさらにintervalだけ先になる
+    let interval = ctx.regs.eax as u32;
+    check_timeout(interval.checked_mul(2).ok_or(E_INVAL)?)?;
+    TIMER_MANAGER.lock().set_periodic(timer_index, interval);
+    Ok(())
+}
+
+fn api_sleep(ctx: &mut ApiContext) -> Result<(), i32> {
+    if ctx.regs.eax <= 0 {
+        return Ok(());
+    }
+    // 10ms単位に切り上げる
+    let ticks = check_timeout((ctx.regs.eax as u32 + 9) / 10)?;
+    // 寝ている間に強制終了されてもcancel_allで解放されるように、アプリ用のタイマを使う
+    let timer_index = TIMER_MANAGER.lock().alloc_app().map_err(|_| E_NOTIMER)?;
+    TIMER_MANAGER
+        .lock()
+        .init_timer(timer_index, ctx.task.fifo_addr, APP_SLEEP);
+    TIMER_MANAGER.lock().set_time(timer_index, ticks);
+    let mut result = Ok(());
+    loop {
+        cli();
+        if TIMER_MANAGER.lock().queue.timers_data[timer_index].flag != TimerFlag::COUNTING {
+            sti();
+            break;
+        }
+        if signal::has_pending(ctx.job()) {
+            sti();
+            result = Err(E_INTR);
+            break;
+        }
+        // キー入力などで起こされることもあるので、タイマが切れるまで寝なおす
+        ctx.task_manager.sleep(ctx.task_index);
+        sti();
+    }
+    TIMER_MANAGER.lock().free(timer_index);
+    result
+}
+
+fn api_canceltimer(ctx: &mut ApiContext) -> Result<(), i32> {
+    let timer_index = ctx.timer(ctx.regs.ebx)?;
+    let cancelled = TIMER_MANAGER.lock().cancel(timer_index);
+    ctx.set_eax(cancelled as i32);
+    Ok(())
+}
+
+fn api_gettick(ctx: &mut ApiContext) -> Result<(), i32> {
+    let count = TIMER_MANAGER.lock().queue.count;
+    ctx.set_eax(count as i32);
+    Ok(())
+}
+
+fn api_getheap(ctx: &mut ApiContext) -> Result<(), i32> {
+    let addr = ctx.app_ptr(ctx.regs.ebx, size_of::<[i32; 2]>())?;
+    let info = unsafe { &mut *(addr as *mut [i32; 2]) };
+    *info = [ctx.task.heap_addr as i32, ctx.task.heap_size as i32];
+    Ok(())
+}
+
+fn args(ctx: &ApiContext) -> Option<&'static Args> {
+    if ctx.task.args_addr == 0 {
+        return None;
+    }
+    Some(unsafe { &*(ctx.task.args_addr as *const Args) })
+}
+
+fn api_argc(ctx: &mut ApiContext) -> Result<(), i32> {
+    let argc = args(ctx).map(|args| args.len()).unwrap_or(0);
+    ctx.set_eax(argc as i32);
+    Ok(())
+}
+
+fn api_getarg(ctx: &mut ApiContext) -> Result<(), i32> {
+    if ctx.regs.eax < 0 {
+        return Err(E_INVAL);
+    }
+    let arg = args(ctx)
+        .and_then(|args| args.get(ctx.regs.eax as usize))
+        .ok_or(E_NOENT)?;
+    ctx.copy_to_app(ctx.regs.ebx, ctx.regs.ecx, arg)
+}
+
+fn api_getenv(ctx: &mut ApiContext) -> Result<(), i32> {
+    let (name_addr, name_len) = ctx.app_str(ctx.regs.eax)?;
+    let name = unsafe { core::slice::from_raw_parts(name_addr as *const u8, name_len) };
+    if ctx.task.env_addr == 0 {
+        return Err(E_NOENT);
+    }
+    let env = unsafe { &*(ctx.task.env_addr as *const Env) };
+    let value = env.get(name).ok_or(E_NOENT)?;
+    ctx.copy_to_app(ctx.regs.ebx, ctx.regs.ecx, value)
+}
+
+/// コンソールから1行読む。Enterが押されると改行まで返し、行の先頭でCtrl+Dが押されると0を返す
+fn read_line(ctx: &mut ApiContext, buf: &mut [u8]) -> Result<usize, i32> {
+    let mut len = 0;
+    while len < buf.len() {
+        if ctx.console.sheet_index == 0 {
+            // キー入力を受け取れないコンソール
+            break;
+        }
+        let key = wait_key(ctx, true)?;
+        if key == KEY_EOF && len == 0 {
+            break;
+        } else if key == CONSOLE_ENTER as i32 {
+            buf[len] = b'\n';
+            len += 1;
+            ctx.console.cursor_x = 8;
+            ctx.console.newline();
+            break;
+        } else if key == CONSOLE_BACKSPACE as i32 {
+            if len > 0 && ctx.console.cursor_x > 8 {
+                len -= 1;
+                ctx.console.cursor_x -= 8;
+                ctx.console.put_char(b' ', false);
+            }
+        } else if key >= b' ' as i32 && key != CONSOLE_DELETE as i32 {
+            buf[len] = key as u8;
+            len += 1;
+            ctx.console
+                .put_string(&buf[len - 1] as *const u8 as usize, 1, None);
+        }
+    }
+    Ok(len)
+}
+
+fn api_read(ctx: &mut ApiContext) -> Result<(), i32> {
+    let maxsize = ctx.regs.ecx.max(0) as usize;
+    let addr = ctx.app_ptr(ctx.regs.ebx, maxsize)?;
+    let buf = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, maxsize) };
+    let size = match ctx.stdio().get_mut(ctx.regs.eax).ok_or(E_BADF)? {
+        Stream::Console => read_line(ctx, buf)?,
+        Stream::Buffer(b) => b.read(buf),
+    };
+    ctx.set_eax(size as i32);
+    Ok(())
+}
+
+fn api_write(ctx: &mut ApiContext) -> Result<(), i32> {
+    let len = ctx.regs.ecx.max(0) as usize;
+    let addr = ctx.app_ptr(ctx.regs.ebx, len)?;
+    ctx.write(ctx.regs.eax, addr, len)?;
+    ctx.set_eax(len as i32);
+    Ok(())
+}
+
+fn api_signal(ctx: &mut ApiContext) -> Result<(), i32> {
+    let action = SigAction {
+        handler: ctx.regs.ebx,
+        restorer: ctx.regs.ecx,
+    };
+    let old = signal::set_action(ctx.job(), ctx.regs.eax, action)?;
+    ctx.set_eax(old);
+    Ok(())
+}
+
+fn api_alarm(ctx: &mut ApiContext) -> Result<(), i32> {
+    if ctx.regs.eax < 0 {
+        return Err(E_INVAL);
+    }
+    let timeout = check_timeout(ctx.regs.eax as u32)?;
+    signal::set_alarm(ctx.task_manager, ctx.task_index, ctx.job(), timeout)
+}
@@ -34,6 +34,15 @@ pub fn store_cr0(cr0: u32) {
     }
 }
 
+/// 今の関数のフレームポインタ。バックトレースをたどるのに使う
+pub fn load_ebp() -> usize {
+    let result: usize;
+    unsafe {
+        asm!("MOV EAX,EBP" : "={EAX}"(result) : : : "intel");
+    }
+    result
+}
+
 pub fn cli() {
     unsafe {
         asm!("CLI" : : : : "intel");
@@ -69,6 +78,54 @@ pub fn in8(port: u32) -> u8 {
     r
 }
 
+pub fn out16(port: u32, data: u16) {
+    unsafe {
+        asm!("OUT DX,AX" : : "{EDX}"(port), "{AX}"(data) : : "intel");
+    }
+}
+
+pub fn in16(port: u32) -> u16 {
+    let r: u16;
+    unsafe {
+        asm!("IN AX,DX" : "={AX}"(r) : "{EDX}"(port) : : "intel", "volatile");
+    }
+    r
+}
+
+pub fn out32(port: u32, data: u32) {
+    unsafe {
+        asm!("OUT DX,EAX" : : "{EDX}"(port), "{EAX}"(data) : : "intel");
+    }
+}
+
+pub fn in32(port: u32) -> u32 {
+    let r: u32;
+    unsafe {
+        asm!("IN EAX,DX" : "={EAX}"(r) : "{EDX}"(port) : : "intel", "volatile");
+    }
+    r
+}
+
+pub fn rdtsc() -> u64 {
+    let low: u32;
+    let high: u32;
+    unsafe {
+        asm!("RDTSC" : "={EAX}"(low), "={EDX}"(high) : : : "intel", "volatile");
+    }
+    (high as u64) << 32 | low as u64
+}
+
+pub fn cpuid(leaf: u32) -> (u32, u32, u32, u32) {
+    let eax: u32;
+    let ebx: u32;
+    let ecx: u32;
+    let edx: u32;
+    unsafe {
+        asm!("CPUID" : "={EAX}"(eax), "={EBX}"(ebx), "={ECX}"(ecx), "={EDX}"(edx) : "{EAX}"(leaf) : : "intel");
+    }
+    (eax, ebx, ecx, edx)
+}
+
 #[derive(Debug, Clone, Copy)]
 #[repr(C, packed)]
 struct Dtr {
@@ -136,6 +193,35 @@ macro_rules! handler {
                     let task_manager = &mut *(TASK_MANAGER_ADDR as *mut TaskManager);
                     task_manager.switch();
                 }
+                // アプリに戻るときは、届いているシグナルのハンドラを呼ぶ
+                asm!("CALL $0" : : "r"(crate::signal::irq_return as extern "C" fn(*mut crate::signal::UserFrame)) : : "intel");
+                asm!("POP EAX
+                    POPAD
+                    POP DS
+                    POP ES
+                    IRETD" : : : : "intel", "volatile");
+            }
+        }
+        wrapper
+    }}
+}
+
+/// 割り込まれたところのフレームを見たり書き換えたりするハンドラ用。タスクは切り替えない
+#[macro_export]
+macro_rules! trap_handler {
+    ($name: ident) => {{
+        #[naked]
+        pub extern "C" fn wrapper() {
+            unsafe {
+                asm!("PUSH ES
+                      PUSH DS
+                      PUSHAD
+                      MOV EAX,ESP
+                      PUSH EAX
+                      MOV AX,SS
+                      MOV DS,AX
+                      MOV ES,AX" : : : : "intel", "volatile");
+                asm!("CALL $0" : : "r"($name as extern "C" fn(*mut crate::signal::UserFrame)) : : "intel");
                 asm!("POP EAX
                     POPAD
                     POP DS
@@ -0,0 +1,82 @@
+use crate::multiboot;
+use crate::vga;
+
+/// asmheadが作るBootInfoの版。並びを変えたり後ろに足したりしたら上げる
+pub const BOOT_INFO_VERSION: u32 = 2;
+
+/// Multibootで起動して、ローダが画面モードを変えなかったときの解像度
+const SCREEN_WIDTH: u16 = 640;
+const SCREEN_HEIGHT: u16 = 480;
+
+/// asmhead.asm(Multibootで起動したときはmbhead.asm)が書き、hrmainに番地を渡す
+/// 並びはasmhead.asmのBI_*と合わせる
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct BootInfo {
+    pub version: u32,
+    /// asmheadが書いた大きさ。後ろに足した項目があるかはこれでわかる
+    pub size: u32,
+    /// IPLが読み込んだシリンダ数
+    pub cyls: u8,
+    /// キーボードのLEDの状態(BIOSのINT 0x16, AH=0x02)
+    pub leds: u8,
+    /// 何ビットカラーか
+    pub vmode: u8,
+    /// 起動したドライブ(BIOSのドライブ番号、わからなければ0xff)
+    pub boot_drive: u8,
+    pub scrnx: u16,
+    pub scrny: u16,
+    pub vram: u32,
+    /// 使えるメモリの終わり。0ならmemtestで調べる
+    pub memory_end: u32,
+    /// Multibootのローダから渡された情報の番地。0ならasmheadから起動した
+    pub multiboot_info: u32,
+}
+
+static mut BOOT_INFO: BootInfo = BootInfo {
+    version: 0,
+    size: 0,
+    cyls: 0,
+    leds: 0,
+    vmode: 0,
+    boot_drive: 0,
+    scrnx: 0,
+    scrny: 0,
+    vram: 0,
+    memory_end: 0,
+    multiboot_info: 0,
+};
+
+/// 後から書き換えられないように、カーネルの中に写しておく
+/// 版か大きさが合わなければ、何も写さずにエラーにする
+/// Multibootで起動したときは、画面と使えるメモリをMultiboot情報から埋める
+pub fn init(boot_info: *const BootInfo) -> Result<(), &'static str> {
+    let mut boot_info = unsafe { *boot_info };
+    if boot_info.version != BOOT_INFO_VERSION {
+        return Err("BootInfo version mismatch");
+    }
+    if (boot_info.size as usize) < core::mem::size_of::<BootInfo>() {
+        return Err("BootInfo too small");
+    }
+    if boot_info.multiboot_info != 0 {
+        let info = multiboot::info(boot_info.multiboot_info);
+        let (vram, scrnx, scrny) = match info.framebuffer() {
+            Some(fb) => fb,
+            // ローダが8bitカラーにしてくれなかったときは、自分で画面モードを変える
+            None => vga::init_bochs_vbe(SCREEN_WIDTH, SCREEN_HEIGHT)
+                .map(|vram| (vram, SCREEN_WIDTH, SCREEN_HEIGHT))
+                .ok_or("No 8bit color screen")?,
+        };
+        boot_info.vmode = 8;
+        boot_info.scrnx = scrnx;
+        boot_info.scrny = scrny;
+        boot_info.vram = vram;
+        boot_info.memory_end = info.memory_end().unwrap_or(0);
+    }
+    unsafe { BOOT_INFO = boot_info };
+    Ok(())
+}
+
+pub fn boot_info() -> BootInfo {
+    unsafe { BOOT_INFO }
+}
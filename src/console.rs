@@ -1,384 +1,138 @@
+use core::fmt::{self, Write};
 use core::str::from_utf8;
 
-use crate::asm::{cli, in8, out8, sti};
+use haribote_core::args::{Args, Pipeline, MAX_CMD};
+use haribote_core::elf::Elf;
+use haribote_core::env::Env;
+use haribote_core::hrb::Hrb;
+use haribote_core::line_editor::LineEditor;
+use haribote_core::script::{self, Line, Script, MAX_NEST};
+use haribote_core::vt100::{Action, Csi, Vt100};
+
+use crate::abi::{SIGALRM, SIGCLOSE, SIGINT, SIGKILL, SIGTERM};
+use crate::asm::{cli, load_eflags, sti, store_eflags};
 use crate::descriptor_table::{SegmentDescriptor, AR_CODE32_ER, AR_DATA32_RW};
 use crate::fifo::Fifo;
 use crate::file::*;
+use crate::gdb;
+use crate::handle::{Handle, HandleTable};
+use crate::job::{Job, JobState, JobTable, MAX_JOBS};
 use crate::keyboard::KEYBOARD_OFFSET;
+use crate::log::Level;
 use crate::memory::{MemMan, MEMMAN_ADDR};
 use crate::mt::{to_lang_mode, LangMode, TaskManager, TASK_MANAGER_ADDR};
+use crate::rtc;
+use crate::scrollback::{self, Scrollback};
 use crate::sheet::{SheetFlag, SheetManager, MAX_SHEETS};
+use crate::signal::{self, ALARM_OFFSET};
+use crate::stdio::{Buffer, Stdio, Stream};
 use crate::timer::TIMER_MANAGER;
 use crate::vga::{
-    boxfill, draw_line, make_window, print_char_wrapper, to_color, Color, SCREEN_HEIGHT,
-    SCREEN_WIDTH,
+    boxfill, make_textbox, make_window, print_char_wrapper, Color, SCREEN_HEIGHT, SCREEN_WIDTH,
 };
 use crate::{
-    open_console, open_console_task, write_with_bg, EXIT_CONSOLE, EXIT_OFFSET,
-    EXIT_ONLY_CONSOLE_OFFSET, EXIT_TASK_OFFSET, NIHONGO_ADDR, SHEET_MANAGER_ADDR, TASK_A_FIFO_ADDR,
+    log, open_console, open_console_task, EXIT_CONSOLE, EXIT_OFFSET, EXIT_ONLY_CONSOLE_OFFSET,
+    EXIT_TASK_OFFSET, NIHONGO_ADDR, SHEET_MANAGER_ADDR, TASK_A_FIFO_ADDR,
 };
 
 pub const CONSOLE_CURSOR_ON: u32 = 2;
 pub const CONSOLE_CURSOR_OFF: u32 = 3;
+// ホイールとShift+PageUp/PageDownでのスクロール
+pub const CONSOLE_SCROLL_UP: u32 = 5;
+pub const CONSOLE_SCROLL_DOWN: u32 = 6;
+pub const CONSOLE_PAGE_UP: u32 = 7;
+pub const CONSOLE_PAGE_DOWN: u32 = 8;
+/// 裏で動いていたジョブが終わった
+pub const CONSOLE_JOB_DONE: u32 = 9;
+// 行の編集に使うキー。Ctrlを押しながら打っても同じになるようにしてある
+pub const CONSOLE_HOME: u32 = 0x01; // Ctrl+A
+pub const CONSOLE_LEFT: u32 = 0x02; // Ctrl+B
+pub const CONSOLE_END: u32 = 0x05; // Ctrl+E
+pub const CONSOLE_RIGHT: u32 = 0x06; // Ctrl+F
 pub const CONSOLE_BACKSPACE: u32 = 8;
+pub const CONSOLE_TAB: u32 = 9;
 pub const CONSOLE_ENTER: u32 = 10;
+pub const CONSOLE_DOWN: u32 = 0x0e; // Ctrl+N
+pub const CONSOLE_UP: u32 = 0x10; // Ctrl+P
+pub const CONSOLE_DELETE: u32 = 0x7f;
+// フォアグラウンドのジョブを強制終了させる/止めるキー
+pub const CONSOLE_INTERRUPT: u32 = 0x03; // Ctrl+C
+const CONSOLE_SUSPEND: u32 = 0x1a; // Ctrl+Z
+pub const CONSOLE_FIFO_SIZE: usize = 128;
 const MIN_CURSOR_X: isize = 16;
 const MIN_CURSOR_Y: isize = 28;
-const MAX_CURSOR_X: isize = 8 + 240;
-const MAX_CURSOR_Y: isize = 28 + 112;
-const MAX_CMD: usize = 30;
+/// 新しく開くコンソールの大きさ(文字数)
+pub const CONSOLE_COLUMNS: usize = 30;
+pub const CONSOLE_ROWS: usize = 8;
+const MIN_CONSOLE_COLUMNS: usize = 16;
+const MIN_CONSOLE_ROWS: usize = 2;
+/// ホイールを1目盛り回したときにスクロールする行数
+const WHEEL_LINES: usize = 3;
+/// 補完の候補を一覧表示するときの1つ分の幅
+const COMPLETION_WIDTH: usize = 15;
+/// Tabで補完する組み込みコマンド
+const BUILTIN_COMMANDS: [&[u8]; 28] = [
+    b"bg",
+    b"cat",
+    b"clear",
+    b"console",
+    b"copy",
+    b"date",
+    b"debug",
+    b"del",
+    b"dir",
+    b"dmesg",
+    b"echo",
+    b"exit",
+    b"fg",
+    b"jobs",
+    b"kill",
+    b"langmode",
+    b"ls",
+    b"mem",
+    b"mkdir",
+    b"ncst",
+    b"ren",
+    b"resize",
+    b"set",
+    b"start",
+    b"test",
+    b"time",
+    b"type",
+    b"unset",
+];
+/// 例外や強制終了で終わったアプリの終了ステータス
+pub const STATUS_ABORTED: i32 = -1;
+/// スクリプトから別のスクリプトを呼べる深さ
+/// 1段ごとにコンソールのスタック(64KB)を15KBほど使うので深くはできない
+const MAX_SCRIPT_DEPTH: usize = 2;
 
-const MAX_FILE_HANDLER: usize = 8;
+/// ESC [ 30 mから37 m(明るい色は90 mから97 m)で選ぶ色
+const ANSI_COLORS: [Color; 16] = [
+    Color::Black,
+    Color::DarkRed,
+    Color::DarkGreen,
+    Color::DarkYellow,
+    Color::DarkBlue,
+    Color::DarkPurple,
+    Color::DarkCyan,
+    Color::LightGray,
+    Color::DarkGray,
+    Color::LightRed,
+    Color::LightGreen,
+    Color::LightYellow,
+    Color::LightBlue,
+    Color::LightPurple,
+    Color::LightCyan,
+    Color::White,
+];
+const DEFAULT_FG: u8 = 15;
+const DEFAULT_BG: u8 = 0;
 
 extern "C" {
     fn _start_app(eip: i32, cs: i32, esp: i32, ds: i32, tss_esp_addr: i32);
 }
 
-#[no_mangle]
-pub extern "C" fn hrb_api(
-    edi: i32,
-    esi: i32,
-    ebp: i32,
-    esp: i32,
-    ebx: i32,
-    edx: i32,
-    ecx: i32,
-    eax: i32,
-) -> usize {
-    let memman = unsafe { &mut *(MEMMAN_ADDR as *mut MemMan) };
-    let task_manager = unsafe { &mut *(TASK_MANAGER_ADDR as *mut TaskManager) };
-    let task_index = task_manager.now_index();
-    let task = task_manager.tasks_data[task_index];
-    let ds_base = task.ds_base;
-    let console = unsafe { &mut *(task.console_addr as *mut Console) };
-    let sheet_manager = unsafe { &mut *(console.sheet_manager_addr as *mut SheetManager) };
-    let reg = &eax as *const i32 as usize + 4;
-
-    if edx == 1 {
-        // 1文字出力
-        console.put_string([eax as u8].as_ptr() as usize, 1, None);
-    } else if edx == 2 {
-        // 0まで出力
-        let mut i = 0;
-        loop {
-            let chr = unsafe { *((ebx as usize + i as usize + ds_base) as *const u8) };
-            if chr == 0 {
-                break;
-            }
-            i += 1;
-        }
-        console.put_string(ebx as usize + ds_base, i, None);
-    } else if edx == 3 {
-        // 指定した文字数出力
-        console.put_string(ebx as usize, ecx as usize, None);
-    } else if edx == 4 {
-        return unsafe { &(task.tss.esp0) } as *const i32 as usize;
-    } else if edx == 5 {
-        let sheet_index = sheet_manager.alloc().unwrap();
-        {
-            let mut new_sheet = &mut sheet_manager.sheets_data[sheet_index];
-            new_sheet.set(ebx as usize + ds_base, esi, edi, to_color(eax as i8));
-            new_sheet.task_index = task_index;
-            new_sheet.from_app = true;
-        }
-        let title = unsafe { *((ecx as usize + ds_base) as *const [u8; 30]) };
-        let mut t = title.iter().take_while(|t| **t != 0);
-        let mut i = 0;
-        for n in 0..30 {
-            i = n;
-            if t.next().is_none() {
-                break;
-            }
-        }
-        make_window(
-            ebx as usize + ds_base,
-            esi as isize,
-            edi as isize,
-            from_utf8(&title[0..i]).unwrap(),
-            false,
-        );
-        sheet_manager.slide(
-            sheet_index,
-            ((*SCREEN_WIDTH as i32 - esi) / 2) & !3,
-            (*SCREEN_HEIGHT as i32 - edi) / 2,
-        );
-        sheet_manager.updown(sheet_index, sheet_manager.z_max);
-        let reg_eax = unsafe { &mut *((reg + 7 * 4) as *mut i32) };
-        *reg_eax = sheet_index as i32;
-    } else if edx == 6 {
-        let mut sheet_index = ebx as usize;
-        let mut refresh = true;
-        if sheet_index >= MAX_SHEETS {
-            refresh = false;
-            sheet_index -= MAX_SHEETS;
-        }
-        let string_ptr = ebp as usize + ds_base;
-        let mut cursor_x = esi;
-        let mut i = 0;
-        loop {
-            let chr = unsafe { *((string_ptr + i) as *const u8) };
-            if chr == 0 {
-                break;
-            }
-            print_char_wrapper(
-                console.sheet_manager_addr,
-                sheet_index,
-                chr,
-                to_color(eax as i8).unwrap(),
-                cursor_x as isize,
-                edi as isize,
-            );
-            i += 1;
-            cursor_x += 8;
-        }
-        if refresh {
-            sheet_manager.refresh(sheet_index, esi, edi, esi + ecx * 8, edi + 16);
-        }
-    } else if edx == 7 {
-        let mut sheet_index = ebx as usize;
-        let mut refresh = true;
-        if sheet_index >= MAX_SHEETS {
-            refresh = false;
-            sheet_index -= MAX_SHEETS;
-        }
-        let sheet = sheet_manager.sheets_data[sheet_index];
-        boxfill(
-            sheet.buf_addr,
-            sheet.width as isize,
-            to_color(ebp as i8).unwrap(),
-            eax as isize,
-            ecx as isize,
-            esi as isize,
-            edi as isize,
-        );
-        if refresh {
-            sheet_manager.refresh(sheet_index, eax, ecx, esi + 1, edi + 1);
-        }
-    } else if edx == 8 {
-        let memman = unsafe { &mut *((ebx as usize + ds_base) as *mut MemMan) };
-        *memman = MemMan::new();
-        let bytes = ecx as u32 & 0xfffffff0;
-        memman.free(eax as u32, bytes).unwrap();
-    } else if edx == 9 {
-        let bytes = (ecx as u32 + 0x0f) & 0xfffffff0;
-        let reg_eax = unsafe { &mut *((reg + 7 * 4) as *mut u32) };
-        let memman = unsafe { &mut *((ebx as usize + ds_base) as *mut MemMan) };
-        *reg_eax = memman.alloc(bytes).unwrap();
-    } else if edx == 10 {
-        let bytes = (ecx as u32 + 0x0f) & 0xfffffff0;
-        let memman = unsafe { &mut *((ebx as usize + ds_base) as *mut MemMan) };
-        memman.free(eax as u32, bytes).unwrap();
-    } else if edx == 11 {
-        let mut sheet_index = ebx as usize;
-        let mut refresh = true;
-        if sheet_index >= MAX_SHEETS {
-            refresh = false;
-            sheet_index -= MAX_SHEETS;
-        }
-
-        let sheet = sheet_manager.sheets_data[sheet_index];
-        let ptr = unsafe {
-            &mut *((sheet.buf_addr + sheet.width as usize * edi as usize + esi as usize) as *mut u8)
-        };
-        *ptr = eax as u8;
-        if refresh {
-            sheet_manager.refresh(sheet_index, esi, edi, esi + 1, edi + 1);
-        }
-    } else if edx == 12 {
-        let sheet_index = ebx as usize;
-        sheet_manager.refresh(sheet_index, eax, ecx, esi, edi);
-    } else if edx == 13 {
-        let mut sheet_index = ebx as usize;
-        let mut refresh = true;
-        if sheet_index >= MAX_SHEETS {
-            refresh = false;
-            sheet_index -= MAX_SHEETS;
-        }
-        let sheet = sheet_manager.sheets_data[sheet_index];
-        draw_line(sheet.buf_addr, sheet.width, eax, ecx, esi, edi, ebp);
-        if refresh {
-            let mut eax = eax;
-            let mut esi = esi;
-            if eax > esi {
-                core::mem::swap(&mut eax, &mut esi);
-            }
-            let mut ecx = ecx;
-            let mut edi = edi;
-            if ecx > edi {
-                core::mem::swap(&mut ecx, &mut edi);
-            }
-            sheet_manager.refresh(sheet_index, eax, ecx, esi + 1, edi + 1);
-        }
-    } else if edx == 14 {
-        let sheet_index = ebx as usize;
-        sheet_manager.free(sheet_index);
-    } else if edx == 15 {
-        loop {
-            cli();
-            let fifo = { unsafe { &*(task.fifo_addr as *const Fifo) } };
-            if fifo.status() == 0 {
-                if eax != 0 {
-                    task_manager.sleep(task_index);
-                } else {
-                    sti();
-                    let reg_eax = unsafe { &mut *((reg + 7 * 4) as *mut i32) };
-                    *reg_eax = -1;
-                    return 0;
-                }
-            }
-            let i = fifo.get().unwrap();
-            sti();
-            if i <= 1 {
-                TIMER_MANAGER
-                    .lock()
-                    .init_timer(console.timer_index, task.fifo_addr, 1);
-                TIMER_MANAGER.lock().set_time(console.timer_index, 50);
-            } else if i == 2 {
-                console.cursor_c = Color::White
-            } else if i == 3 {
-                console.cursor_c = Color::Black
-            } else if i == 4 {
-                TIMER_MANAGER.lock().cancel(console.timer_index);
-                cli();
-                let task_a_fifo_addr = unsafe { *(TASK_A_FIFO_ADDR as *const usize) };
-                let task_a_fifo = unsafe { &mut *(task_a_fifo_addr as *mut Fifo) };
-                task_a_fifo
-                    .put(console.sheet_index as u32 + EXIT_ONLY_CONSOLE_OFFSET as u32)
-                    .unwrap();
-                console.sheet_index = 0;
-                sti();
-            } else if 256 <= i {
-                let reg_eax = unsafe { &mut *((reg + 7 * 4) as *mut u32) };
-                *reg_eax = i - 256;
-                return 0;
-            }
-        }
-    } else if edx == 16 {
-        let reg_eax = unsafe { &mut *((reg + 7 * 4) as *mut usize) };
-        {
-            let mut timer_manager = TIMER_MANAGER.lock();
-            let timer_index = timer_manager.alloc().unwrap();
-            let mut timer = &mut timer_manager.timers_data[timer_index];
-            timer.from_app = true;
-            *reg_eax = timer_index;
-        }
-    } else if edx == 17 {
-        TIMER_MANAGER
-            .lock()
-            .init_timer(ebx as usize, task.fifo_addr, eax + 256);
-    } else if edx == 18 {
-        TIMER_MANAGER.lock().set_time(ebx as usize, eax as u32);
-    } else if edx == 19 {
-        TIMER_MANAGER.lock().free(ebx as usize);
-    } else if edx == 20 {
-        if eax == 0 {
-            let i = in8(0x61);
-            out8(0x61, i & 0x0d);
-        } else {
-            let i = 1193180000 / eax;
-            out8(0x43, 0xb6);
-            out8(0x42, i as u8);
-            out8(0x42, (i >> 8) as u8);
-            let i = in8(0x61);
-            out8(0x61, (i | 0x03) & 0x0f);
-        }
-    } else if edx == 21 {
-        let fhandlers =
-            unsafe { &mut *(task.file_handler_addr as *mut [FileHandler; MAX_FILE_HANDLER]) };
-        let mut fhandler: Option<&mut FileHandler> = None;
-        for i in 0..MAX_FILE_HANDLER {
-            if fhandlers[i].buf_addr == 0 {
-                fhandler = Some(&mut fhandlers[i]);
-                break;
-            }
-        }
-        let mut i = 0;
-        loop {
-            let chr = unsafe { *((ebx as usize + i as usize + ds_base) as *const u8) };
-            if chr == 0 {
-                break;
-            }
-            i += 1;
-        }
-        let filename = unsafe { *((ebx as usize + ds_base) as *const [u8; 30]) };
-        let fat = unsafe { *(task.fat_addr as *const [u32; MAX_FAT]) };
-        if let Some(fhandler) = fhandler {
-            let finfo = search_file(&filename[0..i]);
-            let reg_eax = unsafe { &mut *((reg + 7 * 4) as *mut usize) };
-            if let Some(finfo) = finfo {
-                *reg_eax = fhandler as *const FileHandler as usize;
-                fhandler.buf_addr = memman.alloc_4k(finfo.size).unwrap() as usize;
-                fhandler.size = finfo.size as i32;
-                fhandler.pos = 0;
-                finfo.load_file(fhandler.buf_addr, &fat, ADR_DISKIMG + 0x003e00);
-            } else {
-                *reg_eax = 0;
-            }
-        }
-    } else if edx == 22 {
-        let mut fh = unsafe { &mut *(eax as *mut FileHandler) };
-        memman.free_4k(fh.buf_addr as u32, fh.size as u32).unwrap();
-        fh.buf_addr = 0;
-    } else if edx == 23 {
-        let mut fh = unsafe { &mut *(eax as *mut FileHandler) };
-        if ecx == 0 {
-            fh.pos = ebx;
-        } else if ecx == 1 {
-            fh.pos += ebx;
-        } else if ecx == 2 {
-            fh.pos = fh.size + ebx;
-        }
-        if fh.pos < 0 {
-            fh.pos = 0;
-        }
-        if fh.pos > fh.size {
-            fh.pos = fh.size;
-        }
-    } else if edx == 24 {
-        let fh = unsafe { &mut *(eax as *mut FileHandler) };
-        let reg_eax = unsafe { &mut *((reg + 7 * 4) as *mut i32) };
-        if ecx == 0 {
-            *reg_eax = fh.size;
-        } else if ecx == 1 {
-            *reg_eax = fh.pos;
-        } else if ecx == 2 {
-            *reg_eax = fh.pos - fh.size;
-        }
-    } else if edx == 25 {
-        let mut fh = unsafe { &mut *(eax as *mut FileHandler) };
-        let mut size: usize = 0;
-        for i in 0..(ecx as usize) {
-            if fh.pos == fh.size {
-                break;
-            }
-            let ptr = unsafe { &mut *((ebx as usize + ds_base + i) as *mut u8) };
-            let buf = unsafe { &*((fh.buf_addr + fh.pos as usize) as *const u8) };
-            *ptr = *buf;
-            fh.pos += 1;
-            size = i + 1;
-        }
-        let reg_eax = unsafe { &mut *((reg + 7 * 4) as *mut usize) };
-        *reg_eax = size;
-    } else if edx == 26 {
-        let mut i = 0;
-        loop {
-            let ptr = unsafe { &mut *((ebx as usize + ds_base + i) as *mut u8) };
-            let buf = unsafe { &*((task.cmdline_addr + i) as *const u8) };
-            *ptr = *buf;
-            if *buf == 0 {
-                break;
-            }
-            i += 1;
-        }
-        let reg_eax = unsafe { &mut *((reg + 7 * 4) as *mut usize) };
-        *reg_eax = i;
-    } else if edx == 27 {
-        let reg_eax = unsafe { &mut *((reg + 7 * 4) as *mut LangMode) };
-        *reg_eax = task.lang_mode;
-    }
-    0
-}
-
 #[repr(C, packed)]
 pub struct Console {
     pub cursor_x: isize,
@@ -388,10 +142,80 @@ pub struct Console {
     pub sheet_index: usize,
     pub sheet_manager_addr: usize,
     pub timer_index: usize,
+    /// 直前のコマンドの終了ステータス($?)
+    pub status: i32,
+    script_depth: usize,
+    /// スクリプトの中でexitが実行された
+    script_exit: bool,
+    /// 実行中のスクリプトの引数($0から$9)
+    script_args_addr: usize,
+    /// 入力中の行の1文字目があるy座標。スクロールすると上にずれる
+    input_y: isize,
+    /// 1行の文字数と行数
+    columns: usize,
+    rows: usize,
+    /// 表示した文字を覚えておくScrollback
+    scrollback_addr: usize,
+    /// 何行前までさかのぼって表示しているか。0なら最新の画面
+    scroll: usize,
+    /// 文字色と背景色(ANSI_COLORSの番号)
+    fg: u8,
+    bg: u8,
+    /// 太字。暗い文字色を明るくして表示する
+    bold: bool,
+    /// ESC 7やESC [ sで覚えたカーソルの位置
+    saved_cursor_x: isize,
+    saved_cursor_y: isize,
+    /// 出力中のエスケープシーケンスを読むVt100
+    vt100_addr: usize,
+    /// このコンソールから起動したジョブのJobTable
+    jobs_addr: usize,
+}
+
+/// 生きている間は割り込みを止めておく
+/// コンソールのタスクとジョブのタスクが同じConsoleに書くので、表示を更新している途中で
+/// タスクが切り替わって、カーソルやScrollback、Vt100の状態が混ざらないようにする
+struct OutputLock(i32);
+
+impl OutputLock {
+    fn new() -> OutputLock {
+        let eflags = load_eflags();
+        cli();
+        OutputLock(eflags)
+    }
+}
+
+impl Drop for OutputLock {
+    fn drop(&mut self) {
+        store_eflags(self.0);
+    }
+}
+
+/// 文字数からコンソールのウィンドウの大きさを求める
+pub fn console_window_size(columns: usize, rows: usize) -> (i32, i32) {
+    (columns as i32 * 8 + 16, rows as i32 * 16 + 37)
+}
+
+/// 画面に収まる大きさかどうか
+pub fn check_console_size(columns: usize, rows: usize) -> Result<(), &'static str> {
+    let (width, height) = console_window_size(columns, rows);
+    if columns < MIN_CONSOLE_COLUMNS
+        || rows < MIN_CONSOLE_ROWS
+        || width > *SCREEN_WIDTH as i32
+        || height > *SCREEN_HEIGHT as i32
+    {
+        return Err("Bad Console Size");
+    }
+    Ok(())
 }
 
 impl Console {
-    pub fn new(sheet_index: usize, sheet_manager_addr: usize) -> Console {
+    pub fn new(
+        sheet_index: usize,
+        sheet_manager_addr: usize,
+        columns: usize,
+        rows: usize,
+    ) -> Console {
         Console {
             cursor_x: MIN_CURSOR_X,
             cursor_y: MIN_CURSOR_Y,
@@ -400,10 +224,67 @@ impl Console {
             sheet_index,
             sheet_manager_addr,
             timer_index: 0,
+            status: 0,
+            script_depth: 0,
+            script_exit: false,
+            script_args_addr: 0,
+            input_y: MIN_CURSOR_Y,
+            columns,
+            rows,
+            scrollback_addr: 0,
+            scroll: 0,
+            fg: DEFAULT_FG,
+            bg: DEFAULT_BG,
+            bold: false,
+            saved_cursor_x: MIN_CURSOR_X,
+            saved_cursor_y: MIN_CURSOR_Y,
+            vt100_addr: 0,
+            jobs_addr: 0,
+        }
+    }
+
+    fn max_cursor_x(&self) -> isize {
+        8 + self.columns as isize * 8
+    }
+
+    fn max_cursor_y(&self) -> isize {
+        MIN_CURSOR_Y + (self.rows as isize - 1) * 16
+    }
+
+    fn scrollback(&self) -> &'static mut Scrollback {
+        unsafe { &mut *(self.scrollback_addr as *mut Scrollback) }
+    }
+
+    fn vt100(&self) -> &'static mut Vt100 {
+        unsafe { &mut *(self.vt100_addr as *mut Vt100) }
+    }
+
+    fn jobs(&self) -> &'static mut JobTable {
+        unsafe { &mut *(self.jobs_addr as *mut JobTable) }
+    }
+
+    /// 今の(文字色, 背景色)
+    fn colors(&self) -> (Color, Color) {
+        let fg = if self.bold && self.fg < 8 {
+            self.fg + 8
+        } else {
+            self.fg
+        };
+        (ANSI_COLORS[fg as usize], ANSI_COLORS[self.bg as usize])
+    }
+
+    /// 色を元に戻し、途中まで受け取ったエスケープシーケンスを捨てる
+    pub fn reset_attributes(&mut self) {
+        self.fg = DEFAULT_FG;
+        self.bg = DEFAULT_BG;
+        self.bold = false;
+        if self.vt100_addr != 0 {
+            self.vt100().reset();
         }
     }
 
     pub fn show_prompt(&mut self) {
+        let _lock = OutputLock::new();
         let cx = self.cursor_x;
         self.cursor_x = 8;
         self.put_char(b'>', false);
@@ -411,12 +292,24 @@ impl Console {
     }
 
     pub fn put_char(&mut self, chr: u8, move_cursor: bool) {
+        let _lock = OutputLock::new();
+        self.scroll_to_bottom();
+        // 描き直せるように覚えておく
+        let column = ((self.cursor_x - 8) / 8) as usize;
+        let row = ((self.cursor_y - MIN_CURSOR_Y) / 16) as usize;
+        let (fg, bg) = self.colors();
+        if self.scrollback_addr != 0 && column < self.columns && row < self.rows {
+            let scrollback = self.scrollback();
+            let i = scrollback.len() - self.rows + row;
+            scrollback.line_mut(i)[column] = chr;
+            scrollback.attrs_mut(i)[column] = scrollback::attr(fg, bg);
+        }
         let sheet_manager = unsafe { &mut *(self.sheet_manager_addr as *mut SheetManager) };
         let sheet = sheet_manager.sheets_data[self.sheet_index];
         boxfill(
             sheet.buf_addr,
             sheet.width as isize,
-            Color::Black,
+            bg,
             self.cursor_x,
             self.cursor_y,
             self.cursor_x + 8,
@@ -426,7 +319,7 @@ impl Console {
             self.sheet_manager_addr,
             self.sheet_index,
             chr,
-            Color::White,
+            fg,
             self.cursor_x,
             self.cursor_y,
         );
@@ -436,20 +329,25 @@ impl Console {
     }
 
     pub fn newline(&mut self) {
+        let _lock = OutputLock::new();
         let sheet_manager = unsafe { &mut *(self.sheet_manager_addr as *mut SheetManager) };
         let sheet = sheet_manager.sheets_data[self.sheet_index];
         let task_manager = unsafe { &mut *(TASK_MANAGER_ADDR as *mut TaskManager) };
         let task_index = task_manager.now_index();
         let task = &task_manager.tasks_data[task_index];
 
-        if self.cursor_y < MAX_CURSOR_Y {
+        if self.cursor_y < self.max_cursor_y() {
             self.cursor_y += 16;
         } else {
             if self.sheet_index == 0 {
                 return;
             }
-            for y in MIN_CURSOR_Y..MAX_CURSOR_Y {
-                for x in (MIN_CURSOR_X - 8)..MAX_CURSOR_X {
+            self.scroll_to_bottom();
+            self.scrollback().push_line();
+            let max_cursor_x = self.max_cursor_x();
+            let max_cursor_y = self.max_cursor_y();
+            for y in MIN_CURSOR_Y..max_cursor_y {
+                for x in (MIN_CURSOR_X - 8)..max_cursor_x {
                     let x = x as usize;
                     let y = y as usize;
                     // 下の画素をコピーする
@@ -461,8 +359,8 @@ impl Console {
                     }
                 }
             }
-            for y in MAX_CURSOR_Y..(MAX_CURSOR_Y + 16) {
-                for x in (MIN_CURSOR_X - 8)..MAX_CURSOR_X {
+            for y in max_cursor_y..(max_cursor_y + 16) {
+                for x in (MIN_CURSOR_X - 8)..max_cursor_x {
                     let x = x as usize;
                     let y = y as usize;
                     // 最後の行は黒で埋める
@@ -477,8 +375,8 @@ impl Console {
                 self.sheet_index,
                 (MIN_CURSOR_X - 8) as i32,
                 MIN_CURSOR_Y as i32,
-                MAX_CURSOR_X as i32,
-                (MAX_CURSOR_Y + 16) as i32,
+                max_cursor_x as i32,
+                (max_cursor_y + 16) as i32,
             );
         }
         if task.lang_mode == LangMode::JpJis && task.lang_byte1 != 0 {
@@ -486,96 +384,844 @@ impl Console {
         }
     }
 
-    fn run_cmd(&mut self, cmdline: [u8; MAX_CMD], memtotal: usize, fat: &[u32; MAX_FAT]) {
+    /// カーソルを描く。白いときは下にある文字を黒で描く
+    pub fn draw_cursor(&mut self, chr: u8) {
+        let _lock = OutputLock::new();
+        if self.sheet_index == 0 || self.scroll > 0 {
+            return;
+        }
+        let sheet_manager = unsafe { &mut *(self.sheet_manager_addr as *mut SheetManager) };
+        let sheet = sheet_manager.sheets_data[self.sheet_index];
+        boxfill(
+            sheet.buf_addr,
+            sheet.width as isize,
+            self.cursor_c,
+            self.cursor_x,
+            self.cursor_y,
+            self.cursor_x + 7,
+            self.cursor_y + 15,
+        );
+        let fg = if self.cursor_c == Color::White {
+            Color::Black
+        } else {
+            Color::White
+        };
+        print_char_wrapper(
+            self.sheet_manager_addr,
+            self.sheet_index,
+            chr,
+            fg,
+            self.cursor_x,
+            self.cursor_y,
+        );
+    }
+
+    /// 覚えている文字から画面を描き直す
+    fn render(&mut self) {
+        if self.sheet_index == 0 {
+            return;
+        }
+        let sheet_manager = unsafe { &mut *(self.sheet_manager_addr as *mut SheetManager) };
+        let sheet = sheet_manager.sheets_data[self.sheet_index];
+        let max_cursor_x = self.max_cursor_x();
+        let max_cursor_y = self.max_cursor_y();
+        boxfill(
+            sheet.buf_addr,
+            sheet.width as isize,
+            Color::Black,
+            MIN_CURSOR_X - 8,
+            MIN_CURSOR_Y,
+            max_cursor_x - 1,
+            max_cursor_y + 15,
+        );
+        // 全角文字の1バイト目を受け取った途中かもしれないので、描き終わったら戻す
+        let task_manager = unsafe { &mut *(TASK_MANAGER_ADDR as *mut TaskManager) };
+        let task_index = task_manager.now_index();
+        let lang_byte1 = task_manager.tasks_data[task_index].lang_byte1;
+        task_manager.tasks_data[task_index].lang_byte1 = 0;
+        let scrollback = self.scrollback();
+        let top = scrollback.len() - self.rows - self.scroll;
+        for row in 0..self.rows {
+            let line = scrollback.line(top + row);
+            let attrs = scrollback.attrs(top + row);
+            for column in 0..self.columns {
+                let (fg, bg) = scrollback::colors(attrs[column]);
+                let x = 8 + column as isize * 8;
+                let y = MIN_CURSOR_Y + row as isize * 16;
+                if bg != Color::Black {
+                    boxfill(
+                        sheet.buf_addr,
+                        sheet.width as isize,
+                        bg,
+                        x,
+                        y,
+                        x + 7,
+                        y + 15,
+                    );
+                }
+                if line[column] != b' ' {
+                    print_char_wrapper(
+                        self.sheet_manager_addr,
+                        self.sheet_index,
+                        line[column],
+                        fg,
+                        x,
+                        y,
+                    );
+                }
+            }
+        }
+        task_manager.tasks_data[task_index].lang_byte1 = lang_byte1;
+        sheet_manager.refresh(
+            self.sheet_index,
+            (MIN_CURSOR_X - 8) as i32,
+            MIN_CURSOR_Y as i32,
+            max_cursor_x as i32,
+            (max_cursor_y + 16) as i32,
+        );
+    }
+
+    /// さかのぼって表示していたら最新の画面に戻す
+    fn scroll_to_bottom(&mut self) {
+        if self.scroll > 0 {
+            self.scroll = 0;
+            self.render();
+        }
+    }
+
+    /// 正ならさかのぼり、負なら新しい方へ戻る
+    fn scroll_by(&mut self, lines: isize) {
+        if self.sheet_index == 0 {
+            return;
+        }
+        let max = (self.scrollback().len() - self.rows) as isize;
+        let scroll = (self.scroll as isize + lines).max(0).min(max) as usize;
+        if scroll != self.scroll {
+            self.scroll = scroll;
+            self.render();
+        }
+    }
+
+    /// CONSOLE_SCROLL_UPなどを受け取ったらスクロールする
+    pub fn handle_scroll(&mut self, i: u32) {
+        let _lock = OutputLock::new();
+        let page = self.rows as isize - 1;
+        match i {
+            CONSOLE_SCROLL_UP => self.scroll_by(WHEEL_LINES as isize),
+            CONSOLE_SCROLL_DOWN => self.scroll_by(-(WHEEL_LINES as isize)),
+            CONSOLE_PAGE_UP => self.scroll_by(page),
+            CONSOLE_PAGE_DOWN => self.scroll_by(-page),
+            _ => {}
+        }
+    }
+
+    /// ウィンドウの大きさを変えて、覚えている文字から描き直す
+    pub fn resize(&mut self, columns: usize, rows: usize) -> Result<(), &'static str> {
+        let _lock = OutputLock::new();
+        if self.sheet_index == 0 {
+            return Err("No Window");
+        }
+        check_console_size(columns, rows)?;
+        let memman = unsafe { &mut *(MEMMAN_ADDR as *mut MemMan) };
+        let sheet_manager = unsafe { &mut *(self.sheet_manager_addr as *mut SheetManager) };
+        let (width, height) = console_window_size(columns, rows);
+        let buf_addr = memman
+            .alloc_4k((width * height) as u32)
+            .map_err(|_| "Out of Memory")? as usize;
+        let scrollback = self.scrollback();
+        if let Err(e) = scrollback.set_columns(columns) {
+            memman
+                .free_4k(buf_addr as u32, (width * height) as u32)
+                .unwrap();
+            return Err(e);
+        }
+        // カーソルより下の行は捨て、足りなければ空白の行を足す
+        let row = ((self.cursor_y - MIN_CURSOR_Y) / 16) as usize;
+        let current = scrollback.len() - self.rows + row;
+        scrollback.truncate(current + 1);
+        while scrollback.len() < rows {
+            scrollback.push_line();
+        }
+        let row = current + rows - scrollback.len();
+
+        let sheet = sheet_manager.sheets_data[self.sheet_index];
+        // タイトルバーの色でアクティブかどうかを判断する
+        let active = unsafe { *((sheet.buf_addr + 4 * sheet.width as usize + 4) as *const Color) }
+            == Color::DarkBlue;
+        make_window(buf_addr, width as isize, height as isize, "console", active);
+        make_textbox(
+            buf_addr,
+            width as isize,
+            8,
+            28,
+            columns as isize * 8,
+            rows as isize * 16,
+            Color::Black,
+        );
+        sheet_manager.resize(self.sheet_index, buf_addr, width, height);
+        memman
+            .free_4k(sheet.buf_addr as u32, (sheet.width * sheet.height) as u32)
+            .unwrap();
+        self.columns = columns;
+        self.rows = rows;
+        self.scroll = 0;
         self.cursor_x = 8;
-        let cmdline_strs = cmdline.split(|s| *s == 0 || *s == b' ');
-        let mut cmdline_strs = cmdline_strs.skip_while(|cmd| cmd.len() == 0);
-        let cmd = cmdline_strs.next();
-        if cmd.is_none() {
-            self.display_error("Bad Command");
+        self.cursor_y = MIN_CURSOR_Y + row as isize * 16;
+        self.render();
+        Ok(())
+    }
+
+    /// 入力中の行のi文字目にカーソルを移す。画面の下からはみ出すならスクロールする
+    fn move_to_input(&mut self, i: usize) {
+        let column = i + 1; // プロンプトの分
+        let columns = self.columns;
+        let y = |input_y: isize| input_y + (column / columns) as isize * 16;
+        while y(self.input_y) > self.max_cursor_y() && self.sheet_index != 0 {
+            self.cursor_y = self.max_cursor_y();
+            self.newline();
+            self.input_y -= 16;
+        }
+        self.cursor_x = 8 + (column % columns) as isize * 8;
+        self.cursor_y = y(self.input_y);
+    }
+
+    /// 入力中の行をfrom文字目から後ろだけ描き直す
+    /// old_lenは変更前の長さで、短くなった分は空白で消す
+    fn redraw_input(&mut self, editor: &LineEditor, from: usize, old_len: usize) {
+        let _lock = OutputLock::new();
+        let line = editor.as_bytes();
+        for i in from..line.len().max(old_len) {
+            self.move_to_input(i);
+            self.put_char(line.get(i).cloned().unwrap_or(b' '), false);
+        }
+        self.move_to_input(editor.pos());
+    }
+
+    /// カーソルの前の単語を組み込みコマンドとファイル名で補完する
+    /// 候補が1つに決まらなければ共通する部分まで補完し、それ以上伸ばせなければ候補を一覧表示する
+    fn complete(&mut self, editor: &mut LineEditor) {
+        let (start, is_command) = editor.word_before_cursor();
+        let mut word = [0; MAX_CMD];
+        let word_len = editor.pos() - start;
+        word[..word_len].copy_from_slice(&editor.as_bytes()[start..editor.pos()]);
+        let word = &word[..word_len];
+
+        let mut count = 0;
+        let mut common = [0; 12];
+        let mut common_len = 0;
+        for_each_candidate(is_command, |name| {
+            if !starts_with_ignore_case(name, word) {
+                return;
+            }
+            if count == 0 {
+                common[..name.len()].copy_from_slice(name);
+                common_len = name.len();
+            } else {
+                common_len = common[..common_len]
+                    .iter()
+                    .zip(name.iter())
+                    .take_while(|(a, b)| a.eq_ignore_ascii_case(b))
+                    .count();
+            }
+            count += 1;
+        });
+        if count == 0 {
+            return;
+        }
+        let old_len = editor.len();
+        let from = editor.pos();
+        if common_len > word_len || count == 1 {
+            for c in common[word_len..common_len].iter() {
+                editor.insert(*c);
+            }
+            if count == 1 {
+                editor.insert(b' ');
+            }
+            self.redraw_input(editor, from, old_len);
             return;
         }
-        let cmd = cmd.unwrap();
-        let cmd_str = from_utf8(&cmd).unwrap();
-        if cmd_str == "mem" && self.sheet_index != 0 {
+        // 候補を2列で一覧表示して、入力中の行を表示し直す
+        self.move_to_input(old_len);
+        self.cursor_x = 8;
+        self.newline();
+        for_each_candidate(is_command, |name| {
+            if starts_with_ignore_case(name, word) {
+                let mut column = [b' '; COMPLETION_WIDTH];
+                column[..name.len()].copy_from_slice(name);
+                self.put_string(column.as_ptr() as usize, column.len(), None);
+            }
+        });
+        if self.cursor_x != 8 {
+            self.cursor_x = 8;
+            self.newline();
+        }
+        self.show_prompt();
+        self.input_y = self.cursor_y;
+        self.redraw_input(editor, 0, 0);
+    }
+
+    fn run_cmd(&mut self, cmdline: &[u8], memtotal: usize, fat: &mut [u32; MAX_FAT]) {
+        self.cursor_x = 8;
+        let mut expanded = [0; MAX_CMD];
+        let script_args = if self.script_args_addr != 0 {
+            Some(unsafe { &*(self.script_args_addr as *const Args) })
+        } else {
+            None
+        };
+        let cmdline =
+            match script::expand(cmdline, self.env(), self.status, script_args, &mut expanded) {
+                Ok(len) => &expanded[..len],
+                Err(e) => {
+                    self.display_error(e);
+                    return;
+                }
+            };
+        let pipeline = match Pipeline::parse(cmdline) {
+            Ok(pipeline) => pipeline,
+            Err(e) => {
+                self.display_error(e);
+                return;
+            }
+        };
+        match pipeline.iter().next().and_then(|args| args.get(0)) {
+            None => {
+                self.display_error("Bad Command");
+                return;
+            }
+            // startとncstは|や>も含めて新しいコンソールに渡す
+            Some(b"start") => {
+                self.cmd_start(rest_of_cmdline(cmdline), memtotal as u32);
+                return;
+            }
+            Some(b"ncst") => {
+                self.cmd_ncst(rest_of_cmdline(cmdline), memtotal as u32);
+                return;
+            }
+            Some(_) => {}
+        }
+        if pipeline.background() {
+            self.run_background(&pipeline);
+            return;
+        }
+        // コマンドは1つずつ順番に実行する
+        // 標準出力はバッファにためておき、終わったら次のコマンドの標準入力にする
+        // スクリプトの中から呼ばれたときは、最初の入力と最後の出力はスクリプトのものを引き継ぐ
+        let stdio = self.stdio();
+        let mut outer = *stdio;
+        let mut stdin = *outer.stdin();
+        for (i, args) in pipeline.iter().enumerate() {
+            let last = i + 1 == pipeline.len();
+            let inherit_stdin = i == 0 && args.input().is_none();
+            let inherit_stdout = last && args.output().is_none();
+            if let Some(name) = args.input() {
+                if i > 0 {
+                    stdin.free();
+                }
+                stdin = match search_file(name).map(|finfo| Buffer::from_file(&finfo, fat)) {
+                    Some(Ok(buf)) => Stream::Buffer(buf),
+                    Some(Err(e)) => {
+                        *stdio = outer;
+                        self.display_error(e);
+                        return;
+                    }
+                    None => {
+                        *stdio = outer;
+                        self.display_error("File Not Found");
+                        return;
+                    }
+                };
+            }
+            *stdio.stdin() = stdin;
+            *stdio.stdout() = if inherit_stdout {
+                *outer.stdout()
+            } else {
+                Stream::Buffer(Buffer::new())
+            };
+            self.run_args(args, memtotal, fat);
+            // 読んだ位置や書いた大きさが変わっているので、引き継いだものは書き戻す
+            if inherit_stdin {
+                *outer.stdin() = *stdio.stdin();
+            } else {
+                stdio.stdin().free();
+            }
+            let stdout = *stdio.stdout();
+            *stdio = outer;
+            if inherit_stdout {
+                *outer.stdout() = stdout;
+                *stdio = outer;
+                break;
+            }
+            stdin = match (args.output(), stdout) {
+                (Some(name), Stream::Buffer(buf)) => {
+                    let result = write_file(name, buf.as_slice(), fat);
+                    buf.free();
+                    if let Err(e) = result {
+                        self.display_error(e);
+                        return;
+                    }
+                    // ファイルに書き出したので、次のコマンドには何も渡さない
+                    Stream::Buffer(Buffer::new())
+                }
+                (_, stdout) => stdout,
+            };
+            if last {
+                stdin.free();
+            }
+        }
+    }
+
+    fn run_args(&mut self, args: &Args, memtotal: usize, fat: &mut [u32; MAX_FAT]) {
+        let cmd = args.get(0).unwrap_or(b"");
+        let cmdline_strs = args.iter().skip(1);
+        let cmd_str = from_utf8(&cmd).unwrap_or("");
+        self.status = 0;
+        if cmd_str == "mem" {
             self.cmd_mem(memtotal);
         } else if cmd_str == "clear" && self.sheet_index != 0 {
             self.cmd_clear();
-        } else if cmd_str == "ls" && self.sheet_index != 0 {
+        } else if cmd_str == "ls" {
             self.cmd_ls();
-        } else if cmd_str == "start" {
-            self.cmd_start(cmdline_strs, memtotal as u32);
-        } else if cmd_str == "ncst" {
-            self.cmd_ncst(cmdline_strs, memtotal as u32);
+        } else if cmd_str == "dir" {
+            self.cmd_dir(args, fat);
+        } else if cmd_str == "type" || cmd_str == "cat" {
+            self.cmd_type(args, fat);
+        } else if cmd_str == "copy" {
+            self.cmd_copy(args, fat);
+        } else if cmd_str == "del" {
+            self.cmd_del(args, fat);
+        } else if cmd_str == "ren" {
+            self.cmd_ren(args);
+        } else if cmd_str == "mkdir" {
+            self.cmd_mkdir(args, fat);
+        } else if cmd_str == "date" {
+            self.cmd_date();
+        } else if cmd_str == "time" {
+            self.cmd_time();
+        } else if cmd_str == "echo" {
+            self.cmd_echo(cmdline_strs);
+        } else if cmd_str == "test" {
+            self.cmd_test(args);
+        } else if cmd_str == "set" {
+            self.cmd_set(cmdline_strs);
+        } else if cmd_str == "unset" {
+            self.cmd_unset(cmdline_strs);
+        } else if cmd_str == "resize" {
+            self.cmd_resize(args);
+        } else if cmd_str == "console" {
+            self.cmd_console(args, memtotal as u32);
         } else if cmd_str == "langmode" {
             self.cmd_langmode(cmdline_strs);
+        } else if cmd_str == "jobs" {
+            self.cmd_jobs();
+        } else if cmd_str == "fg" {
+            self.cmd_fg(args);
+        } else if cmd_str == "bg" {
+            self.cmd_bg(args);
+        } else if cmd_str == "kill" {
+            self.cmd_kill(args);
+        } else if cmd_str == "dmesg" {
+            self.cmd_dmesg(args);
+        } else if cmd_str == "debug" {
+            self.cmd_debug(args);
+        } else if cmd_str == "exit" && self.script_depth > 0 {
+            // スクリプトの中ではスクリプトだけを終わらせる
+            self.status = args
+                .get(1)
+                .and_then(|s| from_utf8(s).ok())
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0);
+            self.script_exit = true;
         } else if cmd_str == "exit" {
             self.cmd_exit(fat);
         } else {
-            self.cmd_app(&cmd, fat);
+            let finfo = match search_command(cmd) {
+                Some(finfo) => finfo,
+                None => {
+                    self.display_error("Bad Command");
+                    return;
+                }
+            };
+            if finfo.ext == *b"BAT" || finfo.ext == *b"SH " {
+                self.cmd_script(&finfo, args, memtotal, fat);
+                return;
+            }
+            // アプリはジョブのタスクで動かし、終わるまで待つ
+            let task_manager = unsafe { &mut *(TASK_MANAGER_ADDR as *mut TaskManager) };
+            match self
+                .jobs()
+                .start(task_manager, &finfo, args, *self.stdio(), false, false)
+            {
+                Ok(number) => self.wait_job(number),
+                Err(e) => self.display_error(e),
+            }
         }
     }
 
-    pub fn cmd_mem(&mut self, memtotal: usize) {
-        let sheet_manager = unsafe { &mut *(self.sheet_manager_addr as *mut SheetManager) };
-        let sheet = sheet_manager.sheets_data[self.sheet_index];
-        let memman = unsafe { &mut *(MEMMAN_ADDR as *mut MemMan) };
-        write_with_bg!(
-            sheet_manager,
-            self.sheet_index,
-            sheet.width,
-            sheet.height,
-            8,
-            self.cursor_y,
-            Color::White,
-            Color::Black,
-            30,
-            "total   {}MB",
-            memtotal / (1024 * 1024)
-        );
+    /// &をつけたコマンドを裏で動かす。パイプやリダイレクトのない、アプリ1つだけを動かせる
+    fn run_background(&mut self, pipeline: &Pipeline) {
+        let args = pipeline.iter().next().unwrap();
+        let cmd = args.get(0).unwrap_or(b"");
+        if pipeline.len() > 1
+            || args.input().is_some()
+            || args.output().is_some()
+            || BUILTIN_COMMANDS.iter().any(|c| *c == cmd)
+            || self.stdio().streams.iter().any(|s| *s != Stream::Console)
+        {
+            self.display_error("Cannot Run In Background");
+            return;
+        }
+        let finfo = match search_command(cmd) {
+            Some(finfo) if finfo.ext == *b"BAT" || finfo.ext == *b"SH " => {
+                self.display_error("Cannot Run In Background");
+                return;
+            }
+            Some(finfo) => finfo,
+            None => {
+                self.display_error("Bad Command");
+                return;
+            }
+        };
+        let task_manager = unsafe { &mut *(TASK_MANAGER_ADDR as *mut TaskManager) };
+        self.status = 0;
+        match self
+            .jobs()
+            .start(task_manager, &finfo, args, Stdio::new(), true, false)
+        {
+            Ok(number) => {
+                let _ = write!(self, "[{}]\n\n", number);
+            }
+            Err(e) => self.display_error(e),
+        }
+    }
+
+    /// フォアグラウンドのジョブが終わるか止まるまで待つ
+    /// その間にコンソールに来たキーはジョブに渡し、Ctrl+Cで強制終了させ、Ctrl+Zで止める
+    fn wait_job(&mut self, number: usize) {
+        let task_manager = unsafe { &mut *(TASK_MANAGER_ADDR as *mut TaskManager) };
+        let task_index = task_manager.now_index();
+        let fifo_addr = task_manager.tasks_data[task_index].fifo_addr;
+        let fifo = unsafe { &*(fifo_addr as *const Fifo) };
+        let job = match self.jobs().get(number) {
+            Some(job) => job,
+            None => return,
+        };
+        job.background = false;
+        loop {
+            cli();
+            if job.state != JobState::Running {
+                sti();
+                break;
+            }
+            if fifo.status() == 0 {
+                task_manager.sleep(task_index);
+                sti();
+                continue;
+            }
+            let i = fifo.get().unwrap();
+            sti();
+            if i <= 1 && self.sheet_index != 0 {
+                // アプリの実行中はカーソルを点滅させない
+                TIMER_MANAGER
+                    .lock()
+                    .init_timer(self.timer_index, fifo_addr, 1);
+                TIMER_MANAGER.lock().set_time(self.timer_index, 50);
+            } else if i == CONSOLE_CURSOR_ON {
+                self.cursor_c = Color::White;
+                self.cursor_on = true;
+            } else if i == CONSOLE_CURSOR_OFF {
+                self.cursor_c = Color::Black;
+                self.cursor_on = false;
+            } else if i == EXIT_CONSOLE {
+                self.close_window();
+            } else if i == CONSOLE_JOB_DONE {
+                // 裏のジョブは次にプロンプトを出すときに知らせる
+            } else if i >= ALARM_OFFSET {
+                signal::send(task_manager, (i - ALARM_OFFSET) as usize, SIGALRM);
+            } else if KEYBOARD_OFFSET <= i && i <= 511 {
+                let key = i - KEYBOARD_OFFSET;
+                if key == CONSOLE_INTERRUPT {
+                    signal::send(task_manager, job.task_index, SIGINT);
+                } else if key == CONSOLE_SUSPEND && job.is_interactive() {
+                    job.stop(task_manager);
+                } else {
+                    let job_task = &task_manager.tasks_data[job.task_index];
+                    let job_fifo = unsafe { &*(job_task.fifo_addr as *const Fifo) };
+                    let _ = job_fifo.put(i);
+                }
+            } else {
+                self.handle_scroll(i);
+            }
+        }
+        if job.state == JobState::Stopped {
+            job.background = true;
+            self.cursor_x = 8;
+            self.newline();
+            self.show_job(number);
+            self.newline();
+        } else {
+            self.status = job.status;
+            // パイプやリダイレクトのバッファは読んだ位置や書いた大きさが変わっているので書き戻す
+            if !job.is_interactive() {
+                *self.stdio() = job.stdio;
+            }
+            self.jobs().remove(number, task_manager);
+        }
+    }
+
+    /// アプリの実行中にウィンドウが閉じられた
+    /// ウィンドウだけ先に片付け、コンソールはアプリが終わってから終了する
+    fn close_window(&mut self) {
+        let task_a_fifo_addr = unsafe { TASK_A_FIFO_ADDR };
+        let task_a_fifo = unsafe { &mut *(task_a_fifo_addr as *mut Fifo) };
+        TIMER_MANAGER.lock().cancel(self.timer_index);
+        cli();
+        let _ = task_a_fifo.put(self.sheet_index as u32 + EXIT_ONLY_CONSOLE_OFFSET as u32);
+        self.sheet_index = 0;
+        sti();
+    }
+
+    /// [番号] 状態 コマンド の形でジョブを1行表示する
+    fn show_job(&mut self, number: usize) {
+        let job = match self.jobs().get(number) {
+            Some(job) => job,
+            None => return,
+        };
+        let _ = match job.state {
+            JobState::Running => write!(self, "[{}] Running ", number),
+            JobState::Stopped => write!(self, "[{}] Stopped ", number),
+            JobState::Done if job.status == 0 => write!(self, "[{}] Done    ", number),
+            JobState::Done => write!(self, "[{}] Exit {:<3}", number, job.status),
+        };
+        self.write_args(&job.args);
+        if job.background && job.state == JobState::Running {
+            let _ = self.write(b" &");
+        }
+        let _ = self.write(b"\n");
+    }
+
+    /// 引数を空白で区切って書く
+    fn write_args(&mut self, args: &Args) {
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                let _ = self.write(b" ");
+            }
+            let _ = self.write(arg);
+        }
+    }
+
+    /// 裏で動いていて終わったジョブを知らせ、片付ける
+    pub fn report_jobs(&mut self) {
+        let task_manager = unsafe { &mut *(TASK_MANAGER_ADDR as *mut TaskManager) };
+        let mut reported = false;
+        for number in 1..=MAX_JOBS {
+            match self.jobs().get(number) {
+                Some(job) if job.state == JobState::Done => {
+                    self.show_job(number);
+                    self.jobs().remove(number, task_manager);
+                    reported = true;
+                }
+                _ => {}
+            }
+        }
+        if reported {
+            self.newline();
+        }
+    }
+
+    /// argで指定された番号か、省略されていれば番号がいちばん大きいジョブ
+    fn job_number(&self, arg: Option<&[u8]>) -> Result<usize, &'static str> {
+        match arg {
+            Some(arg) => {
+                let arg = if arg.starts_with(b"%") {
+                    &arg[1..]
+                } else {
+                    arg
+                };
+                from_utf8(arg)
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|number| self.jobs().get(*number).is_some())
+                    .ok_or("No Such Job")
+            }
+            None => self.jobs().last().ok_or("No Current Job"),
+        }
+    }
+
+    /// ジョブの一覧を表示する。終わっていたジョブはここで片付ける
+    pub fn cmd_jobs(&mut self) {
+        let task_manager = unsafe { &mut *(TASK_MANAGER_ADDR as *mut TaskManager) };
+        for number in 1..=MAX_JOBS {
+            if let Some(job) = self.jobs().get(number) {
+                self.show_job(number);
+                if job.state == JobState::Done {
+                    self.jobs().remove(number, task_manager);
+                }
+            }
+        }
         self.newline();
-        write_with_bg!(
-            sheet_manager,
-            self.sheet_index,
-            sheet.width,
-            sheet.height,
-            8,
-            self.cursor_y,
-            Color::White,
-            Color::Black,
-            30,
-            "free {}KB",
-            memman.total() / 1024
-        );
+    }
+
+    /// 止まっているか裏で動いているジョブをフォアグラウンドにする
+    pub fn cmd_fg(&mut self, args: &Args) {
+        let number = match self.job_number(args.get(1)) {
+            Ok(number) => number,
+            Err(e) => {
+                self.display_error(e);
+                return;
+            }
+        };
+        let task_manager = unsafe { &mut *(TASK_MANAGER_ADDR as *mut TaskManager) };
+        let job = self.jobs().get(number).unwrap();
+        self.write_args(&job.args);
+        let _ = self.write(b"\n");
+        job.resume(task_manager);
+        self.wait_job(number);
+    }
+
+    /// 止まっているジョブを裏で動かす
+    pub fn cmd_bg(&mut self, args: &Args) {
+        let number = match self.job_number(args.get(1)) {
+            Ok(number) => number,
+            Err(e) => {
+                self.display_error(e);
+                return;
+            }
+        };
+        let task_manager = unsafe { &mut *(TASK_MANAGER_ADDR as *mut TaskManager) };
+        let job = self.jobs().get(number).unwrap();
+        job.background = true;
+        job.resume(task_manager);
+        self.show_job(number);
         self.newline();
+    }
+
+    /// kill [-INT|-TERM|-ALRM|-CLOSE|-KILL] [番号] でジョブにシグナルを送る。省略するとSIGTERM
+    pub fn cmd_kill(&mut self, args: &Args) {
+        let (signal, number) = match args.get(1) {
+            Some(arg) if arg.starts_with(b"-") => {
+                let signal = match &arg[1..] {
+                    b"INT" => SIGINT,
+                    b"TERM" => SIGTERM,
+                    b"ALRM" => SIGALRM,
+                    b"CLOSE" => SIGCLOSE,
+                    b"KILL" => SIGKILL,
+                    _ => {
+                        self.display_error("Bad Signal");
+                        return;
+                    }
+                };
+                (signal, args.get(2))
+            }
+            arg => (SIGTERM, arg),
+        };
+        let number = match self.job_number(number) {
+            Ok(number) => number,
+            Err(e) => {
+                self.display_error(e);
+                return;
+            }
+        };
+        let task_manager = unsafe { &mut *(TASK_MANAGER_ADDR as *mut TaskManager) };
+        let job = self.jobs().get(number).unwrap();
+        signal::send(task_manager, job.task_index, signal);
         self.newline();
     }
 
-    pub fn cmd_clear(&mut self) {
-        let sheet_manager = unsafe { &mut *(self.sheet_manager_addr as *mut SheetManager) };
-        let sheet = sheet_manager.sheets_data[self.sheet_index];
-        for y in MIN_CURSOR_Y..(MAX_CURSOR_Y + 16) {
-            for x in (MIN_CURSOR_X - 8)..MAX_CURSOR_X {
-                let x = x as usize;
-                let y = y as usize;
-                let ptr =
-                    unsafe { &mut *((sheet.buf_addr + x + y * sheet.width as usize) as *mut u8) };
-                *ptr = Color::Black as u8;
+    /// 残っているジョブにSIGTERMを送り、すべて終わるまで待つ
+    fn end_jobs(&mut self) {
+        let task_manager = unsafe { &mut *(TASK_MANAGER_ADDR as *mut TaskManager) };
+        let task_index = task_manager.now_index();
+        let fifo = unsafe { &*(task_manager.tasks_data[task_index].fifo_addr as *const Fifo) };
+        for number in 1..=MAX_JOBS {
+            if let Some(job) = self.jobs().get(number) {
+                signal::send(task_manager, job.task_index, SIGTERM);
             }
         }
-        sheet_manager.refresh(
-            self.sheet_index,
-            (MIN_CURSOR_X - 8) as i32,
-            MIN_CURSOR_Y as i32,
-            MAX_CURSOR_X as i32,
-            (MAX_CURSOR_Y + 16) as i32,
+        loop {
+            cli();
+            if self.jobs().all_done() {
+                sti();
+                break;
+            }
+            if fifo.status() == 0 {
+                task_manager.sleep(task_index);
+            } else {
+                let _ = fifo.get();
+            }
+            sti();
+        }
+        for number in 1..=MAX_JOBS {
+            self.jobs().remove(number, task_manager);
+        }
+    }
+
+    pub fn cmd_mem(&mut self, memtotal: usize) {
+        let memman = unsafe { &mut *(MEMMAN_ADDR as *mut MemMan) };
+        let _ = write!(
+            self,
+            "total   {}MB\nfree {}KB\n\n",
+            memtotal / (1024 * 1024),
+            memman.total() / 1024
         );
+    }
+
+    /// カーネルのログを表示する。dmesg -n LEVEL でためるログのレベルを変える
+    pub fn cmd_dmesg(&mut self, args: &Args) {
+        match (args.get(1), args.get(2)) {
+            (None, _) => {
+                log::read(|data| {
+                    let _ = self.write(data);
+                });
+                self.newline();
+            }
+            (Some(b"-n"), Some(level)) => match Level::parse(level) {
+                Some(level) => log::set_level(level),
+                None => self.display_error("Bad Level"),
+            },
+            _ => self.display_error("Bad Option"),
+        }
+    }
+
+    /// debug アプリ 引数... で、アプリを最初の命令で止めて起動し、COM2につないだGDBを待つ
+    pub fn cmd_debug(&mut self, args: &Args) {
+        if let Err(e) = gdb::check_available() {
+            self.display_error(e);
+            return;
+        }
+        let args = args.shift();
+        let finfo = match args.get(0).and_then(search_command) {
+            Some(finfo) if finfo.ext != *b"BAT" && finfo.ext != *b"SH " => finfo,
+            _ => {
+                self.display_error("Bad Command");
+                return;
+            }
+        };
+        let _ = self.write(b"waiting for gdb on COM2\n");
+        let task_manager = unsafe { &mut *(TASK_MANAGER_ADDR as *mut TaskManager) };
+        match self
+            .jobs()
+            .start(task_manager, &finfo, &args, *self.stdio(), false, true)
+        {
+            Ok(number) => self.wait_job(number),
+            Err(e) => self.display_error(e),
+        }
+    }
+
+    pub fn cmd_clear(&mut self) {
+        let _lock = OutputLock::new();
+        // 今の行から下を捨ててから空白の行で画面を埋める。前の内容はさかのぼって見られる
+        let scrollback = self.scrollback();
+        let row = ((self.cursor_y - MIN_CURSOR_Y) / 16) as usize;
+        scrollback.truncate(scrollback.len() - self.rows + row);
+        for _ in 0..self.rows {
+            scrollback.push_line();
+        }
+        self.scroll = 0;
+        self.render();
         self.cursor_y = MIN_CURSOR_Y;
     }
 
     pub fn cmd_ls(&mut self) {
-        let sheet_manager = unsafe { &mut *(self.sheet_manager_addr as *mut SheetManager) };
-        let sheet = sheet_manager.sheets_data[self.sheet_index];
         for findex in 0..MAX_FILE_INFO {
             let finfo = unsafe {
                 *((ADR_DISKIMG + ADR_FILE_OFFSET + findex * core::mem::size_of::<FileInfo>())
@@ -587,29 +1233,203 @@ impl Console {
             let size = finfo.size;
             if finfo.name[0] != 0xe5 {
                 if (finfo.ftype & 0x18) == 0 {
-                    write_with_bg!(
-                        sheet_manager,
-                        self.sheet_index,
-                        sheet.width,
-                        sheet.height,
-                        8,
-                        self.cursor_y,
-                        Color::White,
-                        Color::Black,
-                        30,
-                        "{:>8}.{:>3}   {:>7}",
+                    let _ = write!(
+                        self,
+                        "{:>8}.{:>3}   {:>7}\n",
                         from_utf8(&finfo.name).unwrap(),
                         from_utf8(&finfo.ext).unwrap(),
                         size
                     );
-                    self.newline();
                 }
             }
         }
+        let _ = self.write(b"\n");
+    }
+
+    /// パターンに当てはまるファイルを日時や属性と一緒に表示する
+    pub fn cmd_dir(&mut self, args: &Args, fat: &[u32; MAX_FAT]) {
+        let mut files = 0;
+        let mut dirs = 0;
+        let mut bytes = 0;
+        for finfo in find_files(b"*") {
+            if args.len() > 1 && !args.iter().skip(1).any(|p| finfo.matches(p)) {
+                continue;
+            }
+            let (name, len) = finfo.file_name();
+            let _ = self.write(&name[..len]);
+            let _ = self.write(&[b' '; 12][len..]);
+            if finfo.is_dir() {
+                let _ = write!(self, "{:>8}", "<DIR>");
+                dirs += 1;
+            } else {
+                let size = finfo.size;
+                let _ = write!(self, "{:>8}", size);
+                files += 1;
+                bytes += size as usize;
+            }
+            let mut attrs = *b"----";
+            for (i, attr) in [ATTR_READ_ONLY, ATTR_HIDDEN, ATTR_SYSTEM, ATTR_ARCHIVE]
+                .iter()
+                .enumerate()
+            {
+                if finfo.ftype & attr != 0 {
+                    attrs[i] = b"RHSA"[i];
+                }
+            }
+            let (year, month, day, hour, minute) = finfo.datetime();
+            let _ = write!(
+                self,
+                " {}/{:>02}/{:>02} {:>02}:{:>02} {}\n",
+                year,
+                month,
+                day,
+                hour,
+                minute,
+                from_utf8(&attrs).unwrap()
+            );
+        }
+        if files + dirs == 0 && args.len() > 1 {
+            self.display_error("File Not Found");
+            return;
+        }
+        let _ = write!(
+            self,
+            "{} files {} bytes\n{} bytes free\n\n",
+            files,
+            bytes,
+            free_space(fat)
+        );
+    }
+
+    /// ファイルの中身を出力する。ファイル名がなければ標準入力をそのまま出力する
+    pub fn cmd_type(&mut self, args: &Args, fat: &[u32; MAX_FAT]) {
+        if args.len() < 2 {
+            if let Stream::Buffer(buf) = self.stdio().stdin() {
+                let result = self.write(&buf.as_slice()[buf.pos..]);
+                buf.pos = buf.size;
+                if let Err(e) = result {
+                    self.display_error(e);
+                }
+            } else {
+                self.display_error("Usage: type FILE...");
+            }
+            return;
+        }
+        for pattern in args.iter().skip(1) {
+            let mut found = false;
+            for finfo in find_files(pattern).filter(|finfo| !finfo.is_dir()) {
+                found = true;
+                let result = Buffer::from_file(&finfo, fat).and_then(|buf| {
+                    let result = self.write(buf.as_slice());
+                    buf.free();
+                    result
+                });
+                if let Err(e) = result {
+                    self.display_error(e);
+                    return;
+                }
+            }
+            if !found {
+                self.display_error("File Not Found");
+            }
+        }
+    }
+
+    pub fn cmd_copy(&mut self, args: &Args, fat: &mut [u32; MAX_FAT]) {
+        if args.len() != 3 {
+            self.display_error("Usage: copy FROM TO");
+            return;
+        }
+        let finfo = match search_file(args.get(1).unwrap()) {
+            Some(finfo) => finfo,
+            None => {
+                self.display_error("File Not Found");
+                return;
+            }
+        };
+        // 読み込んでから書くので、同じ名前に上書きしても壊れない
+        let result = Buffer::from_file(&finfo, fat).and_then(|buf| {
+            let result = write_file(args.get(2).unwrap(), buf.as_slice(), fat);
+            buf.free();
+            result
+        });
+        match result {
+            Ok(_) => self.newline(),
+            Err(e) => self.display_error(e),
+        }
+    }
+
+    /// パターンに当てはまるファイルを消す
+    pub fn cmd_del(&mut self, args: &Args, fat: &mut [u32; MAX_FAT]) {
+        if args.len() < 2 {
+            self.display_error("Usage: del FILE...");
+            return;
+        }
+        for pattern in args.iter().skip(1) {
+            let mut found = false;
+            for finfo in find_files(pattern).filter(|finfo| !finfo.is_dir()) {
+                found = true;
+                let (name, len) = finfo.file_name();
+                if let Err(e) = delete_file(&name[..len], fat) {
+                    self.display_error(e);
+                    return;
+                }
+            }
+            if !found {
+                self.display_error("File Not Found");
+                return;
+            }
+        }
+        self.newline();
+    }
+
+    pub fn cmd_ren(&mut self, args: &Args) {
+        if args.len() != 3 {
+            self.display_error("Usage: ren FROM TO");
+            return;
+        }
+        match rename_file(args.get(1).unwrap(), args.get(2).unwrap()) {
+            Ok(_) => self.newline(),
+            Err(e) => self.display_error(e),
+        }
+    }
+
+    pub fn cmd_mkdir(&mut self, args: &Args, fat: &mut [u32; MAX_FAT]) {
+        if args.len() < 2 {
+            self.display_error("Usage: mkdir DIR...");
+            return;
+        }
+        for name in args.iter().skip(1) {
+            if let Err(e) = make_dir(name, fat) {
+                self.display_error(e);
+                return;
+            }
+        }
         self.newline();
     }
 
+    pub fn cmd_date(&mut self) {
+        let now = rtc::now();
+        let _ = write!(self, "{}/{:>02}/{:>02}\n\n", now.year, now.month, now.day);
+    }
+
+    pub fn cmd_time(&mut self) {
+        let now = rtc::now();
+        let _ = write!(
+            self,
+            "{:>02}:{:>02}:{:>02}\n\n",
+            now.hour, now.minute, now.second
+        );
+    }
+
     fn display_error(&mut self, error_message: &'static str) {
+        self.status = 1;
+        self.put_error(error_message);
+    }
+
+    /// エラーを画面に表示する。$?は変えない
+    pub fn put_error(&mut self, error_message: &'static str) {
+        let _lock = OutputLock::new();
         if self.sheet_index != 0 {
             self.put_string(
                 error_message.as_bytes().as_ptr() as usize,
@@ -621,47 +1441,327 @@ impl Console {
         self.newline();
     }
 
-    pub fn cmd_start<'a>(&mut self, cmdline_strs: impl Iterator<Item = &'a [u8]>, memtotal: u32) {
-        let mut cmd = cmdline_strs.skip_while(|strs| strs.len() == 0);
-        let cmd = cmd.next();
-        if cmd.is_none() {
+    pub fn cmd_start(&mut self, cmdline: &[u8], memtotal: u32) {
+        if cmdline.is_empty() {
             self.display_error("Command Not Found");
             return;
         }
-        let cmd = cmd.unwrap();
+        if cmdline.len() >= CONSOLE_FIFO_SIZE {
+            self.display_error("Command Too Long");
+            return;
+        }
         let sheet_manager = unsafe { &mut *(self.sheet_manager_addr as *mut SheetManager) };
         let task_manager = unsafe { &mut *(TASK_MANAGER_ADDR as *mut TaskManager) };
-        let sheet_index = open_console(sheet_manager, task_manager, memtotal);
+        let sheet_index = open_console(
+            sheet_manager,
+            task_manager,
+            memtotal,
+            CONSOLE_COLUMNS,
+            CONSOLE_ROWS,
+        );
         let task = &task_manager.tasks_data[sheet_manager.sheets_data[sheet_index].task_index];
         let fifo = unsafe { &mut *(task.fifo_addr as *mut Fifo) };
         sheet_manager.slide(sheet_index, 32, 4);
         sheet_manager.updown(sheet_index, sheet_manager.z_max);
-        for ci in 0..cmd.len() {
-            fifo.put(cmd[ci] as u32 + 256).unwrap();
+        for ci in 0..cmdline.len() {
+            fifo.put(cmdline[ci] as u32 + 256).unwrap();
         }
         fifo.put(10 + 256).unwrap(); // Enter
         self.newline();
     }
 
-    pub fn cmd_ncst<'a>(&mut self, cmdline_strs: impl Iterator<Item = &'a [u8]>, memtotal: u32) {
+    pub fn cmd_resize(&mut self, args: &Args) {
+        let result = parse_console_size(args).and_then(|size| match size {
+            Some((columns, rows)) => self.resize(columns, rows),
+            None => Err("Usage: resize COLUMNS ROWS"),
+        });
+        if let Err(e) = result {
+            self.display_error(e);
+        }
+    }
+
+    /// 指定した大きさ(省略したら30x8)の新しいコンソールを開く
+    pub fn cmd_console(&mut self, args: &Args, memtotal: u32) {
+        let (columns, rows) = match parse_console_size(args) {
+            Ok(size) => size.unwrap_or((CONSOLE_COLUMNS, CONSOLE_ROWS)),
+            Err(e) => {
+                self.display_error(e);
+                return;
+            }
+        };
+        if let Err(e) = check_console_size(columns, rows) {
+            self.display_error(e);
+            return;
+        }
+        let sheet_manager = unsafe { &mut *(self.sheet_manager_addr as *mut SheetManager) };
+        let task_manager = unsafe { &mut *(TASK_MANAGER_ADDR as *mut TaskManager) };
+        let sheet_index = open_console(sheet_manager, task_manager, memtotal, columns, rows);
+        sheet_manager.slide(sheet_index, 32, 4);
+        sheet_manager.updown(sheet_index, sheet_manager.z_max);
+        self.newline();
+    }
+
+    pub fn cmd_ncst(&mut self, cmdline: &[u8], memtotal: u32) {
         let task_manager = unsafe { &mut *(TASK_MANAGER_ADDR as *mut TaskManager) };
-        let mut cmd = cmdline_strs.skip_while(|strs| strs.len() == 0);
-        let cmd = cmd.next();
-        if cmd.is_none() {
+        if cmdline.is_empty() {
             self.display_error("Command Not Found");
             return;
         }
-        let cmd = cmd.unwrap();
+        if cmdline.len() >= CONSOLE_FIFO_SIZE {
+            self.display_error("Command Too Long");
+            return;
+        }
         let task_index = open_console_task(task_manager, 0, memtotal);
         let task = &task_manager.tasks_data[task_index];
         let fifo = unsafe { &mut *(task.fifo_addr as *mut Fifo) };
-        for ci in 0..cmd.len() {
-            fifo.put(cmd[ci] as u32 + 256).unwrap();
+        for ci in 0..cmdline.len() {
+            fifo.put(cmdline[ci] as u32 + 256).unwrap();
         }
         fifo.put(10 + 256).unwrap(); // Enter
         self.newline();
     }
 
+    pub fn cmd_set<'a>(&mut self, mut cmdline_strs: impl Iterator<Item = &'a [u8]>) {
+        let env = self.env();
+        match cmdline_strs.next() {
+            None => {
+                // 一覧表示
+                for (name, value) in env.iter() {
+                    let _ = self.write(name);
+                    let _ = self.write(b"=");
+                    let _ = self.write(value);
+                    let _ = self.write(b"\n");
+                }
+                let _ = self.write(b"\n");
+            }
+            Some(var) => {
+                let (name, value) = match var.iter().position(|c| *c == b'=') {
+                    Some(i) => (&var[..i], &var[(i + 1)..]),
+                    None => {
+                        self.display_error("Usage: set NAME=VALUE");
+                        return;
+                    }
+                };
+                match env.set(name, value) {
+                    Ok(_) => self.newline(),
+                    Err(e) => self.display_error(e),
+                }
+            }
+        }
+    }
+
+    pub fn cmd_unset<'a>(&mut self, mut cmdline_strs: impl Iterator<Item = &'a [u8]>) {
+        match cmdline_strs.next() {
+            Some(name) => {
+                self.env().unset(name);
+                self.newline();
+            }
+            None => self.display_error("Usage: unset NAME"),
+        }
+    }
+
+    fn env(&self) -> &'static mut Env {
+        let task_manager = unsafe { &mut *(TASK_MANAGER_ADDR as *mut TaskManager) };
+        let task_index = task_manager.now_index();
+        unsafe { &mut *(task_manager.tasks_data[task_index].env_addr as *mut Env) }
+    }
+
+    fn stdio(&self) -> &'static mut Stdio {
+        let task_manager = unsafe { &mut *(TASK_MANAGER_ADDR as *mut TaskManager) };
+        let task_index = task_manager.now_index();
+        unsafe { &mut *(task_manager.tasks_data[task_index].stdio_addr as *mut Stdio) }
+    }
+
+    /// 標準出力に書く。コンソールにつながっていれば画面に表示する
+    pub fn write(&mut self, data: &[u8]) -> Result<(), &'static str> {
+        match self.stdio().stdout() {
+            Stream::Console => {
+                self.put_string(data.as_ptr() as usize, data.len(), None);
+                Ok(())
+            }
+            Stream::Buffer(buf) => buf.write(data),
+        }
+    }
+
+    /// コマンドを実行し、標準出力に書かれたものを受け取る。エラーは画面に表示される
+    #[cfg(feature = "integration-test")]
+    pub fn run_captured(
+        &mut self,
+        cmdline: &[u8],
+        memtotal: usize,
+        fat: &mut [u32; MAX_FAT],
+    ) -> Buffer {
+        let stdout = *self.stdio().stdout();
+        *self.stdio().stdout() = Stream::Buffer(Buffer::new());
+        self.run_cmd(cmdline, memtotal, fat);
+        match core::mem::replace(self.stdio().stdout(), stdout) {
+            Stream::Buffer(buf) => buf,
+            Stream::Console => Buffer::new(),
+        }
+    }
+
+    pub fn cmd_echo<'a>(&mut self, cmdline_strs: impl Iterator<Item = &'a [u8]>) {
+        for (i, s) in cmdline_strs.enumerate() {
+            if i > 0 {
+                let _ = self.write(b" ");
+            }
+            let _ = self.write(s);
+        }
+        let _ = self.write(b"\n");
+    }
+
+    /// 条件が成り立てば終了ステータスを0、成り立たなければ1にする
+    pub fn cmd_test(&mut self, args: &Args) {
+        let arg = |i| args.get(i).unwrap_or(b"");
+        let int = |i| from_utf8(arg(i)).ok().and_then(|s| s.parse::<i32>().ok());
+        let result = match args.len() {
+            2 => Some(!arg(1).is_empty()),
+            3 if arg(1) == b"-n" => Some(!arg(2).is_empty()),
+            3 if arg(1) == b"-z" => Some(arg(2).is_empty()),
+            3 if arg(1) == b"-e" => Some(search_file(arg(2)).is_some()),
+            4 if arg(2) == b"=" => Some(arg(1) == arg(3)),
+            4 if arg(2) == b"!=" => Some(arg(1) != arg(3)),
+            4 => match (int(1), arg(2), int(3)) {
+                (Some(a), b"-eq", Some(b)) => Some(a == b),
+                (Some(a), b"-ne", Some(b)) => Some(a != b),
+                (Some(a), b"-lt", Some(b)) => Some(a < b),
+                (Some(a), b"-le", Some(b)) => Some(a <= b),
+                (Some(a), b"-gt", Some(b)) => Some(a > b),
+                (Some(a), b"-ge", Some(b)) => Some(a >= b),
+                _ => None,
+            },
+            _ => None,
+        };
+        match result {
+            Some(true) => self.status = 0,
+            Some(false) => self.status = 1,
+            None => {
+                self.display_error("Bad Test Expression");
+                self.status = 2;
+            }
+        }
+    }
+
+    pub fn cmd_script(
+        &mut self,
+        finfo: &FileInfo,
+        args: &Args,
+        memtotal: usize,
+        fat: &mut [u32; MAX_FAT],
+    ) {
+        if self.script_depth == MAX_SCRIPT_DEPTH {
+            self.display_error("Script Nested Too Deeply");
+            return;
+        }
+        let buf = match Buffer::from_file(finfo, fat) {
+            Ok(buf) => buf,
+            Err(e) => {
+                self.display_error(e);
+                return;
+            }
+        };
+        match Script::parse(buf.as_slice()) {
+            Ok(script) => {
+                let script_args_addr = self.script_args_addr;
+                self.script_args_addr = args as *const Args as usize;
+                self.script_depth += 1;
+                self.run_script(&script, memtotal, fat);
+                self.script_depth -= 1;
+                self.script_args_addr = script_args_addr;
+                self.script_exit = false;
+            }
+            Err(e) => self.display_error(e),
+        }
+        buf.free();
+    }
+
+    fn run_script(&mut self, script: &Script, memtotal: usize, fat: &mut [u32; MAX_FAT]) {
+        // 実行中のforの(行, 何回目か)
+        let mut loops = [(0, 0); MAX_NEST];
+        let mut depth = 0;
+        let mut pc = 0;
+        while pc < script.len() && !self.script_exit {
+            let line = script.line(pc);
+            match script.kind(pc) {
+                Line::Empty => pc += 1,
+                Line::Command => {
+                    self.run_cmd(line, memtotal, fat);
+                    pc += 1;
+                }
+                Line::If => {
+                    let cond = rest_of_cmdline(line);
+                    let (negate, cond) = match cond.split_first() {
+                        Some((b'!', rest)) => (true, rest),
+                        _ => (false, cond),
+                    };
+                    self.run_cmd(cond, memtotal, fat);
+                    if (self.status == 0) != negate {
+                        pc += 1;
+                    } else {
+                        pc = script.jump(pc) + 1;
+                    }
+                }
+                // ifの中を実行し終わったのでendの次へ
+                Line::Else => pc = script.jump(pc) + 1,
+                Line::End => {
+                    let start = script.jump(pc);
+                    if script.kind(start) == Line::For {
+                        pc = start;
+                    } else {
+                        pc += 1;
+                    }
+                }
+                Line::For => {
+                    if depth == 0 || loops[depth - 1].0 != pc {
+                        loops[depth] = (pc, 0);
+                        depth += 1;
+                    } else {
+                        loops[depth - 1].1 += 1;
+                    }
+                    let mut expanded = [0; MAX_CMD];
+                    let script_args = unsafe { &*(self.script_args_addr as *const Args) };
+                    let words = script::expand(
+                        line,
+                        self.env(),
+                        self.status,
+                        Some(script_args),
+                        &mut expanded,
+                    )
+                    .and_then(|len| Pipeline::parse(&expanded[..len]));
+                    let words = match words {
+                        Ok(ref pipeline) if pipeline.len() == 1 => pipeline.iter().next().unwrap(),
+                        Ok(_) => {
+                            self.display_error("Usage: for NAME in WORDS...");
+                            return;
+                        }
+                        Err(e) => {
+                            self.display_error(e);
+                            return;
+                        }
+                    };
+                    let name = words.get(1).unwrap_or(b"");
+                    if words.get(2) != Some(&b"in"[..]) {
+                        self.display_error("Usage: for NAME in WORDS...");
+                        return;
+                    }
+                    match words.get(3 + loops[depth - 1].1) {
+                        Some(word) => {
+                            if let Err(e) = self.env().set(name, word) {
+                                self.display_error(e);
+                                return;
+                            }
+                            pc += 1;
+                        }
+                        None => {
+                            depth -= 1;
+                            pc = script.jump(pc) + 1;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
     pub fn cmd_langmode<'a>(&mut self, cmdline_strs: impl Iterator<Item = &'a [u8]>) {
         let mut cmd = cmdline_strs.skip_while(|strs| strs.len() == 0);
         let cmd = cmd.next();
@@ -678,13 +1778,15 @@ impl Console {
     }
 
     pub fn cmd_exit(&mut self, fat: &[u32; MAX_FAT]) {
+        self.end_jobs();
         let memman = unsafe { &mut *(MEMMAN_ADDR as *mut MemMan) };
-        let task_a_fifo_addr = unsafe { *(TASK_A_FIFO_ADDR as *const usize) };
+        let task_a_fifo_addr = unsafe { TASK_A_FIFO_ADDR };
         let task_a_fifo = unsafe { &mut *(task_a_fifo_addr as *mut Fifo) };
         let task_manager = unsafe { &mut *(TASK_MANAGER_ADDR as *mut TaskManager) };
         let task_index = task_manager.now_index();
         TIMER_MANAGER.lock().cancel(self.timer_index);
         memman.free_4k(fat.as_ptr() as u32, 4 * 2880).unwrap();
+        self.scrollback().free();
         cli();
         if self.sheet_index != 0 {
             task_a_fifo
@@ -701,74 +1803,100 @@ impl Console {
         }
     }
 
-    pub fn cmd_app<'a>(&mut self, filename: &'a [u8], fat: &[u32; MAX_FAT]) {
+    /// アプリを今のタスクで実行し、終わるまで戻らない。ジョブのタスクから呼ばれる
+    /// 読み込めなかったときはそのエラーを返す
+    pub fn cmd_app(&mut self, finfo: &FileInfo, fat: &[u32; MAX_FAT]) -> Result<(), &'static str> {
         let memman = unsafe { &mut *(MEMMAN_ADDR as *mut MemMan) };
-        let mut finfo = search_file(filename);
-        if finfo.is_none() && filename.len() > 1 && filename[filename.len() - 2] != b'.' {
-            let mut filename_ext = [b' '; MAX_CMD + 4];
-            let filename_ext = &mut filename_ext[0..(filename.len() + 4)];
-            filename_ext[..filename.len()].copy_from_slice(filename);
-            filename_ext[filename.len()] = b'.';
-            filename_ext[filename.len() + 1] = b'h';
-            filename_ext[filename.len() + 2] = b'r';
-            filename_ext[filename.len() + 3] = b'b';
-            finfo = search_file(filename_ext);
-        }
-        if finfo.is_none() {
-            self.display_error("Bad Command");
-            return;
-        }
-        let finfo = finfo.unwrap();
-        let content_addr = memman.alloc_4k(finfo.size).unwrap() as usize;
-        finfo.load_file(content_addr, fat, ADR_DISKIMG + 0x003e00);
+        let content_addr = memman.alloc_4k(finfo.size).map_err(|_| "Out of Memory")? as usize;
+        load_file(finfo, content_addr, fat);
 
         let task_manager = unsafe { &mut *(TASK_MANAGER_ADDR as *mut TaskManager) };
         let task_index = task_manager.now_index();
 
-        // kernel.ldを使ってリンクされたファイルのみ実行可能
+        // kernel.ldを使ってリンクされた.hrbファイルか、静的リンクされたELFファイルを実行できる
+        let content =
+            unsafe { core::slice::from_raw_parts(content_addr as *const u8, finfo.size as usize) };
+        let mut runnable = false;
+        let mut error_message = "Bad Format";
         let mut app_eip = 0;
         let mut app_mem_addr = 0;
         let mut segment_size = 0;
         let mut esp = 0;
-        if finfo.size >= 8 {
-            // 4から7バイト目で判定
-            let bytes = unsafe { *((content_addr + 4) as *const [u8; 4]) };
-            if bytes == *b"Hari" {
-                app_eip = 0x1b;
-                segment_size = unsafe { *((content_addr + 0x0000) as *const usize) };
-                esp = unsafe { *((content_addr + 0x000c) as *const usize) };
-                let data_size = unsafe { *((content_addr + 0x0010) as *const usize) };
-                let content_data_addr = unsafe { *((content_addr + 0x0014) as *const usize) };
-
-                app_mem_addr = memman.alloc_4k(segment_size as u32).unwrap() as usize;
-                {
-                    let mut task = &mut task_manager.tasks_data[task_index];
-                    task.ds_base = app_mem_addr;
-                    task.ldt[0] = SegmentDescriptor::new(
-                        finfo.size - 1,
-                        content_addr as i32,
-                        AR_CODE32_ER + 0x60,
-                    );
-                    task.ldt[1] = SegmentDescriptor::new(
-                        segment_size as u32 - 1,
-                        app_mem_addr as i32,
-                        AR_DATA32_RW + 0x60,
-                    );
-                    task.lang_byte1 = 0;
+        let mut heap = (0, 0);
+        let mut code_addr = content_addr;
+        let mut code_size = finfo.size as usize;
+        if Hrb::is_hrb(content) {
+            match Hrb::parse(content) {
+                Ok(hrb) => {
+                    segment_size = hrb.segment_size();
+                    if let Ok(addr) = memman.alloc_4k(segment_size as u32) {
+                        app_mem_addr = addr as usize;
+                        let segment = unsafe {
+                            core::slice::from_raw_parts_mut(app_mem_addr as *mut u8, segment_size)
+                        };
+                        hrb.load(segment);
+                        app_eip = hrb.entry();
+                        esp = hrb.esp();
+                        heap = (hrb.heap_addr(), hrb.heap_size());
+                        runnable = true;
+                    } else {
+                        error_message = "Out of Memory";
+                    }
                 }
-
-                for i in 0..data_size {
-                    let app_ptr = unsafe { &mut *((app_mem_addr + esp + i) as *mut u8) };
-                    *app_ptr = unsafe { *((content_addr + content_data_addr + i) as *const u8) };
+                Err(e) => error_message = e,
+            }
+        } else if Elf::is_elf(content) {
+            match Elf::parse(content) {
+                Ok(elf) => {
+                    // コードもデータも同じセグメントに読み込む
+                    segment_size = elf.segment_size();
+                    if let Ok(addr) = memman.alloc_4k(segment_size as u32) {
+                        app_mem_addr = addr as usize;
+                        let segment = unsafe {
+                            core::slice::from_raw_parts_mut(app_mem_addr as *mut u8, segment_size)
+                        };
+                        elf.load(segment);
+                        app_eip = elf.entry();
+                        esp = segment_size;
+                        heap = (elf.heap_addr(), elf.heap_size());
+                        code_addr = app_mem_addr;
+                        code_size = segment_size;
+                        runnable = true;
+                    } else {
+                        error_message = "Out of Memory";
+                    }
                 }
+                Err(e) => error_message = e,
             }
         }
 
-        if app_eip > 0 {
-            let task = &task_manager.tasks_data[task_index];
+        if runnable {
+            let mut task = &mut task_manager.tasks_data[task_index];
+            task.ds_base = app_mem_addr;
+            task.ds_limit = segment_size;
+            task.heap_addr = heap.0;
+            task.heap_size = heap.1;
+            task.ldt[0] =
+                SegmentDescriptor::new(code_size as u32 - 1, code_addr as i32, AR_CODE32_ER + 0x60);
+            task.ldt[1] = SegmentDescriptor::new(
+                segment_size as u32 - 1,
+                app_mem_addr as i32,
+                AR_DATA32_RW + 0x60,
+            );
+            task.lang_byte1 = 0;
+            let job = unsafe { &*(task.job_addr as *const Job) };
+            if job.debug {
+                gdb::break_at_entry(code_addr + app_eip);
+            }
             let esp0_addr = unsafe { &(task.tss.esp0) } as *const i32 as usize;
             unsafe {
-                _start_app(app_eip, 0 * 8 + 4, esp as i32, 1 * 8 + 4, esp0_addr as i32);
+                _start_app(
+                    app_eip as i32,
+                    0 * 8 + 4,
+                    esp as i32,
+                    1 * 8 + 4,
+                    esp0_addr as i32,
+                );
             }
             {
                 let sheet_manager = unsafe { &mut *(self.sheet_manager_addr as *mut SheetManager) };
@@ -783,21 +1911,21 @@ impl Console {
                 }
             }
             // クローズしていないファイルをクローズ
-            let fhandlers =
-                unsafe { &mut *(task.file_handler_addr as *mut [FileHandler; MAX_FILE_HANDLER]) };
-            for i in 0..8 {
-                let mut fhandler = &mut fhandlers[i];
-                if fhandler.buf_addr != 0 {
+            let handles = unsafe { &mut *(task.handle_table_addr as *mut HandleTable) };
+            for handle in handles.handles.iter() {
+                if let Handle::File(fhandler) = handle {
                     memman
                         .free_4k(fhandler.buf_addr as u32, fhandler.size as u32)
                         .unwrap();
-                    fhandler.buf_addr = 0;
                 }
             }
+            *handles = HandleTable::new();
             TIMER_MANAGER.lock().cancel_all(task.fifo_addr);
-            self.newline();
-        } else {
-            self.display_error("Bad Format");
+            // 色を戻さずに終わっても、プロンプトはいつもの色で表示する
+            self.reset_attributes();
+            if *self.stdio().stdout() == Stream::Console {
+                self.newline();
+            }
         }
         memman.free_4k(content_addr as u32, finfo.size).unwrap();
         if app_mem_addr > 0 {
@@ -805,6 +1933,11 @@ impl Console {
                 .free_4k(app_mem_addr as u32, segment_size as u32)
                 .unwrap();
         }
+        if runnable {
+            Ok(())
+        } else {
+            Err(error_message)
+        }
     }
 
     pub fn put_string(
@@ -813,75 +1946,359 @@ impl Console {
         string_length: usize,
         initial_x: Option<usize>,
     ) {
+        let _lock = OutputLock::new();
         if initial_x.is_some() {
             self.cursor_x = initial_x.unwrap() as isize
         }
         for x in 0..string_length {
             let chr = unsafe { *((string_addr + x as usize) as *const u8) };
-            if chr == 0x09 {
-                // タブ
-                loop {
-                    if self.sheet_index != 0 {
-                        self.put_char(b' ', true);
-                    }
-                    if self.cursor_x == MAX_CURSOR_X {
-                        self.cursor_x = 8;
-                        self.newline();
-                    }
-                    if (self.cursor_x - 8) & 0x1f == 0 {
-                        // 32で割り切れたらbreak
-                        break;
-                    }
+            match self.vt100().feed(chr) {
+                Action::None => {}
+                Action::Print(chr) => self.print_byte(chr),
+                Action::Csi(csi) => self.handle_csi(&csi),
+                Action::SaveCursor => self.save_cursor(),
+                Action::RestoreCursor => self.restore_cursor(),
+                Action::Reset => {
+                    self.reset_attributes();
+                    self.erase_display(2);
+                    self.move_cursor(0, 0);
                 }
-            } else if chr == 0x0a {
-                // 改行
-                self.cursor_x = 8;
-                self.newline();
-            } else if chr == 0x0d {
-                // 復帰
-                // 何もしない
-            } else {
+            }
+        }
+    }
+
+    /// エスケープシーケンス以外の1バイトを表示する
+    fn print_byte(&mut self, chr: u8) {
+        if chr == 0x09 {
+            // タブ
+            loop {
                 if self.sheet_index != 0 {
-                    self.put_char(chr, true);
+                    self.put_char(b' ', true);
                 }
-                if self.cursor_x == MAX_CURSOR_X {
+                if self.cursor_x == self.max_cursor_x() {
                     self.cursor_x = 8;
                     self.newline();
                 }
+                if (self.cursor_x - 8) & 0x1f == 0 {
+                    // 32で割り切れたらbreak
+                    break;
+                }
+            }
+        } else if chr == 0x0a {
+            // 改行
+            self.cursor_x = 8;
+            self.newline();
+        } else if chr == 0x0d {
+            // 復帰
+            self.cursor_x = 8;
+        } else if chr == 0x08 {
+            // 後退
+            if self.cursor_x > 8 {
+                self.cursor_x -= 8;
+            }
+        } else {
+            if self.sheet_index != 0 {
+                self.put_char(chr, true);
+            }
+            if self.cursor_x == self.max_cursor_x() {
+                self.cursor_x = 8;
+                self.newline();
+            }
+        }
+    }
+
+    /// ESC [ ... を処理する。対応していないものは無視する
+    fn handle_csi(&mut self, csi: &Csi) {
+        if csi.private {
+            // ESC [ ? 25 lなどのカーソルの表示の切り替えには対応しない
+            return;
+        }
+        let n = csi.param(0, 1) as isize;
+        let column = (self.cursor_x - 8) / 8;
+        let row = (self.cursor_y - MIN_CURSOR_Y) / 16;
+        match csi.command {
+            b'A' => self.move_cursor(column, row - n),
+            b'B' => self.move_cursor(column, row + n),
+            b'C' => self.move_cursor(column + n, row),
+            b'D' => self.move_cursor(column - n, row),
+            b'E' => self.move_cursor(0, row + n),
+            b'F' => self.move_cursor(0, row - n),
+            b'G' => self.move_cursor(n - 1, row),
+            b'd' => self.move_cursor(column, n - 1),
+            b'H' | b'f' => self.move_cursor(csi.param(1, 1) as isize - 1, n - 1),
+            b'J' => self.erase_display(csi.param(0, 0)),
+            b'K' => self.erase_line(csi.param(0, 0)),
+            b'm' => self.set_graphics(csi.params()),
+            b's' => self.save_cursor(),
+            b'u' => self.restore_cursor(),
+            _ => {}
+        }
+    }
+
+    /// 0から数えたcolumn, rowにカーソルを移す。画面の外なら端に止める
+    fn move_cursor(&mut self, column: isize, row: isize) {
+        let column = column.max(0).min(self.columns as isize - 1);
+        let row = row.max(0).min(self.rows as isize - 1);
+        self.cursor_x = 8 + column * 8;
+        self.cursor_y = MIN_CURSOR_Y + row * 16;
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved_cursor_x = self.cursor_x;
+        self.saved_cursor_y = self.cursor_y;
+    }
+
+    fn restore_cursor(&mut self) {
+        // 覚えた後にウィンドウが小さくなっているかもしれない
+        self.move_cursor(
+            (self.saved_cursor_x - 8) / 8,
+            (self.saved_cursor_y - MIN_CURSOR_Y) / 16,
+        );
+    }
+
+    /// 0ならカーソルから画面の最後まで、1なら画面の最初からカーソルまで、2なら画面全体を消す
+    fn erase_display(&mut self, mode: u16) {
+        let row = ((self.cursor_y - MIN_CURSOR_Y) / 16) as usize;
+        match mode {
+            0 => {
+                self.erase_line(0);
+                for r in (row + 1)..self.rows {
+                    self.erase(r, 0, self.columns);
+                }
+            }
+            1 => {
+                for r in 0..row {
+                    self.erase(r, 0, self.columns);
+                }
+                self.erase_line(1);
+            }
+            2 => {
+                for r in 0..self.rows {
+                    self.erase(r, 0, self.columns);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// 0ならカーソルから行末まで、1なら行頭からカーソルまで、2なら行全体を消す
+    fn erase_line(&mut self, mode: u16) {
+        let column = ((self.cursor_x - 8) / 8) as usize;
+        let row = ((self.cursor_y - MIN_CURSOR_Y) / 16) as usize;
+        match mode {
+            0 => self.erase(row, column, self.columns),
+            1 => self.erase(row, 0, (column + 1).min(self.columns)),
+            2 => self.erase(row, 0, self.columns),
+            _ => {}
+        }
+    }
+
+    /// row行目のfromからtoの手前までを今の背景色の空白にする
+    fn erase(&mut self, row: usize, from: usize, to: usize) {
+        if from >= to {
+            return;
+        }
+        self.scroll_to_bottom();
+        let (fg, bg) = self.colors();
+        if self.scrollback_addr != 0 {
+            let scrollback = self.scrollback();
+            let i = scrollback.len() - self.rows + row;
+            for c in scrollback.line_mut(i)[from..to].iter_mut() {
+                *c = b' ';
+            }
+            for a in scrollback.attrs_mut(i)[from..to].iter_mut() {
+                *a = scrollback::attr(fg, bg);
+            }
+        }
+        if self.sheet_index == 0 {
+            return;
+        }
+        let sheet_manager = unsafe { &mut *(self.sheet_manager_addr as *mut SheetManager) };
+        let sheet = sheet_manager.sheets_data[self.sheet_index];
+        let x0 = 8 + from as isize * 8;
+        let x1 = 8 + to as isize * 8;
+        let y0 = MIN_CURSOR_Y + row as isize * 16;
+        boxfill(
+            sheet.buf_addr,
+            sheet.width as isize,
+            bg,
+            x0,
+            y0,
+            x1 - 1,
+            y0 + 15,
+        );
+        sheet_manager.refresh(
+            self.sheet_index,
+            x0 as i32,
+            y0 as i32,
+            x1 as i32,
+            (y0 + 16) as i32,
+        );
+    }
+
+    /// ESC [ ... mで色を変える
+    fn set_graphics(&mut self, params: &[u16]) {
+        for p in params.iter() {
+            match *p {
+                0 => {
+                    self.fg = DEFAULT_FG;
+                    self.bg = DEFAULT_BG;
+                    self.bold = false;
+                }
+                1 => self.bold = true,
+                22 => self.bold = false,
+                30..=37 => self.fg = (*p - 30) as u8,
+                39 => self.fg = DEFAULT_FG,
+                40..=47 => self.bg = (*p - 40) as u8,
+                49 => self.bg = DEFAULT_BG,
+                90..=97 => self.fg = (*p - 90) as u8 + 8,
+                100..=107 => self.bg = (*p - 100) as u8 + 8,
+                _ => {}
             }
         }
     }
 }
 
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+/// コマンドのファイルを探す。拡張子が省略されていたら.hrb、.elf、.batの順に探す
+fn search_command(filename: &[u8]) -> Option<FileInfo> {
+    let finfo = search_file(filename);
+    if finfo.is_some() || filename.contains(&b'.') {
+        return finfo;
+    }
+    for ext in [b"hrb", b"elf", b"bat"].iter() {
+        let mut filename_ext = [b' '; MAX_CMD + 4];
+        let filename_ext = &mut filename_ext[0..(filename.len() + 4)];
+        filename_ext[..filename.len()].copy_from_slice(filename);
+        filename_ext[filename.len()] = b'.';
+        filename_ext[(filename.len() + 1)..].copy_from_slice(*ext);
+        let finfo = search_file(filename_ext);
+        if finfo.is_some() {
+            return finfo;
+        }
+    }
+    None
+}
+
+/// 標準出力ではなく、コンソールの画面に直接書く
+struct Screen<'a>(&'a mut Console);
+
+impl<'a> fmt::Write for Screen<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.put_string(s.as_ptr() as usize, s.len(), None);
+        Ok(())
+    }
+}
+
+/// COLUMNS ROWSの形の引数を読む。何も指定されていなければNone
+fn parse_console_size(args: &Args) -> Result<Option<(usize, usize)>, &'static str> {
+    let number = |i| {
+        args.get(i)
+            .and_then(|s| from_utf8(s).ok())
+            .and_then(|s| s.parse::<usize>().ok())
+    };
+    match (args.len(), number(1), number(2)) {
+        (1, _, _) => Ok(None),
+        (3, Some(columns), Some(rows)) => Ok(Some((columns, rows))),
+        _ => Err("Bad Console Size"),
+    }
+}
+
+/// 補完の候補を順に渡す。ファイル名は小文字にする
+fn for_each_candidate(is_command: bool, mut f: impl FnMut(&[u8])) {
+    if is_command {
+        for name in BUILTIN_COMMANDS.iter() {
+            f(name);
+        }
+    }
+    for findex in 0..MAX_FILE_INFO {
+        let finfo = *file_info(findex);
+        if finfo.name[0] == 0x00 {
+            break;
+        }
+        if finfo.name[0] == 0xe5 || (finfo.ftype & 0x18) != 0 {
+            continue;
+        }
+        let (mut name, len) = finfo.file_name();
+        name.make_ascii_lowercase();
+        f(&name[..len]);
+    }
+}
+
+fn starts_with_ignore_case(s: &[u8], prefix: &[u8]) -> bool {
+    s.len() >= prefix.len() && s[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+/// コマンドラインから最初の単語を除いた残り(startやncstで新しいコンソールに渡す)
+fn rest_of_cmdline(cmdline: &[u8]) -> &[u8] {
+    let cmdline = match cmdline.iter().position(|c| *c == 0) {
+        Some(len) => &cmdline[..len],
+        None => cmdline,
+    };
+    let mut i = 0;
+    while i < cmdline.len() && cmdline[i] == b' ' {
+        i += 1;
+    }
+    while i < cmdline.len() && cmdline[i] != b' ' {
+        i += 1;
+    }
+    while i < cmdline.len() && cmdline[i] == b' ' {
+        i += 1;
+    }
+    &cmdline[i..]
+}
+
 pub extern "C" fn console_task(sheet_index: usize, memtotal: usize) {
     let task_manager = unsafe { &mut *(TASK_MANAGER_ADDR as *mut TaskManager) };
     let task_index = task_manager.now_index();
 
     let memman = unsafe { &mut *(MEMMAN_ADDR as *mut MemMan) };
 
-    // コマンドを保持するための配列
-    let mut cmdline: [u8; MAX_CMD] = [0; MAX_CMD];
+    // 入力中のコマンドと履歴
+    let mut editor = LineEditor::new();
 
     let sheet_manager_addr = unsafe { SHEET_MANAGER_ADDR };
-    let sheet_manager = unsafe { &mut *(sheet_manager_addr as *mut SheetManager) };
 
     let fat_addr = memman.alloc_4k(4 * MAX_FAT as u32).unwrap();
     let fat = unsafe { &mut *(fat_addr as *mut [u32; (MAX_FAT)]) };
-    read_fat(fat, unsafe {
-        *((ADR_DISKIMG + 0x000200) as *const [u8; (MAX_FAT * 4)])
-    });
+    read_fat(fat);
 
-    let mut console = Console::new(sheet_index, sheet_manager_addr);
+    let (columns, rows) = if sheet_index != 0 {
+        let sheet_manager = unsafe { &*(sheet_manager_addr as *const SheetManager) };
+        let sheet = sheet_manager.sheets_data[sheet_index];
+        (
+            (sheet.width as usize - 16) / 8,
+            (sheet.height as usize - 37) / 16,
+        )
+    } else {
+        (CONSOLE_COLUMNS, CONSOLE_ROWS)
+    };
+    let mut console = Console::new(sheet_index, sheet_manager_addr, columns, rows);
+    let mut scrollback = Scrollback::new(columns, rows).unwrap();
+    console.scrollback_addr = &mut scrollback as *mut Scrollback as usize;
+    let mut vt100 = Vt100::new();
+    console.vt100_addr = &mut vt100 as *mut Vt100 as usize;
+    let mut jobs = JobTable::new();
+    console.jobs_addr = &mut jobs as *mut JobTable as usize;
     let fifo_addr: usize;
-    let fhandlers: [FileHandler; MAX_FILE_HANDLER] = [FileHandler::new(); MAX_FILE_HANDLER];
-    let nihongo_font = unsafe { *((NIHONGO_ADDR as usize + 4096 * 4) as *const u8) };
+    let mut handles = HandleTable::new();
+    let mut env = Env::new();
+    let mut stdio = Stdio::new();
+    let nihongo_font = unsafe { *((NIHONGO_ADDR + 4096 * 4) as *const u8) };
     {
         let mut task = &mut task_manager.tasks_data[task_index];
         task.console_addr = &console as *const Console as usize;
         fifo_addr = task.fifo_addr;
-        task.file_handler_addr = fhandlers.as_ptr() as usize;
+        task.handle_table_addr = &mut handles as *mut HandleTable as usize;
         task.fat_addr = fat_addr as usize;
-        task.cmdline_addr = cmdline.as_ptr() as usize;
+        task.cmdline_addr = editor.as_bytes().as_ptr() as usize;
+        task.env_addr = &mut env as *mut Env as usize;
+        task.stdio_addr = &mut stdio as *mut Stdio as usize;
         if nihongo_font != 0xff {
             task.lang_mode = LangMode::JpJis
         }
@@ -895,11 +2312,18 @@ pub extern "C" fn console_task(sheet_index: usize, memtotal: usize) {
             .init_timer(console.timer_index, fifo_addr, 1);
         TIMER_MANAGER.lock().set_time(console.timer_index, 50);
     }
-    let sheet = sheet_manager.sheets_data[sheet_index];
+    // autoexec.batがあれば最初に実行する
+    if search_file(b"autoexec.bat").is_some() {
+        console.run_cmd(b"autoexec.bat", memtotal, fat);
+    }
+    #[cfg(feature = "integration-test")]
+    crate::itest::run(&mut console, memtotal, fat);
 
     if sheet_index != 0 {
         console.show_prompt();
     }
+    console.cursor_x = 16;
+    console.input_y = console.cursor_y;
 
     loop {
         cli();
@@ -927,72 +2351,82 @@ pub extern "C" fn console_task(sheet_index: usize, memtotal: usize) {
                 }
                 TIMER_MANAGER.lock().set_time(console.timer_index, 50);
             } else if KEYBOARD_OFFSET <= i && i <= 511 {
-                let key = (i - KEYBOARD_OFFSET) as u8;
-                if key != 0 {
-                    // バックスペース
-                    if key == CONSOLE_BACKSPACE as u8 {
-                        if console.cursor_x > MIN_CURSOR_X {
-                            console.put_char(b' ', false);
-                            cmdline[console.cursor_x as usize / 8 - 2] = b' ';
-                            console.cursor_x -= 8;
-                        }
-                    } else if key == CONSOLE_ENTER as u8 {
-                        console.put_char(b' ', false);
-                        console.newline();
-                        console.run_cmd(cmdline, memtotal, fat);
-                        if console.sheet_index == 0 {
-                            console.cmd_exit(fat);
-                        }
-                        cmdline = [0; MAX_CMD];
-                        // プロンプト表示
-                        if sheet_index != 0 {
-                            console.show_prompt();
-                        }
-                        console.cursor_x = 16;
-                    } else {
-                        if console.cursor_x < MAX_CURSOR_X {
-                            cmdline[console.cursor_x as usize / 8 - 2] = key;
-                            console.put_char(key, true);
-                        }
+                let key = (i - KEYBOARD_OFFSET) as u32;
+                // カーソルを消しておく
+                console.put_char(editor.current(), false);
+                let old_len = editor.len();
+                if key == CONSOLE_ENTER {
+                    console.move_to_input(editor.len());
+                    console.newline();
+                    // 他のコンソールでファイルが書き込まれているかもしれないので読み直す
+                    read_fat(fat);
+                    console.run_cmd(editor.as_bytes(), memtotal, fat);
+                    if console.sheet_index == 0 {
+                        console.cmd_exit(fat);
+                    }
+                    editor.finish();
+                    console.report_jobs();
+                    // プロンプト表示
+                    if sheet_index != 0 {
+                        console.show_prompt();
+                    }
+                    console.cursor_x = 16;
+                    console.input_y = console.cursor_y;
+                } else if key == CONSOLE_BACKSPACE {
+                    if editor.backspace() {
+                        console.redraw_input(&editor, editor.pos(), old_len);
+                    }
+                } else if key == CONSOLE_DELETE {
+                    if editor.delete() {
+                        console.redraw_input(&editor, editor.pos(), old_len);
+                    }
+                } else if key == CONSOLE_LEFT {
+                    editor.left();
+                    console.move_to_input(editor.pos());
+                } else if key == CONSOLE_RIGHT {
+                    editor.right();
+                    console.move_to_input(editor.pos());
+                } else if key == CONSOLE_HOME {
+                    editor.move_to(0);
+                    console.move_to_input(editor.pos());
+                } else if key == CONSOLE_END {
+                    editor.move_to(editor.len());
+                    console.move_to_input(editor.pos());
+                } else if key == CONSOLE_UP {
+                    if editor.history_prev() {
+                        console.redraw_input(&editor, 0, old_len);
+                    }
+                } else if key == CONSOLE_DOWN {
+                    if editor.history_next() {
+                        console.redraw_input(&editor, 0, old_len);
+                    }
+                } else if key == CONSOLE_TAB {
+                    console.complete(&mut editor);
+                } else if key >= b' ' as u32 {
+                    // Ctrl+Dなどの制御文字は入力しない
+                    if editor.insert(key as u8) {
+                        console.redraw_input(&editor, editor.pos() - 1, old_len);
                     }
                 }
             } else if i == CONSOLE_CURSOR_ON {
                 console.cursor_c = Color::White;
                 console.cursor_on = true;
             } else if i == CONSOLE_CURSOR_OFF {
-                if console.sheet_index != 0 {
-                    let sheet = sheet_manager.sheets_data[console.sheet_index];
-                    boxfill(
-                        sheet.buf_addr,
-                        sheet.width as isize,
-                        Color::Black,
-                        console.cursor_x,
-                        console.cursor_y,
-                        console.cursor_x + 7,
-                        console.cursor_y + 15,
-                    );
+                if console.sheet_index != 0 && console.scroll == 0 {
+                    console.put_char(editor.current(), false);
                 }
                 console.cursor_on = false;
             } else if i == EXIT_CONSOLE {
                 console.cmd_exit(fat);
+            } else if i == CONSOLE_JOB_DONE {
+                // 終わったジョブは次にプロンプトを出すときに知らせる
+            } else if i >= ALARM_OFFSET {
+                signal::send(task_manager, (i - ALARM_OFFSET) as usize, SIGALRM);
+            } else {
+                console.handle_scroll(i);
             }
-            if console.sheet_index != 0 && console.cursor_on {
-                boxfill(
-                    sheet.buf_addr,
-                    sheet.width as isize,
-                    console.cursor_c,
-                    console.cursor_x,
-                    console.cursor_y,
-                    console.cursor_x + 7,
-                    console.cursor_y + 15,
-                );
-                sheet_manager.refresh(
-                    console.sheet_index,
-                    console.cursor_x as i32,
-                    console.cursor_y as i32,
-                    console.cursor_x as i32 + 8,
-                    console.cursor_y as i32 + 16,
-                );
+            if console.cursor_on {
+                console.draw_cursor(editor.current());
             }
         }
     }
@@ -1011,24 +2445,21 @@ pub extern "C" fn exception_handler(message: &[u8], esp: *const usize) -> usize
     let task_index = task_manager.now_index();
     let task = &task_manager.tasks_data[task_index];
     let console = unsafe { &mut *(task.console_addr as *mut Console) };
-    let sheet_manager_addr = unsafe { SHEET_MANAGER_ADDR };
-    let sheet_manager = unsafe { &mut *(sheet_manager_addr as *mut SheetManager) };
-    let sheet = sheet_manager.sheets_data[console.sheet_index];
+    // esp[0..8]がPUSHAD、esp[8..10]がDS・ES、esp[10]がエラーコード、esp[11]がEIP
+    let eip = unsafe { *esp.add(11) };
     console.newline();
     console.put_string(message.as_ptr() as usize, message.len(), Some(8));
-    write_with_bg!(
-        sheet_manager,
-        console.sheet_index,
-        sheet.width,
-        sheet.height,
-        8,
-        console.cursor_y,
-        Color::White,
-        Color::Black,
-        30,
-        "EIP = {:>08X}",
-        unsafe { *((esp as usize + 11) as *const usize) }
-    );
+    let _ = write!(Screen(&mut *console), "EIP = {:>08X}", eip);
     console.newline();
+    let mut lines = from_utf8(message).unwrap_or("").lines().map(str::trim);
+    log!(
+        Error,
+        "{} {} EIP = {:08X}",
+        lines.next().unwrap_or(""),
+        lines.next().unwrap_or(""),
+        eip
+    );
+    let job = unsafe { &mut *(task.job_addr as *mut Job) };
+    job.status = STATUS_ABORTED;
     return unsafe { &(task.tss.esp0) } as *const i32 as usize;
 }
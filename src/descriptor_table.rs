@@ -1,9 +1,11 @@
 use crate::asm;
 use crate::console::{inthandler0c, inthandler0d};
+use crate::gdb::{inthandler01, inthandler03, inthandler23};
 use crate::keyboard::inthandler21;
 use crate::mouse::inthandler2c;
+use crate::serial::inthandler24;
 use crate::timer::inthandler20;
-use crate::{exception_handler, handler};
+use crate::{exception_handler, handler, trap_handler};
 use asm::{interrupt_hrb_api, load_gdtr, load_idtr};
 
 #[derive(Debug, Clone, Copy)]
@@ -32,6 +34,20 @@ impl SegmentDescriptor {
             base_high: (base >> 24) as u8,
         }
     }
+
+    pub fn base(&self) -> usize {
+        self.base_low as usize | (self.base_mid as usize) << 16 | (self.base_high as usize) << 24
+    }
+
+    /// セグメントの最後のバイトのオフセット
+    pub fn limit(&self) -> usize {
+        let limit = self.limit_low as usize | ((self.limit_high & 0x0f) as usize) << 16;
+        if self.limit_high & 0x80 != 0 {
+            limit << 12 | 0xfff
+        } else {
+            limit
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -88,12 +104,25 @@ pub fn init() {
     }
 
     // 割り込みの設定
+    let idt = unsafe { &mut *((ADR_IDT + 0x01 * 8) as *mut GateDescriptor) };
+    *idt = GateDescriptor::new(trap_handler!(inthandler01) as u32, 2 * 8, AR_INTGATE32);
+    // アプリの中のINT3でも呼べるようにする
+    let idt = unsafe { &mut *((ADR_IDT + 0x03 * 8) as *mut GateDescriptor) };
+    *idt = GateDescriptor::new(
+        trap_handler!(inthandler03) as u32,
+        2 * 8,
+        AR_INTGATE32 + 0x60,
+    );
     let idt = unsafe { &mut *((ADR_IDT + 0x0c * 8) as *mut GateDescriptor) };
     *idt = GateDescriptor::new(exception_handler!(inthandler0c) as u32, 2 * 8, AR_INTGATE32);
     let idt = unsafe { &mut *((ADR_IDT + 0x0d * 8) as *mut GateDescriptor) };
     *idt = GateDescriptor::new(exception_handler!(inthandler0d) as u32, 2 * 8, AR_INTGATE32);
     let idt = unsafe { &mut *((ADR_IDT + 0x21 * 8) as *mut GateDescriptor) };
     *idt = GateDescriptor::new(handler!(inthandler21) as u32, 2 * 8, AR_INTGATE32);
+    let idt = unsafe { &mut *((ADR_IDT + 0x23 * 8) as *mut GateDescriptor) };
+    *idt = GateDescriptor::new(trap_handler!(inthandler23) as u32, 2 * 8, AR_INTGATE32);
+    let idt = unsafe { &mut *((ADR_IDT + 0x24 * 8) as *mut GateDescriptor) };
+    *idt = GateDescriptor::new(handler!(inthandler24) as u32, 2 * 8, AR_INTGATE32);
     let idt = unsafe { &mut *((ADR_IDT + 0x2c * 8) as *mut GateDescriptor) };
     *idt = GateDescriptor::new(handler!(inthandler2c) as u32, 2 * 8, AR_INTGATE32);
     let idt = unsafe { &mut *((ADR_IDT + 0x20 * 8) as *mut GateDescriptor) };
@@ -1,119 +1,267 @@
+use core::slice;
+
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+use haribote_core::fat::{
+    self, chain, free_cluster, free_clusters, to_fat_name, to_valid_fat_name, CLUSTER_OFFSET,
+    CLUSTER_SIZE, FAT0_OFFSET, FAT1_OFFSET, FAT_BYTES, FAT_EOF, FILE_OFFSET, IMAGE_SIZE,
+};
+pub use haribote_core::fat::{
+    free_space, FileInfo, ATTR_ARCHIVE, ATTR_DIRECTORY, ATTR_HIDDEN, ATTR_READ_ONLY, ATTR_SYSTEM,
+    ATTR_VOLUME, MAX_FAT, MAX_FILE_INFO,
+};
+
+use crate::log;
+use crate::multiboot::Module;
+use crate::rtc;
+
 pub const ADR_DISKIMG: usize = 0x00100000;
-pub const ADR_FILE_OFFSET: usize = 0x002600;
-pub const MAX_FILE_INFO: usize = 224;
-pub const MAX_FAT: usize = 2880;
+pub const ADR_FILE_OFFSET: usize = FILE_OFFSET;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(C, packed)]
-pub struct FileInfo {
-    pub name: [u8; 8],
-    pub ext: [u8; 3],
-    pub ftype: u8,
-    pub reserve: [i8; 10],
-    pub time: u16,
-    pub date: u16,
-    pub clustno: u16,
-    pub size: u32,
-}
-
-impl FileInfo {
-    pub fn load_file(&self, buf_addr: usize, fat: &[u32; MAX_FAT], img_addr: usize) {
-        let mut size = self.size as usize;
-        let mut buf_addr = buf_addr as usize;
-        let mut clustno = self.clustno as usize;
-        loop {
-            if size <= 512 {
-                for i in 0..size {
-                    let buf = unsafe { &mut *((buf_addr + i) as *mut u8) };
-                    *buf = unsafe { *((img_addr + clustno * 512 + i) as *const u8) };
-                }
-                break;
-            }
-            for i in 0..512 {
-                let buf = unsafe { &mut *((buf_addr + i) as *mut u8) };
-                *buf = unsafe { *((img_addr + clustno * 512 + i) as *const u8) };
-            }
-            size -= 512;
-            buf_addr += 512;
-            clustno = fat[clustno] as usize;
-        }
+lazy_static! {
+    /// ディスクイメージのFATやディレクトリを書き換えるときに取る
+    /// コンソールやジョブは途中で切り替わるので、取ったあとにFATを読み直してから書き換える
+    static ref DISK_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// メモリに読み込んだディスクイメージ
+fn disk_image() -> &'static mut [u8] {
+    unsafe { slice::from_raw_parts_mut(ADR_DISKIMG as *mut u8, IMAGE_SIZE) }
+}
+
+/// ルートディレクトリのエントリ
+fn root_dir() -> &'static mut [FileInfo] {
+    unsafe {
+        slice::from_raw_parts_mut(
+            (ADR_DISKIMG + ADR_FILE_OFFSET) as *mut FileInfo,
+            MAX_FILE_INFO,
+        )
     }
 }
 
+pub fn file_info(findex: usize) -> &'static mut FileInfo {
+    &mut root_dir()[findex]
+}
+
+/// ファイルの中身をbuf_addrから後ろに読み込む
+pub fn load_file(finfo: &FileInfo, buf_addr: usize, fat: &[u32; MAX_FAT]) {
+    let buf = unsafe { slice::from_raw_parts_mut(buf_addr as *mut u8, finfo.size as usize) };
+    finfo.load(buf, fat, &disk_image()[CLUSTER_OFFSET..]);
+}
+
 pub fn search_file(filename: &[u8]) -> Option<FileInfo> {
-    let mut target_finfo = None;
-    // 拡張子の前後でわける
-    let mut filename = filename.split(|c| *c == b'.');
-    let basename = filename.next();
-    let extname = filename.next();
-    let mut b = [b' '; 8];
-    let mut e = [b' '; 3];
-    if let Some(basename) = basename {
-        for fi in 0..b.len() {
-            if basename.len() <= fi {
-                break;
-            }
-            if b'a' <= basename[fi] && basename[fi] <= b'z' {
-                // 小文字は大文字で正規化しておく
-                b[fi] = basename[fi] - 0x20;
-            } else {
-                b[fi] = basename[fi];
-            }
+    fat::search_file(root_dir(), filename)
+}
+
+/// パターンに当てはまるファイルとディレクトリ。ボリュームラベルは除く
+pub fn find_files(pattern: &[u8]) -> impl Iterator<Item = FileInfo> + '_ {
+    let dir: &'static [FileInfo] = root_dir();
+    fat::entries(dir)
+        .map(move |findex| dir[findex])
+        .filter(move |finfo| finfo.ftype & ATTR_VOLUME == 0 && finfo.matches(pattern))
+}
+
+/// 今の時刻を更新日時にする
+fn touch(finfo: &mut FileInfo) {
+    let now = rtc::now();
+    finfo.set_datetime(
+        now.year as u32,
+        now.month as u32,
+        now.day as u32,
+        now.hour as u32,
+        now.minute as u32,
+        now.second as u32,
+    );
+}
+
+/// ディスクイメージの中にファイルを作る。同じ名前のファイルがあれば中身を置き換える
+/// メモリに読み込んだイメージを書き換えるだけなので、フロッピーディスクには書き戻されない
+pub fn write_file(
+    filename: &[u8],
+    data: &[u8],
+    fat: &mut [u32; MAX_FAT],
+) -> Result<(), &'static str> {
+    let _lock = DISK_LOCK.lock();
+    read_fat(fat);
+    let (b, e) = to_valid_fat_name(filename)?;
+    let target = fat::find_entry(root_dir(), &b, &e);
+    if let Some(findex) = target {
+        if file_info(findex).ftype & (ATTR_READ_ONLY | ATTR_VOLUME | ATTR_DIRECTORY) != 0 {
+            return Err("Permission Denied");
+        }
+    }
+    let findex = target
+        .or_else(|| fat::free_entry(root_dir()))
+        .ok_or("Too Many Files")?;
+
+    // 置き換える前に、古い中身の分も含めて空きが足りるか確かめる
+    let clusters = (data.len() + CLUSTER_SIZE - 1) / CLUSTER_SIZE;
+    let old_clusters = match target {
+        Some(_) => chain(fat, file_info(findex).clustno as usize).count(),
+        None => 0,
+    };
+    if free_space(fat) / CLUSTER_SIZE + old_clusters < clusters {
+        return Err("Disk Full");
+    }
+    if target.is_some() {
+        free_clusters(file_info(findex).clustno as usize, fat);
+    }
+
+    let image = disk_image();
+    let mut first = 0;
+    let mut prev = 0;
+    let mut clustno = 2;
+    for chunk in data.chunks(CLUSTER_SIZE) {
+        while fat[clustno] != 0 {
+            clustno += 1;
+        }
+        let start = CLUSTER_OFFSET + clustno * CLUSTER_SIZE;
+        image[start..(start + chunk.len())].copy_from_slice(chunk);
+        if prev == 0 {
+            first = clustno;
+        } else {
+            fat[prev] = clustno as u32;
         }
-    } else {
-        return None;
+        // 次のクラスタを探すときに選ばれないよう、ひとまず終端にしておく
+        fat[clustno] = FAT_EOF;
+        prev = clustno;
     }
-    if let Some(extname) = extname {
-        for fi in 0..e.len() {
-            if extname.len() <= fi {
-                break;
-            }
-            if b'a' <= extname[fi] && extname[fi] <= b'z' {
-                e[fi] = extname[fi] - 0x20;
-            } else {
-                e[fi] = extname[fi];
-            }
+    write_fat(fat);
+
+    let finfo = file_info(findex);
+    finfo.name = b;
+    finfo.ext = e;
+    finfo.ftype = ATTR_ARCHIVE;
+    finfo.reserve = [0; 10];
+    touch(finfo);
+    finfo.clustno = first as u16;
+    finfo.size = data.len() as u32;
+    Ok(())
+}
+
+/// ファイルを消す。ディレクトリは消さない
+pub fn delete_file(filename: &[u8], fat: &mut [u32; MAX_FAT]) -> Result<(), &'static str> {
+    let _lock = DISK_LOCK.lock();
+    read_fat(fat);
+    let (b, e) = to_fat_name(filename);
+    let findex = fat::find_entry(root_dir(), &b, &e).ok_or("File Not Found")?;
+    let finfo = file_info(findex);
+    if finfo.ftype & (ATTR_READ_ONLY | ATTR_VOLUME | ATTR_DIRECTORY) != 0 {
+        return Err("Permission Denied");
+    }
+    free_clusters(finfo.clustno as usize, fat);
+    write_fat(fat);
+    finfo.name[0] = 0xe5;
+    Ok(())
+}
+
+/// 名前を変える。新しい名前のファイルがすでにあればエラーにする
+pub fn rename_file(from: &[u8], to: &[u8]) -> Result<(), &'static str> {
+    let _lock = DISK_LOCK.lock();
+    let (b, e) = to_fat_name(from);
+    let findex = fat::find_entry(root_dir(), &b, &e).ok_or("File Not Found")?;
+    let (b, e) = to_valid_fat_name(to)?;
+    if let Some(other) = fat::find_entry(root_dir(), &b, &e) {
+        if other != findex {
+            return Err("File Exists");
         }
     }
-    for findex in 0..MAX_FILE_INFO {
-        let finfo = unsafe {
-            *((ADR_DISKIMG + ADR_FILE_OFFSET + findex * core::mem::size_of::<FileInfo>())
-                as *const FileInfo)
-        };
-        if finfo.name[0] == 0x00 {
-            break;
+    let finfo = file_info(findex);
+    if finfo.ftype & ATTR_VOLUME != 0 {
+        return Err("Permission Denied");
+    }
+    finfo.name = b;
+    finfo.ext = e;
+    Ok(())
+}
+
+/// ルートディレクトリに空のディレクトリを作る
+/// 中に.と..のエントリを書いておくが、ディレクトリの中のファイルを読み書きする機能はまだない
+pub fn make_dir(dirname: &[u8], fat: &mut [u32; MAX_FAT]) -> Result<(), &'static str> {
+    let _lock = DISK_LOCK.lock();
+    read_fat(fat);
+    let (b, e) = to_valid_fat_name(dirname)?;
+    if fat::find_entry(root_dir(), &b, &e).is_some() {
+        return Err("File Exists");
+    }
+    let findex = fat::free_entry(root_dir()).ok_or("Too Many Files")?;
+    let clustno = free_cluster(fat).ok_or("Disk Full")?;
+    fat[clustno] = FAT_EOF;
+    write_fat(fat);
+
+    let entries = unsafe {
+        slice::from_raw_parts_mut(
+            (ADR_DISKIMG + CLUSTER_OFFSET + clustno * CLUSTER_SIZE) as *mut FileInfo,
+            CLUSTER_SIZE / core::mem::size_of::<FileInfo>(),
+        )
+    };
+    for entry in entries.iter_mut() {
+        *entry = FileInfo::empty();
+    }
+    let finfo = file_info(findex);
+    *finfo = FileInfo::empty();
+    finfo.name = b;
+    finfo.ext = e;
+    finfo.ftype = ATTR_DIRECTORY;
+    touch(finfo);
+    finfo.clustno = clustno as u16;
+    entries[0] = *finfo;
+    entries[0].name = *b".       ";
+    entries[0].ext = *b"   ";
+    entries[1] = entries[0];
+    entries[1].name = *b"..      ";
+    // 親がルートディレクトリなら0
+    entries[1].clustno = 0;
+    Ok(())
+}
+
+/// Multibootのローダが読み込んだモジュールから、ADR_DISKIMGにディスクイメージを作る
+/// 1440KBのモジュールはフロッピーのイメージとしてそのまま使い、それ以外は空のイメージにファイルとして書き込む
+/// モジュールはメモリ管理に渡す領域にあるので、memmanを使い始める前に呼ぶ
+pub fn load_modules(modules: impl Iterator<Item = Module>) {
+    let image = disk_image();
+    for b in image[..(ADR_FILE_OFFSET + MAX_FILE_INFO * 32)].iter_mut() {
+        *b = 0;
+    }
+    let mut fat = [0; MAX_FAT];
+    // 先頭の2つはクラスタとしては使わない
+    fat[0] = 0xff0;
+    fat[1] = FAT_EOF;
+    write_fat(&fat);
+    for module in modules {
+        if module.data.len() == IMAGE_SIZE {
+            image.copy_from_slice(module.data);
+            read_fat(&mut fat);
+            continue;
         }
-        if finfo.name[0] != 0xe5 {
-            if (finfo.ftype & 0x18) == 0 {
-                let mut filename_equal = true;
-                for y in 0..finfo.name.len() {
-                    if finfo.name[y] != b[y] {
-                        filename_equal = false;
-                        break;
-                    }
-                }
-                for y in 0..finfo.ext.len() {
-                    if finfo.ext[y] != e[y] {
-                        filename_equal = false;
-                        break;
-                    }
-                }
-                if filename_equal {
-                    target_finfo = Some(finfo);
-                    break;
-                }
-            }
+        let name = module.name();
+        match write_file(name, module.data, &mut fat) {
+            Ok(()) => log!(
+                Info,
+                "module {} ({} bytes)",
+                core::str::from_utf8(name).unwrap_or("?"),
+                module.data.len()
+            ),
+            Err(e) => log!(
+                Warn,
+                "module {}: {}",
+                core::str::from_utf8(name).unwrap_or("?"),
+                e
+            ),
         }
     }
-    target_finfo
 }
 
-pub fn read_fat(fat: &mut [u32; MAX_FAT], img: [u8; MAX_FAT * 4]) {
-    let mut j = 0;
-    for i in (0..MAX_FAT).step_by(2) {
-        fat[i + 0] = ((img[j + 0] as u32) | (img[j + 1] as u32) << 8) & 0xfff;
-        fat[i + 1] = ((img[j + 1] as u32) >> 4 | (img[j + 2] as u32) << 4) & 0xfff;
-        j += 3;
+/// ディスクイメージのFATを読む
+pub fn read_fat(fat: &mut [u32; MAX_FAT]) {
+    fat::read_fat(fat, &disk_image()[FAT0_OFFSET..(FAT0_OFFSET + FAT_BYTES)]);
+}
+
+/// ディスクイメージの2つのFATに書き戻す
+pub fn write_fat(fat: &[u32; MAX_FAT]) {
+    for offset in [FAT0_OFFSET, FAT1_OFFSET].iter() {
+        fat::write_fat(fat, &mut disk_image()[*offset..(*offset + FAT_BYTES)]);
     }
 }
 
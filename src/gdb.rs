@@ -0,0 +1,604 @@
+use core::mem::size_of;
+
+use crate::asm::{cli, load_eflags, out8, store_eflags};
+use crate::descriptor_table::{SegmentDescriptor, ADR_GDT};
+use crate::interrupt::PIC0_OCW2;
+use crate::mt::{Task, TaskManager, TASK_MANAGER_ADDR};
+use crate::serial::COM2;
+use crate::signal::UserFrame;
+
+/// パケットの中身の最大長。qSupportedで16進で知らせる
+const PACKET_SIZE: usize = 0x200;
+const MAX_BREAKPOINTS: usize = 16;
+const INT3: u8 = 0xcc;
+const EFLAGS_TF: i32 = 0x0100;
+/// Gで変えてもいいEFLAGSのビット(CF, PF, AF, ZF, SF, TF, DF, OF)
+const EFLAGS_WRITABLE: i32 = 0x0dd5;
+/// gで返すレジスタの数(eax, ecx, edx, ebx, esp, ebp, esi, edi, eip, eflags, cs, ss, ds, es, fs, gs)
+const NUM_REGS: usize = 16;
+
+/// GDBに知らせるシグナルの番号
+const GDB_SIGINT: u8 = 2;
+const GDB_SIGTRAP: u8 = 5;
+
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+#[derive(Clone, Copy)]
+struct Breakpoint {
+    /// INT3を書き込んだリニアアドレス
+    linear: usize,
+    /// 書き込む前の1バイト
+    saved: u8,
+}
+
+/// COM2が見つかった
+static mut PRESENT: bool = false;
+/// GDBがcかsのあと、止まったと知らせるのを待っている
+static mut WAITING: bool = false;
+static mut BREAKPOINTS: [Option<Breakpoint>; MAX_BREAKPOINTS] = [None; MAX_BREAKPOINTS];
+/// debugで起動したアプリの最初の命令に置く、一度だけのブレークポイント
+static mut ENTRY_BREAK: Option<Breakpoint> = None;
+/// debugで起動したアプリのタスク
+static mut TARGET: Option<usize> = None;
+
+/// COM2があればGDBのスタブを使えるようにする
+pub fn init_gdb() -> bool {
+    let present = COM2.init();
+    unsafe {
+        PRESENT = present;
+    }
+    present
+}
+
+/// debugでアプリを起動できるか
+pub fn check_available() -> Result<(), &'static str> {
+    if unsafe { !PRESENT } {
+        return Err("No Debugger");
+    }
+    if unsafe { TARGET }.is_some() || unsafe { ENTRY_BREAK }.is_some() {
+        return Err("Already Debugging");
+    }
+    Ok(())
+}
+
+/// debugで起動したアプリの最初の命令(linearはそのリニアアドレス)で止まり、GDBを待つようにする
+pub fn break_at_entry(linear: usize) {
+    let saved = unsafe { *(linear as *const u8) };
+    unsafe {
+        *(linear as *mut u8) = INT3;
+        ENTRY_BREAK = Some(Breakpoint { linear, saved });
+    }
+}
+
+/// debugで起動したアプリが終わった。ブレークポイントはアプリのメモリごと捨て、GDBに終了ステータスを知らせる
+pub fn exited(status: i32) {
+    let eflags = load_eflags();
+    // COM2の割り込みにGDBの返事を取られないようにする
+    cli();
+    unsafe {
+        BREAKPOINTS = [None; MAX_BREAKPOINTS];
+        ENTRY_BREAK = None;
+        TARGET = None;
+        if WAITING {
+            WAITING = false;
+            let mut reply = Packet::new();
+            reply.push(b'W');
+            reply.push_hex(status as u8);
+            put_packet(reply.as_bytes());
+        }
+    }
+    store_eflags(eflags);
+}
+
+/// 止まったところ。アプリならLDTとds_baseを使って番地を読み替える
+struct Context {
+    frame: &'static mut UserFrame,
+    /// アプリのタスク。Noneならカーネル
+    task: Option<&'static Task>,
+}
+
+impl Context {
+    fn new(frame: &'static mut UserFrame) -> Context {
+        let task_manager = unsafe { &*(TASK_MANAGER_ADDR as *const TaskManager) };
+        if frame.cs & 3 == 3 {
+            let task = &task_manager.tasks_data[task_manager.now_index()];
+            return Context {
+                frame,
+                task: Some(task),
+            };
+        }
+        // カーネルで止まったときは、debugで起動したアプリがカーネルに入ったところを見せる
+        if let Some(task_index) = unsafe { TARGET } {
+            let task = &task_manager.tasks_data[task_index];
+            return Context {
+                frame: UserFrame::of(task),
+                task: Some(task),
+            };
+        }
+        Context { frame, task: None }
+    }
+
+    fn is_user(&self) -> bool {
+        self.frame.cs & 3 == 3
+    }
+
+    /// GDBの番地をリニアアドレスにする
+    /// コードセグメントに収まる番地はコード、そうでなければデータとして読む
+    /// (.hrbはデータとスタックを0x00310000から置くので重ならない)
+    fn linear(&self, addr: usize, len: usize) -> Option<usize> {
+        let (code_base, code_limit, data_base, data_limit) = match self.task {
+            Some(task) => (
+                task.ldt[0].base(),
+                task.ldt[0].limit(),
+                task.ds_base,
+                task.ds_limit.checked_sub(1)?,
+            ),
+            None => {
+                let cs = descriptor(self.frame.cs);
+                (cs.base(), cs.limit(), 0, usize::max_value())
+            }
+        };
+        let last = addr.checked_add(len.max(1) - 1)?;
+        if last <= code_limit {
+            Some(code_base + addr)
+        } else if addr > code_limit && last <= data_limit {
+            Some(data_base + addr)
+        } else {
+            None
+        }
+    }
+
+    fn register(&self, n: usize) -> Option<u32> {
+        let frame = &self.frame;
+        let regs = &frame.regs;
+        // カーネルで割り込まれたときはESPとSSが積まれていない
+        let (esp, ss) = if self.is_user() {
+            (frame.esp, frame.ss)
+        } else {
+            let esp = &**frame as *const UserFrame as usize + size_of::<UserFrame>() - 8;
+            (esp as i32, 1 * 8)
+        };
+        let value = match n {
+            0 => regs.eax,
+            1 => regs.ecx,
+            2 => regs.edx,
+            3 => regs.ebx,
+            4 => esp,
+            5 => regs.ebp,
+            6 => regs.esi,
+            7 => regs.edi,
+            8 => frame.eip,
+            9 => frame.eflags,
+            10 => frame.cs,
+            // DSとESはSSと同じものしか使っていない
+            11 | 12 | 13 => ss,
+            _ => return None,
+        };
+        Some(value as u32)
+    }
+
+    /// 書き換えられないレジスタは無視する
+    fn set_register(&mut self, n: usize, value: u32) {
+        let value = value as i32;
+        let is_user = self.is_user();
+        let frame = &mut *self.frame;
+        match n {
+            0 => frame.regs.eax = value,
+            1 => frame.regs.ecx = value,
+            2 => frame.regs.edx = value,
+            3 => frame.regs.ebx = value,
+            4 if is_user => frame.esp = value,
+            5 => frame.regs.ebp = value,
+            6 => frame.regs.esi = value,
+            7 => frame.regs.edi = value,
+            8 => frame.eip = value,
+            9 => frame.eflags = (frame.eflags & !EFLAGS_WRITABLE) | (value & EFLAGS_WRITABLE),
+            _ => {}
+        }
+    }
+}
+
+fn descriptor(selector: i32) -> &'static SegmentDescriptor {
+    let addr = ADR_GDT as usize + (selector as usize & !7);
+    unsafe { &*(addr as *const SegmentDescriptor) }
+}
+
+/// 返すパケットの中身
+struct Packet {
+    buf: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Packet {
+    fn new() -> Packet {
+        Packet {
+            buf: [0; PACKET_SIZE],
+            len: 0,
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    fn push(&mut self, b: u8) {
+        if self.len < PACKET_SIZE {
+            self.buf[self.len] = b;
+            self.len += 1;
+        }
+    }
+
+    fn push_str(&mut self, s: &[u8]) {
+        for &b in s.iter() {
+            self.push(b);
+        }
+    }
+
+    fn push_hex(&mut self, b: u8) {
+        self.push(HEX[(b >> 4) as usize]);
+        self.push(HEX[(b & 0x0f) as usize]);
+    }
+
+    /// レジスタの値はリトルエンディアンのバイト順で送る
+    fn push_word(&mut self, value: u32) {
+        for b in value.to_le_bytes().iter() {
+            self.push_hex(*b);
+        }
+    }
+}
+
+fn get_char() -> u8 {
+    loop {
+        if let Some(c) = COM2.read_byte() {
+            return c;
+        }
+    }
+}
+
+fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// 先頭の16進数を読み、残りと一緒に返す
+fn parse_hex(s: &[u8]) -> Option<(usize, &[u8])> {
+    let len = s.iter().take_while(|c| hex_digit(**c).is_some()).count();
+    if len == 0 {
+        return None;
+    }
+    let value = s[..len]
+        .iter()
+        .fold(0usize, |v, c| (v << 4) | hex_digit(*c).unwrap() as usize);
+    Some((value, &s[len..]))
+}
+
+/// 2文字ずつの16進数をバイト列として読む
+fn parse_bytes<'a>(s: &'a [u8]) -> impl Iterator<Item = Option<u8>> + 'a {
+    s.chunks(2).map(|c| {
+        if c.len() != 2 {
+            return None;
+        }
+        Some(hex_digit(c[0])? << 4 | hex_digit(c[1])?)
+    })
+}
+
+/// 4バイトずつのリトルエンディアンの値として読む
+fn parse_word(s: &[u8]) -> Option<u32> {
+    let mut bytes = [0; 4];
+    if s.len() != 8 {
+        return None;
+    }
+    for (b, v) in bytes.iter_mut().zip(parse_bytes(s)) {
+        *b = v?;
+    }
+    Some(u32::from_le_bytes(bytes))
+}
+
+/// $...#xxの中身をbufに読み、チェックサムが合えば+を返す。startedなら$は読み終わっている
+fn get_packet(buf: &mut [u8; PACKET_SIZE], mut started: bool) -> usize {
+    loop {
+        if !started {
+            while get_char() != b'$' {}
+        }
+        started = false;
+        let mut len = 0;
+        let mut sum = 0u8;
+        loop {
+            let c = get_char();
+            if c == b'$' {
+                // 途中から送り直された
+                len = 0;
+                sum = 0;
+                continue;
+            }
+            if c == b'#' {
+                break;
+            }
+            if len < PACKET_SIZE {
+                buf[len] = c;
+                len += 1;
+            }
+            sum = sum.wrapping_add(c);
+        }
+        let high = hex_digit(get_char());
+        let low = hex_digit(get_char());
+        if let (Some(high), Some(low)) = (high, low) {
+            if high << 4 | low == sum {
+                COM2.write_byte(b'+');
+                return len;
+            }
+        }
+        COM2.write_byte(b'-');
+    }
+}
+
+/// GDBが受け取ったと返すまで送り直す
+fn put_packet(data: &[u8]) {
+    loop {
+        COM2.write_byte(b'$');
+        let mut sum = 0u8;
+        for &b in data.iter() {
+            COM2.write_byte(b);
+            sum = sum.wrapping_add(b);
+        }
+        COM2.write_byte(b'#');
+        COM2.write_byte(HEX[(sum >> 4) as usize]);
+        COM2.write_byte(HEX[(sum & 0x0f) as usize]);
+        if get_char() != b'-' {
+            return;
+        }
+    }
+}
+
+fn find_breakpoint(linear: usize) -> Option<usize> {
+    (0..MAX_BREAKPOINTS).find(|&i| match unsafe { BREAKPOINTS[i] } {
+        Some(bp) => bp.linear == linear,
+        None => false,
+    })
+}
+
+/// ブレークポイントのINT3ではなく、もとの値を読む
+fn read_memory(linear: usize) -> u8 {
+    if let Some(i) = find_breakpoint(linear) {
+        return unsafe { BREAKPOINTS[i] }.unwrap().saved;
+    }
+    match unsafe { ENTRY_BREAK } {
+        Some(bp) if bp.linear == linear => bp.saved,
+        _ => unsafe { *(linear as *const u8) },
+    }
+}
+
+/// ブレークポイントを置いたところなら、INT3は残してもとの値のほうを書き換える
+fn write_memory(linear: usize, value: u8) {
+    let bp = Some(Breakpoint {
+        linear,
+        saved: value,
+    });
+    unsafe {
+        if let Some(i) = find_breakpoint(linear) {
+            BREAKPOINTS[i] = bp;
+        } else if ENTRY_BREAK.map_or(false, |bp| bp.linear == linear) {
+            ENTRY_BREAK = bp;
+        } else {
+            *(linear as *mut u8) = value;
+        }
+    }
+}
+
+fn insert_breakpoint(linear: usize) -> Option<()> {
+    if find_breakpoint(linear).is_some() {
+        return Some(());
+    }
+    let i = (0..MAX_BREAKPOINTS).find(|&i| unsafe { BREAKPOINTS[i] }.is_none())?;
+    let saved = read_memory(linear);
+    unsafe {
+        BREAKPOINTS[i] = Some(Breakpoint { linear, saved });
+        *(linear as *mut u8) = INT3;
+    }
+    Some(())
+}
+
+fn remove_breakpoint(linear: usize) {
+    if let Some(i) = find_breakpoint(linear) {
+        unsafe {
+            *(linear as *mut u8) = BREAKPOINTS[i].unwrap().saved;
+            BREAKPOINTS[i] = None;
+        }
+    }
+}
+
+fn remove_all_breakpoints() {
+    for i in 0..MAX_BREAKPOINTS {
+        if let Some(bp) = unsafe { BREAKPOINTS[i] } {
+            remove_breakpoint(bp.linear);
+        }
+    }
+}
+
+/// 1つのパケットを処理する。動き出すときはtrueを返す。Noneならエラーを返す
+fn handle(ctx: &mut Context, packet: &[u8], signal: u8, reply: &mut Packet) -> Option<bool> {
+    let (&cmd, args) = match packet.split_first() {
+        Some(split) => split,
+        None => return Some(false),
+    };
+    match cmd {
+        b'?' => {
+            reply.push(b'S');
+            reply.push_hex(signal);
+        }
+        b'g' => {
+            for n in 0..NUM_REGS {
+                match ctx.register(n) {
+                    Some(value) => reply.push_word(value),
+                    None => reply.push_str(b"xxxxxxxx"),
+                }
+            }
+        }
+        b'G' => {
+            for (n, word) in args.chunks(8).enumerate() {
+                // 読めないレジスタはxxxxxxxxで送られてくる
+                if let Some(value) = parse_word(word) {
+                    ctx.set_register(n, value);
+                }
+            }
+            reply.push_str(b"OK");
+        }
+        b'p' => {
+            let (n, _) = parse_hex(args)?;
+            match ctx.register(n) {
+                Some(value) => reply.push_word(value),
+                None => reply.push_str(b"xxxxxxxx"),
+            }
+        }
+        b'P' => {
+            let (n, rest) = parse_hex(args)?;
+            ctx.set_register(n, parse_word(rest.get(1..)?)?);
+            reply.push_str(b"OK");
+        }
+        b'm' => {
+            let (addr, rest) = parse_hex(args)?;
+            let (len, _) = parse_hex(rest.get(1..)?)?;
+            let len = len.min(PACKET_SIZE / 2);
+            let linear = ctx.linear(addr, len)?;
+            for i in 0..len {
+                reply.push_hex(read_memory(linear + i));
+            }
+        }
+        b'M' => {
+            let (addr, rest) = parse_hex(args)?;
+            let (len, rest) = parse_hex(rest.get(1..)?)?;
+            let data = rest.get(1..)?;
+            if data.len() != len * 2 {
+                return None;
+            }
+            let linear = ctx.linear(addr, len)?;
+            for (i, b) in parse_bytes(data).enumerate() {
+                write_memory(linear + i, b?);
+            }
+            reply.push_str(b"OK");
+        }
+        b'c' | b's' => {
+            if let Some((addr, _)) = parse_hex(args) {
+                ctx.frame.eip = addr as i32;
+            }
+            if cmd == b's' {
+                ctx.frame.eflags |= EFLAGS_TF;
+            } else {
+                ctx.frame.eflags &= !EFLAGS_TF;
+            }
+            unsafe {
+                WAITING = true;
+            }
+            return Some(true);
+        }
+        b'Z' | b'z' => {
+            // ソフトウェアブレークポイントだけ
+            if args.first() != Some(&b'0') {
+                return Some(false);
+            }
+            let (addr, _) = parse_hex(args.get(2..)?)?;
+            let linear = ctx.linear(addr, 1)?;
+            if cmd == b'Z' {
+                insert_breakpoint(linear)?;
+            } else {
+                remove_breakpoint(linear);
+            }
+            reply.push_str(b"OK");
+        }
+        b'D' | b'k' => {
+            // killでもアプリは終わらせず、切り離すだけにする
+            remove_all_breakpoints();
+            ctx.frame.eflags &= !EFLAGS_TF;
+            if cmd == b'D' {
+                put_packet(b"OK");
+            }
+            return Some(true);
+        }
+        b'q' if packet.starts_with(b"qSupported") => {
+            reply.push_str(b"PacketSize=");
+            reply.push_hex((PACKET_SIZE >> 8) as u8);
+            reply.push_hex(PACKET_SIZE as u8);
+        }
+        b'q' if packet == b"qAttached" => reply.push(b'1'),
+        // 知らないパケットには空で返す
+        _ => {}
+    }
+    Some(false)
+}
+
+/// 割り込みを禁止したまま、GDBのパケットを処理する。c、s、Dで割り込まれたところに戻る
+fn stop(frame: &'static mut UserFrame, signal: u8, mut started: bool) {
+    let mut ctx = Context::new(frame);
+    unsafe {
+        if WAITING {
+            WAITING = false;
+            let mut reply = Packet::new();
+            reply.push(b'S');
+            reply.push_hex(signal);
+            put_packet(reply.as_bytes());
+        }
+    }
+    let mut buf = [0; PACKET_SIZE];
+    loop {
+        let len = get_packet(&mut buf, started);
+        started = false;
+        let mut reply = Packet::new();
+        match handle(&mut ctx, &buf[..len], signal, &mut reply) {
+            Some(true) => return,
+            Some(false) => {}
+            None => {
+                reply = Packet::new();
+                reply.push_str(b"E01");
+            }
+        }
+        put_packet(reply.as_bytes());
+    }
+}
+
+/// デバッグ例外。sで1命令実行したところ
+pub extern "C" fn inthandler01(frame: *mut UserFrame) {
+    let frame = unsafe { &mut *frame };
+    frame.eflags &= !EFLAGS_TF;
+    if unsafe { PRESENT } {
+        stop(frame, GDB_SIGTRAP, false);
+    }
+}
+
+/// ブレークポイント。EIPはINT3の次を指している
+/// GDBが置いたものはGDBがEIPを戻すので、debugで置いたものだけここで戻す
+pub extern "C" fn inthandler03(frame: *mut UserFrame) {
+    if unsafe { !PRESENT } {
+        return;
+    }
+    let frame = unsafe { &mut *frame };
+    if let Some(bp) = unsafe { ENTRY_BREAK } {
+        let task_manager = unsafe { &*(TASK_MANAGER_ADDR as *const TaskManager) };
+        let task_index = task_manager.now_index();
+        let code_base = task_manager.tasks_data[task_index].ldt[0].base();
+        if frame.cs & 3 == 3 && code_base + frame.eip as usize - 1 == bp.linear {
+            unsafe {
+                *(bp.linear as *mut u8) = bp.saved;
+                ENTRY_BREAK = None;
+                TARGET = Some(task_index);
+            }
+            frame.eip -= 1;
+        }
+    }
+    stop(frame, GDB_SIGTRAP, false);
+}
+
+/// COM2からの割り込み。GDBのCtrl+C(0x03)か、動いている間に送られてきたパケットで止まる
+pub extern "C" fn inthandler23(frame: *mut UserFrame) {
+    out8(PIC0_OCW2, 0x63); // IRQ-03 受付終了
+    while let Some(c) = COM2.read_byte() {
+        if unsafe { PRESENT } && (c == 0x03 || c == b'$') {
+            let frame = unsafe { &mut *frame };
+            stop(frame, GDB_SIGINT, c == b'$');
+            return;
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use crate::file::FileHandler;
+
+pub const MAX_HANDLES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Handle {
+    Free,
+    File(FileHandler),
+    Window(usize),
+    Timer(usize),
+}
+
+/// アプリに渡すファイル・ウィンドウ・タイマの番号と、カーネル内部の番号との対応表
+/// アプリから見える番号は表の位置+1なので、0はいつも無効
+pub struct HandleTable {
+    pub handles: [Handle; MAX_HANDLES],
+}
+
+impl HandleTable {
+    pub fn new() -> HandleTable {
+        HandleTable {
+            handles: [Handle::Free; MAX_HANDLES],
+        }
+    }
+
+    pub fn insert(&mut self, handle: Handle) -> Option<i32> {
+        for i in 0..MAX_HANDLES {
+            if self.handles[i] == Handle::Free {
+                self.handles[i] = handle;
+                return Some(i as i32 + 1);
+            }
+        }
+        None
+    }
+
+    pub fn get_mut(&mut self, handle: i32) -> Option<&mut Handle> {
+        if handle <= 0 || handle as usize > MAX_HANDLES {
+            return None;
+        }
+        match self.handles[handle as usize - 1] {
+            Handle::Free => None,
+            _ => Some(&mut self.handles[handle as usize - 1]),
+        }
+    }
+
+    pub fn remove(&mut self, handle: i32) -> Option<Handle> {
+        let h = *self.get_mut(handle)?;
+        self.handles[handle as usize - 1] = Handle::Free;
+        Some(h)
+    }
+}
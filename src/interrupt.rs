@@ -35,6 +35,6 @@ pub fn init() {
 }
 
 pub fn allow_input() {
-    out8(PIC0_IMR, 0xf8); // PITとPIC1とキーボードを許可(11111000)
+    out8(PIC0_IMR, 0xe0); // PITとPIC1とキーボードとCOM2とCOM1を許可(11100000)
     out8(PIC1_IMR, 0xef); // マウスを許可(11101111)
 }
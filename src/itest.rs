@@ -0,0 +1,149 @@
+use core::str::from_utf8;
+
+use crate::asm::out8;
+use crate::console::Console;
+use crate::file::MAX_FAT;
+use crate::log;
+
+/// QEMUの-device isa-debug-exit,iobase=0xf4,iosize=0x04
+const ISA_DEBUG_EXIT_PORT: u32 = 0x00f4;
+// QEMUは(書いた値 << 1) | 1で終了する。成功なら33、失敗なら35になる
+const EXIT_SUCCESS: u8 = 0x10;
+const EXIT_FAILURE: u8 = 0x11;
+
+/// 失敗したときにログに出す出力の長さ
+const MAX_SHOWN_OUTPUT: usize = 64;
+
+/// 標準出力に書かれたものの確かめ方
+enum Expect {
+    Exact(&'static [u8]),
+    StartsWith(&'static [u8]),
+    EndsWith(&'static [u8]),
+    Contains(&'static [u8]),
+}
+
+impl Expect {
+    fn check(&self, output: &[u8]) -> bool {
+        match self {
+            Expect::Exact(s) => output == *s,
+            Expect::StartsWith(s) => output.starts_with(s),
+            Expect::EndsWith(s) => output.ends_with(s),
+            Expect::Contains(s) => output.windows(s.len()).any(|w| w == *s),
+        }
+    }
+}
+
+struct Case {
+    cmdline: &'static [u8],
+    status: i32,
+    expects: &'static [Expect],
+}
+
+/// 上から順に実行する。前のコマンドで作ったファイルを後で読んでもよい
+const CASES: [Case; 9] = [
+    Case {
+        cmdline: b"echo hello world",
+        status: 0,
+        expects: &[Expect::Exact(b"hello world\n")],
+    },
+    Case {
+        // make itestは-m 32でQEMUを起動する
+        cmdline: b"mem",
+        status: 0,
+        expects: &[Expect::StartsWith(b"total   32MB\nfree ")],
+    },
+    Case {
+        cmdline: b"ls",
+        status: 0,
+        expects: &[
+            Expect::Contains(b"SJIS    .TXT"),
+            Expect::Contains(b"PRIM    .HRB"),
+            Expect::Contains(b"NIHONGO .FNT"),
+        ],
+    },
+    Case {
+        cmdline: b"cat sjis.txt",
+        status: 0,
+        expects: &[Expect::Exact(include_bytes!("../texts/sjis.txt"))],
+    },
+    Case {
+        // ELFのアプリもSDKのヒープが使える
+        cmdline: b"hello a 'b c'",
+        status: 0,
+        expects: &[Expect::Exact(
+            b"hello, ELF\narg1 = a\narg2 = b c\nsum = 385\n",
+        )],
+    },
+    Case {
+        cmdline: b"prim",
+        status: 0,
+        expects: &[
+            Expect::StartsWith(b"2 3 5 7 11 13 "),
+            Expect::EndsWith(b" 983 991 997 "),
+        ],
+    },
+    Case {
+        cmdline: b"echo written > itest.txt",
+        status: 0,
+        expects: &[Expect::Exact(b"")],
+    },
+    Case {
+        cmdline: b"cat itest.txt",
+        status: 0,
+        expects: &[Expect::Exact(b"written\n")],
+    },
+    Case {
+        cmdline: b"nosuchcmd",
+        status: 1,
+        expects: &[Expect::Exact(b"")],
+    },
+];
+
+static mut STARTED: bool = false;
+
+/// 最初に呼ばれたコンソールでCASESを実行し、結果をログに書いてQEMUを終了する
+/// isa-debug-exitがなければそのままコンソールとして使える
+pub fn run(console: &mut Console, memtotal: usize, fat: &mut [u32; MAX_FAT]) {
+    if unsafe { STARTED } {
+        return;
+    }
+    unsafe { STARTED = true };
+    log!(Info, "itest: {} cases", CASES.len());
+    let mut failed = 0;
+    for case in CASES.iter() {
+        let cmdline = from_utf8(case.cmdline).unwrap_or("");
+        let output = console.run_captured(case.cmdline, memtotal, fat);
+        let status = console.status;
+        let ok = status == case.status
+            && case.expects.iter().all(|e| e.check(output.as_slice()));
+        if ok {
+            log!(Info, "itest: PASS {}", cmdline);
+        } else {
+            failed += 1;
+            let shown = &output.as_slice()[..output.size.min(MAX_SHOWN_OUTPUT)];
+            log!(
+                Error,
+                "itest: FAIL {} (status {}, expected {}) output {:?}",
+                cmdline,
+                status,
+                case.status,
+                from_utf8(shown).unwrap_or("(not utf-8)")
+            );
+        }
+        output.free();
+    }
+    if failed == 0 {
+        log!(Info, "itest: all {} cases passed", CASES.len());
+    } else {
+        log!(Error, "itest: {} of {} cases failed", failed, CASES.len());
+    }
+    exit(failed == 0);
+}
+
+/// isa-debug-exitでQEMUを終了する
+pub fn exit(success: bool) {
+    out8(
+        ISA_DEBUG_EXIT_PORT,
+        if success { EXIT_SUCCESS } else { EXIT_FAILURE },
+    );
+}
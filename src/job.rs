@@ -0,0 +1,309 @@
+use core::mem::size_of;
+use core::str::from_utf8;
+
+use haribote_core::args::{Args, MAX_CMD};
+
+use crate::abi::NSIG;
+use crate::asm::{cli, end_app, sti};
+use crate::console::{Console, CONSOLE_FIFO_SIZE, CONSOLE_JOB_DONE, STATUS_ABORTED};
+use crate::fifo::Fifo;
+use crate::file::{FileInfo, MAX_FAT};
+use crate::gdb;
+use crate::handle::HandleTable;
+use crate::log;
+use crate::memory::{MemMan, MEMMAN_ADDR};
+use crate::mt::{TaskManager, TASK_MANAGER_ADDR};
+use crate::signal::{self, SigAction};
+use crate::stdio::{Stdio, Stream};
+
+/// 1つのコンソールで同時に持てるジョブの数
+pub const MAX_JOBS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Running,
+    /// Ctrl+Zで止められた
+    Stopped,
+    /// アプリが終わり、コンソールが片付けるのを待っている
+    Done,
+}
+
+/// コンソールから起動したアプリ1つ分
+/// アプリはジョブごとのタスクで動き、出力は起動したコンソールに表示する
+pub struct Job {
+    pub task_index: usize,
+    /// 起動したコンソールのタスク。終わったらそのFIFOに知らせる
+    pub console_task_index: usize,
+    pub state: JobState,
+    /// アプリの終了ステータス
+    pub status: i32,
+    /// 強制終了させられた
+    pub aborted: bool,
+    /// &をつけて起動したか、止められてから裏に回された
+    pub background: bool,
+    /// debugで起動した。最初の命令で止まってGDBを待つ
+    pub debug: bool,
+    pub finfo: FileInfo,
+    pub args: Args,
+    /// api_cmdlineで返すコマンドライン
+    pub cmdline: [u8; MAX_CMD],
+    pub stdio: Stdio,
+    pub handles: HandleTable,
+    /// api_signalで登録されたハンドラ。シグナルの番号で引く
+    pub actions: [SigAction; NSIG as usize],
+    /// 届いていて、まだハンドラを呼んでいないシグナル。ビットの位置がシグナルの番号
+    pub pending: u32,
+    /// ハンドラを実行中なので届けないシグナル
+    pub blocked: u32,
+    /// api_alarmのタイマ
+    pub alarm_timer: Option<usize>,
+}
+
+impl Job {
+    /// 標準入出力がすべてコンソールにつながっている
+    /// パイプやリダイレクトのバッファはコンソールが片付けてしまうので、そうでないジョブは止められない
+    pub fn is_interactive(&self) -> bool {
+        self.stdio.streams.iter().all(|s| *s == Stream::Console)
+    }
+
+    fn fifo(&self, task_manager: &TaskManager) -> &'static mut Fifo {
+        let task = &task_manager.tasks_data[self.task_index];
+        unsafe { &mut *(task.fifo_addr as *mut Fifo) }
+    }
+
+    /// タスクを眠らせる。FIFOにキーやタイマが来ても起こさない
+    pub fn stop(&mut self, task_manager: &mut TaskManager) {
+        let fifo = self.fifo(task_manager);
+        cli();
+        fifo.task_index = None;
+        task_manager.sleep(self.task_index);
+        self.state = JobState::Stopped;
+        sti();
+    }
+
+    /// 止めたタスクをまた動かす
+    pub fn resume(&mut self, task_manager: &mut TaskManager) {
+        if self.state == JobState::Done {
+            return;
+        }
+        let fifo = self.fifo(task_manager);
+        cli();
+        fifo.task_index = Some(self.task_index);
+        self.state = JobState::Running;
+        task_manager.run(self.task_index, -1, 0);
+        sti();
+    }
+}
+
+/// コンソールのジョブの一覧。ジョブの番号は位置+1
+pub struct JobTable {
+    /// Jobの番地。0なら空き
+    jobs: [usize; MAX_JOBS],
+}
+
+impl JobTable {
+    pub fn new() -> JobTable {
+        JobTable {
+            jobs: [0; MAX_JOBS],
+        }
+    }
+
+    pub fn get(&self, number: usize) -> Option<&'static mut Job> {
+        match self.jobs.get(number.wrapping_sub(1)) {
+            Some(&addr) if addr != 0 => Some(unsafe { &mut *(addr as *mut Job) }),
+            _ => None,
+        }
+    }
+
+    /// 番号がいちばん大きい、まだ終わっていないジョブ
+    pub fn last(&self) -> Option<usize> {
+        (1..=MAX_JOBS).rev().find(|n| {
+            self.get(*n)
+                .map_or(false, |job| job.state != JobState::Done)
+        })
+    }
+
+    pub fn all_done(&self) -> bool {
+        (1..=MAX_JOBS).all(|n| self.get(n).map_or(true, |job| job.state == JobState::Done))
+    }
+
+    /// finfoのアプリを動かすタスクを作ってジョブにし、その番号を返す
+    /// 環境変数・FAT・言語モードは今のタスク(コンソール)のものを引き継ぐ
+    pub fn start(
+        &mut self,
+        task_manager: &mut TaskManager,
+        finfo: &FileInfo,
+        args: &Args,
+        stdio: Stdio,
+        background: bool,
+        debug: bool,
+    ) -> Result<usize, &'static str> {
+        let slot = self
+            .jobs
+            .iter()
+            .position(|addr| *addr == 0)
+            .ok_or("Too Many Jobs")?;
+        let memman = unsafe { &mut *(MEMMAN_ADDR as *mut MemMan) };
+        let job_addr = memman
+            .alloc_4k(size_of::<Job>() as u32)
+            .map_err(|_| "Out of Memory")? as usize;
+        let task_index = match task_manager.alloc() {
+            Ok(task_index) => task_index,
+            Err(e) => {
+                memman
+                    .free_4k(job_addr as u32, size_of::<Job>() as u32)
+                    .unwrap();
+                return Err(e);
+            }
+        };
+        let fifo_addr = memman.alloc_4k(CONSOLE_FIFO_SIZE as u32 * 4);
+        let console_stack = memman.alloc_4k(64 * 1024);
+        let (fifo_addr, console_stack) = match (fifo_addr, console_stack) {
+            (Ok(fifo_addr), Ok(console_stack)) => (fifo_addr as usize, console_stack as usize),
+            (fifo_addr, console_stack) => {
+                // 確保できた分を返して、ジョブを作らずに終わる
+                if let Ok(addr) = fifo_addr {
+                    memman.free_4k(addr, CONSOLE_FIFO_SIZE as u32 * 4).unwrap();
+                }
+                if let Ok(addr) = console_stack {
+                    memman.free_4k(addr, 64 * 1024).unwrap();
+                }
+                task_manager.free(task_index);
+                memman
+                    .free_4k(job_addr as u32, size_of::<Job>() as u32)
+                    .unwrap();
+                return Err("Out of Memory");
+            }
+        };
+        let parent = task_manager.tasks_data[task_manager.now_index()];
+        let job = unsafe { &mut *(job_addr as *mut Job) };
+        *job = Job {
+            task_index,
+            console_task_index: task_manager.now_index(),
+            state: JobState::Running,
+            status: 0,
+            aborted: false,
+            background,
+            debug,
+            finfo: *finfo,
+            args: *args,
+            cmdline: [0; MAX_CMD],
+            stdio,
+            handles: HandleTable::new(),
+            actions: [SigAction::new(); NSIG as usize],
+            pending: 0,
+            blocked: 0,
+            alarm_timer: None,
+        };
+        if parent.cmdline_addr != 0 {
+            job.cmdline = unsafe { *(parent.cmdline_addr as *const [u8; MAX_CMD]) };
+        }
+
+        let mut task = &mut task_manager.tasks_data[task_index];
+        let fifo = unsafe { &mut *(fifo_addr as *mut Fifo) };
+        *fifo = Fifo::new(CONSOLE_FIFO_SIZE as u32, Some(task_index));
+        task.fifo_addr = fifo_addr;
+
+        task.console_stack = console_stack;
+        task.tss.esp = task.console_stack as i32 + 64 * 1024 - 8;
+        task.tss.eip = job_task as i32;
+        task.tss.es = 1 * 8;
+        task.tss.cs = 2 * 8;
+        task.tss.ss = 1 * 8;
+        task.tss.ds = 1 * 8;
+        task.tss.fs = 1 * 8;
+        task.tss.gs = 1 * 8;
+        task.tss.ss0 = 0;
+        let ptr = unsafe { &mut *((task.tss.esp + 4) as *mut usize) };
+        *ptr = job_addr;
+
+        task.console_addr = parent.console_addr;
+        task.handle_table_addr = &mut job.handles as *mut HandleTable as usize;
+        task.fat_addr = parent.fat_addr;
+        task.cmdline_addr = job.cmdline.as_ptr() as usize;
+        task.args_addr = &job.args as *const Args as usize;
+        task.env_addr = parent.env_addr;
+        task.stdio_addr = &mut job.stdio as *mut Stdio as usize;
+        task.lang_mode = parent.lang_mode;
+        task.job_addr = job_addr;
+
+        self.jobs[slot] = job_addr;
+        task_manager.run(task_index, 2, 2);
+        Ok(slot + 1)
+    }
+
+    /// 終わったジョブのタスクを閉じ、一覧から外す
+    pub fn remove(&mut self, number: usize, task_manager: &mut TaskManager) {
+        if let Some(job) = self.get(number) {
+            // 遅れて届いたアラームなどでJobを見に行かないようにする
+            task_manager.tasks_data[job.task_index].job_addr = 0;
+            task_manager.close_task(job.task_index);
+            let memman = unsafe { &mut *(MEMMAN_ADDR as *mut MemMan) };
+            memman
+                .free_4k(self.jobs[number - 1] as u32, size_of::<Job>() as u32)
+                .unwrap();
+            self.jobs[number - 1] = 0;
+        }
+    }
+}
+
+/// task_indexのタスクで動いているアプリを強制終了させる
+/// アプリの中にいればend_appに飛ばしてcmd_appに戻し、まだ始まっていなければ次のシステムコールで終わらせる
+pub fn abort_app(task_manager: &mut TaskManager, task_index: usize, message: &[u8]) {
+    let mut task = &mut task_manager.tasks_data[task_index];
+    if task.job_addr == 0 {
+        return;
+    }
+    let job = unsafe { &mut *(task.job_addr as *mut Job) };
+    if job.state == JobState::Done || job.aborted {
+        return;
+    }
+    if !message.is_empty() {
+        let console = unsafe { &mut *(task.console_addr as *mut Console) };
+        console.put_string(message.as_ptr() as usize, message.len(), Some(8));
+    }
+    job.aborted = true;
+    job.status = STATUS_ABORTED;
+    cli();
+    if task.tss.ss0 != 0 {
+        task.tss.eax = unsafe { &task.tss.esp0 } as *const i32 as i32;
+        task.tss.eip = end_app as i32;
+    }
+    sti();
+    job.resume(task_manager);
+}
+
+/// ジョブのタスク。アプリを実行し、終わったらコンソールに知らせて片付けられるまで眠る
+pub extern "C" fn job_task(job_addr: usize) {
+    let task_manager = unsafe { &mut *(TASK_MANAGER_ADDR as *mut TaskManager) };
+    let task_index = task_manager.now_index();
+    let task = task_manager.tasks_data[task_index];
+    let job = unsafe { &mut *(job_addr as *mut Job) };
+    let console = unsafe { &mut *(task.console_addr as *mut Console) };
+    let fat = unsafe { &*(task.fat_addr as *const [u32; MAX_FAT]) };
+    if let Err(e) = console.cmd_app(&job.finfo, fat) {
+        console.put_error(e);
+        job.status = 1;
+    }
+    signal::free_alarm(job);
+    if job.debug {
+        gdb::exited(job.status);
+    }
+    log!(
+        Debug,
+        "{} exited with {}",
+        job.args
+            .get(0)
+            .and_then(|s| from_utf8(s).ok())
+            .unwrap_or(""),
+        job.status
+    );
+    let console_task = &task_manager.tasks_data[job.console_task_index];
+    let console_fifo = unsafe { &*(console_task.fifo_addr as *const Fifo) };
+    cli();
+    job.state = JobState::Done;
+    let _ = console_fifo.put(CONSOLE_JOB_DONE);
+    loop {
+        task_manager.sleep(task_index);
+    }
+}
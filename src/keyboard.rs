@@ -1,6 +1,7 @@
 use lazy_static::lazy_static;
 
 use crate::asm::{in8, out8};
+use crate::boot_info::boot_info;
 use crate::fifo::Fifo;
 use crate::interrupt::{PIC0_OCW2, PORT_KEYCMD, PORT_KEYDAT};
 
@@ -50,8 +51,7 @@ impl LockKeys {
 
 lazy_static! {
     pub static ref LOCK_KEYS: LockKeys = {
-        let keys = unsafe { *(0x0ff1 as *const i16) };
-        let keys = keys >> 4;
+        let keys = boot_info().leds >> 4;
         LockKeys {
             scroll_lock: (keys & 1) != 0,
             num_lock: (keys & 2) != 0,
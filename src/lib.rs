@@ -3,27 +3,48 @@
 #![feature(start)]
 #![feature(naked_functions)]
 
+mod abi;
+mod api;
 mod asm;
+mod boot_info;
 mod console;
 mod descriptor_table;
 mod fifo;
 mod file;
 mod fonts;
+mod gdb;
+mod handle;
 mod interrupt;
+#[cfg(feature = "integration-test")]
+mod itest;
+mod job;
 mod keyboard;
+mod log;
 mod memory;
 mod mouse;
 mod mt;
+mod multiboot;
+mod panic;
+mod rtc;
+mod scrollback;
+mod serial;
 mod sheet;
+mod signal;
+mod stdio;
 mod timer;
 mod vga;
 mod window;
 
-use core::fmt::Write;
 use core::panic::PanicInfo;
 
-use asm::{cli, end_app, out8, sti};
-use console::{console_task, Console, CONSOLE_BACKSPACE, CONSOLE_ENTER};
+use asm::{cli, hlt, out8, sti};
+use boot_info::BootInfo;
+use console::{
+    console_task, console_window_size, CONSOLE_BACKSPACE, CONSOLE_COLUMNS, CONSOLE_DELETE,
+    CONSOLE_DOWN, CONSOLE_END, CONSOLE_ENTER, CONSOLE_FIFO_SIZE, CONSOLE_HOME, CONSOLE_INTERRUPT,
+    CONSOLE_LEFT, CONSOLE_PAGE_DOWN, CONSOLE_PAGE_UP, CONSOLE_RIGHT, CONSOLE_ROWS,
+    CONSOLE_SCROLL_DOWN, CONSOLE_SCROLL_UP, CONSOLE_TAB, CONSOLE_UP,
+};
 use fifo::Fifo;
 use file::*;
 use fonts::HANKAKU;
@@ -32,26 +53,41 @@ use keyboard::{wait_kbc_sendready, KEYBOARD_OFFSET, KEYCMD_LED, KEYTABLE0, KEYTA
 use memory::{MemMan, MEMMAN_ADDR};
 use mouse::{Mouse, MouseDec, MOUSE_CURSOR_HEIGHT, MOUSE_CURSOR_WIDTH};
 use mt::{TaskManager, TASK_MANAGER_ADDR};
+use serial::SERIAL_OFFSET;
 use sheet::{SheetFlag, SheetManager};
+use signal::send as send_signal;
+use timer::TIMER_MANAGER;
 use vga::{
-    init_palette, init_screen, make_textbox, make_window, to_color, Color, ScreenWriter,
-    SCREEN_HEIGHT, SCREEN_WIDTH,
+    init_palette, init_screen, make_textbox, make_window, to_color, Color, SCREEN_HEIGHT,
+    SCREEN_WIDTH,
 };
 use window::*;
 
 pub static mut SHEET_MANAGER_ADDR: usize = 0;
-const CONSOLE_WIDTH: usize = 256;
-const CONSOLE_HEIGHT: usize = 165;
-pub const TASK_A_FIFO_ADDR: usize = 0xfec;
+pub static mut TASK_A_FIFO_ADDR: usize = 0;
 pub const EXIT_OFFSET: usize = 768;
 pub const EXIT_TASK_OFFSET: usize = 1024;
 pub const EXIT_ONLY_CONSOLE_OFFSET: usize = 2024;
 pub const EXIT_CONSOLE: u32 = 4;
-pub const NIHONGO_ADDR: usize = 0x0fe8;
+pub static mut NIHONGO_ADDR: usize = 0;
+const TASKBAR_CLOCK: u32 = 1;
 
 #[no_mangle]
 #[start]
-pub extern "C" fn hrmain() {
+pub extern "C" fn hrmain(boot_info: *const BootInfo) {
+    log::init();
+    if let Err(e) = boot_info::init(boot_info) {
+        // 画面の大きさもわからないので、ログだけ残して止まる
+        log!(Error, "{}", e);
+        loop {
+            hlt();
+        }
+    }
+    let multiboot_info = boot_info::boot_info().multiboot_info;
+    if multiboot_info != 0 {
+        // モジュールはメモリ管理に渡す領域にあるので、先にディスクイメージに写しておく
+        file::load_modules(multiboot::info(multiboot_info).modules());
+    }
     descriptor_table::init();
     interrupt::init();
     sti();
@@ -59,20 +95,33 @@ pub extern "C" fn hrmain() {
 
     let mut fifo = &mut Fifo::new(128, None);
     let fifo_addr = fifo as *const Fifo as usize;
-    let task_a_fifo_addr_ptr = unsafe { &mut *(TASK_A_FIFO_ADDR as *mut usize) };
-    *task_a_fifo_addr_ptr = fifo_addr;
+    unsafe { TASK_A_FIFO_ADDR = fifo_addr };
 
     keyboard::init_keyboard(fifo_addr);
+    serial::init_serial(fifo_addr);
     timer::init_pit();
+    timer::calibrate_tsc();
     init_palette();
     mouse::enable_mouse(fifo_addr);
 
-    let memtotal = memory::memtest(0x00400000, 0xbfffffff);
+    let memtotal = match boot_info::boot_info().memory_end {
+        0 => memory::memtest(0x00400000, 0xbfffffff),
+        memory_end => memory_end,
+    };
     let memman = unsafe { &mut *(MEMMAN_ADDR as *mut MemMan) };
     *memman = MemMan::new();
     memman.free(0x00001000, 0x0009e000).unwrap();
     memman.free(0x00400000, 2).unwrap();
     memman.free(0x00400000, memtotal - 0x00400000).unwrap();
+    log!(
+        Info,
+        "memory {}MB, free {}KB",
+        memtotal / (1024 * 1024),
+        memman.total() / 1024
+    );
+    if gdb::init_gdb() {
+        log!(Info, "gdb stub on COM2");
+    }
 
     let task_manager_addr = memman
         .alloc_4k(core::mem::size_of::<TaskManager>() as u32)
@@ -122,7 +171,13 @@ pub extern "C" fn hrmain() {
 
     task_manager.run(task_a_index, 1, 2);
 
-    let mut active_window = open_console(sheet_manager, task_manager, memtotal);
+    let mut active_window = open_console(
+        sheet_manager,
+        task_manager,
+        memtotal,
+        CONSOLE_COLUMNS,
+        CONSOLE_ROWS,
+    );
 
     sheet_manager.slide(shi_mouse, mx, my);
     sheet_manager.slide(active_window, 56, 6);
@@ -132,8 +187,20 @@ pub extern "C" fn hrmain() {
 
     window_on(sheet_manager, task_manager, active_window);
 
+    // タスクバーの時計は1秒ごとに更新する
+    let clock_timer_index = TIMER_MANAGER.lock().alloc().unwrap();
+    TIMER_MANAGER
+        .lock()
+        .init_timer(clock_timer_index, fifo_addr, TASKBAR_CLOCK as i32);
+    TIMER_MANAGER.lock().set_periodic(clock_timer_index, 100);
+    draw_clock(sheet_manager, shi_bg);
+
     // シフトキー
     let mut key_shift = (false, false);
+    // Ctrlキー
+    let mut key_ctrl = false;
+    // 直前に0xe0(拡張スキャンコードの1バイト目)を受け取ったか
+    let mut key_e0 = false;
     // CapsLock, NumLock, ScreenLock
     let mut lock_keys = *LOCK_KEYS;
     let mut keycmd_wait: i32 = -1;
@@ -145,13 +212,12 @@ pub extern "C" fn hrmain() {
     let nihongo_addr = memman.alloc_4k(16 * 256 + 32 * 94 * 47).unwrap() as usize;
     let fat_addr = memman.alloc_4k(4 * MAX_FAT as u32).unwrap();
     let fat = unsafe { &mut *(fat_addr as *mut [u32; MAX_FAT]) };
-    read_fat(fat, unsafe {
-        *((ADR_DISKIMG + 0x000200) as *const [u8; (MAX_FAT * 4)])
-    });
+    read_fat(fat);
     let finfo = search_file(b"nihongo.fnt");
     if let Some(finfo) = finfo {
-        finfo.load_file(nihongo_addr, fat, ADR_DISKIMG + 0x003e00)
+        load_file(&finfo, nihongo_addr, fat)
     } else {
+        log!(Warn, "nihongo.fnt not found");
         for i in 0..(16 * 256) {
             let font_index = i % 256;
             let byte_index = i - font_index;
@@ -170,8 +236,7 @@ pub extern "C" fn hrmain() {
             *ptr = 0xff;
         }
     }
-    let nihongo_ptr = unsafe { &mut *((NIHONGO_ADDR) as *mut usize) };
-    *nihongo_ptr = nihongo_addr;
+    unsafe { NIHONGO_ADDR = nihongo_addr };
     memman.free_4k(fat_addr, 4 * MAX_FAT as u32).unwrap();
 
     // ウィンドウの移動
@@ -212,8 +277,42 @@ pub extern "C" fn hrmain() {
 
             if KEYBOARD_OFFSET <= i && i <= 511 {
                 let key = i - KEYBOARD_OFFSET;
+                let extended = key_e0;
+                key_e0 = key == 0xe0;
                 let mut chr = 0 as u8;
-                if key < KEYTABLE0.len() as u32 {
+                if extended {
+                    // 矢印キーなどはテンキーと同じスキャンコードの前に0xe0がつく
+                    let code = match key {
+                        0x47 => CONSOLE_HOME,
+                        0x48 => CONSOLE_UP,
+                        0x4b => CONSOLE_LEFT,
+                        0x4d => CONSOLE_RIGHT,
+                        0x4f => CONSOLE_END,
+                        0x50 => CONSOLE_DOWN,
+                        0x53 => CONSOLE_DELETE,
+                        _ => 0,
+                    };
+                    if code != 0 && active_window != 0 {
+                        let ctask = task_manager.tasks_data[active_sheet.task_index];
+                        let fifo = unsafe { &*(ctask.fifo_addr as *const Fifo) };
+                        fifo.put(code + KEYBOARD_OFFSET).unwrap();
+                    }
+                    // Shift + PageUp/PageDown でコンソールをスクロール
+                    if (key == 0x49 || key == 0x51)
+                        && key_shift != (false, false)
+                        && active_window != 0
+                        && active_sheet.cursor
+                    {
+                        let ctask = task_manager.tasks_data[active_sheet.task_index];
+                        let fifo = unsafe { &*(ctask.fifo_addr as *const Fifo) };
+                        let message = if key == 0x49 {
+                            CONSOLE_PAGE_UP
+                        } else {
+                            CONSOLE_PAGE_DOWN
+                        };
+                        fifo.put(message).unwrap();
+                    }
+                } else if key < KEYTABLE0.len() as u32 {
                     if key_shift == (false, false) {
                         chr = KEYTABLE0[key as usize];
                     } else {
@@ -227,6 +326,10 @@ pub extern "C" fn hrmain() {
                     {
                         chr += 0x20;
                     }
+                    if key_ctrl {
+                        // Ctrl+Dなどは制御文字として送る
+                        chr &= 0x1f;
+                    }
                 }
                 if chr != 0 && active_window != 0 {
                     let ctask = task_manager.tasks_data[active_sheet.task_index];
@@ -245,8 +348,14 @@ pub extern "C" fn hrmain() {
                     let fifo = unsafe { &*(ctask.fifo_addr as *const Fifo) };
                     fifo.put(CONSOLE_BACKSPACE + KEYBOARD_OFFSET).unwrap();
                 }
-                // タブ
-                if key == 0x0f && active_window != 0 {
+                // タブはコンソールの補完に使う
+                if key == 0x0f && !key_ctrl && active_window != 0 {
+                    let ctask = task_manager.tasks_data[active_sheet.task_index];
+                    let fifo = unsafe { &*(ctask.fifo_addr as *const Fifo) };
+                    fifo.put(CONSOLE_TAB + KEYBOARD_OFFSET).unwrap();
+                }
+                // Ctrl + Tab でウィンドウを切り替える
+                if key == 0x0f && key_ctrl && active_window != 0 {
                     window_off(sheet_manager, task_manager, active_window);
                     let mut j = active_sheet.z.unwrap() - 1;
                     if j == 0 && sheet_manager.z_max.is_some() && sheet_manager.z_max.unwrap() > 0 {
@@ -267,6 +376,13 @@ pub extern "C" fn hrmain() {
                         },
                     );
                 }
+                // Ctrl ON/OFF
+                if key == 0x1d {
+                    key_ctrl = true;
+                }
+                if key == 0x9d {
+                    key_ctrl = false;
+                }
                 // 左シフト ON
                 if key == 0x2a {
                     key_shift.0 = true;
@@ -306,31 +422,26 @@ pub extern "C" fn hrmain() {
                     if active_window != 0 {
                         window_off(sheet_manager, task_manager, active_window);
                     }
-                    active_window = open_console(sheet_manager, task_manager, memtotal);
+                    active_window = open_console(
+                        sheet_manager,
+                        task_manager,
+                        memtotal,
+                        CONSOLE_COLUMNS,
+                        CONSOLE_ROWS,
+                    );
                     sheet_manager.slide(active_window, 32, 4);
                     sheet_manager.updown(active_window, sheet_manager.z_max);
                     window_on(sheet_manager, task_manager, active_window);
                 }
-                // Shift + F1 でアプリケーションを強制終了
-                {
-                    let tmp_task_index = active_sheet.task_index;
-                    let mut console_task_mut =
-                        &mut task_manager.tasks_data[active_sheet.task_index];
-                    if key == 0x3b
-                        && (key_shift.0 == true || key_shift.1 == true)
-                        && console_task_mut.tss.ss0 != 0
-                        && active_window != 0
-                    {
-                        let console =
-                            unsafe { &mut *(console_task_mut.console_addr as *mut Console) };
-                        let message = b"\nBreak(key) :\n";
-                        console.put_string(message.as_ptr() as usize, message.len(), Some(8));
-                        cli();
-                        console_task_mut.tss.eax =
-                            unsafe { &console_task_mut.tss.esp0 } as *const i32 as i32;
-                        console_task_mut.tss.eip = end_app as i32;
-                        sti();
-                        task_manager.run(tmp_task_index, -1, 0);
+                // Shift + F1 でアプリケーションにSIGINTを送る(ハンドラがなければ強制終了)
+                // コンソールのウィンドウならフォアグラウンドのジョブにCtrl+Cを送る
+                if key == 0x3b && key_shift != (false, false) && active_window != 0 {
+                    let task = task_manager.tasks_data[active_sheet.task_index];
+                    if task.job_addr != 0 {
+                        send_signal(task_manager, active_sheet.task_index, abi::SIGINT);
+                    } else if active_sheet.cursor {
+                        let fifo = unsafe { &*(task.fifo_addr as *const Fifo) };
+                        fifo.put(CONSOLE_INTERRUPT + KEYBOARD_OFFSET).unwrap();
                     }
                 }
                 // F11 で 1 の位置にあるSheetを下げる
@@ -351,6 +462,20 @@ pub extern "C" fn hrmain() {
                 }
             } else if 512 <= i && i <= 767 {
                 if mouse_dec.decode((i - 512) as u8).is_some() {
+                    // ホイールでコンソールをスクロール
+                    let wheel = mouse_dec.wheel.get();
+                    if wheel != 0 && active_window != 0 && active_sheet.cursor {
+                        let ctask = task_manager.tasks_data[active_sheet.task_index];
+                        let fifo = unsafe { &*(ctask.fifo_addr as *const Fifo) };
+                        let message = if wheel < 0 {
+                            CONSOLE_SCROLL_UP
+                        } else {
+                            CONSOLE_SCROLL_DOWN
+                        };
+                        for _ in 0..wheel.abs() {
+                            let _ = fifo.put(message);
+                        }
+                    }
                     let (new_x, new_y) = sheet_manager.get_new_point(
                         shi_mouse,
                         mouse_dec.x.get(),
@@ -413,30 +538,11 @@ pub extern "C" fn hrmain() {
                                             {
                                                 //×ボタンクリック
                                                 if sheet.from_app {
-                                                    let task =
-                                                        task_manager.tasks_data[sheet.task_index];
-                                                    let console = unsafe {
-                                                        &mut *(task.console_addr as *mut Console)
-                                                    };
-                                                    let message = b"\nBreak(mouse) :\n";
-                                                    console.put_string(
-                                                        message.as_ptr() as usize,
-                                                        message.len(),
-                                                        Some(8),
+                                                    send_signal(
+                                                        task_manager,
+                                                        sheet.task_index,
+                                                        abi::SIGCLOSE,
                                                     );
-                                                    cli();
-                                                    {
-                                                        let mut console_task_mut =
-                                                            &mut task_manager.tasks_data
-                                                                [sheet.task_index];
-                                                        console_task_mut.tss.eax =
-                                                            unsafe { &console_task_mut.tss.esp0 }
-                                                                as *const i32
-                                                                as i32;
-                                                        console_task_mut.tss.eip = end_app as i32;
-                                                    }
-                                                    sti();
-                                                    task_manager.run(sheet.task_index, -1, 0);
                                                 } else {
                                                     // コンソールのクローズ
                                                     let task =
@@ -488,10 +594,24 @@ pub extern "C" fn hrmain() {
                 memman
                     .free_4k(
                         free_sheet.buf_addr as u32,
-                        (CONSOLE_WIDTH * CONSOLE_HEIGHT) as u32,
+                        (free_sheet.width * free_sheet.height) as u32,
                     )
                     .unwrap();
                 sheet_manager.free(free_sheet_index);
+            } else if SERIAL_OFFSET <= i && i < SERIAL_OFFSET + 256 {
+                // シリアルポートから来た文字は、アクティブなウィンドウへのキー入力として扱う
+                let code = match i - SERIAL_OFFSET {
+                    0x0d => CONSOLE_ENTER,
+                    0x08 | 0x7f => CONSOLE_BACKSPACE,
+                    c => c,
+                };
+                if active_window != 0 {
+                    let ctask = task_manager.tasks_data[active_sheet.task_index];
+                    let fifo = unsafe { &*(ctask.fifo_addr as *const Fifo) };
+                    let _ = fifo.put(code + KEYBOARD_OFFSET);
+                }
+            } else if i == TASKBAR_CLOCK {
+                draw_clock(sheet_manager, shi_bg);
             }
         } else {
             if new_mx >= 0 {
@@ -519,9 +639,9 @@ pub fn open_console_task(
     let console_task_index = task_manager.alloc().unwrap();
     let mut console_task_mut = &mut task_manager.tasks_data[console_task_index];
 
-    let console_fifo_addr = memman.alloc_4k(128 * 4).unwrap() as usize;
+    let console_fifo_addr = memman.alloc_4k(CONSOLE_FIFO_SIZE as u32 * 4).unwrap() as usize;
     let console_fifo = unsafe { &mut *(console_fifo_addr as *mut Fifo) };
-    *console_fifo = Fifo::new(128, Some(console_task_index));
+    *console_fifo = Fifo::new(CONSOLE_FIFO_SIZE as u32, Some(console_task_index));
     console_task_mut.fifo_addr = console_fifo_addr;
 
     console_task_mut.console_stack = memman.alloc_4k(64 * 1024).unwrap() as usize;
@@ -533,6 +653,7 @@ pub fn open_console_task(
     console_task_mut.tss.ds = 1 * 8;
     console_task_mut.tss.fs = 1 * 8;
     console_task_mut.tss.gs = 1 * 8;
+    console_task_mut.job_addr = 0;
 
     let ptr = unsafe { &mut *((console_task_mut.tss.esp + 4) as *mut usize) };
     *ptr = sheet_index;
@@ -542,37 +663,33 @@ pub fn open_console_task(
     console_task_index
 }
 
+/// columns x rows文字のコンソールを開く
 pub fn open_console(
     sheet_manager: &mut SheetManager,
     task_manager: &mut TaskManager,
     memtotal: u32,
+    columns: usize,
+    rows: usize,
 ) -> usize {
     let console_sheet = sheet_manager.alloc().unwrap();
     let memman = unsafe { &mut *(MEMMAN_ADDR as *mut MemMan) };
-    let console_buf = memman
-        .alloc_4k((CONSOLE_WIDTH * CONSOLE_HEIGHT) as u32)
-        .unwrap() as usize;
-    sheet_manager.set_buf(
-        console_sheet,
-        console_buf,
-        CONSOLE_WIDTH as i32,
-        CONSOLE_HEIGHT as i32,
-        None,
-    );
+    let (width, height) = console_window_size(columns, rows);
+    let console_buf = memman.alloc_4k((width * height) as u32).unwrap() as usize;
+    sheet_manager.set_buf(console_sheet, console_buf, width, height, None);
     make_window(
         console_buf,
-        CONSOLE_WIDTH as isize,
-        CONSOLE_HEIGHT as isize,
+        width as isize,
+        height as isize,
         "console",
         false,
     );
     make_textbox(
         console_buf,
-        CONSOLE_WIDTH as isize,
+        width as isize,
         8,
         28,
-        240,
-        128,
+        columns as isize * 8,
+        rows as isize * 16,
         Color::Black,
     );
     {
@@ -583,18 +700,27 @@ pub fn open_console(
     console_sheet
 }
 
+fn draw_clock(sheet_manager: &mut SheetManager, shi_bg: usize) {
+    let now = rtc::now();
+    let scrnx = *SCREEN_WIDTH as i32;
+    let scrny = *SCREEN_HEIGHT as i32;
+    write_with_bg!(
+        sheet_manager,
+        shi_bg,
+        scrnx,
+        scrny,
+        scrnx - 45,
+        scrny - 21,
+        Color::Black,
+        Color::LightGray,
+        5,
+        "{:>02}:{:>02}",
+        now.hour,
+        now.minute
+    );
+}
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    let mut writer = ScreenWriter::new(
-        None,
-        Color::LightRed,
-        0,
-        0,
-        *SCREEN_WIDTH as usize,
-        *SCREEN_HEIGHT as usize,
-    );
-    write!(writer, "[ERR] {:?}", info).unwrap();
-    loop {
-        asm::hlt()
-    }
+    panic::show(info)
 }
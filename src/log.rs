@@ -0,0 +1,178 @@
+use core::fmt::{self, Write};
+
+use crate::asm::{cli, load_eflags, store_eflags};
+use crate::serial;
+use crate::timer;
+
+const LOG_SIZE: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN ",
+            Level::Info => "INFO ",
+            Level::Debug => "DEBUG",
+        }
+    }
+
+    pub fn parse(s: &[u8]) -> Option<Level> {
+        match s {
+            b"error" => Some(Level::Error),
+            b"warn" => Some(Level::Warn),
+            b"info" => Some(Level::Info),
+            b"debug" => Some(Level::Debug),
+            _ => None,
+        }
+    }
+}
+
+/// カーネルのログ。シリアルポートに送り、dmesgで読めるようにリングバッファにもためる
+pub struct Log {
+    /// これより詳しいログは捨てる
+    pub level: Level,
+    /// 次に書く位置
+    head: usize,
+    /// たまっているバイト数。LOG_SIZEになったら古いものから上書きする
+    len: usize,
+    /// 末尾のうち、まだシリアルポートに送っていないバイト数
+    unsent: usize,
+    /// flush_serialで送っている途中
+    flushing: bool,
+    buf: [u8; LOG_SIZE],
+}
+
+// 全部0にしておくと.bssに置かれ、イメージが大きくならない。levelはinitで設定する
+static mut LOG: Log = Log {
+    level: Level::Error,
+    head: 0,
+    len: 0,
+    unsent: 0,
+    flushing: false,
+    buf: [0; LOG_SIZE],
+};
+
+impl Log {
+    fn get() -> &'static mut Log {
+        unsafe { &mut LOG }
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        for &b in data.iter() {
+            self.buf[self.head] = b;
+            self.head = (self.head + 1) % LOG_SIZE;
+        }
+        self.len = (self.len + data.len()).min(LOG_SIZE);
+        self.unsent = (self.unsent + data.len()).min(LOG_SIZE);
+    }
+}
+
+impl fmt::Write for Log {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// 何よりも先に呼ぶ
+pub fn init() {
+    let log = Log::get();
+    log.level = Level::Info;
+    log.head = 0;
+    log.len = 0;
+    log.unsent = 0;
+    log.flushing = false;
+}
+
+pub fn set_level(level: Level) {
+    Log::get().level = level;
+}
+
+/// [秒.ミリ秒] レベル メッセージ の形で1行書く。割り込みハンドラからも呼べる
+pub fn write_log(level: Level, args: fmt::Arguments) {
+    let log = Log::get();
+    if level > log.level {
+        return;
+    }
+    let msec = timer::nsec() / 1_000_000;
+    let eflags = load_eflags();
+    cli();
+    let _ = write!(
+        log,
+        "[{:>5}.{:03}] {} {}\n",
+        msec / 1000,
+        msec % 1000,
+        level.as_str(),
+        args
+    );
+    store_eflags(eflags);
+    flush_serial();
+}
+
+/// まだ送っていないログをシリアルポートに送る。UARTを待つ間は割り込みを止めない。
+/// 送っている途中に割り込みハンドラが書いたログは、送っている側がまとめて送る
+fn flush_serial() {
+    let log = Log::get();
+    let eflags = load_eflags();
+    cli();
+    if log.flushing {
+        store_eflags(eflags);
+        return;
+    }
+    log.flushing = true;
+    while log.unsent > 0 {
+        let b = log.buf[(log.head + LOG_SIZE - log.unsent) % LOG_SIZE];
+        log.unsent -= 1;
+        store_eflags(eflags);
+        serial::write(&[b]);
+        cli();
+    }
+    log.flushing = false;
+    store_eflags(eflags);
+}
+
+/// たまっているログを古い順に渡す。あふれていたら途中から始まる最初の行は飛ばす
+pub fn read<F: FnMut(&[u8])>(mut f: F) {
+    let log = Log::get();
+    let eflags = load_eflags();
+    cli();
+    let (head, len) = (log.head, log.len);
+    store_eflags(eflags);
+    let start = (head + LOG_SIZE - len) % LOG_SIZE;
+    let mut skip = len == LOG_SIZE;
+    let mut chunks = [&log.buf[start..], &log.buf[..head]];
+    if start < head || len == 0 {
+        chunks[0] = &log.buf[start..head];
+        chunks[1] = &[];
+    }
+    for chunk in chunks.iter() {
+        let mut chunk = *chunk;
+        if skip {
+            match chunk.iter().position(|b| *b == b'\n') {
+                Some(i) => {
+                    chunk = &chunk[i + 1..];
+                    skip = false;
+                }
+                None => continue,
+            }
+        }
+        if !chunk.is_empty() {
+            f(chunk);
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! log {
+    ($level: ident, $($arg: tt)*) => {{
+        crate::log::write_log(crate::log::Level::$level, format_args!($($arg)*));
+    }};
+}
@@ -8,34 +8,48 @@ use crate::vga::{putblock, Color};
 
 const KEYCMD_SENDTO_MOUSE: u8 = 0xd4;
 const MOUSECMD_ENABLE: u8 = 0xf4;
+const MOUSECMD_SET_SAMPLE_RATE: u8 = 0xf3;
+const MOUSECMD_GET_ID: u8 = 0xf2;
+/// ホイールつきのマウス(IntelliMouse)のID
+const MOUSE_ID_WHEEL: u8 = 0x03;
 
 static mut MOUSE_FIFO_ADDR: usize = 0;
 
 #[derive(Debug)]
 pub struct MouseDec {
-    pub buf: RefCell<[u8; 3]>,
+    pub buf: RefCell<[u8; 4]>,
     pub phase: Cell<MouseDecPhase>,
     pub x: Cell<i32>,
     pub y: Cell<i32>,
     pub btn: Cell<i32>,
+    /// ホイールの回転量。手前に回すと正
+    pub wheel: Cell<i32>,
+    /// ホイールがあると1回のデータが4バイトになる
+    has_wheel: Cell<bool>,
 }
 
 #[derive(Debug, Clone, Copy)]
 pub enum MouseDecPhase {
+    /// マウスのIDを待っている
     START,
+    /// 有効にしたことへの応答(0xfa)を待っている
+    ENABLED,
     FIRST,
     SECOND,
     THIRD,
+    FOURTH,
 }
 
 impl MouseDec {
     pub fn new() -> MouseDec {
         MouseDec {
-            buf: RefCell::new([0; 3]),
+            buf: RefCell::new([0; 4]),
             phase: Cell::new(MouseDecPhase::START),
             x: Cell::new(0),
             y: Cell::new(0),
             btn: Cell::new(0),
+            wheel: Cell::new(0),
+            has_wheel: Cell::new(false),
         }
     }
 
@@ -43,6 +57,14 @@ impl MouseDec {
         use MouseDecPhase::*;
         match self.phase.get() {
             START => {
+                // enable_mouseで送ったコマンドへの応答(0xfa)の後にIDが来る
+                if data != 0xfa {
+                    self.has_wheel.set(data == MOUSE_ID_WHEEL);
+                    self.phase.set(ENABLED)
+                }
+                None
+            }
+            ENABLED => {
                 if data == 0xfa {
                     self.phase.set(FIRST)
                 }
@@ -65,20 +87,41 @@ impl MouseDec {
             THIRD => {
                 let mut buf = self.buf.borrow_mut();
                 buf[2] = data;
-                self.phase.set(FIRST);
-                self.btn.set((buf[0] & 0x07) as i32);
-                self.x.set(buf[1] as i32);
-                self.y.set(buf[2] as i32);
-                if (buf[0] & 0x10) != 0 {
-                    self.x.set((buf[1] as u32 | 0xffffff00) as i32);
-                }
-                if (buf[0] & 0x20) != 0 {
-                    self.y.set((buf[2] as u32 | 0xffffff00) as i32);
+                if self.has_wheel.get() {
+                    self.phase.set(FOURTH);
+                    return None;
                 }
-                self.y.set(-self.y.get());
+                buf[3] = 0;
+                self.phase.set(FIRST);
+                drop(buf);
+                self.set_packet();
                 Some(())
             }
+            FOURTH => {
+                let mut buf = self.buf.borrow_mut();
+                buf[3] = data;
+                self.phase.set(FIRST);
+                drop(buf);
+                self.set_packet();
+                Some(())
+            }
+        }
+    }
+
+    fn set_packet(&self) {
+        let buf = self.buf.borrow();
+        self.btn.set((buf[0] & 0x07) as i32);
+        self.x.set(buf[1] as i32);
+        self.y.set(buf[2] as i32);
+        if (buf[0] & 0x10) != 0 {
+            self.x.set((buf[1] as u32 | 0xffffff00) as i32);
         }
+        if (buf[0] & 0x20) != 0 {
+            self.y.set((buf[2] as u32 | 0xffffff00) as i32);
+        }
+        self.y.set(-self.y.get());
+        // 4バイト目の下位4ビットが符号つきの回転量
+        self.wheel.set(((buf[3] << 4) as i8 >> 4) as i32);
     }
 }
 
@@ -147,10 +190,28 @@ pub fn enable_mouse(fifo_addr: usize) {
     unsafe {
         MOUSE_FIFO_ADDR = fifo_addr;
     }
+    // サンプリングレートを200, 100, 80の順に設定すると、ホイールつきのマウスはIDが3になる
+    for data in [
+        MOUSECMD_SET_SAMPLE_RATE,
+        200,
+        MOUSECMD_SET_SAMPLE_RATE,
+        100,
+        MOUSECMD_SET_SAMPLE_RATE,
+        80,
+        MOUSECMD_GET_ID,
+        MOUSECMD_ENABLE,
+    ]
+    .iter()
+    {
+        send_to_mouse(*data);
+    }
+}
+
+fn send_to_mouse(data: u8) {
     wait_kbc_sendready();
     out8(PORT_KEYCMD, KEYCMD_SENDTO_MOUSE);
     wait_kbc_sendready();
-    out8(PORT_KEYDAT, MOUSECMD_ENABLE);
+    out8(PORT_KEYDAT, data);
 }
 
 const MOUSE_OFFSET: u32 = 512;
@@ -1,9 +1,9 @@
 use core::default::Default;
 
-use crate::asm::{farjmp, load_tr};
+use crate::asm::{cli, farjmp, load_tr, sti};
 use crate::descriptor_table::{SegmentDescriptor, ADR_GDT, AR_LDT, AR_TSS32};
 use crate::memory::{MemMan, MEMMAN_ADDR};
-use crate::timer::TIMER_MANAGER;
+use crate::timer::{idle_wait, TIMER_MANAGER};
 
 const MAX_TASKS: usize = 1000;
 const MAX_TASKS_LV: usize = 100;
@@ -52,13 +52,22 @@ pub struct Task {
     pub fifo_addr: usize,
     pub console_addr: usize,
     pub ds_base: usize,
+    pub ds_limit: usize,
     pub console_stack: usize,
     pub ldt: [SegmentDescriptor; 2],
-    pub file_handler_addr: usize,
+    pub handle_table_addr: usize,
     pub fat_addr: usize,
     pub cmdline_addr: usize,
+    pub args_addr: usize,
+    pub env_addr: usize,
+    pub stdio_addr: usize,
     pub lang_mode: LangMode,
     pub lang_byte1: u8,
+    /// アプリを動かしているジョブ。コンソールのタスクなら0
+    pub job_addr: usize,
+    /// アプリのヒープ(データセグメントの中のオフセット)。api_getheapで返す
+    pub heap_addr: usize,
+    pub heap_size: usize,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -96,16 +105,23 @@ impl Task {
             fifo_addr: 0,
             console_addr: 0,
             ds_base: 0,
+            ds_limit: 0,
             console_stack: 0,
             ldt: [
                 SegmentDescriptor::new(0, 0, 0),
                 SegmentDescriptor::new(0, 0, 0),
             ],
-            file_handler_addr: 0,
+            handle_table_addr: 0,
             fat_addr: 0,
             cmdline_addr: 0,
+            args_addr: 0,
+            env_addr: 0,
+            stdio_addr: 0,
             lang_mode: LangMode::En,
             lang_byte1: 0,
+            job_addr: 0,
+            heap_addr: 0,
+            heap_size: 0,
         }
     }
 }
@@ -273,6 +289,11 @@ impl TaskManager {
         return Err("CANNOT ALLOCATE TASK");
     }
 
+    /// allocしたが、まだrunしていないタスクを返す
+    pub fn free(&mut self, task_index: usize) {
+        self.tasks_data[task_index].flag = TaskFlag::AVAILABLE;
+    }
+
     pub fn run(&mut self, task_index: usize, level_i32: i32, priority: i32) {
         let task = self.tasks_data[task_index];
         let level: usize;
@@ -335,7 +356,17 @@ impl TaskManager {
 }
 
 pub extern "C" fn task_idle() {
+    let task_manager = unsafe { &mut *(TASK_MANAGER_ADDR as *mut TaskManager) };
     loop {
-        crate::asm::hlt();
+        cli();
+        if task_manager.lv_change {
+            // 割り込みで起こされたタスクがいるので切り替える
+            task_manager.switch();
+            sti();
+            continue;
+        }
+        // 他に動けるタスクがないので、タスクスイッチ用のタイマも止めて次のタイマまで寝る
+        TIMER_MANAGER.lock().cancel(unsafe { MT_TIMER_INDEX });
+        idle_wait();
     }
 }
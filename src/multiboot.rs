@@ -0,0 +1,172 @@
+use core::slice;
+
+// Multiboot情報のflagsのどの項目が有効か
+const INFO_MEMORY: u32 = 1 << 0;
+const INFO_MODS: u32 = 1 << 3;
+const INFO_MEM_MAP: u32 = 1 << 6;
+const INFO_FRAMEBUFFER: u32 = 1 << 12;
+
+const FRAMEBUFFER_TYPE_INDEXED: u8 = 0;
+/// メモリマップで使ってよいメモリの種類
+const MEMORY_AVAILABLE: u32 = 1;
+
+/// カーネルが使う領域の終わり。ここを含む使えるメモリの終わりまでをメモリ管理に渡す
+const KERNEL_END: u64 = 0x00400000;
+/// memtestと同じく、これより上はメモリとして使わない
+const MEMORY_LIMIT: u64 = 0xc0000000;
+
+/// Multiboot(v1)のローダが作る情報。使わない項目も並びを合わせるために置いておく
+#[allow(dead_code)]
+#[repr(C, packed)]
+pub struct MultibootInfo {
+    flags: u32,
+    mem_lower: u32,
+    mem_upper: u32,
+    boot_device: u32,
+    cmdline: u32,
+    mods_count: u32,
+    mods_addr: u32,
+    syms: [u32; 4],
+    mmap_length: u32,
+    mmap_addr: u32,
+    drives_length: u32,
+    drives_addr: u32,
+    config_table: u32,
+    boot_loader_name: u32,
+    apm_table: u32,
+    vbe_control_info: u32,
+    vbe_mode_info: u32,
+    vbe_mode: u16,
+    vbe_interface_seg: u16,
+    vbe_interface_off: u16,
+    vbe_interface_len: u16,
+    framebuffer_addr: u64,
+    framebuffer_pitch: u32,
+    framebuffer_width: u32,
+    framebuffer_height: u32,
+    framebuffer_bpp: u8,
+    framebuffer_type: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct MemoryMapEntry {
+    /// この項目の大きさ(sizeの4バイトは含まない)
+    size: u32,
+    base_addr: u64,
+    length: u64,
+    mem_type: u32,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct ModuleEntry {
+    mod_start: u32,
+    mod_end: u32,
+    string: u32,
+    reserved: u32,
+}
+
+/// ローダが読み込んだファイル
+pub struct Module {
+    pub data: &'static [u8],
+    pub cmdline: &'static [u8],
+}
+
+impl Module {
+    /// ディスクイメージに置くときの名前
+    /// コマンドラインの2つ目の語があればそれを、なければパスの最後の部分を使う
+    pub fn name(&self) -> &'static [u8] {
+        let mut words = self.cmdline.split(|c| *c == b' ').filter(|w| !w.is_empty());
+        let path = words.next().unwrap_or(&[]);
+        if let Some(name) = words.next() {
+            return name;
+        }
+        path.rsplit(|c| *c == b'/').next().unwrap_or(path)
+    }
+}
+
+pub fn info(addr: u32) -> &'static MultibootInfo {
+    unsafe { &*(addr as *const MultibootInfo) }
+}
+
+/// 0で終わる文字列
+fn c_str(addr: u32) -> &'static [u8] {
+    if addr == 0 {
+        return &[];
+    }
+    let ptr = addr as *const u8;
+    let mut len = 0;
+    while unsafe { *ptr.add(len) } != 0 {
+        len += 1;
+    }
+    unsafe { slice::from_raw_parts(ptr, len) }
+}
+
+impl MultibootInfo {
+    /// ローダが用意した画面の(VRAMの番地, 幅, 高さ)
+    /// カーネルは1行がちょうど幅の8bitカラーしか描けないので、それ以外はNone
+    pub fn framebuffer(&self) -> Option<(u32, u16, u16)> {
+        if self.flags & INFO_FRAMEBUFFER == 0
+            || self.framebuffer_type != FRAMEBUFFER_TYPE_INDEXED
+            || self.framebuffer_bpp != 8
+            || self.framebuffer_pitch != self.framebuffer_width
+            || self.framebuffer_addr >= MEMORY_LIMIT
+        {
+            return None;
+        }
+        Some((
+            self.framebuffer_addr as u32,
+            self.framebuffer_width as u16,
+            self.framebuffer_height as u16,
+        ))
+    }
+
+    /// カーネルの後ろに続く使えるメモリの終わり
+    /// メモリマップがなければ、1MBから上の連続したメモリの大きさから求める
+    pub fn memory_end(&self) -> Option<u32> {
+        if self.flags & INFO_MEM_MAP != 0 {
+            let mut addr = self.mmap_addr as usize;
+            let end = addr + self.mmap_length as usize;
+            while addr < end {
+                let entry = unsafe { *(addr as *const MemoryMapEntry) };
+                let base = entry.base_addr;
+                let region_end = base + entry.length;
+                if entry.mem_type == MEMORY_AVAILABLE
+                    && base <= KERNEL_END
+                    && KERNEL_END < region_end
+                {
+                    return Some(region_end.min(MEMORY_LIMIT) as u32);
+                }
+                addr += entry.size as usize + 4;
+            }
+        }
+        if self.flags & INFO_MEMORY != 0 {
+            let end = 0x00100000 + self.mem_upper as u64 * 1024;
+            if end > KERNEL_END {
+                return Some(end.min(MEMORY_LIMIT) as u32);
+            }
+        }
+        None
+    }
+
+    pub fn modules(&self) -> impl Iterator<Item = Module> {
+        let entries: &'static [ModuleEntry] = if self.flags & INFO_MODS == 0 {
+            &[]
+        } else {
+            unsafe {
+                slice::from_raw_parts(
+                    self.mods_addr as *const ModuleEntry,
+                    self.mods_count as usize,
+                )
+            }
+        };
+        entries.iter().map(|m| Module {
+            data: unsafe {
+                slice::from_raw_parts(m.mod_start as *const u8, (m.mod_end - m.mod_start) as usize)
+            },
+            cmdline: c_str(m.string),
+        })
+    }
+}
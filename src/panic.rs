@@ -0,0 +1,69 @@
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+
+use crate::asm::{cli, hlt, load_ebp};
+use crate::mt::{TaskManager, TASK_MANAGER_ADDR};
+use crate::serial;
+use crate::vga::{boxfill, Color, ScreenWriter, SCREEN_HEIGHT, SCREEN_WIDTH, VRAM_ADDR};
+
+/// バックトレースでたどるフレームの数
+const MAX_FRAMES: usize = 16;
+
+/// 画面とシリアルポートの両方に書く
+struct PanicWriter {
+    screen: ScreenWriter,
+}
+
+impl fmt::Write for PanicWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        serial::write(s.as_bytes());
+        self.screen.write_str(s)
+    }
+}
+
+/// 画面をVRAMに直接描いたパニックの画面にして止まる
+/// シートやタスクの状態は壊れているかもしれないので、なるべく何にも頼らずに描く
+pub fn show(info: &PanicInfo) -> ! {
+    cli();
+    let width = *SCREEN_WIDTH as usize;
+    let height = *SCREEN_HEIGHT as usize;
+    boxfill(
+        *VRAM_ADDR,
+        width as isize,
+        Color::DarkBlue,
+        0,
+        0,
+        width as isize - 1,
+        height as isize - 1,
+    );
+    let mut writer = PanicWriter {
+        screen: ScreenWriter::new(None, Color::White, 8, 8, width, height),
+    };
+    let _ = write!(writer, "\nKERNEL PANIC\n\n{}\n\n", info);
+    if unsafe { TASK_MANAGER_ADDR } != 0 {
+        let task_manager = unsafe { &*(TASK_MANAGER_ADDR as *const TaskManager) };
+        let _ = write!(writer, "task: {}\n", task_manager.now_index());
+    }
+
+    // 呼び出し元のEBPと戻り番地はEBPの指す先に積まれている(kernel.mapで関数がわかる)
+    let _ = write!(writer, "backtrace:\n");
+    let mut ebp = load_ebp();
+    for i in 0..MAX_FRAMES {
+        if ebp == 0 || ebp % 4 != 0 || ebp > 0xffff_fff0 {
+            break;
+        }
+        let next = unsafe { *(ebp as *const usize) };
+        let eip = unsafe { *((ebp + 4) as *const usize) };
+        let _ = write!(writer, "  #{:<2} {:08X}\n", i, eip);
+        // スタックは番地の大きいほうに戻っていくので、そうでなければ壊れている
+        if next <= ebp {
+            break;
+        }
+        ebp = next;
+    }
+    #[cfg(feature = "integration-test")]
+    crate::itest::exit(false);
+    loop {
+        hlt();
+    }
+}
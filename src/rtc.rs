@@ -0,0 +1,92 @@
+use crate::asm::{cli, in8, load_eflags, out8, store_eflags};
+
+const CMOS_ADDR: u32 = 0x0070;
+const CMOS_DATA: u32 = 0x0071;
+
+const RTC_SECOND: u8 = 0x00;
+const RTC_MINUTE: u8 = 0x02;
+const RTC_HOUR: u8 = 0x04;
+const RTC_DAY: u8 = 0x07;
+const RTC_MONTH: u8 = 0x08;
+const RTC_YEAR: u8 = 0x09;
+const RTC_STATUS_A: u8 = 0x0a;
+const RTC_STATUS_B: u8 = 0x0b;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 0x80;
+const STATUS_B_24HOUR: u8 = 0x02;
+const STATUS_B_BINARY: u8 = 0x04;
+const HOUR_PM: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct DateTime {
+    pub year: i32,
+    pub month: i32,
+    pub day: i32,
+    pub hour: i32,
+    pub minute: i32,
+    pub second: i32,
+}
+
+fn read_cmos(reg: u8) -> u8 {
+    out8(CMOS_ADDR, reg);
+    in8(CMOS_DATA)
+}
+
+fn read_registers() -> [u8; 6] {
+    // 更新中は値が不定なので終わるのを待つ
+    while read_cmos(RTC_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {}
+    [
+        read_cmos(RTC_SECOND),
+        read_cmos(RTC_MINUTE),
+        read_cmos(RTC_HOUR),
+        read_cmos(RTC_DAY),
+        read_cmos(RTC_MONTH),
+        read_cmos(RTC_YEAR),
+    ]
+}
+
+fn bcd_to_binary(value: u8) -> u8 {
+    (value & 0x0f) + (value >> 4) * 10
+}
+
+pub fn now() -> DateTime {
+    let eflags = load_eflags();
+    cli();
+    // 読んでいる途中で更新が入ることがあるので、2回続けて同じ値になるまで読み直す
+    let mut regs = read_registers();
+    loop {
+        let again = read_registers();
+        if again == regs {
+            break;
+        }
+        regs = again;
+    }
+    let status_b = read_cmos(RTC_STATUS_B);
+    store_eflags(eflags);
+
+    let pm = regs[2] & HOUR_PM != 0;
+    regs[2] &= !HOUR_PM;
+    if status_b & STATUS_B_BINARY == 0 {
+        for r in regs.iter_mut() {
+            *r = bcd_to_binary(*r);
+        }
+    }
+    let mut hour = regs[2] as i32;
+    if status_b & STATUS_B_24HOUR == 0 {
+        // 12時間表記では12時が0時扱い
+        hour %= 12;
+        if pm {
+            hour += 12;
+        }
+    }
+    let year = regs[5] as i32;
+    DateTime {
+        year: if year < 80 { 2000 + year } else { 1900 + year },
+        month: regs[4] as i32,
+        day: regs[3] as i32,
+        hour,
+        minute: regs[1] as i32,
+        second: regs[0] as i32,
+    }
+}
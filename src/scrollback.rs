@@ -0,0 +1,131 @@
+use crate::memory::{MemMan, MEMMAN_ADDR};
+use crate::vga::{to_color, Color};
+
+/// 覚えておく行数。画面から消えた行もここまではさかのぼって表示できる
+pub const MAX_SCROLLBACK_LINES: usize = 4096;
+
+/// 文字色と背景色を1バイトにまとめる
+pub const fn attr(fg: Color, bg: Color) -> u8 {
+    fg as u8 | (bg as u8) << 4
+}
+
+/// attrで1バイトにまとめた色を(文字色, 背景色)に戻す
+pub fn colors(attr: u8) -> (Color, Color) {
+    (
+        to_color((attr & 0x0f) as i8).unwrap(),
+        to_color((attr >> 4) as i8).unwrap(),
+    )
+}
+
+/// 何も書いていないところの色
+pub const DEFAULT_ATTR: u8 = attr(Color::White, Color::Black);
+
+/// コンソールに表示した文字と色を1文字2バイトで覚えておくリングバッファ
+/// 文字の後ろに同じ並びで色(attr)を置く
+/// 最後のrows行が画面に表示されている部分になる
+#[derive(Clone, Copy)]
+pub struct Scrollback {
+    addr: usize,
+    columns: usize,
+    /// 一番古い行の位置
+    first: usize,
+    len: usize,
+}
+
+impl Scrollback {
+    /// 空白で埋めたrows行を用意する
+    pub fn new(columns: usize, rows: usize) -> Result<Scrollback, &'static str> {
+        let memman = unsafe { &mut *(MEMMAN_ADDR as *mut MemMan) };
+        let addr = memman
+            .alloc_4k((columns * MAX_SCROLLBACK_LINES * 2) as u32)
+            .map_err(|_| "Out of Memory")? as usize;
+        let mut scrollback = Scrollback {
+            addr,
+            columns,
+            first: 0,
+            len: 0,
+        };
+        for _ in 0..rows {
+            scrollback.push_line();
+        }
+        Ok(scrollback)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// i行目(0が一番古い行)
+    pub fn line(&self, i: usize) -> &[u8] {
+        let addr = self.addr + (self.first + i) % MAX_SCROLLBACK_LINES * self.columns;
+        unsafe { core::slice::from_raw_parts(addr as *const u8, self.columns) }
+    }
+
+    pub fn line_mut(&mut self, i: usize) -> &mut [u8] {
+        let addr = self.addr + (self.first + i) % MAX_SCROLLBACK_LINES * self.columns;
+        unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, self.columns) }
+    }
+
+    /// i行目の各文字の色
+    pub fn attrs(&self, i: usize) -> &[u8] {
+        let addr = self.attrs_addr() + (self.first + i) % MAX_SCROLLBACK_LINES * self.columns;
+        unsafe { core::slice::from_raw_parts(addr as *const u8, self.columns) }
+    }
+
+    pub fn attrs_mut(&mut self, i: usize) -> &mut [u8] {
+        let addr = self.attrs_addr() + (self.first + i) % MAX_SCROLLBACK_LINES * self.columns;
+        unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, self.columns) }
+    }
+
+    fn attrs_addr(&self) -> usize {
+        self.addr + self.columns * MAX_SCROLLBACK_LINES
+    }
+
+    /// 空白の行を最後に足す。いっぱいなら一番古い行を捨てる
+    pub fn push_line(&mut self) {
+        if self.len == MAX_SCROLLBACK_LINES {
+            self.first = (self.first + 1) % MAX_SCROLLBACK_LINES;
+        } else {
+            self.len += 1;
+        }
+        let last = self.len - 1;
+        for c in self.line_mut(last).iter_mut() {
+            *c = b' ';
+        }
+        for a in self.attrs_mut(last).iter_mut() {
+            *a = DEFAULT_ATTR;
+        }
+    }
+
+    /// 最後の行から捨ててlen行にする
+    pub fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+
+    /// 1行の文字数を変える。長い行の後ろは切り捨てる
+    pub fn set_columns(&mut self, columns: usize) -> Result<(), &'static str> {
+        if columns == self.columns {
+            return Ok(());
+        }
+        let mut new = Scrollback::new(columns, 0)?;
+        for i in 0..self.len {
+            new.push_line();
+            let len = columns.min(self.columns);
+            new.line_mut(i)[..len].copy_from_slice(&self.line(i)[..len]);
+            new.attrs_mut(i)[..len].copy_from_slice(&self.attrs(i)[..len]);
+        }
+        let old = core::mem::replace(self, new);
+        old.free();
+        Ok(())
+    }
+
+    pub fn free(self) {
+        let memman = unsafe { &mut *(MEMMAN_ADDR as *mut MemMan) };
+        memman
+            .free_4k(
+                self.addr as u32,
+                (self.columns * MAX_SCROLLBACK_LINES * 2) as u32,
+            )
+            .unwrap();
+    }
+}
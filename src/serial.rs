@@ -0,0 +1,116 @@
+use crate::asm::{in8, out8};
+use crate::fifo::Fifo;
+use crate::interrupt::PIC0_OCW2;
+
+/// 受信したデータをタスクAのFIFOに送るときに足す値
+pub const SERIAL_OFFSET: u32 = 2304;
+
+/// ログとキー入力に使う(IRQ4)
+pub const COM1: Uart = Uart { port: 0x03f8 };
+/// GDBのスタブが使う(IRQ3)
+pub const COM2: Uart = Uart { port: 0x02f8 };
+
+/// 受信したデータ / 送信するデータ。DLABが1のときは分周比の下位
+const UART_DATA: u32 = 0;
+/// 割り込みを許可するもの。DLABが1のときは分周比の上位
+const UART_IER: u32 = 1;
+const UART_FCR: u32 = 2;
+const UART_LCR: u32 = 3;
+const UART_MCR: u32 = 4;
+const UART_LSR: u32 = 5;
+
+const IER_RECEIVED: u8 = 0x01;
+/// FIFOを有効にし、送受信のFIFOを空にする。14バイトたまったら割り込む
+const FCR_ENABLE: u8 = 0xc7;
+/// 8ビット、パリティなし、ストップビット1
+const LCR_8N1: u8 = 0x03;
+const LCR_DLAB: u8 = 0x80;
+/// DTR、RTSとOUT2(割り込みをPICに通すのに必要)
+const MCR_NORMAL: u8 = 0x0b;
+/// 送ったデータがそのまま受信されるようにする
+const MCR_LOOPBACK: u8 = 0x1e;
+const LSR_DATA_READY: u8 = 0x01;
+const LSR_THR_EMPTY: u8 = 0x20;
+
+/// 115200 / 1 = 115200bps
+const DIVISOR: u16 = 1;
+
+static mut SERIAL_FIFO_ADDR: usize = 0;
+/// COM1が見つかった。なければ送信は何もしない
+static mut PRESENT: bool = false;
+
+/// 16550互換のUART
+#[derive(Clone, Copy)]
+pub struct Uart {
+    port: u32,
+}
+
+impl Uart {
+    /// 115200bps、8N1で初期化し、受信したら割り込むようにする。UARTがなければfalse
+    pub fn init(&self) -> bool {
+        out8(self.port + UART_IER, 0);
+        out8(self.port + UART_LCR, LCR_DLAB);
+        out8(self.port + UART_DATA, DIVISOR as u8);
+        out8(self.port + UART_IER, (DIVISOR >> 8) as u8);
+        out8(self.port + UART_LCR, LCR_8N1);
+        out8(self.port + UART_FCR, FCR_ENABLE);
+
+        // ループバックで送ったものが返ってこなければUARTはない
+        out8(self.port + UART_MCR, MCR_LOOPBACK);
+        out8(self.port + UART_DATA, 0xae);
+        if in8(self.port + UART_DATA) != 0xae {
+            return false;
+        }
+        out8(self.port + UART_MCR, MCR_NORMAL);
+        out8(self.port + UART_IER, IER_RECEIVED);
+        true
+    }
+
+    /// 1バイト送る。送信のFIFOが空くまで待つので、割り込みを禁止していても使える
+    pub fn write_byte(&self, data: u8) {
+        while in8(self.port + UART_LSR) & LSR_THR_EMPTY == 0 {}
+        out8(self.port + UART_DATA, data);
+    }
+
+    /// 受信したデータがあれば1バイト読む
+    pub fn read_byte(&self) -> Option<u8> {
+        if in8(self.port + UART_LSR) & LSR_DATA_READY == 0 {
+            return None;
+        }
+        Some(in8(self.port + UART_DATA))
+    }
+}
+
+/// COM1を初期化し、受信したデータをfifo_addrのFIFOに送る
+pub fn init_serial(fifo_addr: usize) {
+    if COM1.init() {
+        unsafe {
+            SERIAL_FIFO_ADDR = fifo_addr;
+            PRESENT = true;
+        }
+    }
+}
+
+/// COM1に送る。改行はCR LFにする
+pub fn write(data: &[u8]) {
+    if unsafe { !PRESENT } {
+        return;
+    }
+    for &b in data.iter() {
+        if b == b'\n' {
+            COM1.write_byte(b'\r');
+        }
+        COM1.write_byte(b);
+    }
+}
+
+pub extern "C" fn inthandler24() {
+    out8(PIC0_OCW2, 0x64); // IRQ-04 受付終了
+    let fifo = unsafe { &mut *(SERIAL_FIFO_ADDR as *mut Fifo) };
+    // FIFOにたまっている分をすべて読む
+    while let Some(data) = COM1.read_byte() {
+        if unsafe { PRESENT } {
+            let _ = fifo.put(data as u32 + SERIAL_OFFSET);
+        }
+    }
+}
@@ -478,6 +478,19 @@ impl SheetManager {
         }
     }
 
+    /// 新しいバッファに入れ替えて大きさを変える
+    pub fn resize(&mut self, sheet_index: usize, buf_addr: usize, width: i32, height: i32) {
+        let sheet = self.sheets_data[sheet_index];
+        self.sheets_data[sheet_index].set(buf_addr, width, height, sheet.transparent);
+        if let Some(z) = sheet.z {
+            // 小さくなったときに見えるようになる部分も描き直す
+            let x1 = sheet.x + max(sheet.width, width);
+            let y1 = sheet.y + max(sheet.height, height);
+            self.refresh_map(sheet.x, sheet.y, x1, y1, 0);
+            self.refresh_part(sheet.x, sheet.y, x1, y1, 0, z as i32);
+        }
+    }
+
     pub fn free(&mut self, sheet_index: usize) {
         let sheet = self.sheets_data[sheet_index];
         if sheet.z.is_some() {
@@ -491,7 +504,9 @@ impl SheetManager {
         let memman = unsafe { &mut *(MEMMAN_ADDR as *mut MemMan) };
         let task_manager = unsafe { &mut *(TASK_MANAGER_ADDR as *mut TaskManager) };
         let sheet = self.sheets_data[sheet_index];
-        memman.free_4k(sheet.buf_addr as u32, 256 * 165).unwrap();
+        memman
+            .free_4k(sheet.buf_addr as u32, (sheet.width * sheet.height) as u32)
+            .unwrap();
         self.free(sheet_index);
         task_manager.close_task(sheet.task_index);
     }
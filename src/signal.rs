@@ -0,0 +1,241 @@
+use core::mem::size_of;
+
+use crate::abi::*;
+use crate::api::ApiRegs;
+use crate::job::{abort_app, Job, JobState};
+use crate::mt::{Task, TaskManager, TASK_MANAGER_ADDR};
+use crate::timer::TIMER_MANAGER;
+
+/// api_alarmのタイマがコンソールのFIFOに送るデータ。これにジョブのタスクの番号を足す
+pub const ALARM_OFFSET: u32 = 1024;
+
+/// アプリが変えてもいいEFLAGSのビット(CF, PF, AF, ZF, SF, DF, OF)
+const EFLAGS_USER: i32 = 0x0cd5;
+
+/// api_signalで登録されたハンドラ
+#[derive(Debug, Clone, Copy)]
+pub struct SigAction {
+    /// SIG_DFL、SIG_IGNか、ハンドラの番地
+    pub handler: i32,
+    /// ハンドラからretしたときに戻る番地。ここでapi_sigreturnを呼ぶ
+    pub restorer: i32,
+}
+
+impl SigAction {
+    pub const fn new() -> SigAction {
+        SigAction {
+            handler: SIG_DFL,
+            restorer: 0,
+        }
+    }
+}
+
+/// アプリからカーネルに入ったときにesp0の下に積まれているもの
+/// PUSHAD、セグメントレジスタ2つ(順番は入口によって違う)、CPUが積んだIRETDの戻り先の順
+#[repr(C)]
+pub struct UserFrame {
+    pub regs: ApiRegs,
+    segs: [i32; 2],
+    pub eip: i32,
+    pub cs: i32,
+    pub eflags: i32,
+    pub esp: i32,
+    pub ss: i32,
+}
+
+impl UserFrame {
+    /// アプリを動かしているタスクの、カーネルに入ったときのフレーム
+    pub fn of(task: &Task) -> &'static mut UserFrame {
+        let addr = task.tss.esp0 as usize - size_of::<UserFrame>();
+        unsafe { &mut *(addr as *mut UserFrame) }
+    }
+}
+
+/// ハンドラを呼ぶときにアプリのスタックに積むもの
+/// ハンドラからは、restorerへの戻り番地とシグナルの番号を引数にして呼ばれたように見える
+#[repr(C)]
+struct SignalFrame {
+    restorer: i32,
+    signal: i32,
+    /// 割り込まれたときのレジスタ
+    regs: ApiRegs,
+    eip: i32,
+    eflags: i32,
+    /// ハンドラを呼ぶ前に届けないことにしていたシグナル
+    blocked: u32,
+}
+
+fn job_of(task: &Task) -> Option<&'static mut Job> {
+    if task.job_addr == 0 {
+        return None;
+    }
+    Some(unsafe { &mut *(task.job_addr as *mut Job) })
+}
+
+/// ハンドラがないときに強制終了する理由
+fn message(signal: i32) -> &'static [u8] {
+    match signal {
+        SIGINT => b"\nBreak(key) :\n",
+        SIGCLOSE => b"\nBreak(mouse) :\n",
+        SIGALRM => b"\nAlarm :\n",
+        _ => b"",
+    }
+}
+
+/// task_indexのタスクで動いているアプリにシグナルを送る
+/// ハンドラがあれば、アプリに戻るときに呼ばれるように印をつけ、止まっていたり寝ていたりすれば起こす
+pub fn send(task_manager: &mut TaskManager, task_index: usize, signal: i32) {
+    let job = match job_of(&task_manager.tasks_data[task_index]) {
+        Some(job) => job,
+        None => return,
+    };
+    if job.state == JobState::Done || signal <= 0 || signal >= NSIG {
+        return;
+    }
+    let handler = job.actions[signal as usize].handler;
+    if signal == SIGKILL || handler == SIG_DFL {
+        abort_app(task_manager, task_index, message(signal));
+    } else if handler != SIG_IGN {
+        job.pending |= 1 << signal;
+        job.resume(task_manager);
+    }
+}
+
+/// 届けられるシグナルが来ている。待っているシステムコールはE_INTRで戻る
+pub fn has_pending(job: &Job) -> bool {
+    job.pending & !job.blocked != 0
+}
+
+/// アプリに戻る直前に呼ぶ。届いたシグナルがあれば、ハンドラから動き出すようにframeを書き換える
+fn deliver(task: &Task, job: &mut Job, frame: &mut UserFrame) {
+    let pending = job.pending & !job.blocked;
+    if pending == 0 {
+        return;
+    }
+    let signal = pending.trailing_zeros() as i32;
+    job.pending &= !(1 << signal);
+    let action = job.actions[signal as usize];
+    // 送られたあとでハンドラが外された
+    if action.handler == SIG_DFL || action.handler == SIG_IGN {
+        return;
+    }
+    let esp = frame.esp as u32 as usize;
+    let size = size_of::<SignalFrame>();
+    if esp < size || esp > task.ds_limit {
+        // ハンドラを呼べるだけのスタックがない
+        return;
+    }
+    let new_esp = esp - size;
+    let sf = unsafe { &mut *((task.ds_base + new_esp) as *mut SignalFrame) };
+    *sf = SignalFrame {
+        restorer: action.restorer,
+        signal,
+        regs: frame.regs,
+        eip: frame.eip,
+        eflags: frame.eflags,
+        blocked: job.blocked,
+    };
+    // ハンドラの中では同じシグナルを届けない
+    job.blocked |= 1 << signal;
+    frame.esp = new_esp as i32;
+    frame.eip = action.handler;
+}
+
+/// hrb_apiからアプリに戻る直前に呼ぶ
+pub fn api_return(task: &Task, job: &mut Job) {
+    deliver(task, job, UserFrame::of(task));
+}
+
+/// 割り込みから戻る直前にhandler!から呼ばれる。frameはPUSHADしたところ
+/// アプリを動かしているときに割り込まれたのでなければ、次のシステムコールから戻るときに届ける
+pub extern "C" fn irq_return(frame: *mut UserFrame) {
+    let frame = unsafe { &mut *frame };
+    if frame.cs & 3 != 3 {
+        return;
+    }
+    let task_manager = unsafe { &mut *(TASK_MANAGER_ADDR as *mut TaskManager) };
+    let task = task_manager.tasks_data[task_manager.now_index()];
+    if let Some(job) = job_of(&task) {
+        deliver(&task, job, frame);
+    }
+}
+
+/// ハンドラからapi_sigreturnで戻ってきたので、割り込まれたところに戻れるようにレジスタを書き戻す
+/// SIGTERMのハンドラだったときやスタックが壊れていたときはfalseを返すので、アプリを終了させる
+pub fn sigreturn(task: &Task, job: &mut Job) -> bool {
+    let frame = UserFrame::of(task);
+    // ハンドラのretでrestorerの番地は取り除かれている
+    let addr = (frame.esp as u32 as usize).wrapping_sub(4);
+    let size = size_of::<SignalFrame>();
+    match addr.checked_add(size) {
+        Some(end) if end <= task.ds_limit => {}
+        _ => return false,
+    }
+    let sf = unsafe { &*((task.ds_base + addr) as *const SignalFrame) };
+    if sf.signal == SIGTERM {
+        return false;
+    }
+    frame.regs = sf.regs;
+    frame.eip = sf.eip;
+    frame.eflags = (frame.eflags & !EFLAGS_USER) | (sf.eflags & EFLAGS_USER);
+    frame.esp = (addr + size) as i32;
+    job.blocked = sf.blocked;
+    deliver(task, job, frame);
+    true
+}
+
+/// signalのハンドラを変え、前のハンドラを返す
+pub fn set_action(job: &mut Job, signal: i32, action: SigAction) -> Result<i32, i32> {
+    if signal <= 0 || signal >= NSIG || signal == SIGKILL {
+        return Err(E_INVAL);
+    }
+    // SIGTERMは無視させない
+    if signal == SIGTERM && action.handler == SIG_IGN {
+        return Err(E_INVAL);
+    }
+    let old = job.actions[signal as usize].handler;
+    job.actions[signal as usize] = action;
+    if action.handler == SIG_DFL || action.handler == SIG_IGN {
+        job.pending &= !(1 << signal);
+    }
+    Ok(old)
+}
+
+/// timeout(10ms単位)後にSIGALRMを送る。0なら取り消す
+/// タイマはコンソールのFIFOに送り、コンソールがsendする
+pub fn set_alarm(
+    task_manager: &TaskManager,
+    task_index: usize,
+    job: &mut Job,
+    timeout: u32,
+) -> Result<(), i32> {
+    if let Some(timer_index) = job.alarm_timer {
+        TIMER_MANAGER.lock().cancel(timer_index);
+    }
+    if timeout == 0 {
+        return Ok(());
+    }
+    let timer_index = match job.alarm_timer {
+        Some(timer_index) => timer_index,
+        None => {
+            let timer_index = TIMER_MANAGER.lock().alloc().map_err(|_| E_NOTIMER)?;
+            let console_task = &task_manager.tasks_data[job.console_task_index];
+            TIMER_MANAGER.lock().init_timer(
+                timer_index,
+                console_task.fifo_addr,
+                (ALARM_OFFSET + task_index as u32) as i32,
+            );
+            job.alarm_timer = Some(timer_index);
+            timer_index
+        }
+    };
+    TIMER_MANAGER.lock().set_time(timer_index, timeout);
+    Ok(())
+}
+
+/// アプリが終わったときに、api_alarmのタイマを片付ける
+pub fn free_alarm(job: &mut Job) {
+    if let Some(timer_index) = job.alarm_timer.take() {
+        TIMER_MANAGER.lock().free(timer_index);
+    }
+}
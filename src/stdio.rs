@@ -0,0 +1,135 @@
+use crate::abi::{STDIN, STDOUT};
+use crate::file::{load_file, FileInfo, MAX_FAT};
+use crate::memory::{MemMan, MEMMAN_ADDR};
+
+/// 標準入出力のつながっている先
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    /// 入力はキーボード、出力はコンソールの画面
+    Console,
+    /// パイプやリダイレクトで使うメモリ上のバッファ
+    Buffer(Buffer),
+}
+
+impl Stream {
+    pub fn free(self) {
+        if let Stream::Buffer(buf) = self {
+            buf.free();
+        }
+    }
+}
+
+/// 書き込むと足りない分だけ大きくなるバッファ
+/// 前のコマンドの出力をためておき、次のコマンドが先頭から読む
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Buffer {
+    pub addr: usize,
+    pub capacity: usize,
+    pub size: usize,
+    pub pos: usize,
+}
+
+impl Buffer {
+    pub fn new() -> Buffer {
+        Buffer {
+            addr: 0,
+            capacity: 0,
+            size: 0,
+            pos: 0,
+        }
+    }
+
+    pub fn from_file(finfo: &FileInfo, fat: &[u32; MAX_FAT]) -> Result<Buffer, &'static str> {
+        let mut buf = Buffer::new();
+        buf.reserve(finfo.size as usize)?;
+        load_file(finfo, buf.addr, fat);
+        buf.size = finfo.size as usize;
+        Ok(buf)
+    }
+
+    fn reserve(&mut self, size: usize) -> Result<(), &'static str> {
+        if size <= self.capacity {
+            return Ok(());
+        }
+        let memman = unsafe { &mut *(MEMMAN_ADDR as *mut MemMan) };
+        let capacity = ((size.max(self.capacity * 2) + 0xfff) & !0xfff) as u32;
+        let addr = memman.alloc_4k(capacity).map_err(|_| "Out of Memory")? as usize;
+        if self.capacity > 0 {
+            unsafe {
+                core::ptr::copy_nonoverlapping(self.addr as *const u8, addr as *mut u8, self.size);
+            }
+            memman
+                .free_4k(self.addr as u32, self.capacity as u32)
+                .unwrap();
+        }
+        self.addr = addr;
+        self.capacity = capacity as usize;
+        Ok(())
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<(), &'static str> {
+        self.reserve(self.size + data.len())?;
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                (self.addr + self.size) as *mut u8,
+                data.len(),
+            );
+        }
+        self.size += data.len();
+        Ok(())
+    }
+
+    /// 読めたバイト数を返す。0なら終わり
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let len = buf.len().min(self.size - self.pos);
+        buf[..len].copy_from_slice(&self.as_slice()[self.pos..(self.pos + len)]);
+        self.pos += len;
+        len
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        if self.capacity == 0 {
+            return &[];
+        }
+        unsafe { core::slice::from_raw_parts(self.addr as *const u8, self.size) }
+    }
+
+    pub fn free(self) {
+        if self.capacity > 0 {
+            let memman = unsafe { &mut *(MEMMAN_ADDR as *mut MemMan) };
+            memman
+                .free_4k(self.addr as u32, self.capacity as u32)
+                .unwrap();
+        }
+    }
+}
+
+/// タスクの標準入力・標準出力・標準エラー出力
+#[derive(Clone, Copy)]
+pub struct Stdio {
+    pub streams: [Stream; 3],
+}
+
+impl Stdio {
+    pub fn new() -> Stdio {
+        Stdio {
+            streams: [Stream::Console; 3],
+        }
+    }
+
+    pub fn get_mut(&mut self, fd: i32) -> Option<&mut Stream> {
+        if fd < 0 {
+            return None;
+        }
+        self.streams.get_mut(fd as usize)
+    }
+
+    pub fn stdin(&mut self) -> &mut Stream {
+        &mut self.streams[STDIN as usize]
+    }
+
+    pub fn stdout(&mut self) -> &mut Stream {
+        &mut self.streams[STDOUT as usize]
+    }
+}
@@ -1,194 +1,209 @@
 use lazy_static::lazy_static;
 use spin::Mutex;
 
-use crate::asm::{cli, load_eflags, out8, store_eflags};
+use core::cmp::min;
+
+use crate::asm::{cli, cpuid, in8, load_eflags, out8, rdtsc, sti, stihlt, store_eflags};
 use crate::fifo::Fifo;
 use crate::interrupt::PIC0_OCW2;
 
 const PIT_CTRL: u32 = 0x0043;
 const PIT_CNT0: u32 = 0x0040;
+const PIT_CNT2: u32 = 0x0042;
+const PORT_SPEAKER: u32 = 0x0061;
+// チャンネル0, 下位->上位バイトの順に書き込み, モード2(周期)
+const PIT_MODE_PERIODIC: u8 = 0x34;
+// チャンネル0, 下位->上位バイトの順に書き込み, モード0(ワンショット)
+const PIT_MODE_ONESHOT: u8 = 0x30;
+// チャンネル0のカウンタをラッチ
+const PIT_LATCH_CNT0: u8 = 0x00;
+// チャンネル2, 下位->上位バイトの順に書き込み, モード0(ワンショット)
+const PIT_MODE_ONESHOT_CNT2: u8 = 0xb0;
+const PIT_HZ: u64 = 1193182;
+// 1193182Hz / 100Hz = 11932
+const PIT_COUNT_PER_TICK: u32 = 0x2e9c;
+const NSEC_PER_TICK: u64 = 10_000_000;
+const EFLAGS_ID_BIT: u32 = 0x00200000;
+const CPUID_EDX_TSC: u32 = 0x00000010;
+// カウンタは16bitなので一度に止められるのは5tick(50ms)まで
+const MAX_ONESHOT_TICKS: u32 = 0xffff / PIT_COUNT_PER_TICK;
 
 pub fn init_pit() {
-    out8(PIT_CTRL, 0x34);
-    out8(PIT_CNT0, 0x9c);
-    out8(PIT_CNT0, 0x2e);
+    set_pit(PIT_MODE_PERIODIC, PIT_COUNT_PER_TICK);
 }
 
-const MAX_TIMER: usize = 500;
+fn set_pit(mode: u8, count: u32) {
+    out8(PIT_CTRL, mode);
+    out8(PIT_CNT0, count as u8);
+    out8(PIT_CNT0, (count >> 8) as u8);
+}
 
-#[derive(Debug, Clone, Copy)]
-pub struct Timer {
-    pub timeout: u32,
-    pub flag: TimerFlag,
-    pub from_app: bool,
-    pub data: i32,
-    pub fifo_addr: usize,
-    pub next: Option<usize>,
+fn read_pit_count() -> u32 {
+    out8(PIT_CTRL, PIT_LATCH_CNT0);
+    let low = in8(PIT_CNT0) as u32;
+    let high = in8(PIT_CNT0) as u32;
+    low | high << 8
 }
 
-impl Timer {
-    pub fn new() -> Timer {
-        Timer {
-            timeout: 0,
-            flag: TimerFlag::AVAILABLE,
-            from_app: false,
-            data: 0,
-            fifo_addr: 0,
-            next: None,
-        }
+// 1tickあたりのTSCのクロック数。0ならTSCは使わない
+static mut TSC_PER_TICK: u64 = 0;
+static mut TSC_BASE: u64 = 0;
+// TSC_BASEをはかったときの経過時間(ナノ秒)。PITで数える場合と同じく起動からの時間にそろえる
+static mut TSC_BASE_NSEC: u64 = 0;
+static mut LAST_NSEC: u64 = 0;
+
+fn has_tsc() -> bool {
+    // EFLAGSのIDビットが書き換えられればCPUIDが使える
+    let eflags = load_eflags() as u32;
+    store_eflags((eflags ^ EFLAGS_ID_BIT) as i32);
+    let toggled = load_eflags() as u32;
+    store_eflags(eflags as i32);
+    if (toggled ^ eflags) & EFLAGS_ID_BIT == 0 {
+        return false;
     }
+    let (_, _, _, edx) = cpuid(1);
+    edx & CPUID_EDX_TSC != 0
 }
 
-pub struct TimerManager {
-    pub count: u32,
-    pub next_tick: u32,
-    pub t0: Option<usize>,
-    pub timers_data: [Timer; MAX_TIMER],
+/// PITのチャンネル2で1tick(10ms)をはかり、その間に進んだTSCのクロック数を記録する
+pub fn calibrate_tsc() {
+    if !has_tsc() {
+        return;
+    }
+    let eflags = load_eflags();
+    cli();
+    let speaker = in8(PORT_SPEAKER);
+    // チャンネル2のゲートをONにして、スピーカーはOFFにしておく
+    out8(PORT_SPEAKER, (speaker & 0xfd) | 0x01);
+    out8(PIT_CTRL, PIT_MODE_ONESHOT_CNT2);
+    out8(PIT_CNT2, PIT_COUNT_PER_TICK as u8);
+    out8(PIT_CNT2, (PIT_COUNT_PER_TICK >> 8) as u8);
+    let start = rdtsc();
+    let start_nsec = TIMER_MANAGER.lock().queue.count as u64 * NSEC_PER_TICK;
+    // カウントが終わるとOUT2(bit5)が立つ
+    while in8(PORT_SPEAKER) & 0x20 == 0 {}
+    let end = rdtsc();
+    out8(PORT_SPEAKER, speaker);
+    store_eflags(eflags);
+    unsafe {
+        TSC_PER_TICK = end - start;
+        TSC_BASE = start;
+        TSC_BASE_NSEC = start_nsec;
+    }
+}
+
+/// 起動してからの経過時間(ナノ秒)。TSCがなければPITのカウンタを読んで補間する
+pub fn nsec() -> u64 {
+    let eflags = load_eflags();
+    cli();
+    let tsc_per_tick = unsafe { TSC_PER_TICK };
+    let now = if tsc_per_tick > 0 {
+        let elapsed = rdtsc() - unsafe { TSC_BASE };
+        // 掛け算があふれないようにtick単位と端数にわけて計算する
+        let ticks = elapsed / tsc_per_tick;
+        let rest = elapsed % tsc_per_tick;
+        let base = unsafe { TSC_BASE_NSEC };
+        base + ticks * NSEC_PER_TICK + rest * NSEC_PER_TICK / tsc_per_tick
+    } else {
+        let tm = TIMER_MANAGER.lock();
+        let remain = read_pit_count();
+        let elapsed = if tm.oneshot_ticks > 0 {
+            let total = tm.oneshot_total();
+            let oneshot_elapsed = if remain > total {
+                total
+            } else {
+                total - remain
+            };
+            tm.pit_remainder + oneshot_elapsed
+        } else {
+            PIT_COUNT_PER_TICK - min(remain, PIT_COUNT_PER_TICK)
+        };
+        tm.queue.count as u64 * NSEC_PER_TICK + elapsed as u64 * 1_000_000_000 / PIT_HZ
+    };
+    // IRQ0が保留中だとカウンタが戻って見えることがあるので、前回の値より小さくしない
+    let result = unsafe {
+        if now > LAST_NSEC {
+            LAST_NSEC = now;
+        }
+        LAST_NSEC
+    };
+    store_eflags(eflags);
+    result
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum TimerFlag {
-    AVAILABLE,
-    USED,
-    COUNTING,
+pub use haribote_core::timer::TimerFlag;
+
+use haribote_core::timer::TimerQueue;
+
+pub struct TimerManager {
+    pub oneshot_ticks: u32, // ワンショットモードで待っているtick数。0なら周期モード
+    // countに数えていない、1tickに満たない経過時間(PITのカウント数)。
+    // 周期モードで0以外なら、最初の周期をその分だけ短くしている
+    pub pit_remainder: u32,
+    pub queue: TimerQueue,
 }
 
 impl TimerManager {
     pub fn new() -> TimerManager {
-        let mut tm = TimerManager {
-            count: 0,
-            next_tick: 0xffffffff,
-            t0: Some(MAX_TIMER - 1),
-            timers_data: [Timer::new(); MAX_TIMER],
-        };
-        // 番兵
-        tm.timers_data[MAX_TIMER - 1] = Timer {
-            timeout: 0xffffffff,
-            flag: TimerFlag::COUNTING,
-            from_app: false,
-            data: 0,
-            fifo_addr: 0,
-            next: None,
-        };
-        tm
+        TimerManager {
+            oneshot_ticks: 0,
+            pit_remainder: 0,
+            queue: TimerQueue::new(),
+        }
+    }
+
+    /// ワンショットモードでPITに設定したカウント数。期限がtickの境目にそろうように端数を引いておく
+    fn oneshot_total(&self) -> u32 {
+        self.oneshot_ticks * PIT_COUNT_PER_TICK - self.pit_remainder
     }
 
     pub fn alloc(&mut self) -> Result<usize, &'static str> {
-        for i in 0..MAX_TIMER {
-            if self.timers_data[i].flag == TimerFlag::AVAILABLE {
-                self.timers_data[i].flag = TimerFlag::USED;
-                return Ok(i);
-            }
-        }
-        Err("CANNOT ASSIGN TIMER")
+        self.queue.alloc()
+    }
+
+    /// アプリ用のタイマを確保する。アプリ終了時にcancel_allでまとめて解放できるように一覧につなぐ
+    pub fn alloc_app(&mut self) -> Result<usize, &'static str> {
+        self.queue.alloc_app()
     }
 
     pub fn set_time(&mut self, timer_index: usize, timeout: u32) {
-        {
-            let mut timer = &mut self.timers_data[timer_index];
-            timer.timeout = timeout + self.count;
-            timer.flag = TimerFlag::COUNTING;
-        }
-        if self.t0.is_none() {
-            return;
-        }
         let eflags = load_eflags();
         cli();
-        let mut t_index = self.t0.unwrap();
-        if &self.timers_data[timer_index].timeout <= &self.timers_data[t_index].timeout {
-            // 先頭に入れる
-            let mut timer = &mut self.timers_data[timer_index];
-            self.t0 = Some(timer_index);
-            timer.next = Some(t_index);
-            self.next_tick = timer.timeout;
-            store_eflags(eflags);
-            return;
-        }
-        let mut old_t_index: usize;
-        // 挿入できるインデックスをさがす
-        loop {
-            old_t_index = t_index;
-            if self.timers_data[t_index].next.is_none() {
-                store_eflags(eflags);
-                break;
-            }
-            t_index = self.timers_data[t_index].next.unwrap();
-            if self.timers_data[timer_index].timeout <= self.timers_data[t_index].timeout {
-                {
-                    let mut s = &mut self.timers_data[old_t_index];
-                    s.next = Some(timer_index);
-                }
-                {
-                    let mut timer = &mut self.timers_data[timer_index];
-                    timer.next = Some(t_index);
-                }
-                store_eflags(eflags);
-                return;
-            }
-        }
+        self.queue.set_time(timer_index, timeout);
+        store_eflags(eflags);
+    }
+
+    /// intervalごとに自動で再設定されるタイマ
+    pub fn set_periodic(&mut self, timer_index: usize, interval: u32) {
+        let eflags = load_eflags();
+        cli();
+        self.queue.set_periodic(timer_index, interval);
+        store_eflags(eflags);
     }
 
     pub fn init_timer(&mut self, timer_index: usize, fifo_addr: usize, data: i32) {
-        let mut timer = &mut self.timers_data[timer_index];
-        timer.fifo_addr = fifo_addr;
-        timer.data = data;
+        self.queue.init_timer(timer_index, fifo_addr, data);
     }
 
     pub fn free(&mut self, i: usize) {
-        let mut timer = &mut self.timers_data[i];
-        timer.flag = TimerFlag::AVAILABLE;
+        let eflags = load_eflags();
+        cli();
+        self.queue.free(i);
+        store_eflags(eflags);
     }
 
     pub fn cancel(&mut self, timer_index: usize) -> bool {
-        let timer = self.timers_data[timer_index];
         let eflags = load_eflags();
         cli();
-        if timer.flag == TimerFlag::COUNTING {
-            if self.t0.is_some() && timer_index == self.t0.unwrap() {
-                if let Some(next) = timer.next {
-                    let n = &mut self.timers_data[next];
-                    self.next_tick = n.timeout;
-                }
-            } else {
-                if let Some(t0) = self.t0 {
-                    let mut t_index = t0;
-                    let mut t = self.timers_data[t_index];
-                    loop {
-                        if let Some(next) = t.next {
-                            if next == timer_index {
-                                break;
-                            }
-                            t_index = next;
-                            t = self.timers_data[next];
-                        } else {
-                            break;
-                        }
-                    }
-                    let mut t = &mut self.timers_data[t_index];
-                    t.next = timer.next;
-                }
-            }
-            {
-                let mut timer = &mut self.timers_data[timer_index];
-                timer.flag = TimerFlag::USED;
-            }
-            store_eflags(eflags);
-            return true;
-        }
+        let canceled = self.queue.cancel(timer_index);
         store_eflags(eflags);
-        false
+        canceled
     }
 
     pub fn cancel_all(&mut self, fifo_addr: usize) {
         let eflags = load_eflags();
         cli();
-        for i in 0..MAX_TIMER {
-            let t = self.timers_data[i];
-            if t.flag != TimerFlag::AVAILABLE && t.from_app && t.fifo_addr == fifo_addr {
-                self.cancel(i);
-                self.free(i);
-            }
-        }
+        self.queue.cancel_all(fifo_addr);
         store_eflags(eflags);
     }
 }
@@ -202,32 +217,80 @@ pub static mut NEED_SWITCH: bool = false;
 pub extern "C" fn inthandler20() {
     out8(PIC0_OCW2, 0x60); // IRQ-00受付完了をPICに通知
     let mut tm = TIMER_MANAGER.lock();
-    tm.count += 1;
-    if tm.next_tick > tm.count {
+    if tm.oneshot_ticks > 0 {
+        // アイドル中に止めていた分をまとめて進めて、周期モードに戻す
+        tm.queue.count += tm.oneshot_ticks;
+        tm.oneshot_ticks = 0;
+        tm.pit_remainder = 0;
+        set_pit(PIT_MODE_PERIODIC, PIT_COUNT_PER_TICK);
+    } else {
+        if tm.pit_remainder > 0 {
+            // 端数の分だけ短くした最初の周期が終わったので、元の周期に戻す
+            tm.pit_remainder = 0;
+            set_pit(PIT_MODE_PERIODIC, PIT_COUNT_PER_TICK);
+        }
+        tm.queue.count += 1;
+    }
+    if tm.queue.next_tick > tm.queue.count {
         return;
     }
-    let mut timer_index = tm.t0;
     let mut need_taskswitch = false;
-    loop {
-        let t_index = timer_index.unwrap();
-        if tm.timers_data[t_index].timeout > tm.count {
-            break;
-        }
-        let mut timer = &mut tm.timers_data[t_index];
-        timer.flag = TimerFlag::USED;
+    tm.queue.expire(|t_index, timer| {
         if t_index != unsafe { crate::mt::MT_TIMER_INDEX } {
             let fifo = unsafe { &mut *(timer.fifo_addr as *mut Fifo) };
-            fifo.put(timer.data as u32).unwrap();
+            // 周期タイマを読まないアプリもいるので、あふれた分は捨てる
+            let _ = fifo.put(timer.data as u32);
         } else {
             need_taskswitch = true;
         }
-        timer_index = timer.next;
-    }
-    tm.t0 = timer_index;
-    if let Some(t_index) = timer_index {
-        tm.next_tick = tm.timers_data[t_index].timeout;
-    }
+    });
     if need_taskswitch {
         unsafe { NEED_SWITCH = true };
     }
 }
+
+/// 次のタイマの期限までPITをワンショットモードにしてHLTする。
+/// タイマ以外の割り込みで起こされた場合は、経過した分だけcountを進めて周期モードに戻す。
+/// 1tickに満たない端数はpit_remainderに残して、次の周期の長さで埋め合わせる。
+pub fn idle_wait() {
+    cli();
+    let ticks = {
+        let mut tm = TIMER_MANAGER.lock();
+        let ticks = min(
+            tm.queue.next_tick.saturating_sub(tm.queue.count),
+            MAX_ONESHOT_TICKS,
+        );
+        if ticks > 1 {
+            // 今の周期ですでに進んだ分も端数に含める
+            let remain = read_pit_count();
+            tm.pit_remainder = PIT_COUNT_PER_TICK - min(remain, PIT_COUNT_PER_TICK);
+            tm.oneshot_ticks = ticks;
+            set_pit(PIT_MODE_ONESHOT, tm.oneshot_total());
+        }
+        ticks
+    };
+    stihlt();
+    if ticks <= 1 {
+        return;
+    }
+    cli();
+    {
+        let mut tm = TIMER_MANAGER.lock();
+        let total = tm.oneshot_total();
+        let remain = if tm.oneshot_ticks > 0 {
+            read_pit_count()
+        } else {
+            0
+        };
+        // カウンタが0になったか一周している場合はIRQ0が保留中なので、
+        // sti()のあとのinthandler20にまとめて進めてもらう
+        if remain != 0 && remain <= total {
+            let elapsed = tm.pit_remainder + total - remain;
+            tm.queue.count += elapsed / PIT_COUNT_PER_TICK;
+            tm.pit_remainder = elapsed % PIT_COUNT_PER_TICK;
+            tm.oneshot_ticks = 0;
+            set_pit(PIT_MODE_PERIODIC, PIT_COUNT_PER_TICK - tm.pit_remainder);
+        }
+    }
+    sti();
+}
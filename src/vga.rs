@@ -3,6 +3,7 @@ use core::fmt::Write;
 use lazy_static::lazy_static;
 
 use crate::asm;
+use crate::boot_info::boot_info;
 use crate::fonts::{HANKAKU, HANKAKU_HEIGHT, HANKAKU_WIDTH};
 use crate::mt::{LangMode, TaskManager, TASK_MANAGER_ADDR};
 use crate::sheet::SheetManager;
@@ -75,9 +76,9 @@ pub fn to_color(n: i8) -> Option<Color> {
 pub const MAX_BLOCK_SIZE: usize = 16;
 
 lazy_static! {
-    pub static ref SCREEN_WIDTH: i16 = unsafe { *(0x0ff4 as *const i16) };
-    pub static ref SCREEN_HEIGHT: i16 = unsafe { *(0x0ff6 as *const i16) };
-    pub static ref VRAM_ADDR: usize = unsafe { *(0xff8 as *const usize) };
+    pub static ref SCREEN_WIDTH: i16 = boot_info().scrnx as i16;
+    pub static ref SCREEN_HEIGHT: i16 = boot_info().scrny as i16;
+    pub static ref VRAM_ADDR: usize = boot_info().vram as usize;
 }
 
 pub fn init_palette() {
@@ -108,6 +109,54 @@ pub fn init_palette() {
     asm::store_eflags(eflags);
 }
 
+// Bochs/QEMUの標準VGA(-vga std)の画面モード設定用レジスタ
+const VBE_DISPI_IOPORT_INDEX: u32 = 0x01ce;
+const VBE_DISPI_IOPORT_DATA: u32 = 0x01cf;
+const VBE_DISPI_INDEX_ID: u16 = 0;
+const VBE_DISPI_INDEX_XRES: u16 = 1;
+const VBE_DISPI_INDEX_YRES: u16 = 2;
+const VBE_DISPI_INDEX_BPP: u16 = 3;
+const VBE_DISPI_INDEX_ENABLE: u16 = 4;
+const VBE_DISPI_ID0: u16 = 0xb0c0;
+const VBE_DISPI_ID5: u16 = 0xb0c5;
+const VBE_DISPI_ENABLED: u16 = 0x01;
+const VBE_DISPI_LFB_ENABLED: u16 = 0x40;
+/// 標準VGAのPCIのベンダIDとデバイスID。VRAMの番地はBAR0にある
+const BOCHS_VGA_PCI_ID: u32 = 0x1111_1234;
+const PCI_CONFIG_ADDRESS: u32 = 0x0cf8;
+const PCI_CONFIG_DATA: u32 = 0x0cfc;
+
+fn pci_read(device: u32, offset: u32) -> u32 {
+    asm::out32(PCI_CONFIG_ADDRESS, 0x80000000 | device << 11 | offset);
+    asm::in32(PCI_CONFIG_DATA)
+}
+
+fn write_dispi(index: u16, data: u16) {
+    asm::out16(VBE_DISPI_IOPORT_INDEX, index);
+    asm::out16(VBE_DISPI_IOPORT_DATA, data);
+}
+
+/// BIOSを使わずに8bitカラーの画面モードにして、VRAMの番地を返す
+/// Multibootのローダが画面モードを変えてくれなかったときに使う。Bochs/QEMUの標準VGAでなければNone
+pub fn init_bochs_vbe(width: u16, height: u16) -> Option<u32> {
+    asm::out16(VBE_DISPI_IOPORT_INDEX, VBE_DISPI_INDEX_ID);
+    let id = asm::in16(VBE_DISPI_IOPORT_DATA);
+    if id < VBE_DISPI_ID0 || id > VBE_DISPI_ID5 {
+        return None;
+    }
+    let device = (0..32).find(|d| pci_read(*d, 0x00) == BOCHS_VGA_PCI_ID)?;
+    let vram = pci_read(device, 0x10) & 0xfffffff0;
+    write_dispi(VBE_DISPI_INDEX_ENABLE, 0);
+    write_dispi(VBE_DISPI_INDEX_XRES, width);
+    write_dispi(VBE_DISPI_INDEX_YRES, height);
+    write_dispi(VBE_DISPI_INDEX_BPP, 8);
+    write_dispi(
+        VBE_DISPI_INDEX_ENABLE,
+        VBE_DISPI_ENABLED | VBE_DISPI_LFB_ENABLED,
+    );
+    Some(vram)
+}
+
 pub fn init_screen(buf: usize) {
     use Color::*;
     let xsize = *SCREEN_WIDTH as isize;
@@ -196,7 +245,7 @@ pub fn print_char_wrapper(
                 cursor_y,
             );
         } else if task.lang_mode == LangMode::JpJis {
-            let nihongo_addr = unsafe { *(NIHONGO_ADDR as *const usize) };
+            let nihongo_addr = unsafe { NIHONGO_ADDR };
             if task.lang_byte1 == 0 {
                 if (0x81 <= chr && chr <= 0x9f) || (0xe0 <= chr && chr <= 0xfc) {
                     let mut task = &mut task_manager.tasks_data[task_index];
@@ -261,7 +310,7 @@ pub fn print_char_wrapper(
                 );
             }
         } else if task.lang_mode == LangMode::JpEuc {
-            let nihongo_addr = unsafe { *(NIHONGO_ADDR as *const usize) };
+            let nihongo_addr = unsafe { NIHONGO_ADDR };
             if task.lang_byte1 == 0 {
                 if 0x81 <= chr && chr <= 0xfe {
                     let mut task = &mut task_manager.tasks_data[task_index];